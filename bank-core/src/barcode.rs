@@ -0,0 +1,120 @@
+// Kept as a GTIN-14, so the same product is found however many leading zeros it was written with
+#[derive(Debug, PartialEq, Eq, Hash, Serialize, Deserialize, Clone)]
+pub struct Barcode([u8; 14]);
+
+// What kind of barcode was scanned or typed, judging by its length
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Symbology {
+    Ean8,
+    Ean13,
+    UpcA,
+    Gtin14,
+    // Six digit codes of our own, too short to be any kind of GTIN
+    Short,
+}
+
+impl Symbology {
+    // How many digits it's normally written with
+    pub fn digits(&self) -> usize {
+        match self {
+            Self::Ean8 => 8,
+            Self::Ean13 => 13,
+            Self::UpcA => 12,
+            Self::Gtin14 => 14,
+            Self::Short => 6,
+        }
+    }
+}
+
+impl std::fmt::Display for Symbology {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Ean8 => write!(f, "EAN-8"),
+            Self::Ean13 => write!(f, "EAN-13"),
+            Self::UpcA => write!(f, "UPC-A"),
+            Self::Gtin14 => write!(f, "GTIN-14"),
+            Self::Short => write!(f, "short code"),
+        }
+    }
+}
+
+impl std::fmt::Display for Barcode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.gtin())
+    }
+}
+
+impl Barcode {
+    // Anything from 6 to 14 digits. Codes that have lost leading zeros, e.g. to a spreadsheet,
+    // are padded back out to the nearest length they could have been.
+    pub fn parse(input: &str) -> Option<(Self, Symbology)> {
+        let d = int_digits(input)?;
+        let symbology = match d.len() {
+            6 => Symbology::Short,
+            7 | 8 => Symbology::Ean8,
+            9..=12 => Symbology::UpcA,
+            // UPC-A codes are EAN-13s starting with 0, and GTIN-14s can be padded EAN-13s
+            13 if d[0] == 0 => Symbology::UpcA,
+            13 => Symbology::Ean13,
+            14 if d[0] == 0 => return Self::parse(&input[1..]),
+            14 => Symbology::Gtin14,
+            _ => return None,
+        };
+        let mut gtin = [0; 14];
+        gtin[14 - d.len()..].copy_from_slice(&d);
+        Some((Self(gtin), symbology))
+    }
+
+    pub fn try_parse(input: &str) -> Option<Self> {
+        Self::parse(input).map(|(b, _)| b)
+    }
+
+    // Best guess from the digits alone, for when how it was written isn't known. EAN-8 codes
+    // starting with 0 look like short codes, and UPC-A codes starting with 0000 like EAN-8.
+    pub fn symbology(&self) -> Symbology {
+        let zeros = self.0.iter().take_while(|d| **d == 0).count();
+        match zeros {
+            0 => Symbology::Gtin14,
+            1 => Symbology::Ean13,
+            2..=5 => Symbology::UpcA,
+            6 => Symbology::Ean8,
+            _ => Symbology::Short,
+        }
+    }
+
+    // Padded out to 14 digits with leading zeros
+    pub fn digits(&self) -> &[u8; 14] {
+        &self.0
+    }
+
+    pub fn gtin(&self) -> String {
+        self.0.iter().map(|d| d.to_string()).collect()
+    }
+
+    // Without any leading zeros, e.g. for comparing with codes from elsewhere
+    pub fn stripped(&self) -> String {
+        self.gtin().trim_start_matches('0').to_string()
+    }
+
+    // Written with exactly `len` digits, adding or dropping leading zeros, unless it doesn't fit
+    pub fn padded(&self, len: usize) -> Option<String> {
+        let stripped = self.stripped();
+        (stripped.len() <= len && len <= 14).then(|| format!("{:0>len$}", stripped, len = len))
+    }
+
+    // As it would be printed under the bars
+    pub fn written(&self, symbology: Symbology) -> String {
+        self.padded(symbology.digits()).unwrap_or_else(|| self.gtin())
+    }
+
+    pub fn check_digit(&self) -> bool {
+        let (odd, even): (Vec<_>, Vec<_>) = self.0.iter().enumerate().partition(|&x| x.0 % 2 == 0);
+        let sum = even.iter().map(|x| *x.1 as u32).sum::<u32>() +
+            (odd.iter().map(|x| *x.1 as u32).sum::<u32>() * 3);
+        sum % 10 == 0
+    }
+}
+
+fn int_digits(input: &str) -> Option<Vec<u8>> {
+    input.chars().map(|d| Some(d.to_digit(10)? as u8)).collect::<Option<Vec<_>>>()
+}
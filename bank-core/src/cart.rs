@@ -0,0 +1,100 @@
+#[derive(Clone)]
+pub struct Cart {
+    pub products: Vec<crate::products::Product>,
+    pub promo: Option<crate::promo::Promo>,
+    pub combos: Vec<crate::combo::AppliedCombo>,
+    // Admin who let this cart go over the buyer's spending limits
+    pub limit_override: Option<String>,
+    // Who the cart is for once they've said, to show what they'll have left
+    pub buyer: Option<crate::db::User>,
+    // Money off the whole cart given at the till by an admin
+    pub manual_discount: Option<crate::db::ManualDiscount>,
+    // Becomes the purchase's transaction ID, so charging the same cart again after an error is
+    // turned away rather than taking the money twice
+    pub key: uuid::Uuid,
+}
+
+impl Cart {
+    pub fn new() -> Self {
+        Self {
+            products: Vec::new(),
+            promo: None,
+            combos: Vec::new(),
+            limit_override: None,
+            buyer: None,
+            manual_discount: None,
+            key: uuid::Uuid::new_v4(),
+        }
+    }
+
+    // Sells the last matching line that hasn't already been overridden at a different price
+    pub fn override_price(
+        &mut self,
+        barcode: &crate::barcode::Barcode,
+        price: u32,
+        reason: &str,
+        admin: &str,
+    ) -> Result<&crate::products::Product, String> {
+        let product = self
+            .products
+            .iter_mut()
+            .rev()
+            .find(|p| &p.barcode == barcode && p.price_override.is_none())
+            .ok_or_else(|| format!("no {} in the cart to override", barcode))?;
+        product.price_override = Some(crate::products::PriceOverride {
+            original: product.price,
+            reason: reason.to_string(),
+            admin: admin.to_string(),
+        });
+        product.price = price;
+        Ok(product)
+    }
+
+    // Works out which combos the cart now qualifies for, after it's changed
+    pub fn apply_combos(&mut self, combos: &[crate::combo::Combo]) {
+        self.combos = crate::combo::find_combos(combos, &self.products);
+    }
+
+    // Before any combos or promo code
+    pub fn subtotal(&self) -> u32 {
+        self.products.iter().map(|p| p.price).sum()
+    }
+
+    pub fn combo_discount(&self) -> u32 {
+        self.combos.iter().map(|c| c.discount).sum()
+    }
+
+    // Promo codes apply to the price after combos
+    pub fn discount(&self) -> u32 {
+        self.promo
+            .as_ref()
+            .map_or(0, |p| p.discount_on(self.subtotal() - self.combo_discount()))
+    }
+
+    // Comes off last, after combos and any promo code
+    pub fn manual_discount_amount(&self) -> u32 {
+        self.manual_discount
+            .as_ref()
+            .map_or(0, |m| m.discount.on(self.subtotal() - self.combo_discount() - self.discount()))
+    }
+
+    pub fn total(&self) -> u32 {
+        self.subtotal() - self.combo_discount() - self.discount() - self.manual_discount_amount()
+    }
+
+    // The buyer's balance after paying for everything so far, including their own discount
+    pub fn projected_balance(&self) -> Option<i32> {
+        let buyer = self.buyer.as_ref()?;
+        Some(buyer.balance - (self.total() - buyer.discount_on(self.total())) as i32)
+    }
+
+    pub fn disp_total(&self) -> String {
+        crate::money::format(self.total())
+    }
+}
+
+impl Default for Cart {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,61 @@
+use crate::products::Product;
+
+// A meal deal, e.g. any drink and any snack for £1.80
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Combo {
+    pub name: String,
+    // One product from each of these categories, e.g. ["drink", "snack"]
+    pub categories: Vec<String>,
+    pub price: u32,
+}
+
+// A combo found in a cart, and how much it took off
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct AppliedCombo {
+    pub name: String,
+    pub discount: u32,
+}
+
+// Matches combos in the order given, as many times as each fits. The dearest products are used
+// first so the customer gets the best deal, and no product counts towards more than one combo.
+// Lines already sold at an overridden price are left alone.
+pub fn find_combos(combos: &[Combo], products: &[Product]) -> Vec<AppliedCombo> {
+    let mut order = (0..products.len())
+        .filter(|i| products[*i].price_override.is_none())
+        .collect::<Vec<_>>();
+    order.sort_by_key(|i| std::cmp::Reverse(products[*i].price));
+
+    let mut used = vec![false; products.len()];
+    let mut applied = Vec::new();
+    for combo in combos.iter().filter(|c| !c.categories.is_empty()) {
+        loop {
+            let mut picked = Vec::new();
+            for category in &combo.categories {
+                match order.iter().find(|i| {
+                    !used[**i]
+                        && !picked.contains(*i)
+                        && products[**i].category.as_ref() == Some(category)
+                }) {
+                    Some(i) => picked.push(*i),
+                    None => break,
+                }
+            }
+            if picked.len() < combo.categories.len() {
+                break;
+            }
+
+            let full_price = picked.iter().map(|i| products[*i].price).sum::<u32>();
+            if full_price <= combo.price {
+                break;
+            }
+            for i in picked {
+                used[i] = true;
+            }
+            applied.push(AppliedCombo {
+                name: combo.name.clone(),
+                discount: full_price - combo.price,
+            });
+        }
+    }
+    applied
+}
@@ -0,0 +1,3415 @@
+use crate::money;
+use crate::style::{theme, Style};
+use chrono::prelude::*;
+use std::{
+    collections::{HashSet, VecDeque},
+    fmt::Formatter,
+};
+use rustbreak::deser::DeSerializer;
+use std::sync::atomic::{AtomicBool, Ordering};
+use uuid::Uuid;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct InnerDB {
+    pub users: std::collections::HashMap<String, User>,
+    pub transactions: Vec<Transaction>,
+    // Push target -> IDs of local transactions not yet delivered there, oldest first
+    #[serde(default)]
+    pub outbox: std::collections::HashMap<String, VecDeque<Uuid>>,
+    #[serde(default)]
+    pub standing_orders: Vec<StandingOrder>,
+    // So syncing with a till that still has them doesn't bring them back
+    #[serde(default)]
+    pub cancelled_standing_orders: HashSet<Uuid>,
+    // Code -> voucher, kept after redemption for auditing
+    #[serde(default)]
+    pub vouchers: std::collections::HashMap<String, Voucher>,
+    #[serde(default)]
+    pub promos: std::collections::HashMap<String, crate::promo::Promo>,
+    // Code -> when it was removed
+    #[serde(default)]
+    pub removed_promos: std::collections::HashMap<String, DateTime<Utc>>,
+    // Currency code -> when its cash box was last emptied
+    #[serde(default)]
+    pub cashbox_emptied: std::collections::HashMap<String, DateTime<Utc>>,
+    // Self-registered cards waiting for an admin, oldest first
+    #[serde(default)]
+    pub pending_cards: Vec<PendingCard>,
+    #[serde(default)]
+    pub archive: Archive,
+    // Till name ("" if unnamed) -> when its last Z-report was taken
+    #[serde(default)]
+    pub last_zreport: std::collections::HashMap<String, DateTime<Utc>>,
+    // Name -> supplier
+    #[serde(default)]
+    pub suppliers: std::collections::HashMap<String, Supplier>,
+    #[serde(default)]
+    pub purchase_orders: Vec<PurchaseOrder>,
+    #[serde(default)]
+    pub api_tokens: Vec<ApiToken>,
+}
+
+// Past years of transactions, moved out of the main file into archive/<year> in the data directory
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Archive {
+    pub years: std::collections::BTreeSet<i32>,
+    // What the archived transactions add up to for each account, so balances can still be
+    // worked out from the log that's left
+    pub balances: std::collections::HashMap<String, i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct User {
+    pub id: String,
+    // Cached from the transaction log, which is the source of truth. Only InnerDB::apply
+    // changes it, and it's rebuilt from the log whenever the database is loaded.
+    pub balance: i32,
+
+    // uid, name
+    pub cards: Option<HashSet<(String, String)>>,
+
+    // ID in the space's membership system, set by importusers
+    #[serde(default)]
+    pub membership_id: Option<String>,
+
+    // OIDC subject of the space account this user is linked to
+    #[serde(default)]
+    pub oidc_subject: Option<String>,
+
+    // Argon2id in PHC format, or "<salt>$<sha256 of salt and PIN>" from before that, which is
+    // replaced the next time the PIN is entered
+    #[serde(default)]
+    pub pin_hash: Option<String>,
+    // Wrong PINs in a row, and when PIN entry opens again once there have been too many
+    #[serde(default)]
+    pub pin_failures: u32,
+    #[serde(default)]
+    pub pin_locked_until: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub email: Option<String>,
+
+    // Users who can spend from this account, making it a shared group account
+    #[serde(default)]
+    pub members: Vec<String>,
+
+    // Made with addgroup, so members can be added back after the last one has left
+    #[serde(default)]
+    pub group: bool,
+
+    // Why purchases are blocked, e.g. a lost card being looked into or unpaid debt
+    #[serde(default)]
+    pub frozen: Option<String>,
+
+    // Percentage taken off everything they buy, e.g. for volunteers who staff events
+    #[serde(default)]
+    pub discount: Option<u8>,
+
+    // Caps on spending in pence, e.g. for under-18 members at open evenings
+    #[serde(default)]
+    pub daily_limit: Option<u32>,
+    #[serde(default)]
+    pub purchase_limit: Option<u32>,
+
+    #[serde(default)]
+    pub role: Role,
+
+    // Their own short codes for products they buy a lot, e.g. "me1" -> their usual drink
+    #[serde(default)]
+    pub favourites: std::collections::BTreeMap<String, crate::barcode::Barcode>,
+
+    // Card UID -> when it was last tapped, or first seen by the retention policy if never
+    #[serde(default)]
+    pub card_last_used: std::collections::HashMap<String, DateTime<Utc>>,
+
+    // Card UID -> when it was last added or taken off, so syncing keeps whichever happened last
+    // instead of bringing back cards deleted on another till
+    #[serde(default)]
+    pub card_changes: std::collections::HashMap<String, DateTime<Utc>>,
+}
+
+// Who may do what at the till, each role able to do everything the one before it can
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    #[default]
+    Member,
+    Volunteer,
+    Treasurer,
+    Admin,
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Member => write!(f, "member"),
+            Self::Volunteer => write!(f, "volunteer"),
+            Self::Treasurer => write!(f, "treasurer"),
+            Self::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+impl std::str::FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "member" => Ok(Self::Member),
+            "volunteer" => Ok(Self::Volunteer),
+            "treasurer" => Ok(Self::Treasurer),
+            "admin" => Ok(Self::Admin),
+            _ => Err(format!(
+                "unknown role {}, must be member, volunteer, treasurer, or admin",
+                s
+            )),
+        }
+    }
+}
+
+// When a card was last added or removed, and (uid, name) if it's still there
+type CardState = (DateTime<Utc>, Option<(String, String)>);
+
+impl User {
+    pub fn new(id: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            balance: 0,
+            cards: Some(HashSet::new()),
+            membership_id: None,
+            oidc_subject: None,
+            pin_hash: None,
+            pin_failures: 0,
+            pin_locked_until: None,
+            name: None,
+            email: None,
+            members: Vec::new(),
+            group: false,
+            frozen: None,
+            discount: None,
+            daily_limit: None,
+            purchase_limit: None,
+            role: Role::Member,
+            favourites: std::collections::BTreeMap::new(),
+            card_last_used: std::collections::HashMap::new(),
+            card_changes: std::collections::HashMap::new(),
+        }
+    }
+
+    pub fn add_card(&mut self, uid: &str, name: &str, at: DateTime<Utc>) {
+        let cards = self.cards.get_or_insert_with(HashSet::new);
+        cards.retain(|(c, _)| c != uid);
+        cards.insert((uid.to_string(), name.to_string()));
+        self.card_changes.insert(uid.to_string(), at);
+    }
+
+    pub fn remove_card(&mut self, uid: &str, at: DateTime<Utc>) {
+        if let Some(cards) = self.cards.as_mut() {
+            cards.retain(|(c, _)| c != uid);
+        }
+        self.card_last_used.remove(uid);
+        self.card_changes.insert(uid.to_string(), at);
+    }
+
+    // When the card was last changed, and the card if it's still on the account. Cards from
+    // before changes were recorded count as older than any change.
+    fn card_state(&self, uid: &str) -> Option<CardState> {
+        let card = self.cards.iter().flatten().find(|(c, _)| c == uid).cloned();
+        match (self.card_changes.get(uid), card) {
+            (Some(at), card) => Some((*at, card)),
+            (None, Some(card)) => Some((DateTime::<Utc>::MIN_UTC, Some(card))),
+            (None, None) => None,
+        }
+    }
+
+    // Takes each card as it is on whichever till changed it last, a removal winning a tie.
+    // Returns whether anything changed.
+    pub fn merge_cards(&mut self, peer: &User) -> bool {
+        let uids = self
+            .cards
+            .iter()
+            .chain(peer.cards.iter())
+            .flatten()
+            .map(|(uid, _)| uid)
+            .chain(self.card_changes.keys())
+            .chain(peer.card_changes.keys())
+            .cloned()
+            .collect::<HashSet<_>>();
+        let mut changed = false;
+        for uid in uids {
+            let theirs = match (self.card_state(&uid), peer.card_state(&uid)) {
+                (_, None) => continue,
+                (None, Some(theirs)) => theirs,
+                (Some(ours), Some(theirs))
+                    if theirs.0 > ours.0 || (theirs.0 == ours.0 && ours.1.is_some() && theirs.1.is_none()) =>
+                {
+                    theirs
+                }
+                _ => continue,
+            };
+            match theirs.1 {
+                Some((_, name)) => self.add_card(&uid, &name, theirs.0),
+                None => self.remove_card(&uid, theirs.0),
+            }
+            if !peer.card_changes.contains_key(&uid) {
+                self.card_changes.remove(&uid);
+            }
+            changed = true;
+        }
+        changed
+    }
+
+    // The readable name if they've set one, alongside the ID they log in with
+    pub fn display_name(&self) -> String {
+        match &self.name {
+            Some(name) => format!("{} ({})", name, self.id),
+            None => self.id.clone(),
+        }
+    }
+
+    pub fn is_group(&self) -> bool {
+        !self.members.is_empty()
+    }
+
+    // Rounded down, so the bank never gives away more than the percentage
+    pub fn discount_on(&self, total: u32) -> u32 {
+        total * self.discount.unwrap_or(0) as u32 / 100
+    }
+
+    pub fn has_card(&self, uid: &str) -> bool {
+        self.cards
+            .as_ref()
+            .is_some_and(|cards| cards.iter().any(|(card_id, _)| card_id == uid))
+    }
+
+    pub fn check_pin(&self, pin: &str) -> bool {
+        use argon2::password_hash::{PasswordHash, PasswordVerifier};
+        match self.pin_hash.as_deref() {
+            Some(h) if h.starts_with('$') => PasswordHash::new(h)
+                .is_ok_and(|h| argon2::Argon2::default().verify_password(pin.as_bytes(), &h).is_ok()),
+            Some(h) => match h.split_once('$') {
+                Some((salt, hash)) => legacy_hash_pin(salt, pin) == hash,
+                None => false,
+            },
+            None => false,
+        }
+    }
+
+    pub fn disp_balance(&self) -> String {
+        if self.balance < 0 {
+            Style::new()
+                .fg(theme().negative)
+                .paint(money::format(self.balance))
+                .to_string()
+        } else {
+            money::format(self.balance)
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Transaction {
+    // Transactions from before IDs were introduced are given a deterministic ID on load
+    #[serde(default)]
+    pub id: Uuid,
+    pub timestamp: DateTime<Utc>,
+    pub actor: TransactionActor,
+    pub transaction: TransactionType,
+    // Free text explaining anything unusual, e.g. "birthday money"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    // Which till it was made at, when there's more than one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub till: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum TransactionActor {
+    User(String),
+    Cash,
+    // A named one-off tab for visitors, settled later
+    Tab(String),
+    // A member spending from a shared group account
+    Group { group: String, member: String },
+    // Stock leaving the shelves without anyone paying, e.g. a write-off
+    Stock,
+    // Whoever it was, forgotten under the retention policy
+    Anonymous,
+}
+
+impl TransactionActor {
+    // The user whose balance is affected
+    pub fn account(&self) -> Option<&str> {
+        match self {
+            Self::User(id) | Self::Group { group: id, .. } => Some(id),
+            Self::Cash | Self::Tab(_) | Self::Stock | Self::Anonymous => None,
+        }
+    }
+}
+
+impl std::fmt::Display for TransactionActor {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::User(id) => write!(f, "user {}", id),
+            Self::Cash => write!(f, "cash"),
+            Self::Tab(name) => write!(f, "tab {}", name),
+            Self::Group { group, member } => write!(f, "group {} (by {})", group, member),
+            Self::Stock => write!(f, "stock"),
+            Self::Anonymous => write!(f, "anonymous"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum TransactionType {
+    Purchase {
+        products: Vec<crate::products::Product>,
+        total: u32,
+        // Promo code used, and how much it took off the total
+        #[serde(default)]
+        promo: Option<String>,
+        #[serde(default)]
+        discount: u32,
+        // Set when paid in cash in a currency other than pounds
+        #[serde(default)]
+        tendered: Option<Tendered>,
+        // Meal deals matched in the cart, already taken off the total
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        combos: Vec<crate::combo::AppliedCombo>,
+        // Taken off by the account's own discount, after everything else
+        #[serde(default)]
+        user_discount: u32,
+        // Added to what was actually paid in cash by rounding, in the minor units of the
+        // currency paid in. The total itself is always exact.
+        #[serde(default, skip_serializing_if = "is_zero")]
+        rounding: i32,
+        // Given by hand at the till, already taken off the total
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        manual_discount: Option<ManualDiscount>,
+    },
+    Deposit {
+        // What was paid in, the account is credited with this less the fee
+        amount: u32,
+        method: DepositMethod,
+        #[serde(default, skip_serializing_if = "is_zero")]
+        fee: u32,
+    },
+    StandingOrder {
+        order: Uuid,
+        description: String,
+        amount: u32,
+    },
+    // Paying off a guest tab, either in cash (with the tab as the actor) or by a sponsoring user
+    SettleTab {
+        tab: String,
+        amount: u32,
+    },
+    // A correction made by an admin, positive to credit the user
+    Adjustment {
+        amount: i32,
+        reason: String,
+        admin: String,
+    },
+    // Cash taken out of the cash box from the user's balance
+    Withdrawal {
+        amount: u32,
+    },
+    // Money handed back out of the cash box for an earlier cash purchase or cash deposit
+    Refund {
+        transaction: Uuid,
+        amount: u32,
+        // Set when the purchase was paid in another currency, and refunded in it
+        #[serde(default)]
+        tendered: Option<Tendered>,
+    },
+    // A purchase taken back by whoever made it soon after, e.g. for a mis-scan, with the
+    // products going back on the shelf
+    Void {
+        transaction: Uuid,
+        amount: u32,
+        products: Vec<crate::products::Product>,
+    },
+    // Stock lost to damage, expiry, or theft
+    WriteOff {
+        product: crate::products::Product,
+        quantity: u32,
+        reason: String,
+    },
+    // Stock bought in and put on the shelves
+    Restock {
+        product: crate::products::Product,
+        quantity: u32,
+        // Per unit, in pence, if known
+        cost: Option<u32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        supplier: Option<String>,
+        // The purchase order it arrived on
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        order: Option<Uuid>,
+    },
+    // Cash taken out of the box to pay for things, usually stock
+    Expense {
+        amount: u32,
+        description: String,
+    },
+    // What was actually found on the shelves when counting
+    Stocktake {
+        product: crate::products::Product,
+        counted: u32,
+    },
+    // The cash drawer was opened, e.g. "cash purchase"
+    DrawerOpened {
+        reason: String,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Copy, Hash)]
+pub enum DepositMethod {
+    Cash,
+    BankTransfer,
+    Voucher,
+    Card,
+}
+
+impl std::fmt::Display for DepositMethod {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Cash => write!(f, "cash"),
+            Self::BankTransfer => write!(f, "bank transfer"),
+            Self::Voucher => write!(f, "voucher"),
+            Self::Card => write!(f, "card"),
+        }
+    }
+}
+
+// A card someone has registered that can't be used to pay until an admin approves it
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PendingCard {
+    pub user: String,
+    pub uid: String,
+    pub name: String,
+    pub requested: DateTime<Utc>,
+}
+
+// A one-time code worth some credit, e.g. a prize or welcome credit
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Voucher {
+    pub code: String,
+    pub amount: u32,
+    pub created: DateTime<Utc>,
+    // Who redeemed it, and when
+    pub redeemed: Option<(String, DateTime<Utc>)>,
+    // The treasurer who made it
+    #[serde(default)]
+    pub issued_by: Option<String>,
+}
+
+// No 0/O or 1/I, so codes can be read out and typed in without confusion
+const VOUCHER_ALPHABET: &[u8; 32] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+// Vouchers look like "V-ABCD-EFGH"
+fn voucher_code() -> String {
+    let chars = Uuid::new_v4().as_bytes()[..8]
+        .iter()
+        .map(|b| VOUCHER_ALPHABET[*b as usize % VOUCHER_ALPHABET.len()] as char)
+        .collect::<String>();
+    format!("V-{}-{}", &chars[..4], &chars[4..])
+}
+
+pub fn normalise_voucher_code(code: &str) -> String {
+    code.trim().to_uppercase()
+}
+
+fn voucher_transaction_id(code: &str) -> Uuid {
+    Uuid::new_v5(&VOUCHER_NAMESPACE, code.as_bytes())
+}
+
+// Money off a whole cart, given at the till by an admin
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ManualDiscount {
+    // As it was given, e.g. 10% or £0.50 off
+    pub discount: crate::promo::Discount,
+    // What it came to in pence, once the cart was paid for
+    #[serde(default)]
+    pub amount: u32,
+    pub reason: Option<String>,
+    pub admin: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Tendered {
+    pub currency: String,
+    // In the currency's minor units, e.g. cents
+    pub amount: u32,
+}
+
+// A charge taken from a user's balance on a schedule, e.g. a monthly fridge levy
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StandingOrder {
+    pub id: Uuid,
+    pub user: String,
+    pub description: String,
+    pub amount: u32,
+    pub period: Period,
+    pub next_due: DateTime<Utc>,
+    // When the first charge was due, so monthly charges stay on its day of the month
+    #[serde(default)]
+    pub anchor: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Supplier {
+    pub name: String,
+    // Phone number, website, whatever's useful for ordering
+    pub contact: Option<String>,
+}
+
+// What an API token may do, each scope allowing everything the one before it does
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Scope {
+    ReadOnly,
+    Purchase,
+    Admin,
+}
+
+impl Scope {
+    pub fn allows(&self, needed: Scope) -> bool {
+        *self >= needed
+    }
+}
+
+impl std::fmt::Display for Scope {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ReadOnly => write!(f, "read"),
+            Self::Purchase => write!(f, "purchase"),
+            Self::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+impl std::str::FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read" => Ok(Self::ReadOnly),
+            "purchase" => Ok(Self::Purchase),
+            "admin" => Ok(Self::Admin),
+            _ => Err(format!("unknown scope {}, must be read, purchase, or admin", s)),
+        }
+    }
+}
+
+// A token for the HTTP API, only its hash is kept so the database can't leak it
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ApiToken {
+    pub name: String,
+    pub scope: Scope,
+    // Acts as this user, limited to what their role allows, rather than as an integration
+    #[serde(default)]
+    pub user: Option<String>,
+    pub hash: String,
+    pub created: DateTime<Utc>,
+}
+
+// Stock ordered from a supplier, turned into restocks once it arrives
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PurchaseOrder {
+    pub id: Uuid,
+    pub supplier: String,
+    pub created: DateTime<Utc>,
+    pub lines: Vec<OrderLine>,
+    pub received: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OrderLine {
+    pub product: crate::products::Product,
+    pub quantity: u32,
+    // Per unit, in pence, if known
+    pub cost: Option<u32>,
+}
+
+impl PurchaseOrder {
+    // Only counts lines with a known cost
+    pub fn total(&self) -> u32 {
+        self.lines
+            .iter()
+            .filter_map(|l| Some(l.cost? * l.quantity))
+            .sum()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Period {
+    Weekly,
+    Monthly,
+}
+
+impl Period {
+    // The charge after the one due at `t`, counting months from `anchor` so that the 31st
+    // comes back after a short month
+    fn after(&self, anchor: DateTime<Utc>, t: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            Self::Weekly => t + chrono::Duration::weeks(1),
+            Self::Monthly => {
+                let months = (t.year() - anchor.year()) * 12 + t.month() as i32 - anchor.month() as i32 + 1;
+                anchor
+                    .checked_add_months(chrono::Months::new(months.max(1) as u32))
+                    .unwrap_or(t + chrono::Duration::days(30))
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for Period {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "weekly" => Ok(Self::Weekly),
+            "monthly" => Ok(Self::Monthly),
+            _ => Err(format!("unknown period {}, expected weekly or monthly", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Period {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Weekly => write!(f, "weekly"),
+            Self::Monthly => write!(f, "monthly"),
+        }
+    }
+}
+
+// Where the serialized database lives. Memory storage is used for training mode and tests.
+pub enum Storage {
+    File(rustbreak::backend::PathBackend),
+    Memory(Vec<u8>),
+}
+
+impl rustbreak::backend::Backend for Storage {
+    fn get_data(&mut self) -> rustbreak::error::BackendResult<Vec<u8>> {
+        match self {
+            Self::File(b) => b.get_data(),
+            Self::Memory(m) => Ok(m.clone()),
+        }
+    }
+
+    fn put_data(&mut self, data: &[u8]) -> rustbreak::error::BackendResult<()> {
+        match self {
+            Self::File(b) => b.put_data(data),
+            Self::Memory(m) => {
+                *m = data.to_vec();
+                Ok(())
+            }
+        }
+    }
+}
+
+// Transactions recorded since the last save, one per line
+fn journal_path() -> std::path::PathBuf {
+    crate::paths::data_path("journal")
+}
+
+// The journal as it was when a save started, only removed once that save has finished
+fn saving_journal_path() -> std::path::PathBuf {
+    crate::paths::data_path("journal.saving")
+}
+
+fn read_journal(path: &std::path::Path) -> Result<String, String> {
+    match std::fs::read_to_string(path) {
+        Ok(raw) => Ok(raw),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(format!("cannot read journal {}", e)),
+    }
+}
+
+fn append_to_journal(t: &Transaction) -> Result<(), String> {
+    use std::io::Write;
+
+    let line = ron::to_string(t).map_err(|e| format!("cannot serialise transaction {}", e))?;
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(journal_path())
+        .map_err(|e| format!("cannot open journal {}", e))?;
+    writeln!(file, "{}", line)
+        .and_then(|_| file.sync_data())
+        .map_err(|e| format!("cannot write journal {}", e))
+}
+
+fn db_path() -> std::path::PathBuf {
+    crate::paths::data_path("db")
+}
+
+fn archive_dir() -> std::path::PathBuf {
+    crate::paths::data_path("archive")
+}
+
+const READ_ONLY: &str = "the database is open read-only";
+
+// Each till has its own cash boxes, keyed "<till>/<currency>", or just the currency for a
+// till without a name
+pub fn cashbox_key(till: Option<&str>, currency: &str) -> String {
+    match till {
+        Some(till) => format!("{}/{}", till, currency),
+        None => currency.to_string(),
+    }
+}
+
+// How the database file is written. RON can be read and fixed by hand, CBOR is much quicker to
+// save once the transaction log is a few years long.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Ron,
+    Cbor,
+}
+
+impl Format {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "ron" => Some(Self::Ron),
+            "cbor" => Some(Self::Cbor),
+            _ => None,
+        }
+    }
+
+    // A RON database always starts with the opening bracket of InnerDB, CBOR never does
+    fn detect(raw: &[u8]) -> Self {
+        match raw.iter().find(|b| !b.is_ascii_whitespace()) {
+            Some(b'(') | None => Self::Ron,
+            Some(_) => Self::Cbor,
+        }
+    }
+
+    pub fn encode<T: serde::Serialize>(&self, val: &T) -> Result<Vec<u8>, String> {
+        match self {
+            Self::Ron => ron::ser::to_string_pretty(val, ron::ser::PrettyConfig::default())
+                .map(String::into_bytes)
+                .map_err(|e| format!("cannot write RON {}", e)),
+            Self::Cbor => {
+                let mut out = Vec::new();
+                ciborium::ser::into_writer(val, &mut out)
+                    .map_err(|e| format!("cannot write CBOR {}", e))?;
+                Ok(out)
+            }
+        }
+    }
+
+    // Reads either format, so a database converted while loaded elsewhere still reloads
+    pub fn decode<T: serde::de::DeserializeOwned>(raw: &[u8]) -> Result<T, String> {
+        match Self::detect(raw) {
+            Self::Ron => ron::de::from_bytes(raw).map_err(|e| format!("cannot read RON {}", e)),
+            Self::Cbor => {
+                ciborium::de::from_reader(raw).map_err(|e| format!("cannot read CBOR {}", e))
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Format {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ron => write!(f, "RON"),
+            Self::Cbor => write!(f, "CBOR"),
+        }
+    }
+}
+
+impl<T: serde::Serialize + serde::de::DeserializeOwned> DeSerializer<T> for Format {
+    fn serialize(&self, val: &T) -> rustbreak::error::DeSerResult<Vec<u8>> {
+        self.encode(val).map_err(rustbreak::error::DeSerError::Internal)
+    }
+
+    fn deserialize<R: std::io::Read>(&self, mut s: R) -> rustbreak::error::DeSerResult<T> {
+        let mut raw = Vec::new();
+        s.read_to_end(&mut raw)
+            .map_err(|e| rustbreak::error::DeSerError::Internal(format!("cannot read {}", e)))?;
+        Format::decode(&raw).map_err(rustbreak::error::DeSerError::Internal)
+    }
+}
+
+type FileStore = rustbreak::PathDatabase<InnerDB, Format>;
+type DBStore = rustbreak::Database<InnerDB, Storage, Format>;
+
+pub struct DB {
+    store: DBStore,
+    push_targets: Vec<String>,
+    // Set when saves are left to a background writer, called whenever there's something to save
+    save_needed: Option<Box<dyn Fn() + Send + Sync>>,
+    dirty: AtomicBool,
+    // Whether new transactions are written to the journal ahead of the database file
+    journal: bool,
+    // Also used for the archive files
+    format: Format,
+    // Every change is refused, e.g. for an info terminal or looking at a backup
+    read_only: bool,
+    // Tagged on everything recorded here, and picks which cash box is ours
+    till: Option<String>,
+    // Taken off deposits made each way, e.g. to cover card processing
+    deposit_fees: std::collections::HashMap<DepositMethod, DepositFee>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(default)]
+pub struct DepositFee {
+    // e.g. 1.69 for 1.69%, rounded to the nearest penny
+    pub percent: f64,
+    // Pence, on top of the percentage
+    pub flat: u32,
+}
+
+impl DepositFee {
+    // Never more than the deposit itself
+    pub fn on(&self, amount: u32) -> u32 {
+        let fee = self.flat + (amount as f64 * self.percent / 100.0).round() as u32;
+        fee.min(amount)
+    }
+}
+
+// The transaction log, borrowed rather than copied. This holds the database's read lock, so
+// drop it before changing anything.
+pub struct History<'a> {
+    data: std::sync::RwLockReadGuard<'a, InnerDB>,
+}
+
+impl History<'_> {
+    // Oldest first, use .rev() to start from the most recent
+    pub fn iter(&self) -> std::slice::Iter<'_, Transaction> {
+        self.data.transactions.iter()
+    }
+
+    pub fn for_account<'a>(
+        &'a self,
+        id: &'a str,
+    ) -> impl DoubleEndedIterator<Item = &'a Transaction> + 'a {
+        self.iter().filter(move |t| t.actor.account() == Some(id))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.transactions.is_empty()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SyncSummary {
+    pub users: usize,
+    pub transactions: usize,
+}
+
+// An entry from the space's membership system
+#[derive(Debug, Clone)]
+pub struct Member {
+    pub membership_id: String,
+    pub user_id: String,
+}
+
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+    // IDs of the users that didn't exist before
+    pub created: Vec<String>,
+    pub updated: usize,
+    pub unchanged: usize,
+    pub conflicts: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct InboxSummary {
+    pub applied: usize,
+    // What went wrong with each file that was skipped. Unparsable files are moved to inbox/bad
+    // so they don't hold up everything after them.
+    pub rejected: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct PushSummary {
+    pub target: String,
+    pub delivered: usize,
+    pub pending: usize,
+    pub error: Option<String>,
+}
+
+// Wrong PINs in a row before PIN entry is locked, and for how long
+const PIN_ATTEMPTS: u32 = 5;
+const PIN_LOCKOUT_MINUTES: i64 = 15;
+
+// Namespace for deriving IDs of legacy transactions, so every till assigns the same ID
+const LEGACY_TX_NAMESPACE: Uuid = Uuid::from_u128(0x2b1b_8cf5_6d0e_4c57_9a3e_5710_57ba_4c57);
+// Likewise for standing order charges, so a charge taken by two tills is only counted once
+const STANDING_ORDER_NAMESPACE: Uuid = Uuid::from_u128(0x7c0e_52a1_94d3_4f57_8b21_5710_57ba_0de5);
+// And for voids, from the purchase's ID, so a purchase can only be voided once on any till
+const VOID_NAMESPACE: Uuid = Uuid::from_u128(0x91d4_7e2a_c05b_4f57_b7d3_5710_57ba_701d);
+// And for redeeming vouchers, from the code, so a voucher only ever pays out once
+const VOUCHER_NAMESPACE: Uuid = Uuid::from_u128(0x3d8a_61f4_b29c_4e57_95a0_5710_57ba_50c4);
+// And for idempotency keys sent by API clients that aren't UUIDs already
+const IDEMPOTENCY_NAMESPACE: Uuid = Uuid::from_u128(0x4e9f_0b3c_21d7_4a57_a6c8_5710_57ba_1de9);
+
+// By its ID, or enough of the start of it to only match one
+pub fn find_transaction<'a>(transactions: &'a [Transaction], id: &str) -> Result<&'a Transaction, String> {
+    let matching = transactions
+        .iter()
+        .filter(|t| t.id.to_string().starts_with(id))
+        .collect::<Vec<_>>();
+    match matching[..] {
+        [t] => Ok(t),
+        [] => Err(format!("no transaction {}", id)),
+        _ => Err(format!("{} matches more than one transaction", id)),
+    }
+}
+
+// Refunds and voids made against a transaction
+pub fn linked_transactions(transactions: &[Transaction], id: Uuid) -> Vec<&Transaction> {
+    transactions
+        .iter()
+        .filter(|t| match &t.transaction {
+            TransactionType::Refund { transaction, .. } | TransactionType::Void { transaction, .. } => {
+                *transaction == id
+            }
+            _ => false,
+        })
+        .collect()
+}
+
+// The transaction ID for a client's idempotency key, so a request sent twice is only applied once
+pub fn idempotency_key(key: &str) -> Uuid {
+    Uuid::parse_str(key).unwrap_or_else(|_| Uuid::new_v5(&IDEMPOTENCY_NAMESPACE, key.as_bytes()))
+}
+
+impl Transaction {
+    // Hashed from how it's stored, rather than Debug output that changes whenever a field does
+    fn legacy_id(&self) -> Uuid {
+        let mut stored = Vec::new();
+        ciborium::into_writer(&(&self.timestamp, &self.actor, &self.transaction), &mut stored)
+            .expect("transactions can always be serialised");
+        Uuid::new_v5(&LEGACY_TX_NAMESPACE, &stored)
+    }
+
+    // How legacy IDs used to be made, so ones given out that way are still recognised
+    fn debug_legacy_id(&self) -> Uuid {
+        Uuid::new_v5(
+            &LEGACY_TX_NAMESPACE,
+            format!("{:?}{:?}{:?}", self.timestamp, self.actor, self.transaction).as_bytes(),
+        )
+    }
+
+    // Change to the acting user's balance caused by this transaction
+    pub fn balance_change(&self) -> i32 {
+        // Amounts too big for a balance, which only a bad file or peer could hold, go no
+        // further than the limit rather than wrapping round to the opposite sign
+        let pence = |a: u32| i32::try_from(a).unwrap_or(i32::MAX);
+        match &self.transaction {
+            TransactionType::Purchase { total, .. } => -pence(*total),
+            TransactionType::Deposit { amount, fee, .. } => pence(*amount).saturating_sub(pence(*fee)),
+            TransactionType::StandingOrder { amount, .. } => -pence(*amount),
+            TransactionType::SettleTab { amount, .. } => -pence(*amount),
+            TransactionType::Adjustment { amount, .. } => *amount,
+            TransactionType::Refund { amount, .. } => -pence(*amount),
+            TransactionType::Void { amount, .. } => pence(*amount),
+            TransactionType::Withdrawal { amount } => -pence(*amount),
+            TransactionType::WriteOff { .. }
+            | TransactionType::Restock { .. }
+            | TransactionType::Expense { .. }
+            | TransactionType::Stocktake { .. }
+            | TransactionType::DrawerOpened { .. } => 0,
+        }
+    }
+}
+
+impl InnerDB {
+    // The only way anything gets into the log, keeping the cached balance in step with it
+    pub fn apply(&mut self, t: Transaction) {
+        if let Some(id) = t.actor.account() {
+            // Pushed transactions can arrive before the user record itself
+            let u = self.users.entry(id.to_string()).or_insert_with(|| User::new(id));
+            u.balance = u.balance.saturating_add(t.balance_change());
+        }
+        self.note_use(&t);
+        self.transactions.push(t);
+    }
+
+    // Promo code uses and redeemed vouchers follow from the log, so they come back along with
+    // any transaction replayed from the journal
+    fn note_use(&mut self, t: &Transaction) {
+        match &t.transaction {
+            TransactionType::Purchase { promo: Some(code), .. } => {
+                if let Some(p) = self.promos.get_mut(code) {
+                    p.uses += 1;
+                }
+            }
+            TransactionType::Deposit { method: DepositMethod::Voucher, .. } => {
+                let voucher = self.vouchers.values_mut().find(|v| voucher_transaction_id(&v.code) == t.id);
+                if let (Some(v), Some(id)) = (voucher, t.actor.account()) {
+                    v.redeemed.get_or_insert_with(|| (id.to_string(), t.timestamp));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Recalculates every cached balance from the log, recreating any user that's missing.
+    // Returns (user, old balance, new balance) for each one that changed.
+    pub fn project(&mut self) -> Vec<(String, i32, i32)> {
+        let expected = self
+            .balances_from_transactions()
+            .into_iter()
+            .map(|(id, balance)| (id.to_string(), balance as i32))
+            .collect::<std::collections::HashMap<_, _>>();
+        for id in expected.keys() {
+            if !self.users.contains_key(id) {
+                self.users.insert(id.clone(), User::new(id));
+            }
+        }
+
+        let mut changed = Vec::new();
+        for user in self.users.values_mut() {
+            let balance = expected.get(&user.id).copied().unwrap_or(0);
+            if balance != user.balance {
+                changed.push((user.id.clone(), user.balance, balance));
+                user.balance = balance;
+            }
+        }
+        changed.sort();
+        changed
+    }
+
+    // What each account's balance should be, going by the transaction log alone
+    fn balances_from_transactions(&self) -> std::collections::HashMap<&str, i64> {
+        let mut balances = self
+            .archive
+            .balances
+            .iter()
+            .map(|(id, balance)| (id.as_str(), *balance))
+            .collect::<std::collections::HashMap<_, _>>();
+        for t in &self.transactions {
+            if let Some(id) = t.actor.account() {
+                *balances.entry(id).or_default() += t.balance_change() as i64;
+            }
+        }
+        balances
+    }
+
+    // Makes sure the account is there and not frozen before anything is charged to it
+    fn check_can_spend(&self, id: &str) -> Result<(), String> {
+        match self.users.get(id) {
+            None => Err(format!("user {} does not exist", id)),
+            Some(User { frozen: Some(reason), .. }) => Err(format!("account {} is frozen ({})", id, reason)),
+            Some(_) => Ok(()),
+        }
+    }
+
+    // Makes sure spending this much now keeps the account within its limits
+    fn check_limits(&self, id: &str, total: u32) -> Result<(), String> {
+        let user = &self.users[id];
+        if let Some(limit) = user.purchase_limit {
+            if total > limit {
+                return Err(format!(
+                    "{} is over {}'s limit of {} per purchase",
+                    money::format(total),
+                    id,
+                    money::format(limit)
+                ));
+            }
+        }
+        if let Some(limit) = user.daily_limit {
+            // The day as it is at the till, less anything since voided or refunded
+            let today = Local::now().date_naive();
+            let purchases = self
+                .transactions
+                .iter()
+                .filter(|t| t.actor.account() == Some(id) && t.timestamp.with_timezone(&Local).date_naive() == today)
+                .filter_map(|t| match &t.transaction {
+                    TransactionType::Purchase { total, .. } => Some((t.id, *total)),
+                    _ => None,
+                })
+                .collect::<std::collections::HashMap<_, _>>();
+            let returned = self
+                .transactions
+                .iter()
+                .filter_map(|t| match &t.transaction {
+                    TransactionType::Void { transaction, amount, .. }
+                    | TransactionType::Refund { transaction, amount, .. }
+                        if purchases.contains_key(transaction) =>
+                    {
+                        Some(*amount)
+                    }
+                    _ => None,
+                })
+                .sum::<u32>();
+            let spent = purchases.values().sum::<u32>().saturating_sub(returned);
+            if spent + total > limit {
+                return Err(format!(
+                    "this would take {} over their daily limit of {} ({} spent today)",
+                    id,
+                    money::format(limit),
+                    money::format(spent)
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // The cart with its promo code as it's stored now, as the code may have been deleted and
+    // added again with a different discount since it was entered
+    fn with_current_promo(&self, cart: &crate::cart::Cart) -> Result<crate::cart::Cart, String> {
+        let mut cart = cart.clone();
+        if let Some(entered) = &cart.promo {
+            let promo = self
+                .promos
+                .get(&entered.code)
+                .ok_or_else(|| format!("promo code {} no longer exists", entered.code))?;
+            promo.check_usable(Utc::now())?;
+            cart.promo = Some(promo.clone());
+        }
+        Ok(cart)
+    }
+
+    // Applies journaled transactions that didn't make it into the saved file, queueing them for
+    // push targets like any other local transaction. A line cut short by a crash is skipped, as
+    // whatever wrote it never finished.
+    pub fn replay_journal(&mut self, raw: &str, push_targets: &[String]) -> usize {
+        let mut known = self.transactions.iter().map(|t| t.id).collect::<HashSet<_>>();
+        let mut replayed = 0;
+        for t in raw.lines().filter_map(|l| ron::from_str::<Transaction>(l).ok()) {
+            let id = t.id;
+            if self.merge_transaction(t, &mut known) {
+                for target in push_targets {
+                    self.outbox.entry(target.clone()).or_default().push_back(id);
+                }
+                replayed += 1;
+            }
+        }
+        if replayed > 0 {
+            self.transactions.sort_by_key(|t| t.timestamp);
+        }
+        replayed
+    }
+
+    // Takes out everything from before the given time, by year, for writing to the archive.
+    // Anything still waiting to be pushed to another till stays put until it's been delivered.
+    pub fn split_archive(&mut self, before: DateTime<Utc>) -> std::collections::BTreeMap<i32, Vec<Transaction>> {
+        let queued = self.outbox.values().flatten().copied().collect::<HashSet<_>>();
+        let (old, hot) = std::mem::take(&mut self.transactions)
+            .into_iter()
+            .partition::<Vec<_>, _>(|t| t.timestamp < before && !queued.contains(&t.id));
+        self.transactions = hot;
+
+        let mut years = std::collections::BTreeMap::<i32, Vec<Transaction>>::new();
+        for t in old {
+            if let Some(id) = t.actor.account() {
+                *self.archive.balances.entry(id.to_string()).or_default() +=
+                    t.balance_change() as i64;
+            }
+            self.archive.years.insert(t.timestamp.year());
+            years.entry(t.timestamp.year()).or_default().push(t);
+        }
+        years
+    }
+
+    // Forgets who made transactions from before the given time. What they added up to is kept
+    // with the archived balances, so nobody's balance changes.
+    pub fn anonymise_before(&mut self, before: DateTime<Utc>) -> usize {
+        let mut anonymised = 0;
+        for t in self.transactions.iter_mut().filter(|t| t.timestamp < before) {
+            if let Some(id) = t.actor.account() {
+                *self.archive.balances.entry(id.to_string()).or_default() += t.balance_change() as i64;
+                t.actor = TransactionActor::Anonymous;
+                anonymised += 1;
+            }
+        }
+        anonymised
+    }
+
+    // Drops cards nobody has tapped since the given time. Cards with no record of being used
+    // start counting from now.
+    pub fn forget_cards_before(&mut self, before: DateTime<Utc>, now: DateTime<Utc>) -> usize {
+        let mut forgotten = 0;
+        for user in self.users.values_mut() {
+            let cards = match user.cards.as_mut() {
+                Some(c) => c,
+                None => continue,
+            };
+            let stale = cards
+                .iter()
+                .filter(|(uid, _)| *user.card_last_used.entry(uid.clone()).or_insert(now) < before)
+                .cloned()
+                .collect::<Vec<_>>();
+            for (uid, _) in stale {
+                user.remove_card(&uid, now);
+                forgotten += 1;
+            }
+            let cards = user.cards.clone().unwrap_or_default();
+            user.card_last_used.retain(|uid, _| cards.iter().any(|(c, _)| c == uid));
+        }
+        forgotten
+    }
+
+    // Adds a transaction from another till unless it has already been applied here
+    fn merge_transaction(&mut self, mut t: Transaction, known: &mut HashSet<Uuid>) -> bool {
+        if t.id.is_nil() {
+            if known.contains(&t.debug_legacy_id()) {
+                return false;
+            }
+            t.id = t.legacy_id();
+        }
+        if !known.insert(t.id) {
+            return false;
+        }
+        self.apply(t);
+        true
+    }
+}
+
+impl DB {
+    // Opens the database file. In training mode it's copied into memory and never written back.
+    pub fn load(push_targets: Vec<String>, dry_run: bool) -> Result<DB, String> {
+        // Saves carry on in whichever format the file is already in
+        let format = std::fs::read(db_path())
+            .map(|raw| Format::detect(&raw))
+            .unwrap_or_default();
+        let (mut data, backend, _) = FileStore::load_from_path_or_else(db_path(), InnerDB::default)
+            .and_then(|s| s.into_inner())
+            .map_err(|e| format!("{:?}", e))?;
+        // Anything from a save that never finished, then whatever came after it
+        let raw = read_journal(&saving_journal_path())? + &read_journal(&journal_path())?;
+        let replayed = data.replay_journal(&raw, &push_targets);
+        // Whatever the cached balances say, the log wins
+        let rebuilt = !data.project().is_empty();
+
+        let db = if dry_run {
+            Self::in_memory(data)?
+        } else {
+            Self::open(data, Storage::File(backend), format, push_targets)?
+        };
+        if rebuilt || replayed > 0 {
+            db.persist()?;
+        }
+        if !dry_run {
+            // Keep the working set to this year, older years are read back when needed
+            let year_start = Utc.with_ymd_and_hms(Utc::now().year(), 1, 1, 0, 0, 0).unwrap();
+            db.archive_before(year_start)?;
+        }
+        Ok(db)
+    }
+
+    // Opens the database without ever writing to it. The file is re-read before each lookup, so
+    // changes saved by a till using the same data directory show up.
+    pub fn load_read_only() -> Result<DB, String> {
+        let format = Format::detect(
+            &std::fs::read(db_path()).map_err(|e| format!("cannot read database {}", e))?,
+        );
+        let (data, backend, _) = FileStore::load_from_path(db_path())
+            .and_then(|s| s.into_inner())
+            .map_err(|e| format!("{:?}", e))?;
+
+        Ok(DB {
+            store: DBStore::from_parts(data, Storage::File(backend), format),
+            push_targets: Vec::new(),
+            save_needed: None,
+            dirty: AtomicBool::new(false),
+            journal: false,
+            format,
+            read_only: true,
+            till: None,
+            deposit_fees: std::collections::HashMap::new(),
+        })
+    }
+
+    // Moves transactions older than `before` out into the yearly archive files. The archives are
+    // written first, so a crash part way leaves duplicates (which are ignored) rather than gaps.
+    fn archive_before(&self, before: DateTime<Utc>) -> Result<(), String> {
+        let mut data = self.data()?;
+        let years = data.split_archive(before);
+        if years.is_empty() {
+            return Ok(());
+        }
+
+        std::fs::create_dir_all(archive_dir())
+            .map_err(|e| format!("cannot create archive directory {}", e))?;
+        for (year, transactions) in years {
+            let mut archived = self.archived_year(year)?;
+            let known = archived.iter().map(|t| t.id).collect::<HashSet<_>>();
+            archived.extend(transactions.into_iter().filter(|t| !known.contains(&t.id)));
+            archived.sort_by_key(|t| t.timestamp);
+
+            let path = archive_dir().join(year.to_string());
+            let tmp = path.with_extension("tmp");
+            std::fs::write(&tmp, self.format.encode(&archived)?)
+                .and_then(|_| std::fs::rename(&tmp, &path))
+                .map_err(|e| format!("cannot write archive {} {}", path.display(), e))?;
+        }
+
+        self.store
+            .put_data(data, false)
+            .map_err(|e| format!("{:?}", e))?;
+        self.persist()
+    }
+
+    // Applies the retention policy to the database and any archived years it reaches back into,
+    // returning how many transactions were anonymised and cards forgotten
+    pub fn apply_retention(
+        &self,
+        anonymise_before: Option<DateTime<Utc>>,
+        forget_cards_before: Option<DateTime<Utc>>,
+    ) -> Result<(usize, usize), String> {
+        self.reload()?;
+
+        let (mut anonymised, forgotten, years) = {
+            let mut data = self.store.borrow_data_mut().map_err(|e| format!("{:?}", e))?;
+            let anonymised = anonymise_before.map_or(0, |b| data.anonymise_before(b));
+            let forgotten = forget_cards_before.map_or(0, |b| data.forget_cards_before(b, Utc::now()));
+            let years = data.archive.years.clone();
+            (anonymised, forgotten, years)
+        };
+        // Archived balances already include these, so only the names need to go
+        if let Some(before) = anonymise_before {
+            for year in years.into_iter().filter(|y| *y <= before.year()) {
+                let mut archived = self.archived_year(year)?;
+                let mut changed = 0;
+                for t in archived.iter_mut().filter(|t| t.timestamp < before) {
+                    if t.actor.account().is_some() {
+                        t.actor = TransactionActor::Anonymous;
+                        changed += 1;
+                    }
+                }
+                if changed == 0 {
+                    continue;
+                }
+                let path = archive_dir().join(year.to_string());
+                let tmp = path.with_extension("tmp");
+                std::fs::write(&tmp, self.format.encode(&archived)?)
+                    .and_then(|_| std::fs::rename(&tmp, &path))
+                    .map_err(|e| format!("cannot write archive {} {}", path.display(), e))?;
+                anonymised += changed;
+            }
+        }
+
+        self.persist()?;
+        Ok((anonymised, forgotten))
+    }
+
+    // Keeps track of when each card was last used, for the retention policy
+    pub fn card_used(&self, uid: &str) -> Result<(), String> {
+        let id = match self.get_user_by_card(uid) {
+            Some((u, _)) => u.id,
+            None => return Ok(()),
+        };
+        self.update_user(&id, |u| {
+            u.card_last_used.insert(uid.to_string(), Utc::now());
+        })
+        .map(|_| ())
+    }
+
+    fn archived_year(&self, year: i32) -> Result<Vec<Transaction>, String> {
+        let path = archive_dir().join(year.to_string());
+        match std::fs::read(&path) {
+            Ok(raw) => Format::decode(&raw),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(format!("cannot read archive {} {}", path.display(), e)),
+        }
+    }
+
+    // IDs of everything already here, including archived years any of `incoming` fall in
+    fn known_ids(&self, data: &InnerDB, incoming: &[Transaction]) -> Result<HashSet<Uuid>, String> {
+        let mut known = data.transactions.iter().map(|t| t.id).collect::<HashSet<_>>();
+        let years = incoming
+            .iter()
+            .map(|t| t.timestamp.year())
+            .filter(|y| data.archive.years.contains(y))
+            .collect::<std::collections::BTreeSet<_>>();
+        for year in years {
+            known.extend(self.archived_year(year)?.iter().map(|t| t.id));
+        }
+        Ok(known)
+    }
+
+    // Writes a brand new database file
+    pub fn create(data: InnerDB) -> Result<(), String> {
+        FileStore::create_at_path(db_path(), data)
+            .and_then(|s| s.save())
+            .map_err(|e| format!("{:?}", e))
+    }
+
+    // Rewrites the database file in another format, returning the format it was in before.
+    // Only to be done while the bank isn't running.
+    pub fn convert(format: Format) -> Result<Format, String> {
+        let from = Format::detect(
+            &std::fs::read(db_path()).map_err(|e| format!("cannot read database {}", e))?,
+        );
+        FileStore::load_from_path(db_path())
+            .and_then(|s| s.with_deser(format).save())
+            .map_err(|e| format!("{:?}", e))?;
+        Ok(from)
+    }
+
+    pub fn in_memory(data: InnerDB) -> Result<DB, String> {
+        Self::open(data, Storage::Memory(Vec::new()), Format::default(), Vec::new())
+    }
+
+    fn open(
+        data: InnerDB,
+        storage: Storage,
+        format: Format,
+        push_targets: Vec<String>,
+    ) -> Result<DB, String> {
+        let in_memory = matches!(storage, Storage::Memory(_));
+        let db = DB {
+            store: DBStore::from_parts(data, storage, format),
+            push_targets,
+            save_needed: None,
+            dirty: AtomicBool::new(false),
+            journal: !in_memory,
+            format,
+            read_only: false,
+            till: None,
+            deposit_fees: std::collections::HashMap::new(),
+        };
+
+        let migrated = {
+            let mut data = db.store.borrow_data_mut().map_err(|e| format!("{:?}", e))?;
+            let mut migrated = false;
+            for t in data.transactions.iter_mut().filter(|t| t.id.is_nil()) {
+                t.id = t.legacy_id();
+                migrated = true;
+            }
+            migrated
+        };
+        // Memory storage starts out empty, so always give it something to reload from
+        if migrated || in_memory {
+            db.persist()?;
+        }
+
+        Ok(db)
+    }
+
+    // Pulls users, cards, and transactions this till hasn't seen yet from another till's database.
+    // Transactions are identified by ID, so syncing repeatedly (in either direction) converges.
+    pub fn sync_from(&self, peer_path: &str) -> Result<SyncSummary, String> {
+        let peer = FileStore::load_from_path(peer_path.into())
+            .map_err(|e| format!("cannot open peer database {}: {:?}", peer_path, e))?
+            .get_data(false)
+            .map_err(|e| format!("{:?}", e))?;
+
+        // The peer's archived years sit next to its database, like ours do
+        let dir = std::path::Path::new(peer_path).with_file_name("archive");
+        let mut archived = Vec::new();
+        for year in &peer.archive.years {
+            let path = dir.join(year.to_string());
+            match std::fs::read(&path) {
+                Ok(raw) => archived.extend(Format::decode::<Vec<Transaction>>(&raw)?),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(format!("cannot read peer archive {} {}", path.display(), e)),
+            }
+        }
+        self.merge(peer, archived)
+    }
+
+    // Merges in another till's data, e.g. from snapshot() on a database opened elsewhere
+    pub fn merge_from(&self, peer: InnerDB) -> Result<SyncSummary, String> {
+        self.merge(peer, Vec::new())
+    }
+
+    // `archived` is whatever could be read of the peer's archived years. Merged transactions from
+    // past years are moved into our own archive the next time the database is loaded.
+    fn merge(&self, peer: InnerDB, archived: Vec<Transaction>) -> Result<SyncSummary, String> {
+        self.reload()?;
+
+        let mut summary = SyncSummary::default();
+        let mut changed = false;
+        {
+            let mut data = self.store.borrow_data_mut().map_err(|e| format!("{:?}", e))?;
+
+            // What the peer's archive adds up to beyond the archived transactions we've got, i.e.
+            // anything anonymised or in archive files we couldn't read
+            let hot = peer.transactions.iter().map(|t| t.id).collect::<HashSet<_>>();
+            let mut unarchived = peer.archive.balances.clone();
+            for t in archived.iter().filter(|t| !hot.contains(&t.id)) {
+                if let Some(id) = t.actor.account() {
+                    *unarchived.entry(id.to_string()).or_default() -= t.balance_change() as i64;
+                }
+            }
+
+            for (id, mut peer_user) in peer.users {
+                match data.users.get_mut(&id) {
+                    Some(u) => changed |= u.merge_cards(&peer_user),
+                    None => {
+                        // Their balance comes from the transactions merged below, on top of
+                        // whatever only the peer's archive has a record of
+                        let carried = unarchived.get(&id).copied().unwrap_or(0);
+                        if carried != 0 {
+                            *data.archive.balances.entry(id.clone()).or_default() += carried;
+                        }
+                        peer_user.balance = carried as i32;
+                        data.users.insert(id, peer_user);
+                        summary.users += 1;
+                    }
+                }
+            }
+
+            // Before the transactions, so uses of a promo new to us get counted as they're merged
+            for (code, at) in peer.removed_promos {
+                if data.removed_promos.get(&code).is_none_or(|ours| at > *ours) {
+                    data.removed_promos.insert(code, at);
+                    changed = true;
+                }
+            }
+            // A removal only takes away the promo as it was when it was removed
+            let removed = data.removed_promos.clone();
+            let gone = |p: &crate::promo::Promo| {
+                removed.get(&p.code).is_some_and(|at| p.added.unwrap_or(DateTime::<Utc>::MIN_UTC) <= *at)
+            };
+            for (code, promo) in &peer.promos {
+                let newer = match data.promos.get(code) {
+                    Some(p) => promo.added > p.added,
+                    None => true,
+                };
+                if newer && !gone(promo) {
+                    data.promos.insert(code.clone(), crate::promo::Promo { uses: 0, ..promo.clone() });
+                    changed = true;
+                }
+            }
+            let promos = data.promos.len();
+            data.promos.retain(|_, p| !gone(p));
+            changed |= data.promos.len() != promos;
+
+            let mut incoming = archived;
+            incoming.extend(peer.transactions);
+            let mut known = self.known_ids(&data, &incoming)?;
+            for t in incoming {
+                if data.merge_transaction(t, &mut known) {
+                    summary.transactions += 1;
+                }
+            }
+
+            // Their uses may come from transactions they've archived and we haven't got
+            for (code, promo) in peer.promos {
+                if let Some(p) = data.promos.get_mut(&code).filter(|p| p.added == promo.added) {
+                    if promo.uses > p.uses {
+                        p.uses = promo.uses;
+                        changed = true;
+                    }
+                }
+            }
+
+            // Charges are identified by order and due date, so a later next_due here just means
+            // the peer has taken charges we'll also have merged above
+            let cancelled = data.cancelled_standing_orders.len();
+            data.cancelled_standing_orders.extend(peer.cancelled_standing_orders);
+            changed |= data.cancelled_standing_orders.len() != cancelled;
+            for order in peer.standing_orders {
+                if data.cancelled_standing_orders.contains(&order.id) {
+                    continue;
+                }
+                match data.standing_orders.iter_mut().find(|o| o.id == order.id) {
+                    Some(o) if order.next_due > o.next_due => {
+                        *o = order;
+                        changed = true;
+                    }
+                    Some(_) => {}
+                    None => {
+                        data.standing_orders.push(order);
+                        changed = true;
+                    }
+                }
+            }
+            let cancelled = data.cancelled_standing_orders.clone();
+            let before = data.standing_orders.len();
+            data.standing_orders.retain(|o| !cancelled.contains(&o.id));
+            changed |= data.standing_orders.len() != before;
+
+            // So vouchers made on one till can be redeemed at any of them
+            for (code, peer_voucher) in peer.vouchers {
+                let v = data.vouchers.entry(code).or_insert_with(|| peer_voucher.clone());
+                if v.redeemed.is_none() {
+                    v.redeemed = peer_voucher.redeemed;
+                }
+            }
+
+            data.transactions.sort_by_key(|t| t.timestamp);
+        }
+
+        if summary.users > 0 || summary.transactions > 0 || changed {
+            self.persist()?;
+        }
+        Ok(summary)
+    }
+
+    // Applies transactions other tills have pushed into our inbox directory
+    pub fn ingest_inbox(&self) -> Result<InboxSummary, String> {
+        self.ingest_from(&crate::paths::data_path("inbox"))
+    }
+
+    pub fn ingest_from(&self, inbox: &std::path::Path) -> Result<InboxSummary, String> {
+        let mut files = match std::fs::read_dir(inbox) {
+            Ok(d) => d
+                .filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter(|p| p.extension().is_some_and(|e| e == "ron"))
+                .collect::<Vec<_>>(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(InboxSummary::default()),
+            Err(e) => return Err(format!("cannot open inbox {}", e)),
+        };
+        let mut summary = InboxSummary::default();
+        if files.is_empty() {
+            return Ok(summary);
+        }
+        // File names start with the transaction timestamp, so this replays them in order
+        files.sort();
+
+        let mut incoming = Vec::new();
+        let mut read = Vec::new();
+        for path in files {
+            let raw = match std::fs::read_to_string(&path) {
+                Ok(raw) => raw,
+                Err(e) => {
+                    summary.rejected.push(format!("cannot read {}: {}", path.display(), e));
+                    continue;
+                }
+            };
+            match ron::from_str::<Transaction>(&raw) {
+                Ok(t) => {
+                    incoming.push(t);
+                    read.push(path);
+                }
+                Err(e) => {
+                    let bad = inbox.join("bad");
+                    let moved = std::fs::create_dir_all(&bad)
+                        .and_then(|_| std::fs::rename(&path, bad.join(path.file_name().unwrap_or_default())));
+                    summary.rejected.push(match moved {
+                        Ok(()) => format!("cannot parse {}, moved to {}: {}", path.display(), bad.display(), e),
+                        Err(m) => format!("cannot parse {}: {}, and cannot move it aside: {}", path.display(), e, m),
+                    });
+                }
+            }
+        }
+
+        self.reload()?;
+
+        let mut applied = 0;
+        {
+            let mut data = self.store.borrow_data_mut().map_err(|e| format!("{:?}", e))?;
+            let mut known = self.known_ids(&data, &incoming)?;
+            for t in incoming {
+                if data.merge_transaction(t, &mut known) {
+                    applied += 1;
+                }
+            }
+            data.transactions.sort_by_key(|t| t.timestamp);
+        }
+
+        if applied > 0 {
+            self.persist()?;
+            self.flush()?;
+        }
+        // Only remove the files once their transactions are safely saved
+        for path in &read {
+            let _ = std::fs::remove_file(path);
+        }
+        summary.applied = applied;
+        Ok(summary)
+    }
+
+    // Delivers queued local transactions to each push target's inbox, in order.
+    // Anything that can't be delivered stays queued for the next attempt.
+    pub fn flush_outbox(&self) -> Result<Vec<PushSummary>, String> {
+        self.reload()?;
+
+        let mut summaries = Vec::new();
+        {
+            let mut data = self.store.borrow_data_mut().map_err(|e| format!("{:?}", e))?;
+            let data = &mut *data;
+
+            for (target, queue) in data.outbox.iter_mut() {
+                let mut summary = PushSummary {
+                    target: target.clone(),
+                    delivered: 0,
+                    pending: 0,
+                    error: None,
+                };
+
+                while let Some(id) = queue.front() {
+                    let t = match data.transactions.iter().find(|t| t.id == *id) {
+                        Some(t) => t,
+                        None => {
+                            queue.pop_front();
+                            continue;
+                        }
+                    };
+                    if let Err(e) = deliver(target, t) {
+                        summary.error = Some(e);
+                        break;
+                    }
+                    queue.pop_front();
+                    summary.delivered += 1;
+                }
+
+                summary.pending = queue.len();
+                if summary.delivered > 0 || summary.error.is_some() {
+                    summaries.push(summary);
+                }
+            }
+            data.outbox.retain(|_, queue| !queue.is_empty());
+        }
+
+        if summaries.iter().any(|s| s.delivered > 0) {
+            self.persist()?;
+        }
+        Ok(summaries)
+    }
+
+    // Hands saving over to a background writer: changes only mark the database as dirty and call
+    // `save_needed`, and the writer calls `flush`. The in-memory copy becomes authoritative, so
+    // the file is no longer re-read before every operation.
+    pub fn defer_saves(&mut self, save_needed: impl Fn() + Send + Sync + 'static) {
+        self.save_needed = Some(Box::new(save_needed));
+    }
+
+    pub fn set_till(&mut self, till: &str) {
+        self.till = Some(till.to_string());
+    }
+
+    pub fn set_deposit_fees(&mut self, fees: std::collections::HashMap<DepositMethod, DepositFee>) {
+        self.deposit_fees = fees;
+    }
+
+    // What would be taken off a deposit of this much
+    pub fn deposit_fee(&self, amount: u32, method: DepositMethod) -> u32 {
+        self.deposit_fees.get(&method).map_or(0, |f| f.on(amount))
+    }
+
+    pub fn till(&self) -> Option<&str> {
+        self.till.as_deref()
+    }
+
+    // Writes out any changes not yet saved
+    pub fn flush(&self) -> Result<(), String> {
+        if self.dirty.swap(false, Ordering::SeqCst) {
+            self.rotate_journal();
+            if let Err(e) = self.store.save() {
+                self.dirty.store(true, Ordering::SeqCst);
+                return Err(format!("{:?}", e));
+            }
+            self.clear_journal();
+        }
+        Ok(())
+    }
+
+    fn reload(&self) -> Result<(), String> {
+        if self.save_needed.is_some() {
+            return Ok(());
+        }
+        self.store.load().map_err(|e| format!("{:?}", e))
+    }
+
+    fn persist(&self) -> Result<(), String> {
+        if self.read_only {
+            return Err(READ_ONLY.to_string());
+        }
+        match &self.save_needed {
+            Some(save_needed) => {
+                self.dirty.store(true, Ordering::SeqCst);
+                save_needed();
+                Ok(())
+            }
+            None => {
+                self.rotate_journal();
+                self.store.save().map_err(|e| format!("{:?}", e))?;
+                self.clear_journal();
+                Ok(())
+            }
+        }
+    }
+
+    // Moves the journal aside before saving, so anything recorded while the save is under way
+    // goes into a fresh journal rather than being cleared with it. If an earlier save never
+    // finished its journal is still there, and this one stays put until a save succeeds.
+    fn rotate_journal(&self) {
+        if self.journal && !saving_journal_path().exists() {
+            let _ = std::fs::rename(journal_path(), saving_journal_path());
+        }
+    }
+
+    // Everything in the rotated journal is in the saved file now. Failing to clear it is
+    // harmless, as replaying skips transactions that are already there.
+    fn clear_journal(&self) {
+        if self.journal {
+            let _ = std::fs::remove_file(saving_journal_path());
+        }
+    }
+
+    fn data(&self) -> Result<InnerDB, String> {
+        self.store
+            .get_data(self.save_needed.is_none())
+            .map_err(|e| format!("{:?}", e))
+    }
+
+    // Journals the transaction before applying it, so it survives a crash before the next save
+    fn record(&self, data: &mut InnerDB, mut t: Transaction) -> Result<(), String> {
+        if t.till.is_none() {
+            t.till = self.till.clone();
+        }
+        if self.read_only {
+            return Err(READ_ONLY.to_string());
+        }
+        if data.transactions.iter().rev().any(|x| x.id == t.id) {
+            return Err(format!("transaction {} has already been applied", t.id));
+        }
+        if self.journal {
+            append_to_journal(&t)?;
+        }
+        for target in &self.push_targets {
+            data.outbox.entry(target.clone()).or_default().push_back(t.id);
+        }
+        data.apply(t);
+        Ok(())
+    }
+
+    pub fn get_user(&self, id: &str) -> Option<(User, Vec<Transaction>)> {
+        let history = self.history().ok()?;
+        let u = history.data.users.get(id)?.clone();
+        let t = history.for_account(id).cloned().collect::<Vec<_>>();
+        Some((u, t))
+    }
+
+    // Whether a transaction with this ID, or idempotency key, has been recorded already
+    pub fn applied(&self, id: Uuid) -> bool {
+        self.history().is_ok_and(|h| h.iter().rev().any(|t| t.id == id))
+    }
+
+    pub fn history(&self) -> Result<History<'_>, String> {
+        self.reload()?;
+        Ok(History {
+            data: self.store.borrow_data().map_err(|e| format!("{:?}", e))?,
+        })
+    }
+
+    pub fn get_user_by_card(&self, uid: &str) -> Option<(User, Vec<Transaction>)> {
+        let data = self.data().ok()?;
+
+        let (id, u) = data.users.into_iter().find(|u| {
+            u.1.cards.as_ref().is_some_and(|cards| {
+                cards
+                    .into_iter()
+                    .find(|(card_id, _)| uid == card_id)
+                    .is_some()
+            })
+        })?;
+
+        // let u = data.users.remove(id)?;
+        let t = data
+            .transactions
+            .iter()
+            .filter(|t| t.actor.account() == Some(&*id))
+            .cloned()
+            .collect::<Vec<_>>();
+        Some((u, t))
+    }
+
+    pub fn users(&self) -> Result<Vec<User>, String> {
+        let data = self.data()?;
+        Ok(data.users.into_values().collect())
+    }
+
+    // What an account's balance was at a moment in the past, by taking off everything since. This
+    // still works when older history has been anonymised.
+    pub fn balance_at(&self, id: &str, at: DateTime<Utc>) -> Result<i64, String> {
+        let user = self.get_user(id).ok_or_else(|| format!("user {} does not exist", id))?.0;
+        let since = self
+            .transactions()?
+            .iter()
+            .filter(|t| t.timestamp > at && t.actor.account() == Some(id))
+            .map(|t| t.balance_change() as i64)
+            .sum::<i64>();
+        Ok(user.balance as i64 - since)
+    }
+
+    // Everything in the database file as it is now, without any archived years
+    pub fn snapshot(&self) -> Result<InnerDB, String> {
+        self.data()
+    }
+
+    // Swaps in a whole database, e.g. one restored from a backup. Archived years are left alone.
+    pub fn restore(&self, data: InnerDB) -> Result<(), String> {
+        if self.read_only {
+            return Err(READ_ONLY.to_string());
+        }
+        self.store
+            .put_data(data, false)
+            .map_err(|e| format!("{:?}", e))?;
+        self.persist()
+    }
+
+    // The whole history, reading back any archived years. Use history() for recent activity.
+    pub fn transactions(&self) -> Result<Vec<Transaction>, String> {
+        let data = self.data()?;
+        if data.archive.years.is_empty() {
+            return Ok(data.transactions);
+        }
+
+        let mut transactions = Vec::new();
+        for year in &data.archive.years {
+            transactions.extend(self.archived_year(*year)?);
+        }
+        let mut known = HashSet::new();
+        transactions.retain(|t| known.insert(t.id));
+        transactions.extend(data.transactions.into_iter().filter(|t| !known.contains(&t.id)));
+        transactions.sort_by_key(|t| t.timestamp);
+        Ok(transactions)
+    }
+
+    pub fn apply_cart_to_user(&self, id: &str, cart: &crate::cart::Cart) -> Result<User, String> {
+        self.charge_cart(TransactionActor::User(id.to_string()), cart)
+    }
+
+    // Charges a shared group account, recording which member made the purchase
+    pub fn apply_cart_to_group(
+        &self,
+        group: &str,
+        member: &str,
+        cart: &crate::cart::Cart,
+    ) -> Result<User, String> {
+        match self.get_user(group) {
+            Some((g, _)) if g.members.iter().any(|m| m == member) => {}
+            Some(_) => return Err(format!("{} is not a member of group {}", member, group)),
+            None => return Err(format!("user {} does not exist", group)),
+        }
+        self.charge_cart(
+            TransactionActor::Group {
+                group: group.to_string(),
+                member: member.to_string(),
+            },
+            cart,
+        )
+    }
+
+    fn charge_cart(&self, actor: TransactionActor, cart: &crate::cart::Cart) -> Result<User, String> {
+        self.reload()?;
+
+        let id = actor.account().unwrap_or_default().to_string();
+        let u = {
+            let mut data = self.store.borrow_data_mut().map_err(|e| format!("{:?}", e))?;
+            data.check_can_spend(&id)?;
+            // A frozen member can't get round it by spending from a group
+            if let TransactionActor::Group { member, .. } = &actor {
+                data.check_can_spend(member)?;
+            }
+            let cart = &data.with_current_promo(cart)?;
+            let user_discount = data.users[&id].discount_on(cart.total());
+            let total = cart.total() - user_discount;
+            let note = match (data.check_limits(&id, total), &cart.limit_override) {
+                (Ok(()), _) => None,
+                (Err(_), Some(admin)) => Some(format!("spending limit overridden by {}", admin)),
+                (Err(e), None) => return Err(e),
+            };
+            self.record(&mut data, Transaction {
+                id: cart.key,
+                timestamp: Utc::now(),
+                actor,
+                transaction: purchase(cart, None, user_discount, 0),
+                note,
+                till: None,
+            })?;
+
+            data.users[&id].clone()
+        };
+
+        self.persist()?;
+        Ok(u)
+    }
+
+    pub fn apply_cart_to_cash(
+        &self,
+        cart: &crate::cart::Cart,
+        tendered: Option<Tendered>,
+        rounding: i32,
+        note: Option<&str>,
+    ) -> Result<(), String> {
+        self.apply_cart_to(TransactionActor::Cash, cart, tendered, rounding, note)
+    }
+
+    pub fn apply_cart_to_tab(&self, tab: &str, cart: &crate::cart::Cart) -> Result<(), String> {
+        self.apply_cart_to(TransactionActor::Tab(tab.to_string()), cart, None, 0, None)
+    }
+
+    // For carts not paid for from a user's balance
+    fn apply_cart_to(
+        &self,
+        actor: TransactionActor,
+        cart: &crate::cart::Cart,
+        tendered: Option<Tendered>,
+        rounding: i32,
+        note: Option<&str>,
+    ) -> Result<(), String> {
+        self.reload()?;
+
+        {
+            let mut data = self.store.borrow_data_mut().map_err(|e| format!("{:?}", e))?;
+            let cart = &data.with_current_promo(cart)?;
+
+            self.record(&mut data, Transaction {
+                id: cart.key,
+                timestamp: Utc::now(),
+                actor,
+                transaction: purchase(cart, tendered, 0, rounding),
+                note: note.map(str::to_string),
+                till: None,
+            })?;
+        }
+
+        self.persist()?;
+        Ok(())
+    }
+
+    // Problems found with the stored data, empty if it's all consistent
+    pub fn fsck(&self) -> Result<Vec<String>, String> {
+        let data = self.data()?;
+        let mut problems = Vec::new();
+
+        let expected = data.balances_from_transactions();
+        for t in &data.transactions {
+            if let Some(id) = t.actor.account() {
+                if !data.users.contains_key(id) {
+                    problems.push(format!("transaction {} is by unknown user {}", t.id, id));
+                }
+            }
+            if let TransactionActor::Group { member, .. } = &t.actor {
+                if !data.users.contains_key(member) {
+                    problems.push(format!("transaction {} is by unknown member {}", t.id, member));
+                }
+            }
+        }
+
+        let mut users = data.users.values().collect::<Vec<_>>();
+        users.sort_by(|a, b| a.id.cmp(&b.id));
+        let mut card_owners = std::collections::BTreeMap::<&str, Vec<&str>>::new();
+        for user in users {
+            let sum = expected.get(user.id.as_str()).copied().unwrap_or(0);
+            if sum != user.balance as i64 {
+                problems.push(format!(
+                    "user {} has a balance of {} but their transactions add up to {}",
+                    user.id,
+                    money::format(user.balance),
+                    money::format(sum)
+                ));
+            }
+            for (uid, _) in user.cards.iter().flatten() {
+                card_owners.entry(uid).or_default().push(&user.id);
+            }
+        }
+        for (uid, owners) in card_owners {
+            if owners.len() > 1 {
+                problems.push(format!("card {} is registered to {}", uid, owners.join(", ")));
+            }
+        }
+
+        Ok(problems)
+    }
+
+    // Normally done on load, but the cached balances can also be rebuilt while running
+    pub fn rebuild_balances(&self) -> Result<Vec<(String, i32, i32)>, String> {
+        self.reload()?;
+
+        let changed = self
+            .store
+            .borrow_data_mut()
+            .map_err(|e| format!("{:?}", e))?
+            .project();
+
+        self.persist()?;
+        Ok(changed)
+    }
+
+    // What's still owed on each guest tab
+    pub fn tabs(&self) -> Result<std::collections::BTreeMap<String, u32>, String> {
+        let mut tabs = std::collections::BTreeMap::<String, i64>::new();
+        for t in &self.transactions()? {
+            match (&t.actor, &t.transaction) {
+                (TransactionActor::Tab(name), TransactionType::Purchase { total, .. }) => {
+                    *tabs.entry(name.clone()).or_default() += *total as i64
+                }
+                (_, TransactionType::SettleTab { tab, amount }) => {
+                    *tabs.entry(tab.clone()).or_default() -= *amount as i64
+                }
+                _ => {}
+            }
+        }
+        Ok(tabs
+            .into_iter()
+            .filter(|(_, owed)| *owed > 0)
+            .map(|(name, owed)| (name, owed as u32))
+            .collect())
+    }
+
+    // Pays off everything owed on a tab, in cash or from a sponsoring user's balance
+    pub fn settle_tab(
+        &self,
+        tab: &str,
+        sponsor: Option<&str>,
+        note: Option<&str>,
+    ) -> Result<u32, String> {
+        let owed = *self
+            .tabs()?
+            .get(tab)
+            .ok_or_else(|| format!("nothing is owed on tab {}", tab))?;
+
+        self.reload()?;
+        {
+            let mut data = self.store.borrow_data_mut().map_err(|e| format!("{:?}", e))?;
+            // A sponsor pays like any other purchase, so the same checks apply
+            let actor = match sponsor {
+                Some(id) => {
+                    data.check_can_spend(id)?;
+                    data.check_limits(id, owed)?;
+                    TransactionActor::User(id.to_string())
+                }
+                None => TransactionActor::Tab(tab.to_string()),
+            };
+
+            self.record(&mut data, Transaction {
+                id: Uuid::new_v4(),
+                timestamp: Utc::now(),
+                actor,
+                transaction: TransactionType::SettleTab {
+                    tab: tab.to_string(),
+                    amount: owed,
+                },
+                note: note.map(str::to_string),
+                till: None,
+            })?;
+        }
+
+        self.persist()?;
+        Ok(owed)
+    }
+
+    pub fn deposit_user(
+        &self,
+        id: &str,
+        amount: u32,
+        method: DepositMethod,
+        note: Option<&str>,
+    ) -> Result<User, String> {
+        self.deposit_user_once(Uuid::new_v4(), id, amount, method, note)
+    }
+
+    // A deposit that's turned away if one with the same key has already been made
+    pub fn deposit_user_once(
+        &self,
+        key: Uuid,
+        id: &str,
+        amount: u32,
+        method: DepositMethod,
+        note: Option<&str>,
+    ) -> Result<User, String> {
+        self.reload()?;
+
+        let u = {
+            let mut data = self.store.borrow_data_mut().map_err(|e| format!("{:?}", e))?;
+            if !data.users.contains_key(id) {
+                return Err(format!("user {} does not exist", id));
+            }
+
+            self.record(&mut data, Transaction {
+                id: key,
+                timestamp: Utc::now(),
+                actor: TransactionActor::User(id.to_string()),
+                transaction: TransactionType::Deposit {
+                    amount,
+                    method,
+                    fee: self.deposit_fee(amount, method),
+                },
+                note: note.map(str::to_string),
+                till: None,
+            })?;
+
+            data.users[id].clone()
+        };
+
+        self.persist()?;
+        Ok(u)
+    }
+
+    // Balances can't go negative this way, the bank isn't a cash machine for debt
+    pub fn withdraw_user(&self, id: &str, amount: u32, note: Option<&str>) -> Result<User, String> {
+        self.reload()?;
+
+        let u = {
+            let mut data = self.store.borrow_data_mut().map_err(|e| format!("{:?}", e))?;
+            match data.users.get(id) {
+                None => return Err(format!("user {} does not exist", id)),
+                Some(u) if (u.balance as i64) < amount as i64 => {
+                    return Err(format!(
+                        "can't withdraw more than the balance of {}",
+                        money::format(u.balance)
+                    ))
+                }
+                Some(_) => {}
+            }
+
+            self.record(&mut data, Transaction {
+                id: Uuid::new_v4(),
+                timestamp: Utc::now(),
+                actor: TransactionActor::User(id.to_string()),
+                transaction: TransactionType::Withdrawal { amount },
+                note: note.map(str::to_string),
+                till: None,
+            })?;
+
+            data.users[id].clone()
+        };
+
+        self.persist()?;
+        Ok(u)
+    }
+
+    pub fn expense(&self, amount: u32, description: &str) -> Result<(), String> {
+        self.reload()?;
+
+        {
+            let mut data = self.store.borrow_data_mut().map_err(|e| format!("{:?}", e))?;
+            self.record(&mut data, Transaction {
+                id: Uuid::new_v4(),
+                timestamp: Utc::now(),
+                actor: TransactionActor::Cash,
+                transaction: TransactionType::Expense {
+                    amount,
+                    description: description.to_string(),
+                },
+                note: None,
+                till: None,
+            })?;
+        }
+
+        self.persist()
+    }
+
+    pub fn adjust_user(
+        &self,
+        id: &str,
+        amount: i32,
+        reason: &str,
+        admin: &str,
+        note: Option<&str>,
+    ) -> Result<User, String> {
+        self.reload()?;
+
+        let u = {
+            let mut data = self.store.borrow_data_mut().map_err(|e| format!("{:?}", e))?;
+            if !data.users.contains_key(id) {
+                return Err(format!("user {} does not exist", id));
+            }
+
+            self.record(&mut data, Transaction {
+                id: Uuid::new_v4(),
+                timestamp: Utc::now(),
+                actor: TransactionActor::User(id.to_string()),
+                transaction: TransactionType::Adjustment {
+                    amount,
+                    reason: reason.to_string(),
+                    admin: admin.to_string(),
+                },
+                note: note.map(str::to_string),
+                till: None,
+            })?;
+
+            data.users[id].clone()
+        };
+
+        self.persist()?;
+        Ok(u)
+    }
+
+    // Puts a purchase back on the balance of the user who made it, as long as it was within the
+    // last `window`. Anything older needs an admin and a refund.
+    pub fn void_purchase(&self, id: Uuid, user: &str, window: chrono::Duration) -> Result<Transaction, String> {
+        self.reload()?;
+
+        let void = {
+            let mut data = self.store.borrow_data_mut().map_err(|e| format!("{:?}", e))?;
+            let original = data
+                .transactions
+                .iter()
+                .rev()
+                .find(|t| t.id == id)
+                .cloned()
+                .ok_or_else(|| format!("no transaction {}", id))?;
+            let (amount, products) = match (&original.actor, &original.transaction) {
+                (TransactionActor::User(u), TransactionType::Purchase { total, products, .. }) if u == user => {
+                    (*total, products.clone())
+                }
+                _ => return Err(format!("transaction {} is not a purchase by {}", id, user)),
+            };
+            if Utc::now() - original.timestamp > window {
+                return Err(format!("transaction {} is too old to void", id));
+            }
+            let void_id = Uuid::new_v5(&VOID_NAMESPACE, original.id.as_bytes());
+            if data.transactions.iter().any(|t| t.id == void_id) {
+                return Err(format!("transaction {} has already been voided", id));
+            }
+
+            let void = Transaction {
+                id: void_id,
+                timestamp: Utc::now(),
+                actor: original.actor.clone(),
+                transaction: TransactionType::Void {
+                    transaction: original.id,
+                    amount,
+                    products,
+                },
+                note: None,
+                till: None,
+            };
+            self.record(&mut data, void.clone())?;
+            void
+        };
+
+        self.persist()?;
+        Ok(void)
+    }
+
+    // Records handing the money for a cash purchase or cash deposit back, taking a refunded
+    // deposit back off the user's balance
+    pub fn refund_to_cash(&self, id: &str, note: Option<&str>) -> Result<Transaction, String> {
+        self.reload()?;
+
+        let refund = {
+            let mut data = self.store.borrow_data_mut().map_err(|e| format!("{:?}", e))?;
+            let original = find_transaction(&data.transactions, id)?.clone();
+            if data.transactions.iter().any(|t| {
+                matches!(t.transaction, TransactionType::Refund { transaction, .. } if transaction == original.id)
+            }) {
+                return Err(format!("transaction {} has already been refunded", original.id));
+            }
+
+            let (amount, tendered) = match (&original.actor, &original.transaction) {
+                (TransactionActor::Cash, TransactionType::Purchase { total, tendered, .. }) => {
+                    (*total, tendered.clone())
+                }
+                (
+                    TransactionActor::User(user),
+                    TransactionType::Deposit {
+                        amount,
+                        method: DepositMethod::Cash,
+                        fee,
+                    },
+                ) => {
+                    if !data.users.contains_key(user) {
+                        return Err(format!("user {} no longer exists", user));
+                    }
+                    // The fee's kept, only what was credited comes back
+                    (*amount - *fee, None)
+                }
+                _ => return Err("only cash purchases and cash deposits can be refunded to cash".to_string()),
+            };
+
+            let refund = Transaction {
+                id: Uuid::new_v4(),
+                timestamp: Utc::now(),
+                actor: original.actor.clone(),
+                transaction: TransactionType::Refund {
+                    transaction: original.id,
+                    amount,
+                    tendered,
+                },
+                note: note.map(str::to_string),
+                till: None,
+            };
+            self.record(&mut data, refund.clone())?;
+            refund
+        };
+
+        self.persist()?;
+        Ok(refund)
+    }
+
+    pub fn write_off(
+        &self,
+        product: &crate::products::Product,
+        quantity: u32,
+        reason: &str,
+    ) -> Result<(), String> {
+        self.reload()?;
+
+        {
+            let mut data = self.store.borrow_data_mut().map_err(|e| format!("{:?}", e))?;
+            self.record(&mut data, Transaction {
+                id: Uuid::new_v4(),
+                timestamp: Utc::now(),
+                actor: TransactionActor::Stock,
+                transaction: TransactionType::WriteOff {
+                    product: product.clone(),
+                    quantity,
+                    reason: reason.to_string(),
+                },
+                note: None,
+                till: None,
+            })?;
+        }
+
+        self.persist()
+    }
+
+    pub fn restock(
+        &self,
+        product: &crate::products::Product,
+        quantity: u32,
+        cost: Option<u32>,
+        supplier: Option<&str>,
+    ) -> Result<(), String> {
+        self.reload()?;
+
+        {
+            let mut data = self.store.borrow_data_mut().map_err(|e| format!("{:?}", e))?;
+            if let Some(supplier) = supplier {
+                if !data.suppliers.contains_key(supplier) {
+                    return Err(format!("no supplier {}", supplier));
+                }
+            }
+            self.record(&mut data, Transaction {
+                id: Uuid::new_v4(),
+                timestamp: Utc::now(),
+                actor: TransactionActor::Stock,
+                transaction: TransactionType::Restock {
+                    product: product.clone(),
+                    quantity,
+                    cost,
+                    supplier: supplier.map(|s| s.to_string()),
+                    order: None,
+                },
+                note: None,
+                till: None,
+            })?;
+        }
+
+        self.persist()
+    }
+
+    pub fn stocktake(&self, product: &crate::products::Product, counted: u32) -> Result<(), String> {
+        self.reload()?;
+
+        {
+            let mut data = self.store.borrow_data_mut().map_err(|e| format!("{:?}", e))?;
+            self.record(&mut data, Transaction {
+                id: Uuid::new_v4(),
+                timestamp: Utc::now(),
+                actor: TransactionActor::Stock,
+                transaction: TransactionType::Stocktake {
+                    product: product.clone(),
+                    counted,
+                },
+                note: None,
+                till: None,
+            })?;
+        }
+
+        self.persist()
+    }
+
+    pub fn drawer_opened(&self, reason: &str) -> Result<(), String> {
+        self.reload()?;
+
+        {
+            let mut data = self.store.borrow_data_mut().map_err(|e| format!("{:?}", e))?;
+            self.record(&mut data, Transaction {
+                id: Uuid::new_v4(),
+                timestamp: Utc::now(),
+                actor: TransactionActor::Cash,
+                transaction: TransactionType::DrawerOpened {
+                    reason: reason.to_string(),
+                },
+                note: None,
+                till: None,
+            })?;
+        }
+
+        self.persist()
+    }
+
+    pub fn add_supplier(&self, name: &str, contact: Option<&str>) -> Result<Supplier, String> {
+        self.reload()?;
+
+        let supplier = Supplier {
+            name: name.to_string(),
+            contact: contact.map(|c| c.to_string()),
+        };
+        {
+            let mut data = self.store.borrow_data_mut().map_err(|e| format!("{:?}", e))?;
+            if data.suppliers.contains_key(name) {
+                return Err(format!("supplier {} already exists", name));
+            }
+            data.suppliers.insert(name.to_string(), supplier.clone());
+        }
+
+        self.persist()?;
+        Ok(supplier)
+    }
+
+    pub fn suppliers(&self) -> Result<Vec<Supplier>, String> {
+        let mut suppliers = self.data()?.suppliers.into_values().collect::<Vec<_>>();
+        suppliers.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(suppliers)
+    }
+
+    pub fn new_purchase_order(
+        &self,
+        supplier: &str,
+        lines: Vec<OrderLine>,
+    ) -> Result<PurchaseOrder, String> {
+        self.reload()?;
+
+        let order = {
+            let mut data = self.store.borrow_data_mut().map_err(|e| format!("{:?}", e))?;
+            if !data.suppliers.contains_key(supplier) {
+                return Err(format!("no supplier {}", supplier));
+            }
+            if lines.is_empty() {
+                return Err("order has nothing on it".to_string());
+            }
+
+            let order = PurchaseOrder {
+                id: Uuid::new_v4(),
+                supplier: supplier.to_string(),
+                created: Utc::now(),
+                lines,
+                received: None,
+            };
+            data.purchase_orders.push(order.clone());
+            order
+        };
+
+        self.persist()?;
+        Ok(order)
+    }
+
+    pub fn purchase_orders(&self) -> Result<Vec<PurchaseOrder>, String> {
+        Ok(self.data()?.purchase_orders)
+    }
+
+    // The token itself is only ever shown here, so it has to be copied down straight away
+    pub fn issue_token(&self, name: &str, scope: Scope, user: Option<&str>) -> Result<String, String> {
+        self.reload()?;
+
+        let token = format!(
+            "57b_{}{}",
+            Uuid::new_v4().simple(),
+            Uuid::new_v4().simple()
+        );
+        {
+            let mut data = self.store.borrow_data_mut().map_err(|e| format!("{:?}", e))?;
+            if data.api_tokens.iter().any(|t| t.name == name) {
+                return Err(format!("token {} already exists", name));
+            }
+            if let Some(user) = user.filter(|u| !data.users.contains_key(*u)) {
+                return Err(format!("user {} does not exist", user));
+            }
+            data.api_tokens.push(ApiToken {
+                name: name.to_string(),
+                scope,
+                user: user.map(|u| u.to_string()),
+                hash: hash_token(&token),
+                created: Utc::now(),
+            });
+        }
+
+        self.persist()?;
+        Ok(token)
+    }
+
+    pub fn revoke_token(&self, name: &str) -> Result<ApiToken, String> {
+        self.reload()?;
+
+        let token = {
+            let mut data = self.store.borrow_data_mut().map_err(|e| format!("{:?}", e))?;
+            let i = data
+                .api_tokens
+                .iter()
+                .position(|t| t.name == name)
+                .ok_or_else(|| format!("no token {}", name))?;
+            data.api_tokens.remove(i)
+        };
+
+        self.persist()?;
+        Ok(token)
+    }
+
+    pub fn api_tokens(&self) -> Result<Vec<ApiToken>, String> {
+        Ok(self.data()?.api_tokens)
+    }
+
+    // Compares against every token without stopping early, so the time taken gives nothing away
+    pub fn check_token(&self, token: &str) -> Option<ApiToken> {
+        use subtle::ConstantTimeEq;
+
+        let hash = hash_token(token);
+        let mut found = None;
+        for t in self.data().ok()?.api_tokens {
+            if bool::from(t.hash.as_bytes().ct_eq(hash.as_bytes())) {
+                found = Some(t);
+            }
+        }
+        found
+    }
+
+    // Restocks everything on the open order whose ID starts with `id`
+    pub fn receive_purchase_order(&self, id: &str) -> Result<PurchaseOrder, String> {
+        self.reload()?;
+
+        let order = {
+            let mut data = self.store.borrow_data_mut().map_err(|e| format!("{:?}", e))?;
+            let matching = data
+                .purchase_orders
+                .iter()
+                .enumerate()
+                .filter(|(_, o)| o.received.is_none() && o.id.to_string().starts_with(id))
+                .map(|(i, _)| i)
+                .collect::<Vec<_>>();
+            let i = match matching[..] {
+                [i] => i,
+                [] => return Err(format!("no open purchase order {}", id)),
+                _ => return Err(format!("{} matches more than one purchase order", id)),
+            };
+
+            let now = Utc::now();
+            data.purchase_orders[i].received = Some(now);
+            let order = data.purchase_orders[i].clone();
+            for line in &order.lines {
+                self.record(&mut data, Transaction {
+                    id: Uuid::new_v4(),
+                    timestamp: now,
+                    actor: TransactionActor::Stock,
+                    transaction: TransactionType::Restock {
+                        product: line.product.clone(),
+                        quantity: line.quantity,
+                        cost: line.cost,
+                        supplier: Some(order.supplier.clone()),
+                        order: Some(order.id),
+                    },
+                    note: None,
+                    till: None,
+                })?;
+            }
+            order
+        };
+
+        self.persist()?;
+        Ok(order)
+    }
+
+    pub fn add_user(&self, id: &str) -> Result<(), String> {
+        self.reload()?;
+
+        {
+            let mut data = self.store.borrow_data_mut().map_err(|e| format!("{:?}", e))?;
+
+            if data.users.contains_key(id) {
+                return Err(format!("user {} already exists", id));
+            }
+
+            data.users.insert(id.to_string(), User::new(id));
+        }
+
+        self.persist()?;
+        Ok(())
+    }
+
+    // Creates accounts for new members and records membership IDs on existing ones
+    pub fn import_members(&self, members: &[Member]) -> Result<ImportSummary, String> {
+        self.reload()?;
+
+        let mut summary = ImportSummary::default();
+        {
+            let mut data = self.store.borrow_data_mut().map_err(|e| format!("{:?}", e))?;
+
+            for member in members {
+                let existing = data.users.values().find(|u| {
+                    u.membership_id.as_ref() == Some(&member.membership_id) && u.id != member.user_id
+                });
+                if let Some(u) = existing {
+                    summary.conflicts.push(format!(
+                        "membership {} is already linked to user {}",
+                        member.membership_id, u.id
+                    ));
+                    continue;
+                }
+
+                match data.users.get_mut(&member.user_id) {
+                    Some(u) if u.membership_id.as_ref() == Some(&member.membership_id) => {
+                        summary.unchanged += 1;
+                    }
+                    Some(u) => {
+                        u.membership_id = Some(member.membership_id.clone());
+                        summary.updated += 1;
+                    }
+                    None => {
+                        let mut u = User::new(&member.user_id);
+                        u.membership_id = Some(member.membership_id.clone());
+                        data.users.insert(member.user_id.clone(), u);
+                        summary.created.push(member.user_id.clone());
+                    }
+                }
+            }
+        }
+
+        if !summary.created.is_empty() || summary.updated > 0 {
+            self.persist()?;
+        }
+        Ok(summary)
+    }
+
+    // Won't move an account that's already linked to a different login unless told to replace it
+    pub fn link_identity(&self, id: &str, subject: &str, replace: bool) -> Result<User, String> {
+        self.reload()?;
+
+        let u = {
+            let mut data = self.store.borrow_data_mut().map_err(|e| format!("{:?}", e))?;
+
+            if let Some(other) = data
+                .users
+                .values()
+                .find(|u| u.id != id && u.oidc_subject.as_deref() == Some(subject))
+            {
+                return Err(format!("that account is already linked to user {}", other.id));
+            }
+
+            match data.users.get_mut(id) {
+                None => return Err(format!("user {} does not exist", id)),
+                Some(u) if !replace && u.oidc_subject.as_deref().is_some_and(|s| s != subject) => {
+                    return Err(format!("user {} is already linked to another account", id))
+                }
+                Some(u) => {
+                    u.oidc_subject = Some(subject.to_string());
+                    u.clone()
+                }
+            }
+        };
+
+        self.persist()?;
+        Ok(u)
+    }
+
+    pub fn set_pin(&self, id: &str, pin: &str) -> Result<User, String> {
+        let pin_hash = hash_pin(pin)?;
+        self.update_user(id, |u| {
+            u.pin_hash = Some(pin_hash);
+            u.pin_failures = 0;
+            u.pin_locked_until = None;
+        })
+    }
+
+    // Checks a PIN typed at the till, locking PIN entry for a while after too many wrong ones
+    // so they can't just be tried one after another
+    pub fn verify_pin(&self, id: &str, pin: &str) -> Result<bool, String> {
+        self.reload()?;
+
+        let now = Utc::now();
+        let correct = {
+            let mut data = self.store.borrow_data_mut().map_err(|e| format!("{:?}", e))?;
+            let u = data.users.get_mut(id).ok_or_else(|| format!("user {} does not exist", id))?;
+            if let Some(until) = u.pin_locked_until.filter(|until| *until > now) {
+                return Err(format!(
+                    "too many wrong PINs, try again after {}",
+                    until.with_timezone(&Local).format("%H:%M")
+                ));
+            }
+            let correct = u.check_pin(pin);
+            if correct {
+                u.pin_failures = 0;
+                u.pin_locked_until = None;
+                if u.pin_hash.as_deref().is_some_and(|h| !h.starts_with('$')) {
+                    u.pin_hash = Some(hash_pin(pin)?);
+                }
+            } else {
+                u.pin_failures += 1;
+                if u.pin_failures >= PIN_ATTEMPTS {
+                    u.pin_failures = 0;
+                    u.pin_locked_until = Some(now + chrono::Duration::minutes(PIN_LOCKOUT_MINUTES));
+                }
+            }
+            correct
+        };
+
+        self.persist()?;
+        Ok(correct)
+    }
+
+    // Creates a shared account that the given existing users can spend from
+    pub fn add_group(&self, id: &str, members: &[&str]) -> Result<(), String> {
+        self.reload()?;
+
+        {
+            let mut data = self.store.borrow_data_mut().map_err(|e| format!("{:?}", e))?;
+            if data.users.contains_key(id) {
+                return Err(format!("user {} already exists", id));
+            }
+            if let Some(m) = members.iter().find(|m| !data.users.contains_key(**m)) {
+                return Err(format!("user {} does not exist", m));
+            }
+
+            let mut group = User::new(id);
+            group.members = members.iter().map(|m| m.to_string()).collect();
+            group.group = true;
+            data.users.insert(id.to_string(), group);
+        }
+
+        self.persist()?;
+        Ok(())
+    }
+
+    pub fn add_group_member(&self, group: &str, member: &str) -> Result<User, String> {
+        if self.get_user(member).is_none() {
+            return Err(format!("user {} does not exist", member));
+        }
+        // Otherwise anyone could be given a way into someone else's personal account
+        match self.get_user(group) {
+            Some((g, _)) if g.group || g.is_group() => {}
+            Some(_) => return Err(format!("{} is not a group account", group)),
+            None => return Err(format!("user {} does not exist", group)),
+        }
+        self.update_user(group, |g| {
+            if !g.members.iter().any(|m| m == member) {
+                g.members.push(member.to_string());
+            }
+        })
+    }
+
+    // Removing the last member turns the group back into an ordinary account
+    pub fn remove_group_member(&self, group: &str, member: &str) -> Result<User, String> {
+        self.update_user(group, |g| g.members.retain(|m| m != member))
+    }
+
+    pub fn set_discount(&self, id: &str, percent: Option<u8>) -> Result<User, String> {
+        if percent.is_some_and(|p| p > 100) {
+            return Err("discount can't be more than 100%".to_string());
+        }
+        self.update_user(id, |u| u.discount = percent)
+    }
+
+    pub fn set_limits(
+        &self,
+        id: &str,
+        daily: Option<u32>,
+        purchase: Option<u32>,
+    ) -> Result<User, String> {
+        self.update_user(id, |u| {
+            u.daily_limit = daily;
+            u.purchase_limit = purchase;
+        })
+    }
+
+    // None removes the code
+    pub fn set_favourite(
+        &self,
+        id: &str,
+        code: &str,
+        barcode: Option<crate::barcode::Barcode>,
+    ) -> Result<User, String> {
+        self.update_user(id, |u| match barcode {
+            Some(b) => {
+                u.favourites.insert(code.to_string(), b);
+            }
+            None => {
+                u.favourites.remove(code);
+            }
+        })
+    }
+
+    pub fn set_role(&self, id: &str, role: Role) -> Result<User, String> {
+        self.update_user(id, |u| u.role = role)
+    }
+
+    pub fn freeze(&self, id: &str, reason: &str) -> Result<User, String> {
+        self.update_user(id, |u| u.frozen = Some(reason.to_string()))
+    }
+
+    pub fn unfreeze(&self, id: &str) -> Result<User, String> {
+        self.update_user(id, |u| u.frozen = None)
+    }
+
+    pub fn set_name(&self, id: &str, name: &str) -> Result<User, String> {
+        self.update_user(id, |u| u.name = Some(name.to_string()))
+    }
+
+    pub fn set_email(&self, id: &str, email: &str) -> Result<User, String> {
+        self.update_user(id, |u| u.email = Some(email.to_string()))
+    }
+
+    fn update_user(&self, id: &str, f: impl FnOnce(&mut User)) -> Result<User, String> {
+        self.reload()?;
+
+        let u = {
+            let mut data = self.store.borrow_data_mut().map_err(|e| format!("{:?}", e))?;
+            match data.users.get_mut(id) {
+                None => return Err(format!("user {} does not exist", id)),
+                Some(u) => {
+                    f(u);
+                    u.clone()
+                }
+            }
+        };
+
+        self.persist()?;
+        Ok(u)
+    }
+
+    // The first charge is taken straight away, on the next call to `apply_standing_orders`
+    pub fn add_standing_order(
+        &self,
+        user: &str,
+        amount: u32,
+        period: Period,
+        description: &str,
+    ) -> Result<StandingOrder, String> {
+        self.reload()?;
+
+        let order = {
+            let mut data = self.store.borrow_data_mut().map_err(|e| format!("{:?}", e))?;
+            if !data.users.contains_key(user) {
+                return Err(format!("user {} does not exist", user));
+            }
+
+            let now = Utc::now();
+            let order = StandingOrder {
+                id: Uuid::new_v4(),
+                user: user.to_string(),
+                description: description.to_string(),
+                amount,
+                period,
+                next_due: now,
+                anchor: Some(now),
+            };
+            data.standing_orders.push(order.clone());
+            order
+        };
+
+        self.persist()?;
+        Ok(order)
+    }
+
+    // Cancels the standing order whose ID starts with `id`
+    pub fn cancel_standing_order(&self, id: &str) -> Result<StandingOrder, String> {
+        self.reload()?;
+
+        let order = {
+            let mut data = self.store.borrow_data_mut().map_err(|e| format!("{:?}", e))?;
+            let matching = data
+                .standing_orders
+                .iter()
+                .enumerate()
+                .filter(|(_, o)| o.id.to_string().starts_with(id))
+                .map(|(i, _)| i)
+                .collect::<Vec<_>>();
+            let order = match matching[..] {
+                [i] => data.standing_orders.remove(i),
+                [] => return Err(format!("no standing order {}", id)),
+                _ => return Err(format!("{} matches more than one standing order", id)),
+            };
+            data.cancelled_standing_orders.insert(order.id);
+            order
+        };
+
+        self.persist()?;
+        Ok(order)
+    }
+
+    pub fn standing_orders(&self) -> Result<Vec<StandingOrder>, String> {
+        Ok(self.data()?.standing_orders)
+    }
+
+    // Takes every standing order charge that has come due, including any missed while the
+    // till was off, and returns the transactions made
+    pub fn apply_standing_orders(&self, now: DateTime<Utc>) -> Result<Vec<Transaction>, String> {
+        self.reload()?;
+
+        let mut applied = Vec::new();
+        {
+            let mut data = self.store.borrow_data_mut().map_err(|e| format!("{:?}", e))?;
+            let data = &mut *data;
+            let mut known = data.transactions.iter().map(|t| t.id).collect::<HashSet<_>>();
+            let mut changed = false;
+
+            for order in data.standing_orders.iter_mut() {
+                while order.next_due <= now {
+                    changed = true;
+                    let due = order.next_due;
+                    let anchor = *order.anchor.get_or_insert(due);
+                    order.next_due = order.period.after(anchor, due);
+
+                    let t = Transaction {
+                        id: Uuid::new_v5(
+                            &STANDING_ORDER_NAMESPACE,
+                            format!("{}{}", order.id, due.to_rfc3339()).as_bytes(),
+                        ),
+                        timestamp: due,
+                        actor: TransactionActor::User(order.user.clone()),
+                        transaction: TransactionType::StandingOrder {
+                            order: order.id,
+                            description: order.description.clone(),
+                            amount: order.amount,
+                        },
+                        note: None,
+                        till: None,
+                    };
+                    // Another till may have already taken this charge and synced it to us
+                    if !known.insert(t.id) {
+                        continue;
+                    }
+                    if !data.users.contains_key(&order.user) {
+                        continue;
+                    }
+                    applied.push(t);
+                }
+            }
+
+            if !changed {
+                return Ok(applied);
+            }
+            for t in &applied {
+                self.record(data, t.clone())?;
+            }
+            data.transactions.sort_by_key(|t| t.timestamp);
+        }
+
+        self.persist()?;
+        Ok(applied)
+    }
+
+    pub fn create_voucher(&self, amount: u32, issued_by: &str) -> Result<Voucher, String> {
+        self.reload()?;
+
+        let voucher = {
+            let mut data = self.store.borrow_data_mut().map_err(|e| format!("{:?}", e))?;
+            let mut code = voucher_code();
+            while data.vouchers.contains_key(&code) {
+                code = voucher_code();
+            }
+
+            let voucher = Voucher {
+                code: code.clone(),
+                amount,
+                created: Utc::now(),
+                redeemed: None,
+                issued_by: Some(issued_by.to_string()),
+            };
+            data.vouchers.insert(code, voucher.clone());
+            voucher
+        };
+
+        self.persist()?;
+        Ok(voucher)
+    }
+
+    pub fn get_voucher(&self, code: &str) -> Option<Voucher> {
+        self.data().ok()?.vouchers.remove(&normalise_voucher_code(code))
+    }
+
+    // Credits the voucher's value to the user, as long as it hasn't been used already
+    pub fn redeem_voucher(&self, code: &str, id: &str) -> Result<(User, Voucher), String> {
+        self.reload()?;
+
+        let (u, voucher) = {
+            let mut data = self.store.borrow_data_mut().map_err(|e| format!("{:?}", e))?;
+            let data = &mut *data;
+            let (code, amount) = match data.vouchers.get(&normalise_voucher_code(code)) {
+                None => return Err(format!("no voucher {}", code)),
+                Some(v) if v.redeemed.is_some() => {
+                    return Err(format!("voucher {} has already been used", v.code))
+                }
+                Some(v) => (v.code.clone(), v.amount),
+            };
+            if !data.users.contains_key(id) {
+                return Err(format!("user {} does not exist", id));
+            }
+
+            // Marked as redeemed once the deposit is applied
+            self.record(data, Transaction {
+                id: voucher_transaction_id(&code),
+                timestamp: Utc::now(),
+                actor: TransactionActor::User(id.to_string()),
+                transaction: TransactionType::Deposit {
+                    amount,
+                    method: DepositMethod::Voucher,
+                    fee: 0,
+                },
+                note: None,
+                till: None,
+            })?;
+
+            (data.users[id].clone(), data.vouchers[&code].clone())
+        };
+
+        self.persist()?;
+        Ok((u, voucher))
+    }
+
+    pub fn add_promo(&self, promo: crate::promo::Promo) -> Result<(), String> {
+        self.reload()?;
+
+        {
+            let mut data = self.store.borrow_data_mut().map_err(|e| format!("{:?}", e))?;
+            if data.promos.contains_key(&promo.code) {
+                return Err(format!("promo code {} already exists", promo.code));
+            }
+            data.promos.insert(promo.code.clone(), promo);
+        }
+
+        self.persist()?;
+        Ok(())
+    }
+
+    pub fn remove_promo(&self, code: &str) -> Result<crate::promo::Promo, String> {
+        self.reload()?;
+
+        let promo = {
+            let mut data = self.store.borrow_data_mut().map_err(|e| format!("{:?}", e))?;
+            let promo = data
+                .promos
+                .remove(&crate::promo::normalise_code(code))
+                .ok_or_else(|| format!("no promo code {}", code))?;
+            data.removed_promos.insert(promo.code.clone(), Utc::now());
+            promo
+        };
+
+        self.persist()?;
+        Ok(promo)
+    }
+
+    pub fn get_promo(&self, code: &str) -> Option<crate::promo::Promo> {
+        self.data().ok()?.promos.remove(&crate::promo::normalise_code(code))
+    }
+
+    pub fn promos(&self) -> Result<Vec<crate::promo::Promo>, String> {
+        Ok(self.data()?.promos.into_values().collect())
+    }
+
+    pub fn empty_cashbox(&self, currency: &str) -> Result<(), String> {
+        self.reload()?;
+        self.store
+            .borrow_data_mut()
+            .map_err(|e| format!("{:?}", e))?
+            .cashbox_emptied
+            .insert(cashbox_key(self.till(), currency), Utc::now());
+        self.persist()
+    }
+
+    pub fn last_zreport(&self) -> Result<Option<DateTime<Utc>>, String> {
+        Ok(self
+            .data()?
+            .last_zreport
+            .get(self.till().unwrap_or_default())
+            .copied())
+    }
+
+    pub fn mark_zreport(&self, at: DateTime<Utc>) -> Result<(), String> {
+        self.reload()?;
+        self.store
+            .borrow_data_mut()
+            .map_err(|e| format!("{:?}", e))?
+            .last_zreport
+            .insert(self.till().unwrap_or_default().to_string(), at);
+        self.persist()
+    }
+
+    pub fn cashbox_emptied(&self) -> Result<std::collections::HashMap<String, DateTime<Utc>>, String> {
+        Ok(self.data()?.cashbox_emptied)
+    }
+
+    pub fn add_card_to_user(
+        &self,
+        id: &str,
+        card_name: Option<impl ToString>,
+        card_uid: impl ToString,
+    ) -> Result<(String, String), String> {
+        let uid = card_uid.to_string();
+        let name = match card_name.map(|n| n.to_string()) {
+            Some(n) => n,
+            None => uid.chars().take(5).collect::<String>(),
+        };
+
+        let mut data = self.store.borrow_data_mut().map_err(|e| format!("{:?}", e))?;
+        let user = data
+            .users
+            .get_mut(id)
+            .ok_or_else(|| String::from("This user does not exist."))?;
+
+        user.add_card(&uid, &name, Utc::now());
+
+        drop(data);
+
+        self.persist()?;
+
+        Ok((name, card_uid.to_string()))
+    }
+
+    pub fn request_card(
+        &self,
+        id: &str,
+        card_name: Option<impl ToString>,
+        card_uid: impl ToString,
+    ) -> Result<PendingCard, String> {
+        self.reload()?;
+
+        let uid = card_uid.to_string();
+        let card = {
+            let mut data = self.store.borrow_data_mut().map_err(|e| format!("{:?}", e))?;
+            if !data.users.contains_key(id) {
+                return Err(String::from("This user does not exist."));
+            }
+            if data.pending_cards.iter().any(|c| c.uid == uid) {
+                return Err(String::from("This card is already waiting for approval."));
+            }
+            let card = PendingCard {
+                user: id.to_string(),
+                name: match card_name.map(|n| n.to_string()) {
+                    Some(n) => n,
+                    None => uid.chars().take(5).collect::<String>(),
+                },
+                uid,
+                requested: Utc::now(),
+            };
+            data.pending_cards.push(card.clone());
+            card
+        };
+
+        self.persist()?;
+        Ok(card)
+    }
+
+    pub fn pending_cards(&self) -> Result<Vec<PendingCard>, String> {
+        Ok(self.data()?.pending_cards)
+    }
+
+    pub fn pending_card(&self, uid: &str) -> Option<PendingCard> {
+        self.data()
+            .ok()?
+            .pending_cards
+            .into_iter()
+            .find(|c| c.uid == uid)
+    }
+
+    // Takes the card off the waiting list, adding it to its user when approved
+    pub fn review_card(&self, uid: &str, approve: bool) -> Result<PendingCard, String> {
+        self.reload()?;
+
+        let card = {
+            let mut data = self.store.borrow_data_mut().map_err(|e| format!("{:?}", e))?;
+            let i = data
+                .pending_cards
+                .iter()
+                .position(|c| c.uid == uid)
+                .ok_or_else(|| format!("no card {} is waiting for approval", uid))?;
+            let card = data.pending_cards.remove(i);
+            if approve {
+                let user = data
+                    .users
+                    .get_mut(&card.user)
+                    .ok_or_else(|| format!("user {} no longer exists", card.user))?;
+                user.add_card(&card.uid, &card.name, Utc::now());
+            }
+            card
+        };
+
+        self.persist()?;
+        Ok(card)
+    }
+
+    pub fn delete_card(&self, id: &str, name_or_id: CardNameOrID) -> Result<(), String> {
+        let mut data = self.store.borrow_data_mut().map_err(|e| format!("{:?}", e))?;
+        let user = data
+            .users
+            .get_mut(id)
+            .ok_or_else(|| String::from("This user does not exist."))?;
+
+        match user.cards.as_ref() {
+            Some(c) => {
+                let (uid, _) = c
+                    .iter()
+                    .find(|(uid, name)| match &name_or_id {
+                        CardNameOrID::ID(id) => uid == id,
+                        CardNameOrID::Name(username) => name == username,
+                    })
+                    .cloned()
+                    .ok_or_else(|| String::from("Error, no card found with that name or ID"))?;
+
+                user.remove_card(&uid, Utc::now());
+            }
+            None => return Err(String::from("Error, no cards to delete")),
+        }
+
+        drop(data);
+
+        self.persist()?;
+
+        Ok(())
+    }
+}
+
+pub enum CardNameOrID {
+    Name(String),
+    ID(String),
+}
+
+fn purchase(
+    cart: &crate::cart::Cart,
+    tendered: Option<Tendered>,
+    user_discount: u32,
+    rounding: i32,
+) -> TransactionType {
+    TransactionType::Purchase {
+        products: cart.products.clone(),
+        total: cart.total() - user_discount,
+        promo: cart.promo.as_ref().map(|p| p.code.clone()),
+        discount: cart.discount(),
+        tendered,
+        combos: cart.combos.clone(),
+        user_discount,
+        rounding,
+        manual_discount: cart.manual_discount.clone().map(|m| ManualDiscount {
+            amount: cart.manual_discount_amount(),
+            ..m
+        }),
+    }
+}
+
+fn is_zero<T: Default + PartialEq>(n: &T) -> bool {
+    *n == T::default()
+}
+
+// PINs are short enough to try every one, so each guess has to be slow and memory hungry
+fn hash_pin(pin: &str) -> Result<String, String> {
+    use argon2::password_hash::{PasswordHasher, SaltString};
+    let salt = SaltString::encode_b64(Uuid::new_v4().as_bytes()).map_err(|e| format!("{}", e))?;
+    argon2::Argon2::default()
+        .hash_password(pin.as_bytes(), &salt)
+        .map(|h| h.to_string())
+        .map_err(|e| format!("cannot hash PIN {}", e))
+}
+
+fn legacy_hash_pin(salt: &str, pin: &str) -> String {
+    use sha2::{Digest, Sha256};
+    hex::encode(Sha256::digest(format!("{}{}", salt, pin)))
+}
+
+// Tokens are long and random, so they don't need salting like PINs do
+fn hash_token(token: &str) -> String {
+    use sha2::{Digest, Sha256};
+    hex::encode(Sha256::digest(token))
+}
+
+// Writes a transaction into a push target's inbox, via a temporary file so the
+// receiving till never reads a partial write
+fn deliver(target: &str, t: &Transaction) -> Result<(), String> {
+    let name = format!(
+        "{:020}-{}.ron",
+        t.timestamp.timestamp_nanos_opt().unwrap_or_default(),
+        t.id
+    );
+    let target = std::path::Path::new(target);
+    let tmp_path = target.join(format!(".{}.tmp", t.id));
+
+    let raw = ron::to_string(t).map_err(|e| format!("{}", e))?;
+    std::fs::write(&tmp_path, raw).map_err(|e| format!("{}", e))?;
+    std::fs::rename(&tmp_path, target.join(name)).map_err(|e| format!("{}", e))
+}
@@ -0,0 +1,13 @@
+#[macro_use]
+extern crate serde;
+
+pub mod barcode;
+pub mod cart;
+pub mod combo;
+pub mod db;
+pub mod money;
+pub mod paths;
+pub mod products;
+pub mod promo;
+pub mod stock;
+pub mod style;
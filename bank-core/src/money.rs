@@ -0,0 +1,123 @@
+use std::sync::OnceLock;
+
+static FORMAT: OnceLock<MoneyFormat> = OnceLock::new();
+
+// How amounts are written, e.g. (locale: "de-DE") for "1.234,50 £", or (decimal: ',').
+// Anything left unset follows the locale, and with no locale it's "£1234.50" as always.
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct MoneyFormat {
+    pub symbol: Option<String>,
+    pub decimal: Option<char>,
+    // Between each group of three digits, e.g. ','
+    pub thousands: Option<char>,
+    // Where the symbol goes and what separates the digits, e.g. "fr-FR" or "nl"
+    #[serde(deserialize_with = "known_locale")]
+    pub locale: Option<String>,
+}
+
+// What's usual for a locale
+struct Convention {
+    decimal: char,
+    thousands: Option<char>,
+    symbol_after: bool,
+}
+
+const UNSET: Convention = Convention {
+    decimal: '.',
+    thousands: None,
+    symbol_after: false,
+};
+
+// Only the language matters, e.g. "de" for "de_AT.UTF-8"
+fn convention(locale: &str) -> Option<Convention> {
+    let language = locale.split(['-', '_', '.']).next().unwrap_or_default().to_lowercase();
+    let (decimal, thousands, symbol_after) = match language.as_str() {
+        "en" | "ga" | "cy" | "gd" => ('.', ',', false),
+        "nl" => (',', '.', false),
+        "de" | "es" | "it" | "pt" | "da" | "el" => (',', '.', true),
+        "fr" | "sv" | "nb" | "nn" | "no" | "fi" | "pl" | "cs" => (',', ' ', true),
+        _ => return None,
+    };
+    Some(Convention {
+        decimal,
+        thousands: Some(thousands),
+        symbol_after,
+    })
+}
+
+fn known_locale<'de, D: serde::Deserializer<'de>>(d: D) -> Result<Option<String>, D::Error> {
+    <Option<String> as serde::Deserialize>::deserialize(d)?
+        .map(|l| match convention(&l) {
+            Some(_) => Ok(l),
+            None => Err(format!("no currency format known for locale {}", l)),
+        })
+        .transpose()
+        .map_err(serde::de::Error::custom)
+}
+
+impl MoneyFormat {
+    // In pence, e.g. -150 as "-£1.50"
+    pub fn format(&self, pence: i64) -> String {
+        let usual = self.locale.as_deref().and_then(convention).unwrap_or(UNSET);
+        let symbol = self.symbol();
+        let decimal = self.decimal();
+        let thousands = self.thousands.or(usual.thousands);
+
+        let pounds = (pence.unsigned_abs() / 100).to_string();
+        let mut whole = String::new();
+        for (i, digit) in pounds.chars().enumerate() {
+            if i > 0 && (pounds.len() - i).is_multiple_of(3) {
+                whole.extend(thousands);
+            }
+            whole.push(digit);
+        }
+        let amount = format!("{}{}{:02}", whole, decimal, pence.unsigned_abs() % 100);
+        let sign = if pence < 0 { "-" } else { "" };
+        if usual.symbol_after {
+            format!("{}{} {}", sign, amount, symbol)
+        } else {
+            format!("{}{}{}", sign, symbol, amount)
+        }
+    }
+
+    pub fn symbol(&self) -> &str {
+        self.symbol.as_deref().unwrap_or("£")
+    }
+
+    pub fn decimal(&self) -> char {
+        let usual = self.locale.as_deref().and_then(convention).unwrap_or(UNSET);
+        self.decimal.unwrap_or(usual.decimal)
+    }
+
+    // For changes, e.g. 150 as "+£1.50"
+    pub fn format_signed(&self, pence: i64) -> String {
+        match pence > 0 {
+            true => format!("+{}", self.format(pence)),
+            false => self.format(pence),
+        }
+    }
+}
+
+// Only the first call has any effect, like the theme
+pub fn set_format(format: MoneyFormat) {
+    let _ = FORMAT.set(format);
+}
+
+// Everywhere money is shown goes through here, in pence
+pub fn format(pence: impl TryInto<i64>) -> String {
+    FORMAT.get_or_init(MoneyFormat::default).format(pence.try_into().unwrap_or(i64::MAX))
+}
+
+// What amounts are written with, so typed ones can be read back the same way
+pub fn symbol() -> &'static str {
+    FORMAT.get_or_init(MoneyFormat::default).symbol()
+}
+
+pub fn decimal() -> char {
+    FORMAT.get_or_init(MoneyFormat::default).decimal()
+}
+
+pub fn format_signed(pence: impl TryInto<i64>) -> String {
+    FORMAT.get_or_init(MoneyFormat::default).format_signed(pence.try_into().unwrap_or(i64::MAX))
+}
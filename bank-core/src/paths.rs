@@ -0,0 +1,36 @@
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+// Where the database, products, config and everything else live, set once at startup
+static DATA_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+pub fn set_data_dir(dir: PathBuf) {
+    let _ = DATA_DIR.set(dir);
+}
+
+pub fn data_dir() -> &'static Path {
+    DATA_DIR.get().map(PathBuf::as_path).unwrap_or(Path::new("./data"))
+}
+
+// A file or directory inside the data directory, e.g. data_path("db")
+pub fn data_path(name: &str) -> PathBuf {
+    data_dir().join(name)
+}
+
+// Without --data-dir: systemd's StateDirectory=, then ./data if it's there like it always used to
+// be, then $XDG_DATA_HOME/57bank, falling back to ~/.local/share/57bank
+pub fn default_data_dir(env: impl Fn(&str) -> Option<String>, cwd_has_data: bool) -> PathBuf {
+    let env = |name| env(name).filter(|v: &String| !v.is_empty());
+    // systemd lists one directory per StateDirectory= entry, separated by colons
+    if let Some(state) = env("STATE_DIRECTORY") {
+        return PathBuf::from(state.split(':').next().unwrap_or_default());
+    }
+    if cwd_has_data {
+        return PathBuf::from("./data");
+    }
+    match (env("XDG_DATA_HOME"), env("HOME")) {
+        (Some(xdg), _) => Path::new(&xdg).join("57bank"),
+        (None, Some(home)) => Path::new(&home).join(".local/share/57bank"),
+        (None, None) => PathBuf::from("./data"),
+    }
+}
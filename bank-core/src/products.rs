@@ -0,0 +1,342 @@
+pub type Products = std::collections::HashMap<crate::barcode::Barcode, Product>;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Product {
+    pub barcode: crate::barcode::Barcode,
+    pub name: String,
+    pub price: u32,
+    // Tax category, recorded with each sale so reports use the rate at the time
+    #[serde(default)]
+    pub tax: Option<String>,
+    // Used to match meal-deal combos, e.g. "drink"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+    // Set on a cart line sold at a one-off price, e.g. damaged goods
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price_override: Option<PriceOverride>,
+    // Priced when it's sold, e.g. donated odds-and-ends, written as "open" in the products file
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub open_price: bool,
+    // Short code that can be typed instead of the barcode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub plu: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PriceOverride {
+    // The price from the product store, before the override
+    pub original: u32,
+    pub reason: String,
+    pub admin: String,
+}
+
+impl Product {
+    pub fn disp_price(&self) -> String {
+        if self.open_price {
+            return "any price".to_string();
+        }
+        crate::money::format(self.price)
+    }
+
+    // A cart line for an open-price product, with what it actually was if given
+    pub fn open_line(&self, price: u32, what: Option<&str>) -> Product {
+        let mut line = self.clone();
+        line.price = price;
+        line.open_price = false;
+        if let Some(what) = what.filter(|w| !w.is_empty()) {
+            line.name = format!("{}: {}", self.name, what);
+        }
+        line
+    }
+
+    // Name and price as shown on a cart or receipt line
+    pub fn disp_line(&self) -> String {
+        match &self.price_override {
+            Some(o) => format!(
+                "{} ({}, was {}: {})",
+                self.name,
+                self.disp_price(),
+                crate::money::format(o.original),
+                o.reason
+            ),
+            None => format!("{} ({})", self.name, self.disp_price()),
+        }
+    }
+}
+
+pub fn read_products() -> Result<Products, String> {
+    let products_raw = match std::fs::read(crate::paths::data_path("products")) {
+        Ok(p) => p,
+        Err(e) => return Err(format!("cannot open products file {}", e)),
+    };
+    let products_str = match String::from_utf8(products_raw) {
+        Ok(p) => p,
+        Err(e) => return Err(format!("cannot parse products file {}", e)),
+    };
+    parse_products(&products_str)
+}
+
+pub fn parse_products(products_str: &str) -> Result<Products, String> {
+    let mut products = std::collections::HashMap::new();
+
+    let product_lines = products_str
+        .split("\n")
+        .filter(|l| !l.trim().is_empty() && l.chars().nth(0).unwrap() != '#')
+        .collect::<Vec<_>>();
+
+    for line in product_lines {
+        let mut left = line.clone();
+
+        let mut take_part = || match left.split_once(" ") {
+            Some(v) => {
+                left = v.1;
+                Ok(v.0.trim())
+            },
+            None => return Err(format!("invalid line {}", line))
+        };
+
+        let barcode = take_part()?;
+        let price = take_part()?;
+
+        // Optional key=value attributes between the price and the descriptor
+        let (mut tax, mut category, mut plu) = (None, None, None);
+        while let Some((attr, rest)) = left.split_once(" ") {
+            match attr.split_once("=") {
+                Some(("tax", v)) => tax = Some(v.to_string()),
+                Some(("category", v)) => category = Some(v.to_string()),
+                Some(("plu", v)) => plu = Some(v.to_string()),
+                _ => break,
+            }
+            left = rest;
+        }
+        let descriptor = left;
+
+        let barcode = match crate::barcode::Barcode::try_parse(barcode) {
+            Some(d) => d,
+            None => return Err(format!("invalid barcode {}", barcode))
+        };
+
+        let open_price = price == "open";
+        let price = match u32::from_str_radix(price, 10) {
+            Ok(p) => p,
+            Err(_) if open_price => 0,
+            Err(e) => return Err(format!("invalid price {}", e))
+        };
+
+        products.insert(barcode.clone(), Product {
+            name: descriptor.to_string(),
+            price,
+            barcode,
+            tax,
+            category,
+            price_override: None,
+            open_price,
+            plu,
+        });
+    }
+
+    Ok(products)
+}
+
+// Found by normalised GTIN, so it doesn't matter how many leading zeros the code has
+pub fn lookup<'a>(products: &'a Products, code: &str) -> Option<&'a Product> {
+    products.get(&crate::barcode::Barcode::try_parse(code)?)
+}
+
+pub fn by_plu<'a>(products: &'a Products, code: &str) -> Option<&'a Product> {
+    products.values().find(|p| p.plu.as_deref() == Some(code))
+}
+
+// Reads "<barcode> <name>" lines of product names in another language
+pub fn parse_translations(
+    translations: &str,
+) -> Result<std::collections::HashMap<crate::barcode::Barcode, String>, String> {
+    let mut names = std::collections::HashMap::new();
+    for line in translations.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (barcode, name) = match line.split_once(' ') {
+            Some((b, n)) if !n.trim().is_empty() => (b, n.trim()),
+            _ => return Err(format!("invalid line {}", line)),
+        };
+        match crate::barcode::Barcode::try_parse(barcode) {
+            Some(b) => names.insert(b, name.to_string()),
+            None => return Err(format!("invalid barcode {}", barcode)),
+        };
+    }
+    Ok(names)
+}
+
+// Translated names from products.<locale> in the data directory, e.g. products.de_DE, falling back to just the
+// language, e.g. products.de. None if there's no file for the locale at all.
+pub fn read_translations(
+    locale: &str,
+) -> Result<Option<std::collections::HashMap<crate::barcode::Barcode, String>>, String> {
+    // Drop any encoding, e.g. "de_DE.UTF-8"
+    let locale = locale.split(['.', '@']).next().unwrap_or_default();
+    let language = locale.split('_').next().unwrap_or_default();
+    for name in [locale, language] {
+        if name.is_empty() {
+            continue;
+        }
+        match std::fs::read_to_string(crate::paths::data_path(&format!("products.{}", name))) {
+            Ok(t) => return parse_translations(&t).map(Some),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(format!("cannot open translations file {}", e)),
+        }
+    }
+    Ok(None)
+}
+
+// Products without a translation keep the name from the products file
+pub fn translate(
+    products: &mut Products,
+    names: &std::collections::HashMap<crate::barcode::Barcode, String>,
+) {
+    for (barcode, product) in products.iter_mut() {
+        if let Some(name) = names.get(barcode) {
+            product.name = name.clone();
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ProductImport {
+    // Barcode as written in the CSV, and the product it describes
+    pub products: Vec<(String, Product)>,
+    // Lines that couldn't be imported, and why
+    pub errors: Vec<String>,
+}
+
+// Reads "<barcode>,<price in pounds>,<name>[,<category>[,<tax category>]]" lines, with an
+// optional header line. Bad lines are reported rather than stopping the whole import.
+pub fn parse_products_csv(csv: &str) -> ProductImport {
+    let mut import = ProductImport::default();
+    for (i, line) in csv.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields = line.split(',').map(|f| f.trim().trim_matches('"')).collect::<Vec<_>>();
+        if i == 0 && fields[0] == "barcode" {
+            continue;
+        }
+
+        match parse_csv_fields(&fields) {
+            Ok(_) if import.products.iter().any(|(code, _)| code == fields[0]) => {
+                import.errors.push(format!("line {}: barcode {} appears twice", i + 1, fields[0]))
+            }
+            Ok(product) => import.products.push((fields[0].to_string(), product)),
+            Err(e) => import.errors.push(format!("line {}: {}", i + 1, e)),
+        }
+    }
+    import
+}
+
+fn parse_csv_fields(fields: &[&str]) -> Result<Product, String> {
+    if fields.len() < 3 || fields.len() > 5 {
+        return Err("expected barcode, price, name, and optionally category and tax".to_string());
+    }
+    let barcode = match crate::barcode::Barcode::try_parse(fields[0]) {
+        Some(b) if b.check_digit() => b,
+        Some(_) => return Err(format!("barcode {} has the wrong check digit", fields[0])),
+        None => return Err(format!("invalid barcode {}", fields[0])),
+    };
+    let price = match fields[1].trim_start_matches('£').parse::<f64>() {
+        Ok(p) if p >= 0.0 => (p * 100.0).round() as u32,
+        _ => return Err(format!("invalid price {}", fields[1])),
+    };
+    if fields[2].is_empty() {
+        return Err("missing name".to_string());
+    }
+    let optional = |i: usize| fields.get(i).filter(|f| !f.is_empty()).map(|f| f.to_string());
+
+    Ok(Product {
+        barcode,
+        name: fields[2].to_string(),
+        price,
+        tax: optional(4),
+        category: optional(3),
+        price_override: None,
+        open_price: false,
+        plu: None,
+    })
+}
+
+// A line for the products file
+fn product_line(code: &str, product: &Product) -> String {
+    let mut line = match product.open_price {
+        true => format!("{} open", code),
+        false => format!("{} {}", code, product.price),
+    };
+    if let Some(tax) = &product.tax {
+        line.push_str(&format!(" tax={}", tax));
+    }
+    if let Some(category) = &product.category {
+        line.push_str(&format!(" category={}", category));
+    }
+    if let Some(plu) = &product.plu {
+        line.push_str(&format!(" plu={}", plu));
+    }
+    format!("{} {}", line, product.name)
+}
+
+#[derive(Debug, Default)]
+pub struct MergeSummary {
+    pub inserted: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+}
+
+// Updates matching lines of a products file in place, keeping comments and ordering, and adds
+// new products at the end
+pub fn merge_products(products_str: &str, imports: &[(String, Product)]) -> (String, MergeSummary) {
+    let mut summary = MergeSummary::default();
+    let mut merged = vec![false; imports.len()];
+    let mut out = String::new();
+
+    for line in products_str.lines() {
+        let existing = parse_products(line).ok().and_then(|p| p.into_values().next());
+        let import = existing.as_ref().and_then(|e| {
+            imports.iter().position(|(_, p)| p.barcode == e.barcode)
+        });
+        match (existing, import) {
+            (Some(existing), Some(i)) => {
+                merged[i] = true;
+                let new = &imports[i].1;
+                if existing.name == new.name
+                    && existing.price == new.price
+                    && existing.tax == new.tax
+                    && existing.category == new.category
+                {
+                    summary.unchanged += 1;
+                    out.push_str(line);
+                } else {
+                    summary.updated += 1;
+                    // Keep the barcode as it was already written
+                    out.push_str(&product_line(line.split(' ').next().unwrap(), new));
+                }
+            }
+            _ => out.push_str(line),
+        }
+        out.push('\n');
+    }
+
+    for ((code, product), _) in imports.iter().zip(merged).filter(|(_, m)| !m) {
+        summary.inserted += 1;
+        out.push_str(&product_line(code, product));
+        out.push('\n');
+    }
+    (out, summary)
+}
+
+pub fn write_products(products_str: &str) -> Result<(), String> {
+    // Written alongside then moved into place, so a crash never leaves half a file
+    let path = crate::paths::data_path("products");
+    std::fs::write(path.with_extension("tmp"), products_str)
+        .map_err(|e| format!("cannot write products file {}", e))?;
+    std::fs::rename(path.with_extension("tmp"), path)
+        .map_err(|e| format!("cannot write products file {}", e))
+}
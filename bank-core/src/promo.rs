@@ -0,0 +1,79 @@
+use chrono::prelude::*;
+
+// A code that can be entered while a cart is in progress for money off
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Promo {
+    pub code: String,
+    pub discount: Discount,
+    // How many times it can be used in total, or unlimited
+    pub max_uses: Option<u32>,
+    pub uses: u32,
+    pub expires: Option<DateTime<Utc>>,
+    // So removing a code on one till doesn't take away one added again since on another
+    #[serde(default)]
+    pub added: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub enum Discount {
+    Percent(u8),
+    // In pence
+    Fixed(u32),
+}
+
+impl std::str::FromStr for Discount {
+    type Err = String;
+
+    // "10%" for a percentage off, or "0.50" for a fixed amount off
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_suffix('%') {
+            Some(p) => match p.parse::<u8>() {
+                Ok(p) if p > 0 && p <= 100 => Ok(Self::Percent(p)),
+                _ => Err(format!("invalid percentage {}", s)),
+            },
+            None => match s.trim_start_matches('£').parse::<f64>() {
+                Ok(a) if a > 0.0 => Ok(Self::Fixed((a * 100.0).round() as u32)),
+                _ => Err(format!("invalid discount {}", s)),
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for Discount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Percent(p) => write!(f, "{}% off", p),
+            Self::Fixed(a) => write!(f, "{} off", crate::money::format(*a)),
+        }
+    }
+}
+
+impl Discount {
+    // Never takes more off than the cart is worth
+    pub fn on(&self, subtotal: u32) -> u32 {
+        match self {
+            Discount::Percent(p) => subtotal * *p as u32 / 100,
+            Discount::Fixed(a) => (*a).min(subtotal),
+        }
+    }
+}
+
+impl Promo {
+    pub fn discount_on(&self, subtotal: u32) -> u32 {
+        self.discount.on(subtotal)
+    }
+
+    pub fn check_usable(&self, now: DateTime<Utc>) -> Result<(), String> {
+        if self.expires.is_some_and(|e| e < now) {
+            return Err(format!("promo code {} has expired", self.code));
+        }
+        if self.max_uses.is_some_and(|m| self.uses >= m) {
+            return Err(format!("promo code {} has been used up", self.code));
+        }
+        Ok(())
+    }
+}
+
+pub fn normalise_code(code: &str) -> String {
+    code.trim().to_uppercase()
+}
@@ -0,0 +1,176 @@
+use crate::barcode::Barcode;
+use crate::db::{Transaction, TransactionType};
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+
+// How many of each product should be on the shelves, going by what's been restocked, sold, and
+// written off, as of the last stocktake. Products never restocked or counted aren't tracked.
+pub fn levels(transactions: &[Transaction]) -> HashMap<Barcode, i64> {
+    let mut levels = HashMap::<Barcode, i64>::new();
+    for t in transactions {
+        match &t.transaction {
+            TransactionType::Stocktake { product, counted } => {
+                levels.insert(product.barcode.clone(), *counted as i64);
+            }
+            TransactionType::Restock { product, quantity, .. } => {
+                *levels.entry(product.barcode.clone()).or_default() += *quantity as i64;
+            }
+            TransactionType::Purchase { products, .. } => {
+                for p in products {
+                    if let Some(level) = levels.get_mut(&p.barcode) {
+                        *level -= 1;
+                    }
+                }
+            }
+            TransactionType::Void { products, .. } => {
+                for p in products {
+                    if let Some(level) = levels.get_mut(&p.barcode) {
+                        *level += 1;
+                    }
+                }
+            }
+            TransactionType::WriteOff { product, quantity, .. } => {
+                if let Some(level) = levels.get_mut(&product.barcode) {
+                    *level -= *quantity as i64;
+                }
+            }
+            _ => {}
+        }
+    }
+    levels
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Movement {
+    pub intake: u32,
+    pub sold: u32,
+    pub written_off: u32,
+    // How many fewer were counted at stocktakes than there should have been. Negative if
+    // more turned up than expected.
+    pub missing: i64,
+}
+
+// What happened to each product between `start` and `end`. Levels are tracked from the start of
+// the log, so a stocktake in the period is compared against everything that came before it.
+pub fn movements(
+    transactions: &[Transaction],
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> HashMap<Barcode, Movement> {
+    let mut levels = HashMap::<Barcode, i64>::new();
+    let mut movements = HashMap::<Barcode, Movement>::new();
+    for t in transactions.iter().take_while(|t| t.timestamp < end) {
+        let in_period = t.timestamp >= start;
+        match &t.transaction {
+            TransactionType::Restock { product, quantity, .. } => {
+                *levels.entry(product.barcode.clone()).or_default() += *quantity as i64;
+                if let Some(m) = movement(&mut movements, in_period, &product.barcode) {
+                    m.intake += quantity;
+                }
+            }
+            TransactionType::Purchase { products, .. } => {
+                for p in products {
+                    if let Some(level) = levels.get_mut(&p.barcode) {
+                        *level -= 1;
+                    }
+                    if let Some(m) = movement(&mut movements, in_period, &p.barcode) {
+                        m.sold += 1;
+                    }
+                }
+            }
+            TransactionType::Void { products, .. } => {
+                for p in products {
+                    if let Some(level) = levels.get_mut(&p.barcode) {
+                        *level += 1;
+                    }
+                    if let Some(m) = movement(&mut movements, in_period, &p.barcode) {
+                        m.sold = m.sold.saturating_sub(1);
+                    }
+                }
+            }
+            TransactionType::WriteOff { product, quantity, .. } => {
+                if let Some(level) = levels.get_mut(&product.barcode) {
+                    *level -= *quantity as i64;
+                }
+                if let Some(m) = movement(&mut movements, in_period, &product.barcode) {
+                    m.written_off += quantity;
+                }
+            }
+            TransactionType::Stocktake { product, counted } => {
+                let expected = levels.insert(product.barcode.clone(), *counted as i64);
+                if let Some(m) = movement(&mut movements, in_period, &product.barcode) {
+                    // The first count of something is where tracking starts, not a loss
+                    if let Some(expected) = expected {
+                        m.missing += expected - *counted as i64;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    movements
+}
+
+fn movement<'a>(
+    movements: &'a mut HashMap<Barcode, Movement>,
+    in_period: bool,
+    barcode: &Barcode,
+) -> Option<&'a mut Movement> {
+    in_period.then(|| movements.entry(barcode.clone()).or_default())
+}
+
+// Units of each product sold since the given time
+pub fn sold_since(transactions: &[Transaction], since: DateTime<Utc>) -> HashMap<Barcode, u32> {
+    let mut sold = HashMap::<Barcode, u32>::new();
+    for t in transactions.iter().filter(|t| t.timestamp >= since) {
+        match &t.transaction {
+            TransactionType::Purchase { products, .. } => {
+                for p in products {
+                    *sold.entry(p.barcode.clone()).or_default() += 1;
+                }
+            }
+            TransactionType::Void { products, .. } => {
+                for p in products {
+                    if let Some(n) = sold.get_mut(&p.barcode) {
+                        *n = n.saturating_sub(1);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    sold
+}
+
+// Total spent with each supplier on restocks with a known cost, in pence
+pub fn spend_by_supplier(transactions: &[Transaction]) -> HashMap<String, u64> {
+    let mut spend = HashMap::<String, u64>::new();
+    for t in transactions {
+        if let TransactionType::Restock {
+            quantity,
+            cost: Some(cost),
+            supplier: Some(supplier),
+            ..
+        } = &t.transaction
+        {
+            *spend.entry(supplier.clone()).or_default() += *cost as u64 * *quantity as u64;
+        }
+    }
+    spend
+}
+
+// What each product cost per unit the last time it was bought in, in pence
+pub fn last_costs(transactions: &[Transaction]) -> HashMap<Barcode, u32> {
+    let mut costs = HashMap::new();
+    for t in transactions {
+        if let TransactionType::Restock {
+            product,
+            cost: Some(cost),
+            ..
+        } = &t.transaction
+        {
+            costs.insert(product.barcode.clone(), *cost);
+        }
+    }
+    costs
+}
@@ -0,0 +1,156 @@
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+
+pub use ansi_term::Color;
+
+// Set once at startup, for terminals and screen readers that can't cope with ANSI escapes
+static PLAIN: AtomicBool = AtomicBool::new(false);
+
+pub fn set_plain(plain: bool) {
+    PLAIN.store(plain, Ordering::Relaxed);
+}
+
+pub fn plain() -> bool {
+    PLAIN.load(Ordering::Relaxed)
+}
+
+static THEME: OnceLock<Theme> = OnceLock::new();
+
+// Colours used across the interface, e.g. (warning: "purple", negative: "208", cart: "#ffd700").
+// Takes colour names, 256-colour numbers, or #rrggbb.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct Theme {
+    #[serde(deserialize_with = "colour")]
+    pub warning: Color,
+    // Balances below zero
+    #[serde(deserialize_with = "colour")]
+    pub negative: Color,
+    // The 57Bank> prompt, in the terminal's own colour if unset
+    #[serde(deserialize_with = "optional_colour")]
+    pub prompt: Option<Color>,
+    // Background of the read-only and training mode banners
+    #[serde(deserialize_with = "colour")]
+    pub mode: Color,
+    // Background of the cart in progress banner
+    #[serde(deserialize_with = "colour")]
+    pub cart: Color,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            warning: Color::Red,
+            negative: Color::Red,
+            prompt: None,
+            mode: Color::Blue,
+            cart: Color::Yellow,
+        }
+    }
+}
+
+// Only the first call has any effect, the theme is fixed once set
+pub fn set_theme(theme: Theme) {
+    let _ = THEME.set(theme);
+}
+
+pub fn theme() -> &'static Theme {
+    THEME.get_or_init(Theme::default)
+}
+
+pub fn parse_colour(colour: &str) -> Result<Color, String> {
+    if let Some(hex) = colour.strip_prefix('#') {
+        let rgb = u32::from_str_radix(hex, 16)
+            .ok()
+            .filter(|_| hex.len() == 6)
+            .ok_or_else(|| format!("invalid colour {}", colour))?;
+        return Ok(Color::RGB((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8));
+    }
+    if let Ok(n) = colour.parse::<u8>() {
+        return Ok(Color::Fixed(n));
+    }
+    Ok(match colour.to_lowercase().as_str() {
+        "black" => Color::Black,
+        "red" => Color::Red,
+        "green" => Color::Green,
+        "yellow" => Color::Yellow,
+        "blue" => Color::Blue,
+        "purple" | "magenta" => Color::Purple,
+        "cyan" => Color::Cyan,
+        "white" => Color::White,
+        _ => return Err(format!("invalid colour {}", colour)),
+    })
+}
+
+fn colour<'de, D: serde::Deserializer<'de>>(d: D) -> Result<Color, D::Error> {
+    parse_colour(&<String as serde::Deserialize>::deserialize(d)?).map_err(serde::de::Error::custom)
+}
+
+fn optional_colour<'de, D: serde::Deserializer<'de>>(d: D) -> Result<Option<Color>, D::Error> {
+    <Option<String> as serde::Deserialize>::deserialize(d)?
+        .map(|c| parse_colour(&c))
+        .transpose()
+        .map_err(serde::de::Error::custom)
+}
+
+// ansi_term's Style, but painting nothing in plain mode
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Style {
+    inner: ansi_term::Style,
+    underline: bool,
+}
+
+impl Style {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn bold(self) -> Self {
+        Self {
+            inner: self.inner.bold(),
+            ..self
+        }
+    }
+
+    pub fn underline(self) -> Self {
+        Self {
+            inner: self.inner.underline(),
+            underline: true,
+        }
+    }
+
+    pub fn fg_opt(self, color: Option<Color>) -> Self {
+        match color {
+            Some(c) => self.fg(c),
+            None => self,
+        }
+    }
+
+    pub fn fg(self, color: Color) -> Self {
+        Self {
+            inner: self.inner.fg(color),
+            ..self
+        }
+    }
+
+    pub fn on(self, color: Color) -> Self {
+        Self {
+            inner: self.inner.on(color),
+            ..self
+        }
+    }
+
+    pub fn paint<'a, I: Into<Cow<'a, str>>>(self, input: I) -> ansi_term::ANSIString<'a> {
+        if !plain() {
+            return self.inner.paint(input);
+        }
+        // Underlines mark headings, so keep them recognisable when read out
+        let input = input.into();
+        if self.underline {
+            ansi_term::Style::default().paint(format!("{}:", input))
+        } else {
+            ansi_term::Style::default().paint(input)
+        }
+    }
+}
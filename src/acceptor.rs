@@ -0,0 +1,233 @@
+use crate::{config::AcceptorConfig, gpio, serial};
+use bank_core::money;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+
+const RETRY_DELAY: std::time::Duration = std::time::Duration::from_secs(5);
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(200);
+
+// ccTalk addresses and headers
+const HOST: u8 = 1;
+const ACK: u8 = 0;
+const SIMPLE_POLL: u8 = 254;
+const MODIFY_INHIBIT_STATUS: u8 = 231;
+const MODIFY_MASTER_INHIBIT: u8 = 228;
+const READ_BUFFERED_CREDIT: u8 = 229;
+
+// Money counted by the machine during a deposit, shared with the thread talking to it
+#[derive(Debug, Clone, Default)]
+pub struct Acceptor {
+    session: Arc<Mutex<Session>>,
+}
+
+#[derive(Debug, Default)]
+struct Session {
+    accepting: bool,
+    credited: u32,
+}
+
+impl Acceptor {
+    // Starts taking money for a deposit
+    pub fn start(&self) {
+        *self.session.lock().unwrap() = Session {
+            accepting: true,
+            credited: 0,
+        };
+    }
+
+    // Stops taking money, returning how much went in, in pence
+    pub fn finish(&self) -> u32 {
+        let mut session = self.session.lock().unwrap();
+        session.accepting = false;
+        std::mem::take(&mut session.credited)
+    }
+
+    fn accepting(&self) -> bool {
+        self.session.lock().unwrap().accepting
+    }
+
+    pub fn credit(&self, amount: u32) {
+        let mut session = self.session.lock().unwrap();
+        if session.accepting {
+            session.credited += amount;
+            println!(
+                "Inserted {}, {} so far",
+                money::format(amount),
+                money::format(session.credited)
+            );
+        } else {
+            // Most likely a pulse acceptor, which can't be told to refuse money
+            println!(
+                "\x07{} was inserted outside of a deposit, and hasn't been credited to anyone",
+                money::format(amount)
+            );
+        }
+    }
+}
+
+// Runs on its own thread, reconnecting whenever the machine goes away
+pub fn acceptor(config: AcceptorConfig, acceptor: Acceptor, stop: Arc<AtomicBool>) {
+    let mut failed = false;
+
+    while !stop.load(Ordering::Relaxed) {
+        let result = match &config {
+            AcceptorConfig::CcTalk { device, address, coins } => {
+                cctalk(device, *address, coins, &acceptor, &stop, &mut failed)
+            }
+            AcceptorConfig::Pulse { gpio, pence } => pulse(*gpio, *pence, &acceptor, &stop, &mut failed),
+        };
+        match result {
+            Ok(()) => return,
+            Err(e) => {
+                // Only report each outage once, rather than every retry
+                if !failed {
+                    println!("Coin acceptor error: {}, retrying in the background", e);
+                }
+                failed = true;
+                let retry_at = std::time::Instant::now() + RETRY_DELAY;
+                while std::time::Instant::now() < retry_at && !stop.load(Ordering::Relaxed) {
+                    std::thread::sleep(std::time::Duration::from_millis(100));
+                }
+            }
+        }
+    }
+}
+
+fn connected(failed: &mut bool) {
+    if *failed {
+        *failed = false;
+        println!("Coin acceptor reconnected");
+    }
+}
+
+fn cctalk(
+    device: &str,
+    address: u8,
+    coins: &HashMap<u8, u32>,
+    acceptor: &Acceptor,
+    stop: &AtomicBool,
+    failed: &mut bool,
+) -> Result<(), String> {
+    let mut port = serial::open(device, 9600)?;
+    request(&mut port, address, SIMPLE_POLL, &[])?;
+    request(&mut port, address, MODIFY_INHIBIT_STATUS, &[0xff, 0xff])?;
+    connected(failed);
+
+    let mut counter = None;
+    let mut inhibited = None;
+    while !stop.load(Ordering::Relaxed) {
+        // Refuse money, handing it straight back, unless there's a deposit going on
+        let accepting = acceptor.accepting();
+        if inhibited != Some(!accepting) {
+            request(&mut port, address, MODIFY_MASTER_INHIBIT, &[accepting as u8])?;
+            inhibited = Some(!accepting);
+        }
+
+        let events = request(&mut port, address, READ_BUFFERED_CREDIT, &[])?;
+        for coin in credits(&mut counter, &events) {
+            match coins.get(&coin) {
+                Some(value) => acceptor.credit(*value),
+                None => println!("\x07Coin in position {} has no value set in the config", coin),
+            }
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+    // Don't take anything more once we're gone
+    let _ = request(&mut port, address, MODIFY_MASTER_INHIBIT, &[0]);
+    Ok(())
+}
+
+// e.g. [2, 0, 1, 254, 255] for a simple poll of address 2, ending in a checksum that makes the
+// whole packet add up to zero
+pub fn packet(address: u8, header: u8, data: &[u8]) -> Vec<u8> {
+    let mut packet = vec![address, data.len() as u8, HOST, header];
+    packet.extend_from_slice(data);
+    let sum = packet.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+    packet.push(sum.wrapping_neg());
+    packet
+}
+
+// Sends a command and returns the data from the reply
+fn request(port: &mut std::fs::File, address: u8, header: u8, data: &[u8]) -> Result<Vec<u8>, String> {
+    let sent = packet(address, header, data);
+    port.write_all(&sent).map_err(|e| e.to_string())?;
+
+    // ccTalk is a single wire, so we hear ourselves first
+    let mut echo = vec![0; sent.len()];
+    read_exact(port, &mut echo)?;
+    let mut head = [0; 4];
+    read_exact(port, &mut head)?;
+    let mut rest = vec![0; head[1] as usize + 1];
+    read_exact(port, &mut rest)?;
+
+    let reply = [&head[..], &rest[..]].concat();
+    if reply.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+        return Err("corrupted reply".to_string());
+    }
+    if head[3] != ACK {
+        return Err(format!("command {} was refused", header));
+    }
+    Ok(rest[..rest.len() - 1].to_vec())
+}
+
+// The port gives up reading after a moment, which here means the machine isn't answering
+fn read_exact(port: &mut std::fs::File, buf: &mut [u8]) -> Result<(), String> {
+    let mut read = 0;
+    while read < buf.len() {
+        match port.read(&mut buf[read..]).map_err(|e| e.to_string())? {
+            0 => return Err("no reply".to_string()),
+            n => read += n,
+        }
+    }
+    Ok(())
+}
+
+// The coin positions credited since the last poll, from a buffered credit reply: an event
+// counter, then the five most recent events, newest first. The counter goes from 1 to 255
+// and round again, and is 0 after a reset.
+pub fn credits(last: &mut Option<u8>, reply: &[u8]) -> Vec<u8> {
+    let (&counter, events) = match reply.split_first() {
+        Some(reply) => reply,
+        None => return Vec::new(),
+    };
+    let new = match *last {
+        // Anything from before we were watching has already been dealt with, or not
+        None => 0,
+        Some(last) if counter >= last => (counter - last) as usize,
+        Some(last) => counter as usize + 255 - last as usize,
+    };
+    *last = Some(counter);
+
+    // More than five since the last poll and the oldest are lost
+    let mut coins = events
+        .chunks(2)
+        .take(new.min(5))
+        // A zero first byte is an error or nothing, rather than a coin
+        .filter(|event| event[0] != 0)
+        .map(|event| event[0])
+        .collect::<Vec<_>>();
+    coins.reverse();
+    coins
+}
+
+// Acceptors that send a pulse for every so many pence. A coin arrives as a quick burst of
+// them, so they're added up until things go quiet.
+fn pulse(number: u32, pence: u32, acceptor: &Acceptor, stop: &AtomicBool, failed: &mut bool) -> Result<(), String> {
+    let mut pin = gpio::Pin::input(number, "falling")?;
+    connected(failed);
+
+    let mut pulses = 0;
+    while !stop.load(Ordering::Relaxed) {
+        if pin.wait(POLL_INTERVAL)? {
+            pulses += 1;
+        } else if pulses > 0 {
+            acceptor.credit(pulses * pence);
+            pulses = 0;
+        }
+    }
+    Ok(())
+}
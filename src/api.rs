@@ -0,0 +1,375 @@
+use crate::{cli, config, labels::escape, live, reports};
+use bank_core::{barcode::Barcode, cart::Cart, db, money, products};
+use futures_util::{SinkExt, StreamExt};
+use hmac::{Hmac, Mac};
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::{Bytes, Incoming};
+use hyper::header::{AUTHORIZATION, CONNECTION, CONTENT_TYPE, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY, UPGRADE};
+use hyper::{Request, Response};
+use serde_json::json;
+use sha2::Sha256;
+use std::fmt::Write;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use tokio_tungstenite::tungstenite::{handshake::derive_accept_key, protocol, Message};
+use tokio_tungstenite::WebSocketStream;
+
+// Nothing the API takes comes anywhere near this
+const MAX_BODY: usize = 64 * 1024;
+// Transactions shown on a member's balance page
+const PAGE_HISTORY: usize = 50;
+
+#[derive(Deserialize)]
+struct PurchaseRequest {
+    user: String,
+    // Barcodes, once for each item
+    products: Vec<String>,
+    // Chosen by the client and sent again on a retry, so it's never charged twice
+    #[serde(default)]
+    key: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DepositRequest {
+    user: String,
+    // Pence
+    amount: u32,
+    method: db::DepositMethod,
+    note: Option<String>,
+    #[serde(default)]
+    key: Option<String>,
+}
+
+pub async fn serve(api: config::ApiConfig, db: Arc<db::DB>, config: config::Config) {
+    let listener = match tokio::net::TcpListener::bind(&api.listen).await {
+        Ok(l) => l,
+        Err(e) => {
+            println!("API error: unable to listen on {}: {}", api.listen, e);
+            return;
+        }
+    };
+    let config = Arc::new(config);
+    loop {
+        let stream = match listener.accept().await {
+            Ok((s, _)) => s,
+            Err(e) => {
+                println!("API error: {}", e);
+                continue;
+            }
+        };
+        let (db, config) = (Arc::clone(&db), Arc::clone(&config));
+        tokio::spawn(async move {
+            let service = hyper::service::service_fn(move |req| {
+                respond(Arc::clone(&db), Arc::clone(&config), req)
+            });
+            // Clients hanging up part way through aren't worth reporting
+            let _ = hyper::server::conn::http1::Builder::new()
+                .serve_connection(hyper_util::rt::TokioIo::new(stream), service)
+                .with_upgrades()
+                .await;
+        });
+    }
+}
+
+async fn respond(
+    db: Arc<db::DB>,
+    config: Arc<config::Config>,
+    req: Request<Incoming>,
+) -> Result<Response<Full<Bytes>>, hyper::Error> {
+    let method = req.method().to_string();
+    let path = req.uri().path().to_string();
+    if path.trim_end_matches('/') == "/api/live" {
+        return Ok(live_feed(&db, &config, req));
+    }
+    if let (true, Some(secret)) = (path.starts_with("/me/"), api_secret(&config)) {
+        let (status, html) = tokio::task::spawn_blocking(move || balance_page(&db, &secret, &path))
+            .await
+            .unwrap_or_else(|e| (500, escape(&e.to_string())));
+        return Ok(Response::builder()
+            .status(status)
+            .header(CONTENT_TYPE, "text/html; charset=utf-8")
+            .body(Full::new(Bytes::from(html)))
+            .unwrap());
+    }
+    let auth = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .map(str::to_string);
+    let (status, body) = match Limited::new(req.into_body(), MAX_BODY).collect().await {
+        Ok(body) => {
+            let body = body.to_bytes();
+            tokio::task::spawn_blocking(move || {
+                // Read fresh, so products added at the till can be bought straight away
+                let products = crate::load_products(&config).unwrap_or_default();
+                handle(&db, &config, &products, &method, &path, auth.as_deref(), &body)
+            })
+            .await
+            .unwrap_or_else(|e| (500, json!({ "error": e.to_string() })))
+        }
+        Err(_) => (413, json!({ "error": "request too large" })),
+    };
+    Ok(json_response(status, body))
+}
+
+fn json_response(status: u16, body: serde_json::Value) -> Response<Full<Bytes>> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Full::new(Bytes::from(body.to_string())))
+        .unwrap()
+}
+
+// Upgrades to a WebSocket sending each purchase, deposit, and card tap as a text message.
+// Browsers can't set headers on a WebSocket, so the token can be given as ?token= instead.
+fn live_feed(db: &db::DB, config: &config::Config, mut req: Request<Incoming>) -> Response<Full<Bytes>> {
+    let query_token = req
+        .uri()
+        .query()
+        .and_then(|q| q.split('&').find_map(|p| p.strip_prefix("token=")))
+        .map(|t| format!("Bearer {}", t));
+    let auth = req.headers().get(AUTHORIZATION).and_then(|h| h.to_str().ok()).map(str::to_string);
+    if let Err((status, body)) = live_allowed(db, config, auth.or(query_token).as_deref()) {
+        return json_response(status, body);
+    }
+    let accept = match req.headers().get(SEC_WEBSOCKET_KEY) {
+        Some(key) => derive_accept_key(key.as_bytes()),
+        None => return json_response(400, json!({ "error": "expected a WebSocket upgrade" })),
+    };
+
+    // Listening before answering, so nothing is missed in between
+    let events = live::subscribe();
+    let upgrade = hyper::upgrade::on(&mut req);
+    tokio::spawn(async move {
+        if let Ok(upgraded) = upgrade.await {
+            let io = hyper_util::rt::TokioIo::new(upgraded);
+            let ws = WebSocketStream::from_raw_socket(io, protocol::Role::Server, None).await;
+            stream_events(ws, events).await;
+        }
+    });
+    Response::builder()
+        .status(101)
+        .header(CONNECTION, "upgrade")
+        .header(UPGRADE, "websocket")
+        .header(SEC_WEBSOCKET_ACCEPT, accept)
+        .body(Full::new(Bytes::new()))
+        .unwrap()
+}
+
+async fn stream_events<S>(mut ws: WebSocketStream<S>, mut events: broadcast::Receiver<String>)
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => {
+                    if ws.send(Message::text(event)).await.is_err() {
+                        return;
+                    }
+                }
+                // Too slow to keep up, carry on from the newest
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(_) => return,
+            },
+            // Pings are answered by tungstenite, anything else from the other end is ignored
+            message = ws.next() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+// The feed is everyone's activity, so tokens acting as a user need the same role as for
+// listing users
+pub fn live_allowed(db: &db::DB, config: &config::Config, auth: Option<&str>) -> Result<(), (u16, serde_json::Value)> {
+    let token = match auth.and_then(|a| a.strip_prefix("Bearer ")).and_then(|t| db.check_token(t)) {
+        Some(t) => t,
+        None => return Err((401, json!({ "error": "missing or invalid token" }))),
+    };
+    let allowed = match &token.user {
+        Some(id) => db.get_user(id).is_some_and(|(user, _)| config.role(&user) >= db::Role::Treasurer),
+        None => true,
+    };
+    match allowed {
+        true => Ok(()),
+        false => Err((403, json!({ "error": format!("needs the {} role", db::Role::Treasurer) }))),
+    }
+}
+
+// Everything but the HTTP, returning the status code and JSON body
+pub fn handle(
+    db: &db::DB,
+    config: &config::Config,
+    products: &products::Products,
+    method: &str,
+    path: &str,
+    auth: Option<&str>,
+    body: &[u8],
+) -> (u16, serde_json::Value) {
+    let needed = match (method, path) {
+        ("POST", "/api/purchase") => db::Scope::Purchase,
+        ("POST", "/api/deposit") => db::Scope::Admin,
+        ("GET", _) => db::Scope::ReadOnly,
+        _ => return (405, json!({ "error": "method not allowed" })),
+    };
+    let token = match auth.and_then(|a| a.strip_prefix("Bearer ")).and_then(|t| db.check_token(t)) {
+        Some(t) => t,
+        None => return (401, json!({ "error": "missing or invalid token" })),
+    };
+    if !token.scope.allows(needed) {
+        return (403, json!({ "error": format!("token {} needs {} scope", token.name, needed) }));
+    }
+    if let Some(role) = role_needed(method, path, body) {
+        let allowed = match &token.user {
+            Some(id) => match db.get_user(id) {
+                Some((user, _)) if config.role(&user) >= role => true,
+                // Members can always see and buy for themselves
+                Some(_) => role == db::Role::Volunteer && target(path, body).as_deref() == Some(id),
+                None => false,
+            },
+            None => true,
+        };
+        if !allowed {
+            return (403, json!({ "error": format!("needs the {} role", role) }));
+        }
+    }
+
+    // A retry of something that went through, where the client never heard back
+    if let Some(key) = request_key(method, body) {
+        if db.applied(key) {
+            return (409, json!({ "error": "already applied", "key": key }));
+        }
+    }
+
+    let result = match (method, path.trim_end_matches('/')) {
+        ("GET", "/api/users") => db.users().map(|mut users| {
+            users.sort_by(|a, b| a.id.cmp(&b.id));
+            json!(users.iter().map(cli::user_json).collect::<Vec<_>>())
+        }),
+        ("GET", path) => match path.strip_prefix("/api/users/") {
+            Some(id) => match db.get_user(id) {
+                Some((user, _)) => Ok(cli::user_json(&user)),
+                None => return (404, json!({ "error": format!("user {} does not exist", id) })),
+            },
+            None => return (404, json!({ "error": "not found" })),
+        },
+        ("POST", "/api/purchase") => serde_json::from_slice::<PurchaseRequest>(body)
+            .map_err(|e| e.to_string())
+            .and_then(|p| purchase(db, config, products, p)),
+        ("POST", "/api/deposit") => serde_json::from_slice::<DepositRequest>(body)
+            .map_err(|e| e.to_string())
+            .and_then(|d| {
+                config.deposits.check(d.amount)?;
+                let key = d.key.as_deref().map_or_else(uuid::Uuid::new_v4, db::idempotency_key);
+                db.deposit_user_once(key, &d.user, d.amount, d.method, d.note.as_deref())
+                    .map(|u| cli::user_json(&u))
+            }),
+        _ => return (404, json!({ "error": "not found" })),
+    };
+    match result {
+        Ok(body) => (200, body),
+        Err(e) => (400, json!({ "error": e })),
+    }
+}
+
+fn request_key(method: &str, body: &[u8]) -> Option<uuid::Uuid> {
+    if method != "POST" {
+        return None;
+    }
+    let request = serde_json::from_slice::<serde_json::Value>(body).ok()?;
+    request.get("key")?.as_str().map(db::idempotency_key)
+}
+
+fn api_secret(config: &config::Config) -> Option<String> {
+    config.api.as_ref().and_then(|a| a.page_secret.clone())
+}
+
+// Goes at the end of a member's balance page link, for /me/<id>/<signature>
+pub fn page_signature(secret: &str, id: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(id.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+// A member's own balance and history, for their phone, only with a link signed for them
+pub fn balance_page(db: &db::DB, secret: &str, path: &str) -> (u16, String) {
+    let (id, signature) = match path.trim_end_matches('/').strip_prefix("/me/").and_then(|p| p.split_once('/')) {
+        Some(p) => p,
+        None => return (404, "Not found".to_string()),
+    };
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(id.as_bytes());
+    let signed = hex::decode(signature).is_ok_and(|s| mac.verify_slice(&s).is_ok());
+    let (user, transactions) = match db.get_user(id) {
+        Some(u) if signed => u,
+        _ => return (404, "Not found".to_string()),
+    };
+
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\
+         <title>57North Snack Bank</title></head><body><h1>{}</h1><p>Balance <strong>{}</strong></p><table>",
+        escape(&user.display_name()),
+        money::format(user.balance),
+    );
+    for t in transactions.iter().rev().filter(|t| t.balance_change() != 0).take(PAGE_HISTORY) {
+        let _ = write!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+            t.timestamp.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M"),
+            escape(&reports::describe(&t.transaction)),
+            money::format(t.balance_change()),
+        );
+    }
+    html.push_str("</table></body></html>");
+    (200, html)
+}
+
+// For tokens acting as a user, what their role has to be for the request
+fn role_needed(method: &str, path: &str, body: &[u8]) -> Option<db::Role> {
+    match (method, path.trim_end_matches('/')) {
+        ("GET", "/api/users") | ("POST", "/api/deposit") => Some(db::Role::Treasurer),
+        ("GET", _) | ("POST", "/api/purchase") if target(path, body).is_some() => Some(db::Role::Volunteer),
+        _ => None,
+    }
+}
+
+// Whose account the request is about
+fn target(path: &str, body: &[u8]) -> Option<String> {
+    match path.trim_end_matches('/').strip_prefix("/api/users/") {
+        Some(id) => Some(id.to_string()),
+        None => serde_json::from_slice::<PurchaseRequest>(body).ok().map(|p| p.user),
+    }
+}
+
+fn purchase(
+    db: &db::DB,
+    config: &config::Config,
+    products: &products::Products,
+    request: PurchaseRequest,
+) -> Result<serde_json::Value, String> {
+    let mut cart = Cart::new();
+    if let Some(key) = &request.key {
+        cart.key = db::idempotency_key(key);
+    }
+    for code in &request.products {
+        let product = Barcode::try_parse(code)
+            .filter(|b| b.check_digit())
+            .and_then(|b| products.get(&b))
+            .ok_or_else(|| format!("unknown product {}", code))?;
+        if product.open_price {
+            return Err(format!("{} has to be priced at the till", product.name));
+        }
+        cart.products.push(product.clone());
+    }
+    if cart.products.is_empty() {
+        return Err("nothing to buy".to_string());
+    }
+    cart.apply_combos(&config.combos);
+    let user = db.apply_cart_to_user(&request.user, &cart)?;
+    Ok(cli::user_json(&user))
+}
@@ -0,0 +1,69 @@
+use crate::config::BackupConfig;
+use bank_core::paths::{data_dir, data_path};
+use std::process::{Command, Stdio};
+
+fn shell(command: &str) -> Command {
+    let mut c = Command::new("sh");
+    c.arg("-c").arg(command);
+    c
+}
+
+fn run(mut command: Command, what: &str) -> Result<(), String> {
+    match command.status() {
+        Ok(s) if s.success() => Ok(()),
+        Ok(s) => Err(format!("{} failed ({})", what, s)),
+        Err(e) => Err(format!("cannot run {} {}", what, e)),
+    }
+}
+
+// Tars up the database and archives, encrypts them if configured, and hands the file to the
+// backup command as $SNAPSHOT. The local copy is removed once it's been shipped.
+pub fn ship_snapshot(config: &BackupConfig) -> Result<String, String> {
+    // Where snapshots are put together before being shipped
+    let snapshot_dir = data_path("backups");
+    std::fs::create_dir_all(&snapshot_dir)
+        .map_err(|e| format!("cannot create backup directory {}", e))?;
+    let name = format!("57bank-{}", chrono::Utc::now().format("%Y%m%d%H%M%S"));
+    let tar_path = format!("{}/{}.tar", snapshot_dir.display(), name);
+
+    let entries = ["db", "archive"]
+        .into_iter()
+        .filter(|e| data_path(e).exists())
+        .collect::<Vec<_>>();
+    let mut tar = Command::new("tar");
+    tar.arg("-C").arg(data_dir()).arg("-cf").arg(&tar_path).args(&entries);
+    run(tar, "tar")?;
+
+    let snapshot = match &config.encrypt {
+        Some(encrypt) => {
+            let enc_path = format!("{}.enc", tar_path);
+            let result = std::fs::File::open(&tar_path)
+                .and_then(|input| Ok((input, std::fs::File::create(&enc_path)?)))
+                .map_err(|e| format!("cannot create {} {}", enc_path, e))
+                .and_then(|(input, output)| {
+                    let mut c = shell(encrypt);
+                    c.stdin(Stdio::from(input)).stdout(Stdio::from(output));
+                    run(c, "encryption command")
+                });
+            // Never leave an unencrypted copy lying around
+            let _ = std::fs::remove_file(&tar_path);
+            if let Err(e) = result {
+                let _ = std::fs::remove_file(&enc_path);
+                return Err(e);
+            }
+            enc_path
+        }
+        None => tar_path,
+    };
+
+    let mut ship = shell(&config.command);
+    ship.env("SNAPSHOT", &snapshot);
+    let result = run(ship, "backup command");
+    let _ = std::fs::remove_file(&snapshot);
+    result.map(|_| name)
+}
+
+// When the database was last written, to skip backups when nothing has changed
+pub fn last_modified() -> Option<std::time::SystemTime> {
+    std::fs::metadata(data_path("db")).and_then(|m| m.modified()).ok()
+}
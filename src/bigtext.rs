@@ -0,0 +1,77 @@
+use bank_core::money;
+use bank_core::style::{theme, Style};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Totals and balances are drawn large, for the kiosk screen above the fridge
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+const HEIGHT: usize = 5;
+
+fn glyph(c: char) -> Option<[&'static str; HEIGHT]> {
+    Some(match c {
+        '0' => [" ███ ", "█   █", "█   █", "█   █", " ███ "],
+        '1' => ["  █  ", " ██  ", "  █  ", "  █  ", " ███ "],
+        '2' => [" ███ ", "█   █", "  ██ ", " █   ", "█████"],
+        '3' => ["████ ", "    █", " ███ ", "    █", "████ "],
+        '4' => ["█   █", "█   █", "█████", "    █", "    █"],
+        '5' => ["█████", "█    ", "████ ", "    █", "████ "],
+        '6' => [" ███ ", "█    ", "████ ", "█   █", " ███ "],
+        '7' => ["█████", "    █", "   █ ", "  █  ", "  █  "],
+        '8' => [" ███ ", "█   █", " ███ ", "█   █", " ███ "],
+        '9' => [" ███ ", "█   █", " ████", "    █", " ███ "],
+        '£' => ["  ██ ", " █   ", "████ ", " █   ", "█████"],
+        '€' => [" ███ ", "█    ", "████ ", "█    ", " ███ "],
+        '$' => [" ████", "█ █  ", " ███ ", "  █ █", "████ "],
+        '.' => ["  ", "  ", "  ", "  ", "██"],
+        ',' => ["  ", "  ", "  ", "██", " █"],
+        '-' => ["    ", "    ", "████", "    ", "    "],
+        '+' => ["     ", "  █  ", "█████", "  █  ", "     "],
+        ' ' => ["  ", "  ", "  ", "  ", "  "],
+        _ => return None,
+    })
+}
+
+// None if the text has anything the font doesn't cover
+pub fn render(text: &str) -> Option<String> {
+    let glyphs = text.chars().map(glyph).collect::<Option<Vec<_>>>()?;
+    let lines = (0..HEIGHT)
+        .map(|row| glyphs.iter().map(|g| g[row]).collect::<Vec<_>>().join(" "))
+        .collect::<Vec<_>>();
+    Some(lines.join("\n"))
+}
+
+fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed) && !bank_core::style::plain()
+}
+
+// e.g. show("Total", "£4.20"), drawn large in kiosk mode
+pub fn show(label: &str, amount: &str) {
+    match render(amount).filter(|_| enabled()) {
+        Some(text) => println!("{}:\n{}", label, text),
+        None => println!("{}: {}", label, amount),
+    }
+}
+
+// Just the large version, for amounts already given in a sentence
+pub fn banner(amount: &str) {
+    if let Some(text) = render(amount).filter(|_| enabled()) {
+        println!("{}", text);
+    }
+}
+
+pub fn show_balance(label: &str, user: &bank_core::db::User) {
+    let amount = money::format(user.balance);
+    match render(&amount).filter(|_| enabled()) {
+        Some(text) if user.balance < 0 => println!(
+            "{}:\n{}",
+            label,
+            Style::new().fg(theme().negative).paint(text)
+        ),
+        Some(text) => println!("{}:\n{}", label, text),
+        None => println!("{}: {}", label, user.disp_balance()),
+    }
+}
@@ -0,0 +1,112 @@
+use crate::{config::ButtonAction, gpio};
+use std::collections::{HashMap, VecDeque};
+use std::os::fd::AsRawFd;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+use tokio::sync::mpsc::Sender;
+
+const RETRY_DELAY: std::time::Duration = std::time::Duration::from_secs(5);
+// Contacts bounce, so a press only counts this long after the last one
+const DEBOUNCE: std::time::Duration = std::time::Duration::from_millis(250);
+
+// Presses waiting to be dealt with, as the line they stand for
+static PRESSED: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+impl ButtonAction {
+    // What typing it would look like
+    pub fn line(&self) -> &'static str {
+        match self {
+            ButtonAction::Confirm => "",
+            ButtonAction::Abort => "abort",
+            ButtonAction::Help => "help",
+        }
+    }
+}
+
+// Watches each pin on its own thread, pinging `notify` whenever there's a press to take
+pub fn watch(
+    buttons: &HashMap<u32, ButtonAction>,
+    notify: Sender<()>,
+    stop: &Arc<AtomicBool>,
+) -> Vec<std::thread::JoinHandle<()>> {
+    ENABLED.store(!buttons.is_empty(), Ordering::Relaxed);
+    buttons
+        .iter()
+        .map(|(&pin, &action)| {
+            let (notify, stop) = (notify.clone(), Arc::clone(stop));
+            std::thread::spawn(move || button(pin, action, notify, stop))
+        })
+        .collect()
+}
+
+fn button(number: u32, action: ButtonAction, notify: Sender<()>, stop: Arc<AtomicBool>) {
+    let mut failed = false;
+
+    while !stop.load(Ordering::Relaxed) {
+        match watch_pin(number, action, &notify, &stop) {
+            Ok(()) => return,
+            Err(e) => {
+                if !failed {
+                    println!("Button on GPIO {} error: {}, retrying in the background", number, e);
+                }
+                failed = true;
+                let retry_at = std::time::Instant::now() + RETRY_DELAY;
+                while std::time::Instant::now() < retry_at && !stop.load(Ordering::Relaxed) {
+                    std::thread::sleep(std::time::Duration::from_millis(100));
+                }
+            }
+        }
+    }
+}
+
+fn watch_pin(number: u32, action: ButtonAction, notify: &Sender<()>, stop: &AtomicBool) -> Result<(), String> {
+    // Buttons pull the pin down to ground when pressed
+    let mut pin = gpio::Pin::input(number, "falling")?;
+    let mut last = None::<std::time::Instant>;
+    while !stop.load(Ordering::Relaxed) {
+        if !pin.wait(std::time::Duration::from_millis(300))? {
+            continue;
+        }
+        let now = std::time::Instant::now();
+        if last.is_some_and(|l| now.duration_since(l) < DEBOUNCE) {
+            continue;
+        }
+        last = Some(now);
+        PRESSED.lock().unwrap().push_back(action.line().to_string());
+        // The main loop has gone away, so we're shutting down
+        if notify.blocking_send(()).is_err() {
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+pub fn take() -> Option<String> {
+    PRESSED.lock().unwrap().pop_front()
+}
+
+// For questions asked part way through a command, e.g. confirming a checkout. Waits for either
+// a typed answer, in which case None, or a press.
+pub fn answer() -> Option<String> {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return None;
+    }
+    // Anything pressed before the question was asked doesn't answer it
+    PRESSED.lock().unwrap().clear();
+    loop {
+        if let Some(line) = take() {
+            return Some(line);
+        }
+        let mut stdin = libc::pollfd {
+            fd: std::io::stdin().as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        if unsafe { libc::poll(&mut stdin, 1, 100) } != 0 {
+            return None;
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use crate::reader::Debouncer;
+use std::io::BufRead;
+use std::os::unix::process::CommandExt;
+use tokio::sync::mpsc::Sender;
+
+const RETRY_DELAY: std::time::Duration = std::time::Duration::from_secs(5);
+// zbarcam keeps reporting a code for as long as it's in view
+const REPEAT_WINDOW: std::time::Duration = std::time::Duration::from_secs(2);
+
+// Runs on its own thread for the life of the program, decoding barcodes from a camera with
+// zbarcam, e.g. for when the laser scanner dies or the code is on a phone screen
+pub fn camera(config: crate::config::CameraConfig, scan_tx: Sender<String>) {
+    let mut failed = false;
+    let mut repeats = Debouncer::new(REPEAT_WINDOW);
+
+    loop {
+        match watch(&config, &scan_tx, &mut repeats, &mut failed) {
+            Ok(()) => return,
+            Err(e) => {
+                // Only report each outage once, rather than every retry
+                if !failed {
+                    println!("Camera error: {}, retrying in the background", e);
+                }
+                failed = true;
+                std::thread::sleep(RETRY_DELAY);
+            }
+        }
+    }
+}
+
+fn watch(
+    config: &crate::config::CameraConfig,
+    scan_tx: &Sender<String>,
+    repeats: &mut Debouncer,
+    failed: &mut bool,
+) -> Result<(), String> {
+    let mut command = std::process::Command::new("zbarcam");
+    command
+        .args(["--raw", "--nodisplay", "--quiet", &config.device])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null());
+    // Take zbarcam down with us, rather than leaving it holding the camera
+    unsafe {
+        command.pre_exec(|| {
+            libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGTERM);
+            Ok(())
+        });
+    }
+    let mut zbarcam = command.spawn().map_err(|e| format!("cannot run zbarcam: {}", e))?;
+    if *failed {
+        *failed = false;
+        println!("Camera reconnected");
+    }
+
+    let stdout = zbarcam.stdout.take().unwrap();
+    for line in std::io::BufReader::new(stdout).lines() {
+        let code = line.map_err(|e| e.to_string())?;
+        let code = code.trim();
+        if code.is_empty() || !repeats.seen(code.as_bytes(), std::time::Instant::now()) {
+            continue;
+        }
+        // The main loop has gone away, so we're shutting down
+        if scan_tx.blocking_send(code.to_string()).is_err() {
+            let _ = zbarcam.kill();
+            return Ok(());
+        }
+    }
+    let status = zbarcam.wait().map_err(|e| e.to_string())?;
+    Err(format!("zbarcam stopped ({})", status))
+}
@@ -0,0 +1,116 @@
+use crate::reports;
+use bank_core::db::{self, TransactionType};
+use bank_core::money;
+
+const USAGE: &str = "\
+Usage: 57bank <command> [--json]
+  users
+  balance <id>
+  purchases [--since <year | year-month | date>] [--user <id>]";
+
+// Runs a single query and exits, for scripts and cron jobs. None if the arguments aren't a
+// query, so the interactive prompt starts as normal.
+pub fn run(args: &[String]) -> Option<i32> {
+    if !matches!(args.first().map(String::as_str), Some("users" | "balance" | "purchases")) {
+        return None;
+    }
+    // Never writes, and doesn't need the till to be stopped
+    let db = match db::DB::load_read_only() {
+        Ok(d) => d,
+        Err(e) => {
+            eprintln!("Error, unable to open database: {}", e);
+            return Some(1);
+        }
+    };
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+    Some(match query(&db, &args) {
+        Ok(out) => {
+            println!("{}", out);
+            0
+        }
+        Err(e) => {
+            eprintln!("Error, {}", e);
+            1
+        }
+    })
+}
+
+pub fn query(db: &db::DB, args: &[&str]) -> Result<String, String> {
+    let json = args.contains(&"--json");
+    let args = args.iter().copied().filter(|a| *a != "--json").collect::<Vec<_>>();
+
+    match args[..] {
+        ["users"] => {
+            let mut users = db.users()?;
+            users.sort_by(|a, b| a.id.cmp(&b.id));
+            if json {
+                return to_json(&users.iter().map(user_json).collect::<Vec<_>>());
+            }
+            Ok(users
+                .iter()
+                .map(|u| format!("{}\t{}", u.id, money::format(u.balance)))
+                .collect::<Vec<_>>()
+                .join("\n"))
+        }
+        ["balance", id] => {
+            let (user, _) = db.get_user(id).ok_or_else(|| format!("user {} does not exist", id))?;
+            if json {
+                return to_json(&user_json(&user));
+            }
+            Ok(money::format(user.balance))
+        }
+        ["purchases", ref rest @ ..] => {
+            let (mut since, mut user) = (None, None);
+            let mut rest = rest.iter();
+            while let Some(arg) = rest.next() {
+                match (*arg, rest.next()) {
+                    ("--since", Some(s)) => since = Some(reports::parse_period(s)?.start),
+                    ("--user", Some(u)) => user = Some(*u),
+                    _ => return Err(USAGE.to_string()),
+                }
+            }
+
+            let transactions = db.transactions()?;
+            let purchases = transactions
+                .iter()
+                .filter(|t| matches!(t.transaction, TransactionType::Purchase { .. }))
+                .filter(|t| since.is_none_or(|s| t.timestamp >= s))
+                .filter(|t| user.is_none_or(|u| t.actor.account() == Some(u)))
+                .collect::<Vec<_>>();
+            if json {
+                return to_json(&purchases);
+            }
+            Ok(purchases
+                .iter()
+                .filter_map(|t| match &t.transaction {
+                    TransactionType::Purchase { products, total, .. } => Some(format!(
+                        "{}\t{}\t{}\t{}",
+                        t.timestamp.to_rfc3339(),
+                        t.actor,
+                        money::format(*total),
+                        products.iter().map(|p| p.name.as_str()).collect::<Vec<_>>().join(", ")
+                    )),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n"))
+        }
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+// Leaves out PINs and card IDs, which scripts have no business seeing
+pub fn user_json(user: &db::User) -> serde_json::Value {
+    serde_json::json!({
+        "id": user.id,
+        "name": user.name,
+        "balance": user.balance,
+        "frozen": user.frozen,
+        "members": user.members,
+        "role": user.role,
+    })
+}
+
+fn to_json<T: serde::Serialize>(value: &T) -> Result<String, String> {
+    serde_json::to_string_pretty(value).map_err(|e| format!("{:?}", e))
+}
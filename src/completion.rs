@@ -1,15 +1,30 @@
 use crate::FORBIDDEN_USERS;
 
 use radix_trie::{Trie, TrieCommon};
-use rustyline::completion::Completer;
+use std::collections::{BTreeSet, HashMap};
+use rustyline::completion::{Completer, Pair};
 use rustyline::Helper;
 use rustyline::highlight::Highlighter;
 use rustyline::hint::{Hint, Hinter};
 use rustyline::validate::Validator;
 
+// Commands whose first argument is an account
+const USER_ARGS: [&str; 11] = [
+    "deposit", "regcard", "delcard", "adjust", "withdraw", "setrole", "balance", "favourites",
+    "setfavourite", "topup", "weblink",
+];
+// Commands whose first argument is a product, completed from its barcode or name
+const PRODUCT_ARGS: [&str; 2] = ["restock", "stocktake"];
+
 #[derive(Debug)]
 pub struct Hintererer {
-    commands: Trie<&'static str, Completion>,
+    commands: Trie<String, Completion>,
+    users: BTreeSet<String>,
+    // Barcode, name
+    products: Vec<(String, String)>,
+    // How many times each command, user ID, or barcode has been used, so the likeliest
+    // completion is offered first
+    usage: HashMap<String, u32>,
 }
 
 #[derive(Debug)]
@@ -50,21 +65,132 @@ impl Completion {
 }
 
 impl Hintererer {
-    pub fn new() -> Self {
-        Self {
+    pub fn new(user_ids: &[String]) -> Self {
+        let mut h = Self {
             commands: Self::load_cmds(),
+            users: BTreeSet::new(),
+            products: Vec::new(),
+            usage: HashMap::new(),
+        };
+        for id in user_ids {
+            h.insert(id);
+            h.insert_user(id);
+        }
+        h
+    }
+
+    pub fn set_products(&mut self, products: &bank_core::products::Products) {
+        self.products = products
+            .values()
+            // Barcodes are stored padded out to 14 digits, which nobody types
+            .map(|p| (p.barcode.to_string().trim_start_matches('0').to_string(), p.name.clone()))
+            .collect();
+        self.products.sort();
+    }
+
+    fn insert_user(&mut self, id: &str) {
+        // Anything that isn't a command or a barcode is a user ID, e.g. one added this session
+        if !FORBIDDEN_USERS.contains(&id) && !id.chars().all(|c| c.is_ascii_digit()) {
+            self.users.insert(id.to_string());
         }
     }
 
-    pub fn load_cmds() -> Trie<&'static str, Completion> {
+    pub fn load_cmds() -> Trie<String, Completion> {
         let mut tr = Trie::new();
 
         for cmd in FORBIDDEN_USERS {
-            tr.insert(cmd, Completion::new(cmd, cmd));
+            tr.insert(cmd.to_string(), Completion::new(cmd, cmd));
         }
 
         tr
     }
+
+    fn insert(&mut self, word: &str) {
+        if self.commands.get(word).is_none() {
+            self.commands.insert(word.to_string(), Completion::new(word, word));
+        }
+    }
+
+    // Counts from a previous run, missing or unreadable stats just mean starting afresh
+    pub fn load_usage(&mut self, path: &std::path::Path) {
+        let usage = std::fs::read_to_string(path)
+            .ok()
+            .and_then(|u| ron::from_str::<HashMap<String, u32>>(&u).ok())
+            .unwrap_or_default();
+        for word in usage.keys() {
+            self.insert(word);
+        }
+        self.usage = usage;
+    }
+
+    pub fn save_usage(&self, path: &std::path::Path) -> Result<(), String> {
+        let usage = ron::to_string(&self.usage).map_err(|e| format!("{:?}", e))?;
+        std::fs::write(path, usage).map_err(|e| format!("cannot write usage stats {}", e))
+    }
+
+    pub fn record_use(&mut self, word: &str) {
+        self.insert(word);
+        self.insert_user(word);
+        *self.usage.entry(word.to_string()).or_default() += 1;
+    }
+
+    // Everything starting with the line, most used first
+    fn ranked(&self, line: &str) -> Vec<&Completion> {
+        let mut matches = self
+            .commands
+            .iter()
+            .filter(|c| c.0.starts_with(line))
+            .collect::<Vec<_>>();
+        // Stable, so equally used words stay in trie order
+        matches.sort_by_key(|c| std::cmp::Reverse(self.usage.get(c.0).copied().unwrap_or(0)));
+        matches.into_iter().map(|c| c.1).collect()
+    }
+
+    // Where the word being completed starts
+    fn word_start(line: &str) -> usize {
+        line.find(' ').map_or(0, |i| i + 1)
+    }
+
+    pub fn suggestions(&self, line: &str) -> Vec<String> {
+        let (command, arg) = match line.split_once(' ') {
+            Some(split) => split,
+            None => {
+                let words = self.ranked(line).into_iter().map(|c| c.display().to_string());
+                return words.collect();
+            }
+        };
+        // Only the first argument is completed
+        if arg.contains(' ') {
+            return Vec::new();
+        }
+
+        let mut candidates = if USER_ARGS.contains(&command) {
+            self.users
+                .iter()
+                .filter(|id| id.starts_with(arg))
+                .cloned()
+                .collect::<Vec<_>>()
+        } else if PRODUCT_ARGS.contains(&command) {
+            let (code, name) = (arg.trim_start_matches('0'), arg.to_lowercase());
+            self.products
+                .iter()
+                .filter(|(c, n)| c.starts_with(code) || n.to_lowercase().contains(&name))
+                .map(|(code, _)| code.clone())
+                .collect()
+        } else {
+            Vec::new()
+        };
+        candidates.sort_by_key(|c| std::cmp::Reverse(self.usage.get(c).copied().unwrap_or(0)));
+        candidates
+    }
+
+    // e.g. "Club-Mate (4029764001401)" for a barcode, for listing candidates
+    fn describe(&self, candidate: &str) -> String {
+        match self.products.iter().find(|(code, _)| code == candidate) {
+            Some((code, name)) => format!("{} ({})", name, code),
+            None => candidate.to_string(),
+        }
+    }
 }
 
 impl Highlighter for Hintererer {}
@@ -75,40 +201,35 @@ impl Hinter for Hintererer {
     type Hint = Completion;
     fn hint(&self, line: &str, pos: usize, _ctx: &rustyline::Context<'_>) -> Option<Self::Hint> {
         if line.is_empty() || pos < line.len() {
-            None
-        } else {
-            self.commands.iter().find_map(|c| {
-                if c.0.starts_with(line) {
-                    Some(c.1.suffix(pos))
-                } else {
-                    None
-                }
-            })
+            return None;
         }
+        let start = Self::word_start(line);
+        let candidates = self.suggestions(line);
+        // Product names match anywhere, so only hint when the candidate carries on the line
+        candidates
+            .into_iter()
+            .find(|c| c.starts_with(&line[start..]))
+            .map(|c| Completion::new(&c, &c).suffix(pos - start))
     }
 }
 
 impl Completer for Hintererer {
-    type Candidate = String;
+    type Candidate = Pair;
     fn complete(
         &self,
         line: &str,
         _pos: usize,
         _ctx: &rustyline::Context<'_>,
     ) -> rustyline::Result<(usize, Vec<Self::Candidate>)> {
-        // let mut hints = Vec::new();
-        let hints = self
-            .commands
-            .iter()
-            .filter_map(|c| {
-                if c.0.starts_with(line) {
-                    Some(c.1.display().to_string())
-                } else {
-                    None
-                }
+        let start = Self::word_start(line);
+        let candidates = self
+            .suggestions(line)
+            .into_iter()
+            .map(|c| Pair {
+                display: self.describe(&c),
+                replacement: c,
             })
             .collect();
-
-        Ok((0, hints))
+        Ok((start, candidates))
     }
 }
@@ -0,0 +1,617 @@
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct Config {
+    // Name of this till, e.g. "kitchen", when there's more than one
+    pub till: Option<String>,
+    pub sync: SyncConfig,
+    pub webhooks: Vec<WebhookConfig>,
+    pub ldap: Option<LdapConfig>,
+    pub oidc: Option<OidcConfig>,
+    pub history: HistoryConfig,
+    pub balance: BalanceConfig,
+    // Other currencies accepted as cash, alongside pounds
+    pub currencies: Vec<CurrencyConfig>,
+    // Tax category -> rate as a percentage, e.g. {"standard": 20.0, "zero": 0.0}.
+    // Prices include tax, products pick a category with tax=<category>.
+    pub tax_rates: std::collections::HashMap<String, f64>,
+    pub ledger: LedgerConfig,
+    // User IDs that are always admins, whatever role they've been given with setrole
+    pub admins: Vec<String>,
+    // Meal deals, e.g. (name: "Meal deal", categories: ["drink", "snack"], price: 180)
+    pub combos: Vec<bank_core::combo::Combo>,
+    // Keeps a PDF price list at this path up to date whenever the products are loaded
+    pub pricelist: Option<String>,
+    pub reader: ReaderConfig,
+    // A barcode scanner on a serial port, rather than one that types like a keyboard
+    pub scanner: Option<ScannerConfig>,
+    // Barcodes read by a camera through zbarcam, which needs to be installed
+    pub camera: Option<CameraConfig>,
+    // Counts cash put in during a deposit, typed in as 'insert' instead of an amount
+    pub acceptor: Option<AcceptorConfig>,
+    // Opened when a cash purchase or cash deposit goes through
+    pub drawer: Option<DrawerConfig>,
+    // A screen facing the person buying, showing the cart and their new balance
+    pub customer_display: Option<DisplayConfig>,
+    // GPIO pin -> what pressing the button wired to it does, e.g. {17: Confirm, 27: Abort}
+    pub buttons: std::collections::HashMap<u32, ButtonAction>,
+    // Status LEDs and a buzzer on GPIO pins
+    pub feedback: Option<FeedbackConfig>,
+    // Reads out what's happening, for anyone who can't see the screen
+    pub speech: Option<SpeechConfig>,
+    pub notify: crate::notify::NotifyConfig,
+    // Tapping a card with an empty cart logs that user in, so commands like setpin and pay act on
+    // them, until they've been idle for this many seconds
+    pub session_timeout: Option<u64>,
+    // Summarises the day's takings at this local time, e.g. "23:00"
+    pub end_of_day: Option<chrono::NaiveTime>,
+    // HTTP API for integrations, authenticated with tokens from issuetoken
+    pub api: Option<ApiConfig>,
+    // Cards registered with regcard wait for an admin to approve them before they can pay
+    pub approve_cards: bool,
+    pub checkout: CheckoutConfig,
+    pub deposits: DepositConfig,
+    pub retention: RetentionConfig,
+    // Taken off what's credited for each way of paying in, e.g. {Card: (percent: 1.69)} to pass on
+    // card processing costs
+    pub deposit_fees: std::collections::HashMap<bank_core::db::DepositMethod, bank_core::db::DepositFee>,
+    // Tapping a card then scanning one item buys it straight away, without a cart
+    pub quick_buy: Option<QuickBuyConfig>,
+    // Card reader for taking deposits by card at the till
+    pub sumup: Option<SumUpConfig>,
+    // Ships snapshots of the database somewhere other than the SD card
+    pub backup: Option<BackupConfig>,
+    // Single keys that run a command when pressed on an empty prompt, e.g. {'c': "cash"}.
+    // Start the line with a space to type something beginning with one of them.
+    pub hotkeys: std::collections::HashMap<char, String>,
+    // Language for product names, e.g. "de". Defaults to the terminal's locale.
+    pub locale: Option<String>,
+    // No colours, block characters, or QR codes, for screen readers. Also set by --no-color
+    // or the NO_COLOR environment variable.
+    pub plain: bool,
+    pub theme: bank_core::style::Theme,
+    // How amounts of money are written, e.g. (locale: "de-DE") or (symbol: "GBP ")
+    pub money: bank_core::money::MoneyFormat,
+    // Totals and balances in large text, to read from across the room. Also set by --big-text.
+    pub big_text: bool,
+}
+
+impl Config {
+    pub fn role(&self, user: &bank_core::db::User) -> bank_core::db::Role {
+        if self.admins.contains(&user.id) {
+            bank_core::db::Role::Admin
+        } else {
+            user.role
+        }
+    }
+
+    pub fn plain(&self) -> bool {
+        self.plain
+            || std::env::args().skip(1).any(|a| a == "--no-color" || a == "--plain")
+            || std::env::var("NO_COLOR").is_ok_and(|v| !v.is_empty())
+    }
+
+    pub fn locale(&self) -> Option<String> {
+        if let Some(locale) = &self.locale {
+            return Some(locale.clone());
+        }
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .into_iter()
+            .filter_map(|v| std::env::var(v).ok())
+            .find(|v| !v.is_empty())
+            .filter(|v| v != "C" && v != "POSIX" && !v.starts_with("C."))
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct SyncConfig {
+    // Paths to the database files of other tills, e.g. on a shared mount
+    pub peers: Vec<String>,
+    // Inbox directories of other tills to push our transactions to. Transactions are queued
+    // locally while a target is unreachable and delivered in order once it's back.
+    pub push: Vec<String>,
+    // Seconds between automatic syncs, 0 to only sync on command
+    pub interval: u64,
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        Self {
+            peers: Vec::new(),
+            push: Vec::new(),
+            interval: 60,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct WebhookConfig {
+    pub url: String,
+    // Used to sign each request body, sent as an HMAC-SHA256 in X-57Bank-Signature
+    pub secret: String,
+    // Events to send, or all events if empty
+    #[serde(default)]
+    pub events: Vec<crate::webhooks::Event>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct LdapConfig {
+    pub url: String,
+    pub bind_dn: Option<String>,
+    pub bind_password: Option<String>,
+    pub base_dn: String,
+    #[serde(default = "default_ldap_filter")]
+    pub filter: String,
+    // Attribute holding the membership ID, e.g. "employeeNumber"
+    pub membership_attr: String,
+    // Attribute to use as the bank user ID, e.g. "uid"
+    pub user_attr: String,
+}
+
+fn default_ldap_filter() -> String {
+    "(objectClass=person)".to_string()
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct OidcConfig {
+    // e.g. "https://sso.57north.org.uk/realms/57north"
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    #[serde(default = "default_oidc_scopes")]
+    pub scopes: String,
+}
+
+fn default_oidc_scopes() -> String {
+    "openid profile".to_string()
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct HistoryConfig {
+    // Also keep scanned barcodes in the history, which along with the card taps
+    // that follow them give away who bought what
+    pub barcodes: bool,
+    // Regular expressions for lines that should never be saved, e.g. "^deposit "
+    pub exclude: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct BalanceConfig {
+    // Nag people at checkout once their balance drops below this many pence, e.g. -500
+    pub warn_below: Option<i32>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct ReaderConfig {
+    // Milliseconds a card has to be off the reader before touching it again counts as
+    // another tap. Too long and quick double taps are missed, too short and a card
+    // hovering at the edge of the field fires repeatedly.
+    pub debounce: u64,
+    // Nfc, Pcsc for readers that work better through pcscd (build with the pcsc feature),
+    // or Mock to develop without a reader, which is the default when built without the
+    // nfc feature
+    pub backend: crate::reader::Backend,
+    // Part of the name of the PC/SC reader to use, when there's more than one
+    pub pcsc_reader: Option<String>,
+    // Script of taps for the mock reader, see reader/mock.rs
+    pub mock: Option<String>,
+    // Milliseconds in which scanning the same barcode again is taken to be the scanner
+    // repeating itself rather than another item, 0 to turn it off
+    pub scan_debounce: u64,
+}
+
+impl Default for ReaderConfig {
+    fn default() -> Self {
+        Self {
+            debounce: 500,
+            backend: Default::default(),
+            pcsc_reader: None,
+            mock: None,
+            scan_debounce: 300,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ScannerConfig {
+    // e.g. "/dev/ttyACM0"
+    pub device: String,
+    #[serde(default = "default_scanner_baud")]
+    pub baud: u32,
+}
+
+fn default_scanner_baud() -> u32 {
+    9600
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct CameraConfig {
+    // e.g. "/dev/video0"
+    pub device: String,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub enum AcceptorConfig {
+    // A ccTalk coin acceptor on a serial port, with the value in pence of each coin position it
+    // has been taught, e.g. CcTalk(device: "/dev/ttyUSB0", coins: {1: 5, 2: 10, 3: 20})
+    CcTalk {
+        device: String,
+        #[serde(default = "default_cctalk_address")]
+        address: u8,
+        coins: std::collections::HashMap<u8, u32>,
+    },
+    // A coin acceptor or note validator pulsing a GPIO pin once for every so many pence,
+    // e.g. Pulse(gpio: 17, pence: 10)
+    Pulse { gpio: u32, pence: u32 },
+}
+
+fn default_cctalk_address() -> u8 {
+    2
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub enum DrawerConfig {
+    // Plugged into the receipt printer, e.g. Printer(device: "/dev/usb/lp0"). Connector 0 is
+    // pin 2 of the drawer port, which is what most drawers use, and 1 is pin 5.
+    Printer {
+        device: String,
+        #[serde(default)]
+        connector: u8,
+    },
+    // A relay on a GPIO pin, e.g. Gpio(pin: 27)
+    Gpio { pin: u32 },
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum ButtonAction {
+    // Like pressing Enter, e.g. to confirm a checkout
+    Confirm,
+    // Empties the cart, or cancels whatever's being asked
+    Abort,
+    Help,
+}
+
+// Pin numbers, any of which can be left out, e.g. (green: 5, red: 6, buzzer: 13)
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct FeedbackConfig {
+    pub green: Option<u32>,
+    pub red: Option<u32>,
+    pub buzzer: Option<u32>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ApiConfig {
+    // e.g. "127.0.0.1:8057", put it behind a TLS proxy before exposing it any further
+    pub listen: String,
+    // Signs links to each member's balance page at /me/, see 'weblink'. Changing it stops all
+    // the old links working.
+    #[serde(default)]
+    pub page_secret: Option<String>,
+    // Where the API can be reached from outside, for those links, e.g. "https://bank.57north.org.uk"
+    #[serde(default)]
+    pub public_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct SpeechConfig {
+    // Run with the text on stdin, e.g. "piper --model en_GB-alan-medium.onnx --output-raw |
+    // aplay -r 22050 -f S16_LE -t raw -"
+    #[serde(default = "default_speech_command")]
+    pub command: String,
+}
+
+fn default_speech_command() -> String {
+    "espeak --stdin".to_string()
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub enum DisplayConfig {
+    // A serial pole display with two lines, e.g. Vfd(device: "/dev/ttyUSB1")
+    Vfd {
+        device: String,
+        #[serde(default = "default_display_baud")]
+        baud: u32,
+        #[serde(default = "default_vfd_width")]
+        width: usize,
+    },
+    // Another terminal, e.g. Terminal(device: "/dev/tty2") for a second monitor
+    Terminal {
+        device: String,
+        #[serde(default = "default_terminal_width")]
+        width: usize,
+    },
+}
+
+fn default_display_baud() -> u32 {
+    9600
+}
+
+fn default_vfd_width() -> usize {
+    20
+}
+
+fn default_terminal_width() -> usize {
+    40
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct CheckoutConfig {
+    // When to show the projected balance and wait for a keypress before charging
+    pub confirm: ConfirmCheckout,
+    // Show a QR code of the receipt after each purchase, for people to scan with their phone
+    pub qr_receipt: bool,
+    // Cash totals in pounds are rounded to the nearest this many pence, e.g. 5. Account
+    // purchases are always charged exactly.
+    pub cash_rounding: u32,
+    // Seconds after paying from their balance that someone can take it back themselves with
+    // 'void' and the card they paid with, 0 to turn it off
+    pub void_window: u64,
+}
+
+impl Default for CheckoutConfig {
+    fn default() -> Self {
+        Self {
+            confirm: Default::default(),
+            qr_receipt: false,
+            cash_rounding: 0,
+            void_window: 120,
+        }
+    }
+}
+
+// Checked once a day, so the bank doesn't keep a permanent record of who bought what
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct RetentionConfig {
+    // Transactions older than this no longer say who made them, e.g. Some(730)
+    pub anonymise_after_days: Option<u32>,
+    // Cards not tapped for this long are removed from their account, e.g. Some(365)
+    pub forget_cards_after_days: Option<u32>,
+}
+
+// In pence, to catch typos like 5000 for 50.00
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct DepositConfig {
+    pub min: Option<u32>,
+    pub max: Option<u32>,
+    // Anything bigger has to be confirmed
+    pub confirm_above: Option<u32>,
+    // Common amounts, picked with a single letter when depositing and shown as QR codes to pay
+    // by bank transfer on the customer display, e.g. [500, 1000, 2000]
+    pub presets: Vec<u32>,
+}
+
+impl DepositConfig {
+    pub fn check(&self, amount: u32) -> Result<(), String> {
+        match (self.min, self.max) {
+            (Some(min), _) if amount < min => {
+                Err(format!("deposits must be at least {}", bank_core::money::format(min)))
+            }
+            (_, Some(max)) if amount > max => {
+                Err(format!("deposits can't be more than {}", bank_core::money::format(max)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn needs_confirming(&self, amount: u32) -> bool {
+        self.confirm_above.is_some_and(|c| amount > c)
+    }
+
+    // e.g. "a: £5.00, b: £10.00"
+    pub fn menu(&self) -> String {
+        self.presets
+            .iter()
+            .zip('a'..='z')
+            .map(|(amount, key)| format!("{}: {}", key, bank_core::money::format(*amount)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    pub fn preset(&self, key: &str) -> Option<u32> {
+        let mut chars = key.chars();
+        match (chars.next(), chars.next()) {
+            (Some(key @ 'a'..='z'), None) => self.presets.get((key as u8 - b'a') as usize).copied(),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct QuickBuyConfig {
+    // Barcodes it works for, or every product if there are none, e.g. ["4029764001401"]
+    #[serde(default)]
+    pub products: Vec<String>,
+    // Seconds after the tap to scan the item in
+    #[serde(default = "default_quick_buy_window")]
+    pub window: u64,
+}
+
+impl QuickBuyConfig {
+    pub fn applies(&self, barcode: &bank_core::barcode::Barcode) -> bool {
+        self.products.is_empty()
+            || self
+                .products
+                .iter()
+                .any(|p| bank_core::barcode::Barcode::try_parse(p).as_ref() == Some(barcode))
+    }
+}
+
+fn default_quick_buy_window() -> u64 {
+    10
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConfirmCheckout {
+    #[default]
+    Never,
+    // Only when the purchase would leave the account below zero
+    WhenNegative,
+    Always,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct SumUpConfig {
+    pub api_key: String,
+    // e.g. "MCXXXXXX"
+    pub merchant_code: String,
+    // ID of the paired Solo reader at the till
+    pub reader_id: String,
+    // Seconds to wait for the card to be presented
+    #[serde(default = "default_sumup_timeout")]
+    pub timeout: u64,
+}
+
+fn default_sumup_timeout() -> u64 {
+    120
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct BackupConfig {
+    // Run with $SNAPSHOT set to the file to ship, e.g. "rclone copy $SNAPSHOT s3:57bank-backups"
+    pub command: String,
+    // Snapshots are piped through this first, e.g. "age -r age1..." or "gpg -e -r treasurer"
+    pub encrypt: Option<String>,
+    // Seconds between backups, skipped if nothing has been saved since the last one
+    #[serde(default = "default_backup_interval")]
+    pub interval: u64,
+}
+
+fn default_backup_interval() -> u64 {
+    6 * 60 * 60
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct CurrencyConfig {
+    // e.g. "EUR"
+    pub code: String,
+    pub symbol: String,
+    // How much of this currency to take for each pound, written as e.g. 1.17 but kept in
+    // ten-thousandths so conversions are exact
+    #[serde(deserialize_with = "rate_in_ten_thousandths")]
+    pub rate: u64,
+    // Digits after the decimal point, e.g. 0 for yen
+    #[serde(default = "default_minor_digits")]
+    pub minor_digits: u32,
+    // Cash totals are rounded to the nearest this many cents, e.g. 5 where 1c and 2c coins
+    // have been withdrawn
+    #[serde(default)]
+    pub rounding: u32,
+}
+
+fn rate_in_ten_thousandths<'de, D: serde::Deserializer<'de>>(d: D) -> Result<u64, D::Error> {
+    let rate = <f64 as serde::Deserialize>::deserialize(d)?;
+    if !rate.is_finite() || rate <= 0.0 {
+        return Err(serde::de::Error::custom(format!("invalid exchange rate {}", rate)));
+    }
+    Ok((rate * 10_000.0).round() as u64)
+}
+
+fn default_minor_digits() -> u32 {
+    2
+}
+
+impl CurrencyConfig {
+    // Converts pence into this currency's minor units, rounding up so the bank never loses out
+    pub fn convert(&self, pence: u32) -> u32 {
+        let minor = pence as u64 * self.rate * 10u64.pow(self.minor_digits);
+        minor.div_ceil(100 * 10_000) as u32
+    }
+
+    // Returns the amount to ask for and how much rounding added to it
+    pub fn round_cash(&self, amount: u32) -> (u32, i32) {
+        round_cash(amount, self.rounding)
+    }
+
+    // In minor units, e.g. 150 as "€1.50", or "¥150" with no minor digits
+    pub fn format(&self, amount: impl Into<i64>) -> String {
+        let amount = amount.into();
+        let sign = if amount < 0 { "-" } else { "" };
+        let unit = 10u64.pow(self.minor_digits);
+        match self.minor_digits {
+            0 => format!("{}{}{}", sign, self.symbol, amount.unsigned_abs()),
+            digits => format!(
+                "{}{}{}.{:0width$}",
+                sign,
+                self.symbol,
+                amount.unsigned_abs() / unit,
+                amount.unsigned_abs() % unit,
+                width = digits as usize
+            ),
+        }
+    }
+}
+
+// Rounds to the nearest multiple of `step`, halves going up, returning the rounded amount and
+// the difference
+pub fn round_cash(amount: u32, step: u32) -> (u32, i32) {
+    if step <= 1 {
+        return (amount, 0);
+    }
+    let rounded = (amount + step / 2) / step * step;
+    (rounded, rounded as i32 - amount as i32)
+}
+
+// Accounts used by exportledger, to match the space's own books
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct LedgerConfig {
+    // Each user gets a sub-account, as their balance is money the bank owes them
+    pub users: String,
+    // Foreign currencies get a sub-account each
+    pub cash: String,
+    pub bank: String,
+    // Card payments waiting to be paid out by SumUp
+    pub card: String,
+    pub sales: String,
+    pub standing_orders: String,
+    // Where voucher credit comes from
+    pub vouchers: String,
+    // Guest tabs are money owed to the bank, with a sub-account each
+    pub tabs: String,
+    // Where manual balance corrections are booked against
+    pub adjustments: String,
+    // Stock and anything else paid for out of the cash box
+    pub expenses: String,
+    // Gained or lost rounding cash totals
+    pub rounding: String,
+    // Taken off deposits, see Config::deposit_fees
+    pub deposit_fees: String,
+}
+
+impl Default for LedgerConfig {
+    fn default() -> Self {
+        Self {
+            users: "Liabilities:SnackBank:Users".to_string(),
+            cash: "Assets:SnackBank:Cash".to_string(),
+            bank: "Assets:Bank".to_string(),
+            card: "Assets:SnackBank:SumUp".to_string(),
+            sales: "Income:SnackBank:Sales".to_string(),
+            standing_orders: "Income:SnackBank:StandingOrders".to_string(),
+            vouchers: "Expenses:SnackBank:Vouchers".to_string(),
+            tabs: "Assets:SnackBank:Tabs".to_string(),
+            adjustments: "Expenses:SnackBank:Adjustments".to_string(),
+            expenses: "Expenses:SnackBank:Stock".to_string(),
+            rounding: "Income:SnackBank:Rounding".to_string(),
+            deposit_fees: "Income:SnackBank:DepositFees".to_string(),
+        }
+    }
+}
+
+pub fn read_config() -> Result<Config, String> {
+    let config_raw = match std::fs::read_to_string(bank_core::paths::data_path("config")) {
+        Ok(c) => c,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Config::default()),
+        Err(e) => return Err(format!("cannot open config file {}", e)),
+    };
+
+    // Tokens and passwords can be kept out of the file, see secrets.rs
+    let config_raw = crate::secrets::resolve_all(&config_raw)?;
+    ron::from_str(&config_raw).map_err(|e| format!("cannot parse config file {}", e))
+}
@@ -0,0 +1,219 @@
+use crate::{bigtext, config::DisplayConfig, serial};
+use bank_core::cart::Cart;
+use bank_core::money;
+use std::io::Write;
+use std::sync::Mutex;
+
+// The screen facing the person buying, rather than the keyboard side
+static DISPLAY: Mutex<Option<Screen>> = Mutex::new(None);
+
+const WELCOME: &str = "57North Snack Bank";
+
+struct Screen {
+    out: std::fs::File,
+    // Width in characters and, for a pole display, how many lines it has
+    width: usize,
+    rows: Option<usize>,
+    // Still showing a thank you, which stays up until the next cart is started
+    thanked: bool,
+    // Shown when there's no cart, rendered once up front
+    idle: Vec<String>,
+}
+
+// Labels and what to encode, for QR codes shown under the welcome on a terminal display
+pub fn open(config: &DisplayConfig, qr_codes: &[(String, String)]) -> Result<(), String> {
+    let (out, width, rows) = match config {
+        DisplayConfig::Vfd { device, baud, width } => {
+            let mut port = serial::open(device, *baud)?;
+            // ESC @ resets the display
+            port.write_all(&[0x1b, b'@']).map_err(|e| e.to_string())?;
+            (port, *width, Some(2))
+        }
+        DisplayConfig::Terminal { device, width } => {
+            let out = std::fs::OpenOptions::new()
+                .write(true)
+                .open(device)
+                .map_err(|e| format!("cannot open {}: {}", device, e))?;
+            (out, *width, None)
+        }
+    };
+    let mut idle = vec![WELCOME.to_string()];
+    // No room on a pole display, and it can't draw them anyway
+    if rows.is_none() && !qr_codes.is_empty() {
+        idle.push(String::new());
+        idle.extend(side_by_side(qr_codes));
+    }
+    *DISPLAY.lock().unwrap() = Some(Screen {
+        out,
+        width,
+        rows,
+        thanked: false,
+        idle: idle.clone(),
+    });
+    show(&idle);
+    Ok(())
+}
+
+// Keeps up with the cart, or says hello when there isn't one
+pub fn cart(cart: Option<&Cart>) {
+    let (width, rows, idle) = {
+        let mut display = DISPLAY.lock().unwrap();
+        let screen = match display.as_mut() {
+            Some(s) => s,
+            None => return,
+        };
+        if cart.is_none() && screen.thanked {
+            return;
+        }
+        screen.thanked = false;
+        (screen.width, screen.rows, screen.idle.clone())
+    };
+    match cart {
+        Some(cart) => show(&cart_lines(cart, width, rows)),
+        None => show(&idle),
+    }
+}
+
+pub fn new_balance(user: &bank_core::db::User) {
+    thanks(&format!("New balance {}", money::format(user.balance)));
+}
+
+// e.g. "Pay £4.20" once a purchase has gone through
+pub fn thanks(detail: &str) {
+    show(&["Thank you!".to_string(), detail.to_string()]);
+    if let Some(screen) = DISPLAY.lock().unwrap().as_mut() {
+        screen.thanked = true;
+    }
+}
+
+// What's in the cart with the total at the bottom. A pole display only has room for the last
+// thing scanned.
+pub fn cart_lines(cart: &Cart, width: usize, rows: Option<usize>) -> Vec<String> {
+    let mut lines = cart
+        .products
+        .iter()
+        .map(|p| columns(&p.name, &p.disp_price(), width))
+        .chain(
+            cart.combos
+                .iter()
+                .map(|c| columns(&c.name, &format!("-{}", money::format(c.discount)), width)),
+        )
+        .chain(cart.promo.iter().map(|p| {
+            columns(&p.code, &format!("-{}", money::format(cart.discount())), width)
+        }))
+        .chain(cart.manual_discount.iter().map(|_| {
+            columns("Discount", &format!("-{}", money::format(cart.manual_discount_amount())), width)
+        }))
+        .collect::<Vec<_>>();
+    if let Some(rows) = rows {
+        lines = lines.split_off(lines.len().saturating_sub(rows - 1));
+    }
+    lines.push(columns("Total", &cart.disp_total(), width));
+    lines
+}
+
+// The left text cut short if need be, so the right always fits
+fn columns(left: &str, right: &str, width: usize) -> String {
+    let room = width.saturating_sub(right.chars().count() + 1);
+    let left = left.chars().take(room).collect::<String>();
+    let gap = width.saturating_sub(left.chars().count() + right.chars().count());
+    format!("{}{}{}", left, " ".repeat(gap), right)
+}
+
+fn show(lines: &[String]) {
+    let mut display = DISPLAY.lock().unwrap();
+    let screen = match display.as_mut() {
+        Some(s) => s,
+        None => return,
+    };
+    let written = match screen.rows {
+        Some(rows) => {
+            // Form feed clears the display, and each line is padded out to wrap onto the next
+            let mut out = vec![0x0c];
+            for line in lines.iter().take(rows) {
+                out.extend(vfd_encode(&format!("{:width$}", line, width = screen.width)));
+            }
+            screen.out.write_all(&out)
+        }
+        None => {
+            let mut out = "\x1b[2J\x1b[H".to_string();
+            for line in lines {
+                out.push_str(&format!("{}\r\n", line));
+            }
+            // The total in big text too, as it's what people are looking for
+            if let Some(total) = lines.last().and_then(|l| l.strip_prefix("Total")) {
+                if let Some(big) = bigtext::render(total.trim()) {
+                    out.push_str(&format!("\r\n{}\r\n", big.replace('\n', "\r\n")));
+                }
+            }
+            screen.out.write_all(out.as_bytes())
+        }
+    };
+    if let Err(e) = written {
+        // Don't keep complaining about a display that's been unplugged
+        println!("Customer display error: {}, turning it off", e);
+        *display = None;
+    }
+}
+
+// Each QR code with its label above, in a row
+fn side_by_side(qr_codes: &[(String, String)]) -> Vec<String> {
+    let blocks = qr_codes
+        .iter()
+        .map(|(label, data)| {
+            let qr = qr_lines(data);
+            let width = qr.first().map_or(0, |l| l.chars().count());
+            std::iter::once(format!("{:^width$}", label, width = width)).chain(qr).collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let height = blocks.iter().map(Vec::len).max().unwrap_or(0);
+    (0..height)
+        .map(|i| {
+            blocks
+                .iter()
+                .map(|b| {
+                    let width = b.first().map_or(0, |l| l.chars().count());
+                    format!("{:width$}", b.get(i).map_or("", String::as_str), width = width)
+                })
+                .collect::<Vec<_>>()
+                .join("  ")
+        })
+        .collect()
+}
+
+// Two rows of modules to a line with half blocks, light modules drawn in so it reads on a dark
+// screen, with a quiet zone all round
+fn qr_lines(data: &str) -> Vec<String> {
+    let matrix = match qrcode_generator::to_matrix(data, qrcode_generator::QrCodeEcc::Low) {
+        Ok(m) => m,
+        Err(_) => return Vec::new(),
+    };
+    let size = matrix.len() + 4;
+    let light = |r: usize, c: usize| {
+        r < 2 || c < 2 || !matrix.get(r - 2).and_then(|row| row.get(c - 2)).copied().unwrap_or(false)
+    };
+    (0..size)
+        .step_by(2)
+        .map(|r| {
+            (0..size)
+                .map(|c| match (light(r, c), light(r + 1, c)) {
+                    (true, true) => '\u{2588}',
+                    (true, false) => '\u{2580}',
+                    (false, true) => '\u{2584}',
+                    (false, false) => ' ',
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// Pole displays use the old PC code page, where £ is 0x9c
+fn vfd_encode(text: &str) -> Vec<u8> {
+    text.chars()
+        .map(|c| match c {
+            '£' => 0x9c,
+            c if c.is_ascii() => c as u8,
+            _ => b'?',
+        })
+        .collect()
+}
@@ -0,0 +1,29 @@
+use crate::{config::DrawerConfig, gpio};
+use std::io::Write;
+
+// How long to hold the solenoid open for
+const PULSE: std::time::Duration = std::time::Duration::from_millis(200);
+
+pub fn kick(config: &DrawerConfig) -> Result<(), String> {
+    match config {
+        DrawerConfig::Printer { device, connector } => {
+            let mut printer = std::fs::OpenOptions::new()
+                .write(true)
+                .open(device)
+                .map_err(|e| format!("cannot open {}: {}", device, e))?;
+            printer.write_all(&escpos_kick(*connector)).map_err(|e| e.to_string())
+        }
+        DrawerConfig::Gpio { pin } => {
+            let mut relay = gpio::Pin::output(*pin)?;
+            relay.set(true)?;
+            std::thread::sleep(PULSE);
+            relay.set(false)
+        }
+    }
+}
+
+// ESC p: pulse the drawer kick connector pin (0 for pin 2, 1 for pin 5) on for 50ms and off
+// for 500ms, in units of 2ms
+pub fn escpos_kick(connector: u8) -> [u8; 5] {
+    [0x1b, b'p', connector, 25, 250]
+}
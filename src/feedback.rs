@@ -0,0 +1,82 @@
+use crate::{config::FeedbackConfig, gpio};
+use std::sync::{mpsc, Mutex};
+
+// Lights and beeps for the person at the till, driven from their own thread so the main loop
+// never waits on them
+static SIGNALS: Mutex<Option<mpsc::Sender<Signal>>> = Mutex::new(None);
+
+const BLINK: std::time::Duration = std::time::Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    // Green flash and a short beep
+    Success,
+    // Red and a long beep
+    Failure,
+    // Slow red blink until the reader's back
+    ReaderOffline,
+    ReaderOnline,
+}
+
+pub fn start(config: &FeedbackConfig) -> Result<(), String> {
+    let mut pins = Pins {
+        green: config.green.map(gpio::Pin::output).transpose()?,
+        red: config.red.map(gpio::Pin::output).transpose()?,
+        buzzer: config.buzzer.map(gpio::Pin::output).transpose()?,
+    };
+    let (tx, rx) = mpsc::channel();
+    *SIGNALS.lock().unwrap() = Some(tx);
+    // Runs until the program exits
+    std::thread::spawn(move || pins.run(rx));
+    Ok(())
+}
+
+pub fn signal(signal: Signal) {
+    if let Some(tx) = SIGNALS.lock().unwrap().as_ref() {
+        let _ = tx.send(signal);
+    }
+}
+
+struct Pins {
+    green: Option<gpio::Pin>,
+    red: Option<gpio::Pin>,
+    buzzer: Option<gpio::Pin>,
+}
+
+impl Pins {
+    fn run(&mut self, rx: mpsc::Receiver<Signal>) {
+        let mut offline = false;
+        let mut lit = false;
+        loop {
+            match rx.recv_timeout(BLINK) {
+                Ok(Signal::Success) => self.flash(true, 100),
+                Ok(Signal::Failure) => self.flash(false, 600),
+                Ok(Signal::ReaderOffline) => offline = true,
+                Ok(Signal::ReaderOnline) => offline = false,
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => return,
+            }
+            lit = offline && !lit;
+            set(&mut self.red, lit);
+        }
+    }
+
+    // Lights up, beeps for `beep_ms`, then stays lit for a moment longer
+    fn flash(&mut self, success: bool, beep_ms: u64) {
+        let led = if success { &mut self.green } else { &mut self.red };
+        set(led, true);
+        set(&mut self.buzzer, true);
+        std::thread::sleep(std::time::Duration::from_millis(beep_ms));
+        set(&mut self.buzzer, false);
+        std::thread::sleep(std::time::Duration::from_millis(1000 - beep_ms));
+        let led = if success { &mut self.green } else { &mut self.red };
+        set(led, false);
+    }
+}
+
+// A pin that's stopped working isn't worth interrupting anyone over
+fn set(pin: &mut Option<gpio::Pin>, high: bool) {
+    if let Some(pin) = pin {
+        let _ = pin.set(high);
+    }
+}
@@ -0,0 +1,76 @@
+use std::io::{Read, Seek, Write};
+use std::os::fd::AsRawFd;
+
+// A pin through the sysfs interface, e.g. /sys/class/gpio/gpio17 for BCM pin 17
+pub struct Pin {
+    value: std::fs::File,
+}
+
+impl Pin {
+    // `edge` is which changes `wait` wakes up for: "rising", "falling", or "both"
+    pub fn input(number: u32, edge: &str) -> Result<Self, String> {
+        let dir = export(number)?;
+        write(&format!("{}/direction", dir), "in")?;
+        write(&format!("{}/edge", dir), edge)?;
+        Self::open(&dir, false)
+    }
+
+    pub fn output(number: u32) -> Result<Self, String> {
+        let dir = export(number)?;
+        // Starts off low, rather than flickering high first
+        write(&format!("{}/direction", dir), "low")?;
+        Self::open(&dir, true)
+    }
+
+    fn open(dir: &str, write: bool) -> Result<Self, String> {
+        let value = std::fs::OpenOptions::new()
+            .read(true)
+            .write(write)
+            .open(format!("{}/value", dir))
+            .map_err(|e| format!("cannot open {}/value: {}", dir, e))?;
+        Ok(Self { value })
+    }
+
+    pub fn get(&mut self) -> Result<bool, String> {
+        let mut value = [0; 1];
+        self.value.rewind().map_err(|e| e.to_string())?;
+        self.value.read_exact(&mut value).map_err(|e| e.to_string())?;
+        Ok(value[0] == b'1')
+    }
+
+    pub fn set(&mut self, high: bool) -> Result<(), String> {
+        self.value
+            .write_all(if high { b"1" } else { b"0" })
+            .map_err(|e| e.to_string())
+    }
+
+    // Waits for the pin to change, returning whether it did before the timeout
+    pub fn wait(&mut self, timeout: std::time::Duration) -> Result<bool, String> {
+        let mut poll = libc::pollfd {
+            fd: self.value.as_raw_fd(),
+            events: libc::POLLPRI | libc::POLLERR,
+            revents: 0,
+        };
+        let ready = unsafe { libc::poll(&mut poll, 1, timeout.as_millis() as libc::c_int) };
+        if ready < 0 {
+            return Err(std::io::Error::last_os_error().to_string());
+        }
+        // Reading the value is what clears the change
+        self.get()?;
+        Ok(ready > 0)
+    }
+}
+
+fn export(number: u32) -> Result<String, String> {
+    let dir = format!("/sys/class/gpio/gpio{}", number);
+    if !std::path::Path::new(&dir).exists() {
+        write("/sys/class/gpio/export", &number.to_string())?;
+        // udev needs a moment to give the new files the gpio group
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    Ok(dir)
+}
+
+fn write(path: &str, value: &str) -> Result<(), String> {
+    std::fs::write(path, value).map_err(|e| format!("cannot write {}: {}", path, e))
+}
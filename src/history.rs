@@ -0,0 +1,79 @@
+use crate::config::HistoryConfig;
+use regex::Regex;
+
+// Decides which lines are kept in the readline history, as the next person at the keyboard
+// can scroll back through it
+pub struct HistoryFilter {
+    barcodes: bool,
+    exclude: Vec<Regex>,
+}
+
+// Commands whose arguments start with someone's ID, e.g. "deposit alice 5"
+const NAMES_SOMEONE: [&str; 28] = [
+    "adduser",
+    "addgroup",
+    "addmember",
+    "delmember",
+    "freeze",
+    "unfreeze",
+    "regcard",
+    "delcard",
+    "deposit",
+    "topup",
+    "withdraw",
+    "setlimit",
+    "setdiscount",
+    "adjust",
+    "setrole",
+    "balance",
+    "favourites",
+    "setfavourite",
+    "deposits",
+    "purchases",
+    "exportuser",
+    "link",
+    "setpin",
+    "weblink",
+    "setname",
+    "setemail",
+    "addstandingorder",
+    "settletab",
+];
+
+pub enum LineKind {
+    Command,
+    Barcode,
+    // Checking a balance or paying for a cart, which says who was just here
+    UserLookup,
+}
+
+impl HistoryFilter {
+    pub fn new(config: &HistoryConfig) -> Result<Self, String> {
+        Ok(Self {
+            barcodes: config.barcodes,
+            exclude: config
+                .exclude
+                .iter()
+                .map(|p| Regex::new(p).map_err(|e| format!("invalid history exclusion {}: {}", p, e)))
+                .try_collect()?,
+        })
+    }
+
+    pub fn records(&self, line: &str, kind: LineKind) -> bool {
+        let allowed = match kind {
+            LineKind::Command => true,
+            LineKind::Barcode => self.barcodes,
+            LineKind::UserLookup => false,
+        };
+        allowed && !self.exclude.iter().any(|r| r.is_match(line))
+    }
+}
+
+// The line as it goes into the history, with only the command kept when it names someone
+pub fn saved(line: &str) -> String {
+    let mut words = line.split_whitespace();
+    match words.next() {
+        Some(command) if NAMES_SOMEONE.contains(&command) && words.next().is_some() => command.to_string(),
+        _ => line.to_string(),
+    }
+}
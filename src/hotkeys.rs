@@ -0,0 +1,62 @@
+use rustyline::{
+    history::History, Cmd, ConditionalEventHandler, Editor, Event, EventContext, EventHandler,
+    Helper, KeyEvent, Modifiers, RepeatCount,
+};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+// Used with --hotkeys when the config doesn't set its own
+pub fn defaults() -> HashMap<char, String> {
+    [('c', "cash"), ('a', "abort"), ('u', "usual"), ('?', "help")]
+        .into_iter()
+        .map(|(k, c)| (k, c.to_string()))
+        .collect()
+}
+
+// The command for the last hotkey pressed, taken once readline returns
+pub type Pressed = Arc<Mutex<Option<String>>>;
+
+struct Hotkey {
+    command: String,
+    pressed: Pressed,
+}
+
+impl ConditionalEventHandler for Hotkey {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, ctx: &EventContext) -> Option<Cmd> {
+        // Only on an empty line, so typing a user ID or a leading space still works as normal
+        if !ctx.line().is_empty() {
+            return None;
+        }
+        *self.pressed.lock().unwrap() = Some(self.command.clone());
+        Some(Cmd::AcceptLine)
+    }
+}
+
+pub fn bind<H: Helper, I: History>(editor: &mut Editor<H, I>, keys: &HashMap<char, String>) -> Pressed {
+    let pressed = Pressed::default();
+    for (key, command) in keys {
+        // Digits would get in the way of barcode scanners
+        if key.is_ascii_digit() {
+            continue;
+        }
+        editor.bind_sequence(
+            KeyEvent::new(*key, Modifiers::NONE),
+            EventHandler::Conditional(Box::new(Hotkey {
+                command: command.clone(),
+                pressed: Arc::clone(&pressed),
+            })),
+        );
+    }
+    pressed
+}
+
+// Swaps in the hotkey's command if one was pressed to end the line
+pub fn take(pressed: &Pressed, line: String) -> String {
+    match pressed.lock().unwrap().take() {
+        Some(command) if line.is_empty() => {
+            println!("{}", command);
+            command
+        }
+        _ => line,
+    }
+}
@@ -0,0 +1,138 @@
+use bank_core::{barcode::{Barcode, Symbology}, products::Product};
+use std::fmt::Write;
+
+// Laid out for common 24-up A4 label sheets, sizes in mm
+const PAGE_WIDTH: f64 = 210.0;
+const PAGE_HEIGHT: f64 = 297.0;
+const COLUMNS: usize = 3;
+const ROWS: usize = 8;
+const LABEL_WIDTH: f64 = 63.5;
+const LABEL_HEIGHT: f64 = 33.9;
+const MARGIN_LEFT: f64 = 7.2;
+const MARGIN_TOP: f64 = 12.9;
+const COLUMN_GAP: f64 = 2.5;
+const MODULE_WIDTH: f64 = 0.33;
+const BAR_HEIGHT: f64 = 12.0;
+
+pub const LABELS_PER_PAGE: usize = COLUMNS * ROWS;
+
+const L_CODES: [&str; 10] = [
+    "0001101", "0011001", "0010011", "0111101", "0100011", "0110001", "0101111", "0111011",
+    "0110111", "0001011",
+];
+// Which of the left hand digits use the G codes, picked by the first digit of an EAN-13
+const PARITY: [&str; 10] = [
+    "LLLLLL", "LLGLGG", "LLGGLG", "LLGGGL", "LGLLGG", "LGGLLG", "LGGGLL", "LGLGLG", "LGLGGL",
+    "LGGLGL",
+];
+
+fn l_code(digit: u8) -> Vec<bool> {
+    L_CODES[digit as usize].chars().map(|c| c == '1').collect()
+}
+
+fn r_code(digit: u8) -> Vec<bool> {
+    l_code(digit).into_iter().map(|m| !m).collect()
+}
+
+fn g_code(digit: u8) -> Vec<bool> {
+    r_code(digit).into_iter().rev().collect()
+}
+
+fn pattern(s: &str) -> Vec<bool> {
+    s.chars().map(|c| c == '1').collect()
+}
+
+// Bars and spaces of the barcode, true for a bar. EAN-8 codes are drawn as they are, UPC-A and
+// short codes as EAN-13, and GTIN-14 codes can't be drawn.
+pub fn modules(barcode: &Barcode) -> Option<Vec<bool>> {
+    let digits = barcode.digits();
+    let mut out = pattern("101");
+    match barcode.symbology() {
+        Symbology::Gtin14 => return None,
+        Symbology::Ean8 => {
+            let d = &digits[6..];
+            for digit in &d[..4] {
+                out.extend(l_code(*digit));
+            }
+            out.extend(pattern("01010"));
+            for digit in &d[4..] {
+                out.extend(r_code(*digit));
+            }
+        }
+        _ => {
+            let d = &digits[1..];
+            for (digit, parity) in d[1..7].iter().zip(PARITY[d[0] as usize].chars()) {
+                out.extend(if parity == 'G' { g_code(*digit) } else { l_code(*digit) });
+            }
+            out.extend(pattern("01010"));
+            for digit in &d[7..] {
+                out.extend(r_code(*digit));
+            }
+        }
+    }
+    out.extend(pattern("101"));
+    Some(out)
+}
+
+pub fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+// One A4 page of labels as SVG, for up to LABELS_PER_PAGE products
+pub fn page(products: &[&Product]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}mm" height="{h}mm" viewBox="0 0 {w} {h}">"#,
+        w = PAGE_WIDTH,
+        h = PAGE_HEIGHT
+    );
+    for (i, product) in products.iter().take(LABELS_PER_PAGE).enumerate() {
+        let x = MARGIN_LEFT + (i % COLUMNS) as f64 * (LABEL_WIDTH + COLUMN_GAP);
+        let y = MARGIN_TOP + (i / COLUMNS) as f64 * LABEL_HEIGHT;
+        let centre = x + LABEL_WIDTH / 2.0;
+        let _ = writeln!(
+            out,
+            r#"<text x="{:.2}" y="{:.2}" font-family="sans-serif" font-size="4" text-anchor="middle">{}</text>"#,
+            centre,
+            y + 6.0,
+            escape(&product.name)
+        );
+        let _ = writeln!(
+            out,
+            r#"<text x="{:.2}" y="{:.2}" font-family="sans-serif" font-size="7" font-weight="bold" text-anchor="middle">{}</text>"#,
+            centre,
+            y + 13.0,
+            product.disp_price()
+        );
+
+        let bars_y = y + 15.5;
+        if let Some(modules) = modules(&product.barcode) {
+            let start = centre - modules.len() as f64 * MODULE_WIDTH / 2.0;
+            for (m, bar) in modules.iter().enumerate() {
+                if *bar {
+                    let _ = writeln!(
+                        out,
+                        r#"<rect x="{:.3}" y="{:.2}" width="{}" height="{}"/>"#,
+                        start + m as f64 * MODULE_WIDTH,
+                        bars_y,
+                        MODULE_WIDTH,
+                        BAR_HEIGHT
+                    );
+                }
+            }
+        }
+        let _ = writeln!(
+            out,
+            r#"<text x="{:.2}" y="{:.2}" font-family="monospace" font-size="3" text-anchor="middle">{}</text>"#,
+            centre,
+            bars_y + BAR_HEIGHT + 3.5,
+            match product.barcode.symbology() {
+                Symbology::Ean8 | Symbology::Gtin14 => product.barcode.written(product.barcode.symbology()),
+                _ => product.barcode.written(Symbology::Ean13),
+            }
+        );
+    }
+    out.push_str("</svg>\n");
+    out
+}
@@ -0,0 +1,347 @@
+use crate::config::LedgerConfig;
+use bank_core::db::{DepositMethod, Transaction, TransactionActor, TransactionType};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Ledger,
+    Beancount,
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ledger" => Ok(Self::Ledger),
+            "beancount" => Ok(Self::Beancount),
+            _ => Err(format!(
+                "unknown format {}, expected ledger or beancount",
+                s
+            )),
+        }
+    }
+}
+
+impl Format {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Ledger => "ledger",
+            Self::Beancount => "beancount",
+        }
+    }
+}
+
+// Where money sits. Everything the bank does moves money between these, so the books always
+// balance.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Account {
+    // A user's or group's balance, money the bank owes them
+    User(String),
+    // Balances that were anonymised, nobody knows whose any more
+    Anonymous,
+    // A guest tab, money owed to the bank
+    Tab(String),
+    // The cash box, in the home currency or another one
+    Cash(Option<String>),
+    Bank,
+    Card,
+    Vouchers,
+    Sales,
+    Rounding,
+    StandingOrders,
+    DepositFees,
+    Adjustments,
+    Expenses,
+}
+
+impl Account {
+    pub fn name(&self, accounts: &LedgerConfig, format: Format) -> String {
+        match self {
+            Self::User(id) => sub_account(&accounts.users, id, format),
+            Self::Anonymous => accounts.users.clone(),
+            Self::Tab(name) => sub_account(&accounts.tabs, name, format),
+            Self::Cash(None) => accounts.cash.clone(),
+            Self::Cash(Some(currency)) => sub_account(&accounts.cash, currency, format),
+            Self::Bank => accounts.bank.clone(),
+            Self::Card => accounts.card.clone(),
+            Self::Vouchers => accounts.vouchers.clone(),
+            Self::Sales => accounts.sales.clone(),
+            Self::Rounding => accounts.rounding.clone(),
+            Self::StandingOrders => accounts.standing_orders.clone(),
+            Self::DepositFees => accounts.deposit_fees.clone(),
+            Self::Adjustments => accounts.adjustments.clone(),
+            Self::Expenses => accounts.expenses.clone(),
+        }
+    }
+}
+
+pub struct Posting {
+    pub account: Account,
+    // In pence
+    pub amount: i64,
+    // Foreign cash actually handed over, as (currency, minor units)
+    pub tendered: Option<(String, u32)>,
+}
+
+pub struct Entry {
+    pub description: String,
+    // Always adding up to zero
+    pub postings: Vec<Posting>,
+}
+
+// A posting before the entry is balanced, with no amount for the one that balances it
+struct Leg {
+    account: Account,
+    amount: Option<i64>,
+    tendered: Option<(String, u32)>,
+}
+
+fn leg(account: Account, amount: i64) -> Leg {
+    Leg {
+        account,
+        amount: Some(amount),
+        tendered: None,
+    }
+}
+
+// Whatever's left over, like leaving out an amount in ledger
+fn rest(account: Account) -> Leg {
+    Leg {
+        account,
+        amount: None,
+        tendered: None,
+    }
+}
+
+fn balanced(description: String, legs: Vec<Leg>) -> Entry {
+    debug_assert_eq!(legs.iter().filter(|l| l.amount.is_none()).count(), 1);
+    let total = legs.iter().filter_map(|l| l.amount).sum::<i64>();
+    Entry {
+        description,
+        postings: legs
+            .into_iter()
+            .map(|l| Posting {
+                account: l.account,
+                amount: l.amount.unwrap_or(-total),
+                tendered: l.tendered,
+            })
+            .collect(),
+    }
+}
+
+// The transaction as a double-entry accounting entry, or None if no money changes hands
+pub fn entry(t: &Transaction) -> Option<Entry> {
+    // The account money moves out of when this actor pays for something
+    let payer = match &t.actor {
+        TransactionActor::User(id) | TransactionActor::Group { group: id, .. } => {
+            Account::User(id.clone())
+        }
+        TransactionActor::Tab(name) => Account::Tab(name.clone()),
+        TransactionActor::Cash | TransactionActor::Stock => Account::Cash(None),
+        TransactionActor::Anonymous => Account::Anonymous,
+    };
+    Some(match &t.transaction {
+        TransactionType::Purchase {
+            total,
+            tendered,
+            rounding,
+            ..
+        } => {
+            let mut legs = vec![
+                Leg {
+                    account: match tendered {
+                        Some(t) => Account::Cash(Some(t.currency.clone())),
+                        None => payer,
+                    },
+                    // Foreign rounding is already in the tendered amount's price
+                    amount: Some(*total as i64 + if tendered.is_none() { *rounding as i64 } else { 0 }),
+                    tendered: tendered.as_ref().map(|t| (t.currency.clone(), t.amount)),
+                },
+                leg(Account::Sales, -(*total as i64)),
+            ];
+            if tendered.is_none() && *rounding != 0 {
+                legs.push(rest(Account::Rounding));
+            } else {
+                legs[1].amount = None;
+            }
+            balanced(
+                match &t.actor {
+                    TransactionActor::Cash => "Cash purchase".to_string(),
+                    actor => format!("Purchase by {}", actor),
+                },
+                legs,
+            )
+        }
+        TransactionType::Deposit { amount, method, fee } => {
+            let from = match method {
+                DepositMethod::Cash => Account::Cash(None),
+                DepositMethod::BankTransfer => Account::Bank,
+                DepositMethod::Voucher => Account::Vouchers,
+                DepositMethod::Card => Account::Card,
+            };
+            let mut legs = vec![leg(from, *amount as i64), rest(payer)];
+            if *fee > 0 {
+                legs.push(leg(Account::DepositFees, -(*fee as i64)));
+            }
+            balanced(format!("Deposit by {} ({})", t.actor, method), legs)
+        }
+        TransactionType::StandingOrder {
+            description,
+            amount,
+            ..
+        } => balanced(
+            format!("Standing order {} for {}", description, t.actor),
+            vec![leg(payer, *amount as i64), rest(Account::StandingOrders)],
+        ),
+        TransactionType::SettleTab { tab, amount } => balanced(
+            format!("Settling tab {}", tab),
+            vec![
+                // The tab itself as the actor means it was paid off in cash
+                match &t.actor {
+                    TransactionActor::Tab(_) => leg(Account::Cash(None), *amount as i64),
+                    _ => leg(payer, *amount as i64),
+                },
+                rest(Account::Tab(tab.clone())),
+            ],
+        ),
+        TransactionType::Adjustment { amount, reason, .. } => balanced(
+            format!("Adjustment for {}: {}", t.actor, reason),
+            vec![leg(Account::Adjustments, *amount as i64), rest(payer)],
+        ),
+        TransactionType::Withdrawal { amount } => balanced(
+            format!("Cash withdrawal by {}", t.actor),
+            vec![leg(payer, *amount as i64), rest(Account::Cash(None))],
+        ),
+        TransactionType::Void { transaction, amount, .. } => balanced(
+            format!("Void of {} by {}", transaction, t.actor),
+            vec![leg(Account::Sales, *amount as i64), rest(payer)],
+        ),
+        TransactionType::Refund {
+            transaction,
+            amount,
+            tendered,
+        } => balanced(
+            format!("Refund of {} to {}", transaction, t.actor),
+            vec![
+                // Cash sales come back out of takings, deposits out of the user's balance
+                match &t.actor {
+                    TransactionActor::Cash => leg(Account::Sales, *amount as i64),
+                    _ => leg(payer, *amount as i64),
+                },
+                Leg {
+                    account: Account::Cash(tendered.as_ref().map(|t| t.currency.clone())),
+                    amount: None,
+                    tendered: tendered.as_ref().map(|t| (t.currency.clone(), t.amount)),
+                },
+            ],
+        ),
+        TransactionType::Expense {
+            amount,
+            description,
+        } => balanced(
+            format!("Paid from the cash box: {}", description),
+            vec![leg(Account::Expenses, *amount as i64), rest(Account::Cash(None))],
+        ),
+        TransactionType::WriteOff { .. }
+        | TransactionType::Restock { .. }
+        | TransactionType::Stocktake { .. }
+        | TransactionType::DrawerOpened { .. } => return None,
+    })
+}
+
+// What each account adds up to, in pence, with money the bank owes as negative. The totals
+// always come to zero.
+pub fn balances<'a>(transactions: impl IntoIterator<Item = &'a Transaction>) -> BTreeMap<Account, i64> {
+    let mut balances = BTreeMap::new();
+    for posting in transactions.into_iter().filter_map(entry).flat_map(|e| e.postings) {
+        *balances.entry(posting.account).or_default() += posting.amount;
+    }
+    balances
+}
+
+// Turns the transaction log into double-entry accounting entries
+pub fn export(transactions: &[Transaction], accounts: &LedgerConfig, format: Format) -> String {
+    let entries = transactions
+        .iter()
+        .filter_map(|t| Some((t, entry(t)?)))
+        .collect::<Vec<_>>();
+
+    let mut out = String::new();
+    if format == Format::Beancount {
+        // Beancount won't accept postings to accounts that haven't been opened
+        let mut opened = entries
+            .iter()
+            .flat_map(|(_, e)| e.postings.iter().map(|p| p.account.name(accounts, format)))
+            .collect::<Vec<_>>();
+        opened.sort();
+        opened.dedup();
+        let start = transactions
+            .first()
+            .map(|t| t.timestamp.format("%Y-%m-%d").to_string());
+        for account in opened {
+            let _ = writeln!(
+                out,
+                "{} open {}",
+                start.as_deref().unwrap_or("1970-01-01"),
+                account
+            );
+        }
+        out.push('\n');
+    }
+
+    for (t, Entry { description, postings }) in entries {
+        match format {
+            Format::Ledger => {
+                let _ = writeln!(out, "{} * {}", t.timestamp.format("%Y/%m/%d"), description);
+                let _ = writeln!(out, "    ; id: {}", t.id);
+            }
+            Format::Beancount => {
+                let _ = writeln!(
+                    out,
+                    "{} * \"{}\"",
+                    t.timestamp.format("%Y-%m-%d"),
+                    description.replace('"', "'")
+                );
+                let _ = writeln!(out, "  id: \"{}\"", t.id);
+            }
+        }
+        for p in postings {
+            let amount = match &p.tendered {
+                // The total cost after @@ is always positive, the sign goes on the quantity
+                Some((currency, minor)) => format!(
+                    "{}{:.2} {} @@ {:.2} GBP",
+                    if p.amount < 0 { "-" } else { "" },
+                    *minor as f64 / 100.0,
+                    currency,
+                    p.amount.abs() as f64 / 100.0
+                ),
+                None => format!("{:.2} GBP", p.amount as f64 / 100.0),
+            };
+            let _ = writeln!(out, "    {}  {}", p.account.name(accounts, format), amount);
+        }
+        out.push('\n');
+    }
+    out
+}
+
+// Beancount account components have to start with a capital letter or digit, and can only
+// contain letters, digits, and dashes
+fn sub_account(parent: &str, name: &str, format: Format) -> String {
+    let name = match format {
+        Format::Ledger => name.to_string(),
+        Format::Beancount => {
+            let mut chars = name
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+                .collect::<Vec<_>>();
+            match chars.first_mut() {
+                Some(c) if c.is_ascii_alphanumeric() => c.make_ascii_uppercase(),
+                _ => chars.insert(0, 'X'),
+            }
+            chars.into_iter().collect()
+        }
+    };
+    format!("{}:{}", parent, name)
+}
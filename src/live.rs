@@ -0,0 +1,31 @@
+use std::sync::OnceLock;
+use tokio::sync::broadcast;
+
+// Events kept for a slow listener before it starts missing them
+const BACKLOG: usize = 64;
+
+// Everything happening at the till as it happens, for /api/live. Nothing is kept until someone
+// starts listening.
+static FEED: OnceLock<broadcast::Sender<String>> = OnceLock::new();
+
+pub fn subscribe() -> broadcast::Receiver<String> {
+    FEED.get_or_init(|| broadcast::channel(BACKLOG).0).subscribe()
+}
+
+// Takes the same {"event", "timestamp", "data"} JSON that webhooks are sent
+pub fn publish(message: &[u8]) {
+    if let Some(feed) = FEED.get() {
+        // Nobody listening right now
+        let _ = feed.send(String::from_utf8_lossy(message).into_owned());
+    }
+}
+
+// Card taps aren't worth a webhook, but a wall display might want to say hello
+pub fn card_tap(user: &str) {
+    let message = serde_json::json!({
+        "event": "card_tap",
+        "timestamp": chrono::Utc::now(),
+        "data": { "user": user },
+    });
+    publish(message.to_string().as_bytes());
+}
@@ -4,28 +4,73 @@
 #[macro_use]
 extern crate serde;
 
-use ansi_term::{Color, Style};
+use bank_core::paths::data_path;
+use bank_core::style::{theme, Style};
+use bank_core::{
+    barcode,
+    cart::Cart,
+    combo::Combo,
+    db::{self, Role, Transaction, User},
+    money, products,
+};
 use completion::Hintererer;
-use db::{User, Transaction};
-use nfc1::target_info;
+use reader::CardEvent;
 use rustyline::{error::ReadlineError, Editor};
 use std::{
     future::Future,
     io::{Stdout, Write},
+    path::{Path, PathBuf},
     pin::Pin,
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
     },
 };
-use tokio::{select, sync::mpsc::{self, Receiver}};
+use tokio::{
+    select,
+    signal::unix::{signal, SignalKind},
+    sync::mpsc::{self, Receiver},
+};
 
-mod barcode;
+mod acceptor;
+mod api;
+mod backup;
+mod bigtext;
+mod camera;
+mod buttons;
+mod cli;
 mod completion;
-mod db;
-mod products;
+mod config;
+mod display;
+mod drawer;
+mod feedback;
+mod gpio;
+mod history;
+mod hotkeys;
+mod labels;
+mod live;
+mod ledger;
+mod members;
+mod notify;
+mod oidc;
+mod pricelist;
+mod reader;
+mod reports;
+mod scanner;
+mod script;
+mod secrets;
+mod seed;
+mod speech;
+mod serial;
+mod session;
+mod sumup;
+mod systemd;
+#[cfg(test)]
+mod tests;
+mod watch;
+mod webhooks;
 
-const FORBIDDEN_USERS: [&str; 16] = [
+const FORBIDDEN_USERS: [&str; 101] = [
     "help",
     "?",
     "hilfe",
@@ -42,236 +87,1044 @@ const FORBIDDEN_USERS: [&str; 16] = [
     "clear",
     "regcard",
     "delcard",
+    "exportuser",
+    "sync",
+    "importusers",
+    "link",
+    "setpin",
+    "setname",
+    "setemail",
+    "clearhistory",
+    "standingorders",
+    "addstandingorder",
+    "cancelstandingorder",
+    "makevoucher",
+    "redeem",
+    "promo",
+    "promos",
+    "addpromo",
+    "delpromo",
+    "cashbox",
+    "taxreport",
+    "exportledger",
+    "guest",
+    "tabs",
+    "settletab",
+    "addgroup",
+    "addmember",
+    "delmember",
+    "freeze",
+    "unfreeze",
+    "adjust",
+    "overrideprice",
+    "discount",
+    "writeoff",
+    "writeoffs",
+    "park",
+    "parked",
+    "resume",
+    "setdiscount",
+    "labels",
+    "pricelist",
+    "importproducts",
+    "pendingcards",
+    "approvecard",
+    "rejectcard",
+    "setlimit",
+    "overridelimit",
+    "buyer",
+    "refund",
+    "withdraw",
+    "fsck",
+    "rebuild-balances",
+    "zreport",
+    "restock",
+    "stock",
+    "reorder",
+    "suppliers",
+    "addsupplier",
+    "po",
+    "expense",
+    "margin",
+    "stocktake",
+    "shrinkage",
+    "forecast",
+    "tokens",
+    "issuetoken",
+    "revoketoken",
+    "setrole",
+    "balance",
+    "pay",
+    "logout",
+    "favourites",
+    "setfavourite",
+    "usual",
+    "chart",
+    "again",
+    "topup",
+    "weblink",
+    "dbdiff",
+    "restore",
+    "books",
+    "stockvalue",
+    "watch",
+    "aging",
+    "void",
+    "tx",
+    "prodstats",
+];
+// Lookups and listings that are still allowed with --read-only
+const READ_ONLY_COMMANDS: [&str; 35] = [
+    "prodstats",
+    "tx",
+    "aging",
+    "watch",
+    "stockvalue",
+    "books",
+    "dbdiff",
+    "topup",
+    "chart",
+    "favourites",
+    "balance",
+    "logout",
+    "tokens",
+    "forecast",
+    "shrinkage",
+    "margin",
+    "suppliers",
+    "stock",
+    "reorder",
+    "help",
+    "?",
+    "hilfe",
+    "products",
+    "users",
+    "deposits",
+    "purchases",
+    "writeoffs",
+    "standingorders",
+    "promos",
+    "pendingcards",
+    "parked",
+    "tabs",
+    "fsck",
+    "taxreport",
+    "clear",
 ];
 const MONZO_USERNAME: &str = "davidhibberd";
+const HOME_CURRENCY: &str = "GBP";
+const STANDING_ORDER_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+const RETENTION_INTERVAL: std::time::Duration = std::time::Duration::from_secs(24 * 60 * 60);
 
-pub struct Cart {
-    products: Vec<products::Product>,
-}
-
-impl Cart {
-    fn new() -> Self {
-        Self {
-            products: Vec::new(),
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let data_dir = std::env::args().skip_while(|a| a != "--data-dir").nth(1).map(PathBuf::from);
+    bank_core::paths::set_data_dir(data_dir.unwrap_or_else(|| {
+        bank_core::paths::default_data_dir(|v| std::env::var(v).ok(), Path::new("./data").is_dir())
+    }));
+    if std::env::args().skip(1).any(|a| a == "--seed") {
+        if let Err(e) = seed::seed() {
+            println!("Error, unable to seed data: {}", e);
         }
+        return Ok(());
     }
-
-    fn total(&self) -> u32 {
-        self.products.iter().map(|p| p.price).sum()
+    if let Some(format) = std::env::args().skip_while(|a| a != "--convert-db").nth(1) {
+        match db::Format::parse(&format) {
+            Some(format) => match db::DB::convert(format) {
+                Ok(from) => println!("Database converted from {} to {}", from, format),
+                Err(e) => println!("Error, unable to convert database: {}", e),
+            },
+            None => println!("Usage: 57bank --convert-db <ron | cbor>"),
+        }
+        return Ok(());
     }
-
-    fn disp_total(&self) -> String {
-        format!("£{:.2}", self.total() as f64 / 100.0)
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let args = match args.iter().position(|a| a == "--data-dir") {
+        Some(i) => [&args[..i], args.get(i + 2..).unwrap_or_default()].concat(),
+        None => args,
+    };
+    if let Some(code) = cli::run(&args) {
+        std::process::exit(code);
     }
-
-    fn print(&self) {
-        println!("{}", Style::new().bold().underline().paint("Current cart"));
-        for product in &self.products {
-            println!("- {} ({})", product.name, product.disp_price());
+    if let Some(path) = std::env::args().skip_while(|a| a != "--script").nth(1) {
+        if let Err(e) = script::open(&path) {
+            println!("Error, {}", e);
+            return Ok(());
         }
-        println!("Total: {}", self.disp_total());
     }
-}
+    // Lines come from a script rather than someone at the till, and it stops at the first error
+    let scripted = script::piped();
+    let read_only = std::env::args().skip(1).any(|a| a == "--read-only");
+    // Read-only mode never writes anything either, it just sees changes others make
+    let dry_run = read_only
+        || std::env::args()
+            .skip(1)
+            .any(|a| a == "--dry-run" || a == "--training");
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let db = match db::DB::load() {
+    let config = match config::read_config() {
+        Ok(c) => c,
+        Err(e) => {
+            println!("Error, unable to load config: {}", e);
+            return Ok(());
+        }
+    };
+    bank_core::style::set_plain(config.plain());
+    bank_core::style::set_theme(config.theme.clone());
+    bank_core::money::set_format(config.money.clone());
+    bigtext::set_enabled(config.big_text || std::env::args().skip(1).any(|a| a == "--big-text"));
+    if let Some(lights) = &config.feedback {
+        if let Err(e) = feedback::start(lights) {
+            println!("Error, unable to set up the status lights: {}", e);
+        }
+    }
+    notify::init(config.notify.clone());
+    if let Some(speech) = &config.speech {
+        speech::start(speech);
+    }
+    if let Some(customer_display) = &config.customer_display {
+        let qr_codes = config
+            .deposits
+            .presets
+            .iter()
+            .map(|a| (money::format(*a), payment_link(*a, "57Bank")))
+            .collect::<Vec<_>>();
+        if let Err(e) = display::open(customer_display, &qr_codes) {
+            println!("Customer display error: {}", e);
+        }
+    }
+    let db = if read_only {
+        db::DB::load_read_only()
+    } else {
+        db::DB::load(config.sync.push.clone(), dry_run)
+    };
+    let mut db = match db {
         Ok(d) => d,
         Err(e) => {
             println!("Error, unable to open database: {}", e);
             return Ok(());
         }
     };
-    let mut product_store = match products::read_products() {
+    if let Some(till) = &config.till {
+        db.set_till(till);
+    }
+    db.set_deposit_fees(config.deposit_fees.clone());
+
+    // Saving rewrites the whole file, so do it in the background rather than making people wait.
+    // The channel only holds one request, so changes made during a save are coalesced into the next one.
+    let (save_tx, mut save_rx) = mpsc::channel::<()>(1);
+    if !read_only {
+        db.defer_saves(move || {
+            let _ = save_tx.try_send(());
+        });
+    }
+    let db = Arc::new(db);
+    let saver_db = Arc::clone(&db);
+    tokio::spawn(async move {
+        while save_rx.recv().await.is_some() {
+            let db = Arc::clone(&saver_db);
+            match tokio::task::spawn_blocking(move || db.flush()).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => {
+                    println!("Error, unable to save database: {}", e);
+                    notify::send(notify::Notice::SaveFailed, &format!("Unable to save the database: {}", e));
+                }
+                Err(e) => println!("Error, database saver failed: {}", e),
+            }
+        }
+    });
+    if let (Some(backup), false) = (config.backup.clone(), dry_run) {
+        let backup_db = Arc::clone(&db);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(backup.interval));
+            let mut shipped = None;
+            loop {
+                interval.tick().await;
+                let db = Arc::clone(&backup_db);
+                let backup = backup.clone();
+                let result = tokio::task::spawn_blocking(move || {
+                    db.flush()?;
+                    let modified = backup::last_modified();
+                    if modified.is_some() && modified == shipped {
+                        return Ok(shipped);
+                    }
+                    backup::ship_snapshot(&backup).map(|_| modified)
+                })
+                .await;
+                match result {
+                    Ok(Ok(m)) => shipped = m,
+                    Ok(Err(e)) => {
+                        println!("Error, unable to back up database: {}", e);
+                        notify::send(notify::Notice::BackupFailed, &format!("Unable to back up the database: {}", e));
+                    }
+                    Err(e) => println!("Error, database backup failed: {}", e),
+                }
+            }
+        });
+    }
+    if let (Some(at), false) = (config.end_of_day, dry_run) {
+        let summary_db = Arc::clone(&db);
+        tokio::spawn(async move {
+            loop {
+                let now = chrono::Local::now();
+                let wait = (reports::next_run(now, at) - now).to_std().unwrap_or_default();
+                tokio::time::sleep(wait).await;
+                let db = Arc::clone(&summary_db);
+                let _ = tokio::task::spawn_blocking(move || reports::end_of_day(&db, HOME_CURRENCY)).await;
+            }
+        });
+    }
+    let retention = config.retention.clone();
+    if (retention.anonymise_after_days.is_some() || retention.forget_cards_after_days.is_some())
+        && !read_only
+        && !dry_run
+    {
+        let retention_db = Arc::clone(&db);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(RETENTION_INTERVAL);
+            loop {
+                interval.tick().await;
+                let db = Arc::clone(&retention_db);
+                let retention = retention.clone();
+                let result = tokio::task::spawn_blocking(move || {
+                    let cutoff = |days: u32| chrono::Utc::now() - chrono::Duration::days(days as i64);
+                    db.apply_retention(
+                        retention.anonymise_after_days.map(cutoff),
+                        retention.forget_cards_after_days.map(cutoff),
+                    )
+                })
+                .await;
+                match result {
+                    Ok(Ok((0, 0))) => {}
+                    Ok(Ok((anonymised, cards))) => println!(
+                        "Retention policy: anonymised {} transactions, forgot {} unused cards",
+                        anonymised, cards
+                    ),
+                    Ok(Err(e)) => println!("Error, unable to apply retention policy: {}", e),
+                    Err(e) => println!("Error, retention policy failed: {}", e),
+                }
+            }
+        });
+    }
+    if let Some(api) = config.api.clone() {
+        tokio::spawn(api::serve(api, Arc::clone(&db), config.clone()));
+    }
+    let history_filter = match history::HistoryFilter::new(&config.history) {
+        Ok(h) => h,
+        Err(e) => {
+            println!("Error, unable to load config: {}", e);
+            return Ok(());
+        }
+    };
+    let mut product_store = match load_products(&config) {
         Ok(p) => p,
         Err(e) => {
             println!("Error, unable to load products: {}", e);
             return Ok(());
         }
     };
+    update_price_list(&product_store, &config);
+    // Nothing happens for real in training mode, so don't tell anyone about it
+    let webhooks = webhooks::Webhooks::new(if dry_run {
+        Vec::new()
+    } else {
+        config.webhooks.clone()
+    });
     let mut cart: Option<Cart> = None;
+    // Carts set aside so someone else can check out in the meantime
+    let mut parked: Vec<Cart> = Vec::new();
+    let mut script_failed = false;
+
+    let mut sync_interval = if config.sync.interval == 0 || dry_run {
+        None
+    } else {
+        Some(tokio::time::interval(std::time::Duration::from_secs(
+            config.sync.interval,
+        )))
+    };
+
+    let mut watchdog = systemd::watchdog_interval().map(tokio::time::interval);
+    // The first tick is straight away, to catch up on anything due while we weren't running
+    let mut standing_order_interval =
+        (!read_only).then(|| tokio::time::interval(STANDING_ORDER_INTERVAL));
+    let mut shutdown_signals = [
+        signal(SignalKind::terminate())?,
+        signal(SignalKind::hangup())?,
+        signal(SignalKind::interrupt())?,
+    ];
+    let terminal = save_terminal();
 
     let mut stdout = std::io::stdout();
-    clear(&mut stdout);
+    if !scripted {
+        clear(&mut stdout);
+    }
+    if read_only {
+        println!(
+            "{}",
+            Style::new()
+                .bold()
+                .on(theme().mode)
+                .paint("READ-ONLY - balances and listings only")
+        );
+    } else if dry_run {
+        println!(
+            "{}",
+            Style::new()
+                .bold()
+                .on(theme().mode)
+                .paint("TRAINING MODE - nothing you do will be saved")
+        );
+    }
 
-    let (card_tx, mut card_rx_handle) = mpsc::channel::<Vec<u8>>(1);
+    let (card_tx, mut card_rx_handle) = mpsc::channel::<CardEvent>(1);
     let stop_reader = Arc::new(AtomicBool::new(false));
     let stop_clone = Arc::clone(&stop_reader);
 
-    std::thread::spawn(move || {
-        let mut context = nfc1::Context::new().unwrap();
-        let mut device = context.open().unwrap();
-        device.initiator_init().unwrap();
+    let debounce = std::time::Duration::from_millis(config.reader.debounce);
+    let card_reader = reader::open(&config.reader);
+    let reader_thread =
+        std::thread::spawn(move || reader::card_reader(card_reader, card_tx, stop_clone, debounce));
 
-        loop {
-            if stop_clone.load(Ordering::Relaxed) {
-                break;
-            }
-            match device.initiator_poll_target(&[nfc1::Modulation {
-                modulation_type: nfc1::ModulationType::Iso14443a,
-                baud_rate: nfc1::BaudRate::Baud106,
-            }], 255, std::time::Duration::from_millis(300)) {
-                Ok(target) => {
-                    match target.target_info {
-                        target_info::TargetInfo::Iso14443a(target_info::Iso14443a { uid, uid_len, .. }) => {
-                            if uid_len != 0 {
-                                card_tx.blocking_send(uid[..uid_len].to_vec()).unwrap();
-                                std::thread::sleep(std::time::Duration::from_secs(1));
-                            }
-                        },
-                        a => {
-                            println!("Unknown target: {:?}", a);
-                        }
-                    }
-                }
-                Err(_) => continue,
-            }
-        }
+    let (scan_tx, mut scan_rx) = mpsc::channel::<String>(5);
+    if let Some(camera) = config.camera.clone() {
+        let scan_tx = scan_tx.clone();
+        // Not waited for at shutdown, as it's blocked reading from zbarcam
+        std::thread::spawn(move || camera::camera(camera, scan_tx));
+    }
+    let scanner_thread = config.scanner.clone().map(|scanner| {
+        let stop = Arc::clone(&stop_reader);
+        std::thread::spawn(move || scanner::scanner(scanner, scan_tx, stop))
+    });
+
+    let (press_tx, mut press_rx) = mpsc::channel::<()>(5);
+    let button_threads = buttons::watch(&config.buttons, press_tx, &stop_reader);
+
+    let acceptor = config.acceptor.clone().map(|config| {
+        let acceptor = acceptor::Acceptor::default();
+        let handle = acceptor.clone();
+        let stop = Arc::clone(&stop_reader);
+        let thread = std::thread::spawn(move || acceptor::acceptor(config, handle, stop));
+        (acceptor, thread)
     });
 
     let (stdin_tx, mut stdin_rx_handle) = mpsc::channel::<StdoutMsg>(5);
-    let (stdin_ready_tx, mut stdin_ready_rx) = mpsc::channel::<bool>(1);
+    let (stdin_ready_tx, mut stdin_ready_rx) = mpsc::channel::<Prompt>(1);
 
     let stop_clone = Arc::clone(&stop_reader);
 
+    // Single keypresses on an empty prompt run a command straight away
+    let hotkeys = match (&config.hotkeys, std::env::args().skip(1).any(|a| a == "--hotkeys")) {
+        (keys, _) if !keys.is_empty() => keys.clone(),
+        (_, true) => hotkeys::defaults(),
+        _ => std::collections::HashMap::new(),
+    };
+    let hinter_products = product_store.clone();
+    let user_ids = db
+        .users()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|u| u.id)
+        .collect::<Vec<_>>();
     std::thread::spawn(move || {
+        if scripted {
+            return script::feed(stdin_tx, stdin_ready_rx);
+        }
+        let (history, usage) = (data_path("history"), data_path("usage"));
         let mut stdin = Editor::new().unwrap();
-        stdin.set_helper(Some(Hintererer::new()));
-        if stdin.load_history("data/history").is_err() {
+        let mut hinter = Hintererer::new(&user_ids);
+        hinter.load_usage(&usage);
+        hinter.set_products(&hinter_products);
+        stdin.set_helper(Some(hinter));
+        let pressed = hotkeys::bind(&mut stdin, &hotkeys);
+        if stdin.load_history(&history).is_err() {
             println!("No previous history.");
         }
 
         let mut cart_in_progress = false;
+        let mut projected: Option<String> = None;
+        let mut logged_in = String::new();
+        let mode = if read_only {
+            Style::new().bold().on(theme().mode).paint("(read-only)").to_string()
+        } else if dry_run {
+            Style::new().bold().on(theme().mode).paint("(training)").to_string()
+        } else {
+            String::new()
+        };
 
         loop {
             let buffer = if !cart_in_progress {
-                stdin.readline(&format!("{} ", Style::new().bold().paint("57Bank>")))
+                stdin.readline(&format!(
+                    "{}{}{}{} ",
+                    Style::new().bold().fg_opt(theme().prompt).paint("57Bank"),
+                    mode,
+                    logged_in,
+                    Style::new().bold().fg_opt(theme().prompt).paint(">")
+                ))
             } else {
                 stdin.readline(&format!(
-                    "{}{}{}",
-                    Style::new().bold().paint("57Bank"),
+                    "{}{}{}{}{}",
+                    Style::new().bold().fg_opt(theme().prompt).paint("57Bank"),
+                    mode,
+                    logged_in,
                     Style::new()
                         .bold()
-                        .on(Color::Yellow)
-                        .paint("(cart in progress)"),
-                    Style::new().bold().paint("> ")
+                        .on(theme().cart)
+                        .paint(match &projected {
+                            Some(p) => format!("(cart in progress, {} left)", p),
+                            None => "(cart in progress)".to_string(),
+                        }),
+                    Style::new().bold().fg_opt(theme().prompt).paint("> ")
                 ))
             };
 
             let buffer = match buffer {
-                Ok(t) => {
-                    stdin.add_history_entry(&t).unwrap();
-                    StdoutMsg::Text(t)
-                },
+                Ok(t) => StdoutMsg::Text(hotkeys::take(&pressed, t)),
                 Err(ReadlineError::Interrupted | ReadlineError::Eof) => {
-                    println!("{}", Style::new().bold().fg(Color::Red).paint("EXITING..."));
+                    println!("{}", Style::new().bold().fg(theme().warning).paint("EXITING..."));
                     StdoutMsg::Signal(Signal::Kill)
                 }
                 Err(_error) => StdoutMsg::Signal(Signal::Kill),
             };
 
+            let line = match &buffer {
+                StdoutMsg::Text(t) => Some(t.clone()),
+                StdoutMsg::Signal(_) => None,
+            };
             stdin_tx.blocking_send(buffer).unwrap();
-            cart_in_progress = match stdin_ready_rx.blocking_recv() {
-                Some(b) => b,
+            let prompt = match stdin_ready_rx.blocking_recv() {
+                Some(p) => p,
                 None => break
             };
-        }
+            cart_in_progress = prompt.cart_in_progress;
+            projected = prompt.projected.clone();
+            logged_in = match &prompt.session {
+                Some(user) => Style::new().bold().on(theme().mode).paint(format!("[{}]", user)).to_string(),
+                None => String::new(),
+            };
+
+            if let (Some(word), Some(hinter)) = (&prompt.used, stdin.helper_mut()) {
+                hinter.record_use(word);
+                if !dry_run {
+                    let _ = hinter.save_usage(&usage);
+                }
+            }
 
-        stdin.save_history("data/history").unwrap();
+            if prompt.clear_history {
+                stdin.clear_history().unwrap();
+                if !dry_run {
+                    let _ = stdin.save_history(&history);
+                }
+            } else if let (Some(line), true) = (line, prompt.record) {
+                stdin.add_history_entry(history::saved(&line)).unwrap();
+                // Written as we go, as we might be killed while waiting for the next line
+                if !dry_run {
+                    let _ = stdin.append_history(&history);
+                }
+            }
+        }
     });
 
+    systemd::ready();
+
+    // Whoever last tapped their card, when sessions are turned on
+    let mut logged_in: Option<session::Session> = None;
+    // Who tapped with nothing in the cart, and when, so the next item scanned can go on their account
+    let mut quick_buyer: Option<(String, std::time::Instant)> = None;
+    // Who tapped to start the cart, whose favourite codes can be typed until it's finished with
+    let mut tapped: Option<String> = None;
+    let mut scan_debouncer =
+        reader::Debouncer::new(std::time::Duration::from_millis(config.reader.scan_debounce));
+    // For adding another on purpose, when it would look like a repeat scan
+    let mut last_scanned: Option<barcode::Barcode> = None;
     loop {
+        // Scans from a serial scanner or camera and button presses are dealt with as if typed, without
+        // the input thread waiting on them
+        let mut scanned = false;
         let buffer = select! {
             msg = stdin_rx_handle.recv() => {
                 match msg {
                     Some(StdoutMsg::Text(t)) => t,
                     Some(StdoutMsg::Signal(_)) => {
-                        stop_clone.store(true, Ordering::Relaxed);
                         stdin_rx_handle.close();
                         break
                     },
                     None => continue,
                 }
             },
-            uid = card_rx_handle.recv() => {
-                if let Some(card_id) = uid {
-                    let card_id_str = card_id.into_iter().map(|b| b.to_string()).collect::<String>();
-                    let user = match db.get_user_by_card(&card_id_str) {
-                        Some(u) => u,
-                        None => continue,
-                    };
-
-                    if cart.is_none() {
+            _ = shutdown_signal(&mut shutdown_signals) => {
+                println!();
+                println!("{}", Style::new().bold().fg(theme().warning).paint("EXITING..."));
+                break
+            }
+            Some(scan) = scan_rx.recv() => {
+                println!();
+                if barcode::Barcode::try_parse(&scan).is_none() {
+                    println!("\x07Scanned something that isn't a barcode: {}", scan);
+                    continue;
+                }
+                println!("> {}", scan);
+                scanned = true;
+                scan
+            }
+            Some(()) = press_rx.recv() => {
+                match buttons::take() {
+                    // Confirming with nothing to confirm
+                    Some(line) if !line.is_empty() => {
                         println!();
-                        user_info(user);
-                        continue;
+                        println!("> {}", line);
+                        scanned = true;
+                        line
                     }
+                    _ => continue,
+                }
+            }
+            Some(event) = card_rx_handle.recv() => {
+                match event {
+                    CardEvent::Card(card_id) => {
+                        let card_id_str = card_id_string(&card_id);
+                        let user = match db.get_user_by_card(&card_id_str) {
+                            Some(u) => u,
+                            None => {
+                                if db.pending_card(&card_id_str).is_some() {
+                                    println!();
+                                    println!("This card is still waiting for an admin to approve it");
+                                }
+                                continue;
+                            }
+                        };
+
+                        if !read_only && !dry_run {
+                            let _ = db.card_used(&card_id_str);
+                        }
+                        live::card_tap(&user.0.id);
+                        let now = std::time::Instant::now();
+                        session::expire(&mut logged_in, now);
+                        if cart.is_none() {
+                            let id = user.0.id.clone();
+                            println!();
+                            if !user.0.favourites.is_empty() {
+                                print_favourites(&user.0, &product_store);
+                            }
+                            if let Some(usual) = usual_cart(&user.1, &id, &product_store) {
+                                println!(
+                                    "Your usual is {} ({}), type 'usual' to add it",
+                                    usual.products.iter().map(|p| p.name.as_str()).collect::<Vec<_>>().join(", "),
+                                    usual.disp_total()
+                                );
+                            }
+                            user_info(user);
+                            tapped = Some(id.clone());
+                            if let Some(timeout) = config.session_timeout {
+                                let timeout = std::time::Duration::from_secs(timeout);
+                                logged_in = Some(session::Session::start(&id, timeout, now));
+                                println!("Logged in as {}, 'logout' when you're done", id);
+                            }
+                            if let Some(quick_buy) = &config.quick_buy {
+                                println!("Scan an item in the next {} seconds to buy it straight away", quick_buy.window);
+                                quick_buyer = Some((id, now));
+                            }
+                            continue;
+                        }
 
-                    println!();
-                    complete_cart(&db, user, &mut cart).await;
+                        println!();
+                        complete_cart(&db, &config, &webhooks, user, &mut cart).await;
+                    }
+                    CardEvent::ReaderError(e) => {
+                        feedback::signal(feedback::Signal::ReaderOffline);
+                        notify::send(notify::Notice::ReaderOffline, &format!("The card reader has stopped working: {}", e));
+                        println!();
+                        println!(
+                            "{}",
+                            Style::new()
+                                .bold()
+                                .fg(theme().warning)
+                                .paint(format!("Card reader error: {}, retrying in the background", e))
+                        );
+                    }
+                    CardEvent::ReaderReady => {
+                        feedback::signal(feedback::Signal::ReaderOnline);
+                        println!();
+                        println!("Card reader reconnected");
+                    }
                 }
                 continue;
             }
+            _ = tick(&mut sync_interval) => {
+                sync_background(&db, &config.sync);
+                continue;
+            }
+            _ = tick(&mut standing_order_interval) => {
+                charge_standing_orders(&db);
+                continue;
+            }
+            // Only pinged from here, so systemd notices if the main loop gets stuck
+            _ = tick(&mut watchdog) => {
+                systemd::watchdog();
+                continue;
+            }
         };
 
+        session::expire(&mut logged_in, std::time::Instant::now());
+        let mut prompt = Prompt {
+            cart_in_progress: false,
+            projected: None,
+            record: history_filter.records(&buffer, history::LineKind::Command),
+            clear_history: false,
+            used: None,
+            session: None,
+        };
+        let capture = if scripted {
+            script::Capture::start().ok()
+        } else {
+            None
+        };
         if !buffer.is_empty() {
             let mut args = buffer.split_whitespace();
             let command = args.next().unwrap();
-            let args = args.collect::<Vec<_>>();
+            let session_user = logged_in.as_ref().map(|s| s.user.clone());
+            // Only the line straight after the tap counts
+            let quick_buyer = quick_buyer.take().and_then(|(id, at)| {
+                let window = std::time::Duration::from_secs(config.quick_buy.as_ref()?.window);
+                (at.elapsed() <= window && cart.is_none()).then_some(id)
+            });
+            let args = session::fill_in(session_user.as_deref(), command, args.collect());
+            if FORBIDDEN_USERS.contains(&command) {
+                prompt.used = Some(command.to_string());
+            }
 
             match command {
+                _ if read_only && FORBIDDEN_USERS.contains(&command) && !READ_ONLY_COMMANDS.contains(&command) => {
+                    println!("{} isn't available in read-only mode", command)
+                }
                 "hilfe" | "help" | "?" => help(),
                 "clear" => clear(&mut stdout),
-                "reload" => reload(&mut product_store),
+                "reload" => reload(&mut product_store, &config),
+                "pricelist" => price_list(&product_store, &args),
                 "products" => products(&product_store),
-                "adduser" => adduser(&db, &args),
-                "regcard" => register_card(&args, &db, &mut card_rx_handle).await,
+                "labels" => shelf_labels(&product_store, &args),
+                "adduser" => adduser(&db, &webhooks, &args),
+                "addgroup" => {
+                    if authenticate_role(&db, &config, &mut card_rx_handle, Role::Admin).await.is_some() {
+                        add_group(&db, &webhooks, &args)
+                    }
+                }
+                "freeze" => {
+                    if authenticate_role(&db, &config, &mut card_rx_handle, Role::Admin).await.is_some() {
+                        freeze(&db, &args)
+                    }
+                }
+                "unfreeze" => {
+                    if authenticate_role(&db, &config, &mut card_rx_handle, Role::Admin).await.is_some() {
+                        unfreeze(&db, &args)
+                    }
+                }
+                "addmember" | "delmember" => {
+                    if authenticate_role(&db, &config, &mut card_rx_handle, Role::Admin).await.is_some() {
+                        group_member(&db, &args, command == "addmember")
+                    }
+                }
+                "regcard" => register_card(&args, &db, &config, &mut card_rx_handle).await,
+                "pendingcards" => {
+                    // Card IDs are as good as the cards, so only for whoever approves them
+                    if authenticate_role(&db, &config, &mut card_rx_handle, Role::Volunteer).await.is_some() {
+                        pending_cards(&db)
+                    }
+                }
+                "approvecard" => review_card(&db, &config, &args, true, &mut card_rx_handle).await,
+                "rejectcard" => review_card(&db, &config, &args, false, &mut card_rx_handle).await,
                 "delcard" => delete_card(&args, &db, &mut card_rx_handle).await,
-                "deposit" => deposit(&db, &args),
-                "users" => users(&db),
-                "deposits" => deposits(&db),
-                "purchases" => purchases(&db),
+                "deposit" => {
+                    deposit(&db, &config, &webhooks, acceptor.as_ref().map(|a| &a.0), &args).await
+                }
+                "topup" => topup(&db, &args),
+                "withdraw" => withdraw(&db, &args, &mut card_rx_handle).await,
+                "setlimit" => set_limit(&db, &config, &args, &mut card_rx_handle).await,
+                "overridelimit" => override_limit(&db, &config, &mut cart, &mut card_rx_handle).await,
+                "setdiscount" => set_discount(&db, &config, &args, &mut card_rx_handle).await,
+                "refund" => refund(&db, &config, &args, &mut card_rx_handle).await,
+                "void" => void(&db, &config, &mut card_rx_handle).await,
+                "tx" => transaction(&db, &config, &args, &mut card_rx_handle).await,
+                "adjust" => adjust(&db, &config, &webhooks, &args, &mut card_rx_handle).await,
+                "users" => {
+                    // Everyone's balances, members only get to see their own
+                    if authenticate_role(&db, &config, &mut card_rx_handle, Role::Treasurer).await.is_some() {
+                        users(&db)
+                    }
+                }
+                "setrole" => set_role(&db, &config, &args, &mut card_rx_handle).await,
+                "balance" => balance(&db, &args, session_user.as_deref(), &mut card_rx_handle).await,
+                "pay" => match session_user.as_deref().and_then(|id| db.get_user(id)) {
+                    Some(user) if cart.is_some() => complete_cart(&db, &config, &webhooks, user, &mut cart).await,
+                    Some(_) => println!("Nothing to pay for, scan something first"),
+                    None => println!("Tap your card first, or type your ID to pay"),
+                },
+                "again" => match &last_scanned {
+                    Some(barcode) => scan_product(&product_store, &config.combos, &mut cart, barcode),
+                    None => println!("Nothing has been scanned yet"),
+                },
+                "favourites" => favourites(&db, &product_store, &args),
+                "usual" => {
+                    let user = tapped.as_deref().or(session_user.as_deref());
+                    usual(&db, &config, &webhooks, &product_store, &mut cart, user).await
+                }
+                "setfavourite" => set_favourite(&db, &product_store, &args, &mut card_rx_handle).await,
+                "logout" => match logged_in.take() {
+                    Some(s) => println!("Logged out {}", s.user),
+                    None => println!("Nobody is logged in"),
+                },
+                "deposits" => {
+                    let user = tapped.as_deref().or(session_user.as_deref());
+                    if let Some(shown) = shown_to(&db, &config, user, &mut card_rx_handle).await {
+                        deposits(&db, &shown)
+                    }
+                }
+                "purchases" => {
+                    let user = tapped.as_deref().or(session_user.as_deref());
+                    if let Some(shown) = shown_to(&db, &config, user, &mut card_rx_handle).await {
+                        purchases(&db, &config, &shown)
+                    }
+                }
+                "watch" => match args.first().map(|v| v.parse::<watch::View>()) {
+                    Some(Ok(watch::View::Users)) => {
+                        if authenticate_role(&db, &config, &mut card_rx_handle, Role::Treasurer).await.is_some() {
+                            watch::watch(&db, &config, watch::View::Users, &Shown::Everyone).await
+                        }
+                    }
+                    Some(Ok(watch::View::Purchases)) => {
+                        let user = tapped.as_deref().or(session_user.as_deref());
+                        if let Some(shown) = shown_to(&db, &config, user, &mut card_rx_handle).await {
+                            watch::watch(&db, &config, watch::View::Purchases, &shown).await
+                        }
+                    }
+                    Some(Err(e)) => println!("Error, {}", e),
+                    None => println!("Usage: watch <users | purchases>"),
+                },
+                "writeoff" => {
+                    if authenticate_role(&db, &config, &mut card_rx_handle, Role::Treasurer).await.is_some() {
+                        write_off(&db, &product_store, &args)
+                    }
+                }
+                "writeoffs" => {
+                    if authenticate_role(&db, &config, &mut card_rx_handle, Role::Treasurer).await.is_some() {
+                        write_offs(&db)
+                    }
+                }
+                "exportuser" => {
+                    if authenticate_role(&db, &config, &mut card_rx_handle, Role::Admin).await.is_some() {
+                        export_user(&db, &args)
+                    }
+                }
+                "exportledger" => {
+                    if authenticate_role(&db, &config, &mut card_rx_handle, Role::Admin).await.is_some() {
+                        export_ledger(&db, &config, &args)
+                    }
+                }
+                "link" => link_account(&db, &config, &args, &mut card_rx_handle).await,
+                "setpin" => set_pin(&args, &db, &mut card_rx_handle).await,
+                "weblink" => web_link(&db, &config, &args, &mut card_rx_handle).await,
+                "setname" => set_name(&args, &db, &mut card_rx_handle).await,
+                "setemail" => set_email(&args, &db, &mut card_rx_handle).await,
+                "importusers" => {
+                    if authenticate_role(&db, &config, &mut card_rx_handle, Role::Admin).await.is_some() {
+                        import_users(&db, &config, &webhooks, &args).await
+                    }
+                }
+                "standingorders" => {
+                    // Shows who pays what into their account
+                    if authenticate_role(&db, &config, &mut card_rx_handle, Role::Treasurer).await.is_some() {
+                        standing_orders(&db, &args)
+                    }
+                }
+                "addstandingorder" => {
+                    if authenticate_role(&db, &config, &mut card_rx_handle, Role::Treasurer).await.is_some() {
+                        add_standing_order(&db, &args)
+                    }
+                }
+                "cancelstandingorder" => {
+                    if authenticate_role(&db, &config, &mut card_rx_handle, Role::Treasurer).await.is_some() {
+                        cancel_standing_order(&db, &args)
+                    }
+                }
+                "makevoucher" => make_voucher(&db, &config, &args, &mut card_rx_handle).await,
+                "promo" => apply_promo(&db, &mut cart, &args),
+                "overrideprice" => {
+                    override_price(&db, &config, &mut cart, &args, &mut card_rx_handle).await
+                }
+                "discount" => cart_discount(&db, &config, &mut cart, &args, &mut card_rx_handle).await,
+                "promos" => promos(&db),
+                "addpromo" => {
+                    if authenticate_role(&db, &config, &mut card_rx_handle, Role::Treasurer).await.is_some() {
+                        add_promo(&db, &args)
+                    }
+                }
+                "delpromo" => {
+                    if authenticate_role(&db, &config, &mut card_rx_handle, Role::Treasurer).await.is_some() {
+                        delete_promo(&db, &args)
+                    }
+                }
+                "redeem" => {
+                    // Unused voucher codes are as good as cash
+                    prompt.record = false;
+                    redeem_voucher(&db, &webhooks, &args)
+                }
+                "clearhistory" => {
+                    if authenticate_role(&db, &config, &mut card_rx_handle, Role::Admin).await.is_some() {
+                        prompt.clear_history = true;
+                        println!("History cleared");
+                    }
+                }
+                "importproducts" if dry_run => {
+                    println!("Importing products is disabled in training mode")
+                }
+                "importproducts" => {
+                    if authenticate_role(&db, &config, &mut card_rx_handle, Role::Admin).await.is_some() {
+                        import_products(&mut product_store, &config, &args)
+                    }
+                }
+                "sync" if dry_run => println!("Syncing is disabled in training mode"),
+                "sync" => {
+                    // Naming a peer pulls in whatever it has, so not just anyone can do it
+                    if authenticate_role(&db, &config, &mut card_rx_handle, Role::Admin).await.is_some() {
+                        if args.is_empty() {
+                            if config.sync.peers.is_empty() && config.sync.push.is_empty() {
+                                println!("No sync peers configured");
+                            }
+                            ingest_inbox(&db, true);
+                            sync_peers(&db, &config.sync.peers, true);
+                            push_outbox(&db, true);
+                        } else {
+                            sync_peers(&db, &[args.join(" ")], true);
+                        }
+                    }
+                }
+                "buyer" => set_buyer(&db, &mut cart, &args),
+                "park" => park(&mut cart, &mut parked),
+                "parked" => list_parked(&parked),
+                "resume" => resume(&mut cart, &mut parked, &args),
                 "abort" | "cancel" => {
                     cart = None;
                     println!("Cart abandoned");
                 }
-                "cash" => {
-                    if cart.is_some() {
-                        let c_cart = cart.as_ref().unwrap();
-                        match db.apply_cart_to_cash(c_cart) {
-                            Ok(()) => {
-                                println!(
-                                    "{}",
-                                    Style::new().bold().paint(format!(
-                                        "Please put {} in the cash box",
-                                        c_cart.disp_total()
-                                    ))
-                                );
-                                cart = None;
-                            }
-                            Err(e) => {
-                                println!("Error, unable to charge: {}", e);
-                            }
-                        }
-                    } else {
-                        println!("Nothing in cart")
+                "cash" => cash_checkout(&db, &config, &webhooks, &mut cart, &args),
+                "cashbox" => {
+                    // Marking a box as emptied hides what should have been in it
+                    if authenticate_role(&db, &config, &mut card_rx_handle, Role::Treasurer).await.is_some() {
+                        cashbox(&db, &config, &args)
+                    }
+                }
+                "guest" => guest_checkout(&db, &webhooks, &mut cart, &args),
+                "tabs" => {
+                    // Everyone who owes the bank and how much
+                    if authenticate_role(&db, &config, &mut card_rx_handle, Role::Treasurer).await.is_some() {
+                        tabs(&db)
+                    }
+                }
+                "fsck" => {
+                    if authenticate_role(&db, &config, &mut card_rx_handle, Role::Treasurer).await.is_some() {
+                        fsck(&db)
+                    }
+                }
+                "restore" => restore(&db, &config, &args, &mut card_rx_handle).await,
+                "rebuild-balances" => rebuild_balances(&db, &config, &mut card_rx_handle).await,
+                "settletab" => {
+                    if authenticate_role(&db, &config, &mut card_rx_handle, Role::Treasurer).await.is_some() {
+                        settle_tab(&db, &args, &mut card_rx_handle).await
+                    }
+                }
+                "taxreport" => reports::tax_report(&db, &config, &args),
+                "zreport" => {
+                    if authenticate_role(&db, &config, &mut card_rx_handle, Role::Treasurer).await.is_some() {
+                        reports::z_report(&db, &config, HOME_CURRENCY)
+                    }
+                }
+                "restock" => {
+                    if authenticate_role(&db, &config, &mut card_rx_handle, Role::Treasurer).await.is_some() {
+                        restock(&db, &product_store, &args)
+                    }
+                }
+                "stock" => stock(&db, &product_store),
+                "stockvalue" => reports::stock_value(&db, &product_store),
+                "reorder" => reports::reorder(&db, &product_store, &args),
+                "forecast" => reports::forecast(&db, &product_store, &args),
+                "prodstats" => reports::product_stats(&db, &product_store, &args),
+                "chart" => reports::chart(&db, &args),
+                "aging" => {
+                    // Who owes what is only for the treasurer to chase
+                    if authenticate_role(&db, &config, &mut card_rx_handle, Role::Treasurer).await.is_some() {
+                        reports::aging(&db)
+                    }
+                }
+                "books" => {
+                    // Every user's balance is in there
+                    if authenticate_role(&db, &config, &mut card_rx_handle, Role::Treasurer).await.is_some() {
+                        reports::books(&db, &config, &args)
+                    }
+                }
+                "dbdiff" => {
+                    // Backups have everyone's balances in
+                    if authenticate_role(&db, &config, &mut card_rx_handle, Role::Treasurer).await.is_some() {
+                        reports::dbdiff(&args)
+                    }
+                }
+                "suppliers" => suppliers(&db),
+                "addsupplier" => {
+                    if authenticate_role(&db, &config, &mut card_rx_handle, Role::Treasurer).await.is_some() {
+                        add_supplier(&db, &args)
+                    }
+                }
+                "po" => {
+                    if authenticate_role(&db, &config, &mut card_rx_handle, Role::Treasurer).await.is_some() {
+                        purchase_orders(&db, &product_store, &args)
+                    }
+                }
+                "expense" => {
+                    if authenticate_role(&db, &config, &mut card_rx_handle, Role::Treasurer).await.is_some() {
+                        expense(&db, &args)
+                    }
+                }
+                "margin" => reports::margin_report(&db, &args),
+                "stocktake" => {
+                    if authenticate_role(&db, &config, &mut card_rx_handle, Role::Treasurer).await.is_some() {
+                        stocktake(&db, &product_store, &args)
+                    }
+                }
+                "shrinkage" => reports::shrinkage(&db, &product_store, &args),
+                "tokens" => {
+                    if authenticate_role(&db, &config, &mut card_rx_handle, Role::Admin).await.is_some() {
+                        api_tokens(&db)
                     }
                 }
+                "issuetoken" => issue_token(&db, &config, &args, &mut card_rx_handle).await,
+                "revoketoken" => revoke_token(&db, &config, &args, &mut card_rx_handle).await,
                 _ => match (barcode::Barcode::try_parse(command), args.is_empty()) {
+                    // Just a price check, there's no paying for anything here
+                    (Some(barcode), true) if read_only => match product_store.get(&barcode) {
+                        Some(product) => println!("{}", product.disp_line()),
+                        None => {
+                            feedback::signal(feedback::Signal::Failure);
+                            speech::say("Unknown product");
+                            println!("\x07Unknown product {}", barcode)
+                        }
+                    },
+                    (Some(barcode), true)
+                        if !scan_debouncer.seen(barcode.to_string().as_bytes(), std::time::Instant::now()) =>
+                    {
+                        prompt.record = false;
+                        println!("Ignored {} scanned twice in a row, type 'again' to add another", command);
+                    }
                     (Some(barcode), true) => {
-                        if !barcode.check_digit() {
-                            println!("Invalid barcode")
-                        } else if let Some(product) = product_store.get(&barcode) {
-                            println!("Adding {} to cart", product.name);
-                            if cart.is_none() {
-                                cart = Some(Cart::new());
-                            }
-
-                            let c_cart = cart.as_mut().unwrap();
-                            c_cart.products.push(product.clone());
-                            c_cart.print();
-                        } else {
-                            println!("Unknown product");
+                        last_scanned = Some(barcode.clone());
+                        prompt.record = history_filter.records(&buffer, history::LineKind::Barcode);
+                        if !dry_run && barcode.check_digit() && !product_store.contains_key(&barcode) {
+                            quick_add_product(&db, &config, &mut product_store, command, &barcode, &mut card_rx_handle).await;
+                        }
+                        if product_store.contains_key(&barcode) {
+                            prompt.used = Some(command.to_string());
+                        }
+                        scan_product(&product_store, &config.combos, &mut cart, &barcode);
+                        let quick = config.quick_buy.as_ref().is_some_and(|q| q.applies(&barcode));
+                        let buyer = quick_buyer.filter(|_| quick && cart.is_some()).and_then(|id| db.get_user(&id));
+                        if let Some(user) = buyer {
+                            complete_cart(&db, &config, &webhooks, user, &mut cart).await
                         }
                     }
                     _ => match (
@@ -280,393 +1133,3823 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                         cart.is_some(),
                     ) {
                         (Some(user), true, false) => {
-                            println!(
-                                "{}",
-                                Style::new()
-                                    .underline()
-                                    .paint(format!("User {}", user.0.id))
-                            );
-                            println!("Balance: {}", user.0.disp_balance());
-                            println!("{}", Style::new().underline().paint("Recent transactions"));
-                            for t in user.1.iter().rev().take(10) {
-                                match &t.transaction {
-                                    db::TransactionType::Deposit { amount, method } => println!(
-                                        "Deposit £{:.2} ({})",
-                                        *amount as f64 / 100.0,
-                                        match method {
-                                            db::DepositMethod::Cash => "cash",
-                                            db::DepositMethod::BankTransfer => "bank transfer",
-                                        }
-                                    ),
-                                    db::TransactionType::Purchase { total, products } => {
-                                        println!("Purchase (total £{:.2})", *total as f64 / 100.0);
-                                        for p in products {
-                                            println!("- {} ({})", p.name, p.disp_price());
-                                        }
-                                    }
-                                }
-                                println!("Timestamp: {}", t.timestamp);
-                                println!()
+                            prompt.record = history_filter.records(&buffer, history::LineKind::UserLookup);
+                            prompt.used = Some(command.to_string());
+                            let own = session_user.as_deref() == Some(user.0.id.as_str());
+                            if own || authenticate(&db, &user.0, &mut card_rx_handle).await {
+                                user_info(user);
                             }
                         }
                         (Some(user), true, true) => {
-                            complete_cart(&db, user, &mut cart).await
+                            prompt.record = history_filter.records(&buffer, history::LineKind::UserLookup);
+                            prompt.used = Some(command.to_string());
+                            complete_cart(&db, &config, &webhooks, user, &mut cart).await
+                        }
+                        (None, true, _) if favourite(&db, tapped.as_deref().or(session_user.as_deref()), command).is_some() => {
+                            let user = tapped.as_deref().or(session_user.as_deref());
+                            let barcode = favourite(&db, user, command).unwrap();
+                            scan_product(&product_store, &config.combos, &mut cart, &barcode)
+                        }
+                        (None, true, _) if products::by_plu(&product_store, command).is_some() => {
+                            let barcode = products::by_plu(&product_store, command).unwrap().barcode.clone();
+                            scan_product(&product_store, &config.combos, &mut cart, &barcode)
+                        }
+                        (None, true, _) if db.get_voucher(command).is_some() => {
+                            prompt.record = false;
+                            redeem_voucher(&db, &webhooks, &[command])
                         }
                         _ => println!("\x07Unknown command: {}", command),
                     },
                 },
             }
         }
-        stdin_ready_tx.send(cart.is_some()).await.unwrap();
+        if let Some(capture) = capture {
+            if script::failed(&capture.finish()) {
+                println!("Script stopped, the last command failed");
+                script_failed = true;
+                break;
+            }
+        }
+        display::cart(cart.as_ref());
+        if cart.is_none() {
+            tapped = None;
+        }
+        prompt.session = logged_in.as_ref().map(|s| s.user.clone());
+        prompt.cart_in_progress = cart.is_some();
+        prompt.projected = cart.as_ref().and_then(|c| {
+            Some(format!(
+                "{}: {}",
+                c.buyer.as_ref()?.id,
+                money::format(c.projected_balance()?)
+            ))
+        });
+        if !scanned {
+            stdin_ready_tx.send(prompt).await.unwrap();
+        }
     }
 
-    clear(&mut stdout);
-
-    Ok(())
-}
+    systemd::stopping();
+    // Closing the channel unblocks the reader thread if it's waiting to hand over a card
+    stop_clone.store(true, Ordering::Relaxed);
+    card_rx_handle.close();
+    let _ = reader_thread.join();
+    scan_rx.close();
+    if let Some(scanner_thread) = scanner_thread {
+        let _ = scanner_thread.join();
+    }
+    press_rx.close();
+    for button_thread in button_threads {
+        let _ = button_thread.join();
+    }
+    if let Some((_, acceptor_thread)) = acceptor {
+        let _ = acceptor_thread.join();
+    }
 
-async fn complete_cart(db: &db::DB, user: (User, Vec<Transaction>), cart: &mut Option<Cart>) {
-    match db.apply_cart_to_user(&user.0.id, cart.as_ref().unwrap()) {
+    restore_terminal(&terminal);
+    if !scripted {
+        clear(&mut stdout);
+    }
+    if let Err(e) = db.flush() {
+        println!("Error, unable to save database: {}", e);
+    }
+    if script_failed {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+// SIGTERM and SIGHUP are how systemd asks us to stop or restart
+async fn shutdown_signal(signals: &mut [tokio::signal::unix::Signal; 3]) {
+    let [term, hup, int] = signals;
+    select! {
+        _ = term.recv() => {}
+        _ = hup.recv() => {}
+        _ = int.recv() => {}
+    }
+}
+
+// readline puts the terminal into raw mode while it waits for input, and won't get a chance to
+// put it back if we exit from a signal, so remember how it was set up to begin with
+fn save_terminal() -> Option<libc::termios> {
+    let mut termios = std::mem::MaybeUninit::<libc::termios>::uninit();
+    if unsafe { libc::tcgetattr(libc::STDIN_FILENO, termios.as_mut_ptr()) } == 0 {
+        Some(unsafe { termios.assume_init() })
+    } else {
+        None
+    }
+}
+
+fn restore_terminal(termios: &Option<libc::termios>) {
+    if let Some(termios) = termios {
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, termios);
+        }
+    }
+}
+
+fn scan_product(
+    product_store: &products::Products,
+    combos: &[Combo],
+    cart: &mut Option<Cart>,
+    barcode: &barcode::Barcode,
+) {
+    if !barcode.check_digit() {
+        println!("Invalid barcode")
+    } else if let Some(product) = product_store.get(barcode) {
+        let product = match product.open_price {
+            true => match ask_open_price(product) {
+                Some(p) => p,
+                None => return,
+            },
+            false => product.clone(),
+        };
+        println!("Adding {} to cart", product.name);
+        speech::say(&format!("Added {}, {}", product.name, speech::amount(product.price as i64)));
+        let c_cart = cart.get_or_insert_with(Cart::new);
+        c_cart.products.push(product);
+        c_cart.apply_combos(combos);
+        print_cart(c_cart);
+    } else {
+        feedback::signal(feedback::Signal::Failure);
+        speech::say("Unknown product");
+        println!("Unknown product");
+    }
+}
+
+fn ask_open_price(product: &products::Product) -> Option<products::Product> {
+    let price = loop {
+        print!("Price of {}, e.g. 1.20 ('abort' to cancel): ", product.name);
+        std::io::stdout().flush().unwrap();
+        let buffer = read_answer();
+        if buffer == "abort" || buffer.is_empty() {
+            return None;
+        }
+        match parse_amount(&buffer) {
+            Some(p) => break p,
+            None => println!("Invalid price"),
+        }
+    };
+    print!("What is it? (blank to skip): ");
+    std::io::stdout().flush().unwrap();
+    Some(product.open_line(price, Some(&read_answer())))
+}
+
+// Offers to add a product nobody has entered yet, so it can be bought straight away
+async fn quick_add_product(
+    db: &db::DB,
+    config: &config::Config,
+    product_store: &mut products::Products,
+    code: &str,
+    barcode: &barcode::Barcode,
+    reader: &mut Receiver<CardEvent>,
+) {
+    let ask = |question: &str| {
+        print!("{}", question);
+        std::io::stdout().flush().unwrap();
+        read_answer()
+    };
+
+    let symbology = barcode::Barcode::parse(code).map_or(barcode.symbology(), |(_, s)| s);
+    if !ask(&format!("Unknown {} product, add it? (y/N): ", symbology)).eq_ignore_ascii_case("y") {
+        return;
+    }
+    // It's sold at whatever price is typed from then on, like an imported product
+    if authenticate_role(db, config, reader, Role::Volunteer).await.is_none() {
+        return;
+    }
+    let name = ask("Name: ");
+    if name.is_empty() {
+        return;
+    }
+    let price = loop {
+        let buffer = ask("Price, e.g. 1.20 ('abort' to cancel): ");
+        if buffer == "abort" {
+            return;
+        }
+        match parse_amount(&buffer) {
+            Some(p) => break p,
+            None => println!("Invalid price"),
+        }
+    };
+    let category = ask("Category (blank for none): ");
+
+    let product = products::Product {
+        barcode: barcode.clone(),
+        name,
+        price,
+        tax: None,
+        category: (!category.is_empty()).then_some(category),
+        price_override: None,
+        open_price: false,
+        plu: None,
+    };
+    let current = match std::fs::read_to_string(data_path("products")) {
+        Ok(p) => p,
+        Err(e) => {
+            println!("Error, unable to load products: {}", e);
+            return;
+        }
+    };
+    // Written out in full, even if it was typed without its leading zeros
+    let code = barcode.written(symbology);
+    let (merged, _) = products::merge_products(&current, &[(code, product.clone())]);
+    match products::write_products(&merged) {
+        Ok(()) => {
+            product_store.insert(barcode.clone(), product);
+        }
+        Err(e) => println!("Error, unable to save product: {}", e),
+    }
+}
+
+fn apply_promo(db: &db::DB, cart: &mut Option<Cart>, args: &[&str]) {
+    let c_cart = match cart.as_mut() {
+        Some(c) => c,
+        None => {
+            println!("Scan something first, then enter the promo code");
+            return;
+        }
+    };
+    if args.is_empty() {
+        println!("Usage: promo <code>");
+        return;
+    }
+
+    let promo = match db.get_promo(args[0]) {
+        Some(p) => p,
+        None => {
+            println!("Unknown promo code");
+            return;
+        }
+    };
+    if let Err(e) = promo.check_usable(chrono::Utc::now()) {
+        println!("Sorry, {}", e);
+        return;
+    }
+    println!("Applying promo code {}, {}", promo.code, promo.discount);
+    c_cart.promo = Some(promo);
+    print_cart(c_cart);
+}
+
+fn promos(db: &db::DB) {
+    let promos = match db.promos() {
+        Ok(p) => p,
+        Err(e) => {
+            println!("Error, unable to list promo codes: {}", e);
+            return;
+        }
+    };
+    if promos.is_empty() {
+        println!("No promo codes");
+        return;
+    }
+
+    println!("{}", Style::new().underline().paint("Promo codes"));
+    for p in promos {
+        println!(
+            "{} - {}, used {}{}{}",
+            p.code,
+            p.discount,
+            p.uses,
+            p.max_uses.map(|m| format!(" of {}", m)).unwrap_or_default(),
+            p.expires
+                .map(|e| format!(", expires {}", e.format("%Y-%m-%d")))
+                .unwrap_or_default()
+        );
+    }
+}
+
+fn add_promo(db: &db::DB, args: &[&str]) {
+    if args.len() < 2 {
+        println!("Usage: addpromo <code> <percent% | amount> [max uses] [expiry date]");
+        return;
+    }
+    let discount = match args[1].parse::<bank_core::promo::Discount>() {
+        Ok(d) => d,
+        Err(e) => {
+            println!("Error, {}", e);
+            return;
+        }
+    };
+    let max_uses = match args.get(2).map(|u| u.parse::<u32>()) {
+        None => None,
+        Some(Ok(u)) => Some(u),
+        Some(Err(_)) => {
+            println!("Invalid number of uses");
+            return;
+        }
+    };
+    // Valid until the end of the given day
+    let expires = match args
+        .get(3)
+        .map(|e| chrono::NaiveDate::parse_from_str(e, "%Y-%m-%d"))
+    {
+        None => None,
+        Some(Ok(d)) => Some(d.and_hms_opt(23, 59, 59).unwrap().and_utc()),
+        Some(Err(_)) => {
+            println!("Invalid expiry date, expected YYYY-MM-DD");
+            return;
+        }
+    };
+
+    let promo = bank_core::promo::Promo {
+        code: bank_core::promo::normalise_code(args[0]),
+        discount,
+        max_uses,
+        uses: 0,
+        expires,
+        added: Some(chrono::Utc::now()),
+    };
+    let code = promo.code.clone();
+    match db.add_promo(promo) {
+        Ok(()) => println!("Added promo code {}", code),
+        Err(e) => println!("Error, unable to add promo code: {}", e),
+    }
+}
+
+fn delete_promo(db: &db::DB, args: &[&str]) {
+    if args.is_empty() {
+        println!("Usage: delpromo <code>");
+        return;
+    }
+    match db.remove_promo(args[0]) {
+        Ok(p) => println!("Removed promo code {}", p.code),
+        Err(e) => println!("Error, unable to remove promo code: {}", e),
+    }
+}
+
+fn print_cart(cart: &Cart) {
+    println!("{}", Style::new().bold().underline().paint("Current cart"));
+    for product in &cart.products {
+        println!("- {}", product.disp_line());
+    }
+    for combo in &cart.combos {
+        println!("- {} (-{})", combo.name, money::format(combo.discount));
+    }
+    if let Some(promo) = &cart.promo {
+        println!(
+            "- Promo code {}, {} (-{})",
+            promo.code,
+            promo.discount,
+            money::format(cart.discount())
+        );
+    }
+    if let Some(manual) = &cart.manual_discount {
+        println!(
+            "- {} (-{})",
+            describe_manual_discount(manual),
+            money::format(cart.manual_discount_amount())
+        );
+    }
+    bigtext::show("Total", &cart.disp_total());
+    if let (Some(buyer), Some(after)) = (&cart.buyer, cart.projected_balance()) {
+        println!(
+            "{} will have {} left",
+            buyer.display_name(),
+            money::format(after)
+        );
+    }
+}
+
+// Sells one line of the cart at a different price, e.g. `overrideprice 5000112637922 0.50 dented can`
+async fn override_price(
+    db: &db::DB,
+    config: &config::Config,
+    cart: &mut Option<Cart>,
+    args: &[&str],
+    reader: &mut Receiver<CardEvent>,
+) {
+    if args.len() < 3 {
+        println!("Usage: overrideprice <barcode> <price> <reason>");
+        return;
+    }
+    let c_cart = match cart.as_mut() {
+        Some(c) => c,
+        None => {
+            println!("Nothing in cart");
+            return;
+        }
+    };
+    let barcode = match barcode::Barcode::try_parse(args[0]) {
+        Some(b) => b,
+        None => {
+            println!("Error, invalid barcode {}", args[0]);
+            return;
+        }
+    };
+    let price = match parse_amount(args[1]) {
+        Some(p) => p,
+        None => {
+            println!("Error, invalid price {}", args[1]);
+            return;
+        }
+    };
+    if !c_cart.products.iter().any(|p| p.barcode == barcode && p.price_override.is_none()) {
+        println!("Error, no {} in the cart to override", barcode);
+        return;
+    }
+
+    let admin = match authenticate_role(db, config, reader, Role::Admin).await {
+        Some(a) => a,
+        None => return,
+    };
+    match c_cart.override_price(&barcode, price, &args[2..].join(" "), &admin) {
+        Ok(_) => {
+            c_cart.apply_combos(&config.combos);
+            print_cart(c_cart)
+        }
+        Err(e) => println!("Error, {}", e),
+    }
+}
+
+// Money off the whole cart, e.g. `discount 10% end of the night` or `discount 0.50`. Giving
+// another replaces the first.
+async fn cart_discount(
+    db: &db::DB,
+    config: &config::Config,
+    cart: &mut Option<Cart>,
+    args: &[&str],
+    reader: &mut Receiver<CardEvent>,
+) {
+    let discount = match args.first().map(|d| d.parse::<bank_core::promo::Discount>()) {
+        Some(Ok(d)) => d,
+        Some(Err(e)) => {
+            println!("Error, {}", e);
+            return;
+        }
+        None => {
+            println!("Usage: discount <amount | percent> [reason]");
+            return;
+        }
+    };
+    let c_cart = match cart.as_mut() {
+        Some(c) => c,
+        None => {
+            println!("Nothing in cart");
+            return;
+        }
+    };
+
+    let admin = match authenticate_role(db, config, reader, Role::Admin).await {
+        Some(a) => a,
+        None => return,
+    };
+    c_cart.manual_discount = Some(db::ManualDiscount {
+        discount,
+        amount: 0,
+        reason: (args.len() > 1).then(|| args[1..].join(" ")),
+        admin,
+    });
+    print_cart(c_cart)
+}
+
+fn describe_manual_discount(manual: &db::ManualDiscount) -> String {
+    match &manual.reason {
+        Some(reason) => format!("Discount {} by {}, {}", manual.discount, manual.admin, reason),
+        None => format!("Discount {} by {}", manual.discount, manual.admin),
+    }
+}
+
+// Says who the cart is for without paying yet, to keep an eye on what they'll have left
+fn set_buyer(db: &db::DB, cart: &mut Option<Cart>, args: &[&str]) {
+    let c_cart = match cart.as_mut() {
+        Some(c) => c,
+        None => {
+            println!("Scan something first, then enter your ID");
+            return;
+        }
+    };
+    if args.len() != 1 {
+        println!("Usage: buyer <id>");
+        return;
+    }
+    match db.get_user(args[0]) {
+        Some((user, _)) => {
+            c_cart.buyer = Some(user);
+            print_cart(c_cart);
+        }
+        None => println!("Error, user {} does not exist", args[0]),
+    }
+}
+
+fn park(cart: &mut Option<Cart>, parked: &mut Vec<Cart>) {
+    match cart.take() {
+        Some(c) => {
+            parked.push(c);
+            println!("Cart parked, type 'resume {}' to pick it up again", parked.len());
+        }
+        None => println!("Nothing in cart"),
+    }
+}
+
+fn list_parked(parked: &[Cart]) {
+    if parked.is_empty() {
+        println!("No parked carts");
+        return;
+    }
+    println!("{}", Style::new().underline().paint("Parked carts"));
+    for (i, c) in parked.iter().enumerate() {
+        let names = c.products.iter().map(|p| p.name.as_str()).collect::<Vec<_>>();
+        println!("{}. {} ({})", i + 1, c.disp_total(), names.join(", "));
+    }
+}
+
+// Brings back a parked cart, parking whatever's in progress in its place
+fn resume(cart: &mut Option<Cart>, parked: &mut Vec<Cart>, args: &[&str]) {
+    let n = match args.first().map(|a| a.parse::<usize>()) {
+        None if parked.len() == 1 => 1,
+        Some(Ok(n)) if n >= 1 && n <= parked.len() => n,
+        _ => {
+            println!("Usage: resume <n>, see 'parked' for the list");
+            return;
+        }
+    };
+    let resumed = parked.remove(n - 1);
+    if let Some(c) = cart.replace(resumed) {
+        parked.push(c);
+        println!("Cart in progress parked as {}", parked.len());
+    }
+    print_cart(cart.as_ref().unwrap());
+}
+
+fn cash_checkout(
+    db: &db::DB,
+    config: &config::Config,
+    webhooks: &webhooks::Webhooks,
+    cart: &mut Option<Cart>,
+    args: &[&str],
+) {
+    let c_cart = match cart.as_ref() {
+        Some(c) => c,
+        None => {
+            println!("Nothing in cart");
+            return;
+        }
+    };
+    let (args, note) = split_note(args);
+
+    let currency = match args.first() {
+        Some(code) => match find_currency(config, code) {
+            Ok(c) => c,
+            Err(e) => {
+                println!("Error, {}", e);
+                return;
+            }
+        },
+        None if config.currencies.is_empty() => None,
+        None => match choose_currency(config, c_cart) {
+            Some(c) => c,
+            None => return,
+        },
+    };
+    let (tendered, rounding) = match currency {
+        Some(c) => {
+            let (amount, rounding) = c.round_cash(c.convert(c_cart.total()));
+            let tendered = db::Tendered {
+                currency: c.code.clone(),
+                amount,
+            };
+            (Some(tendered), rounding)
+        }
+        None => (None, config::round_cash(c_cart.total(), config.checkout.cash_rounding).1),
+    };
+
+    match db.apply_cart_to_cash(c_cart, tendered.clone(), rounding, note.as_deref()) {
+        Ok(()) => {
+            webhooks.fire(
+                webhooks::Event::Purchase,
+                serde_json::json!({
+                    "actor": db::TransactionActor::Cash,
+                    "products": c_cart.products,
+                    "promo": c_cart.promo.as_ref().map(|p| &p.code),
+                    "total": c_cart.total(),
+                    "tendered": tendered,
+                    "rounding": rounding,
+                    "note": note,
+                }),
+            );
+            let (amount, cash_box) = match (currency, &tendered) {
+                (Some(c), Some(t)) => (c.format(t.amount), format!("the {} cash box", c.code)),
+                _ => (
+                    money::format(c_cart.total() as i64 + rounding as i64),
+                    "the cash box".to_string(),
+                ),
+            };
+            println!(
+                "{}",
+                Style::new()
+                    .bold()
+                    .paint(format!("Please put {} in {}", amount, cash_box))
+            );
+            bigtext::banner(&amount);
+            display::thanks(&format!("Pay {}", amount));
+            feedback::signal(feedback::Signal::Success);
+            speech::say(&format!("Please pay {} in cash", amount));
+            open_drawer(db, config, "cash purchase");
+            if currency.is_none() {
+                check_cash_box(db, c_cart.total() as i64 + rounding as i64);
+            }
+            *cart = None;
+        }
+        Err(e) => {
+            println!("Error, unable to charge: {}", e);
+        }
+    }
+}
+
+// Asks for the cash to be banked when taking `added` puts the box over the limit
+fn check_cash_box(db: &db::DB, added: i64) {
+    let limit = match notify::cash_box_limit() {
+        Some(l) => l as i64,
+        None => return,
+    };
+    match reports::cash_in_box(db, HOME_CURRENCY) {
+        Ok(cash) if cash > limit && cash - added <= limit => notify::send(
+            notify::Notice::CashBoxFull,
+            &format!("The cash box has {} in it, please bank it", money::format(cash)),
+        ),
+        Ok(_) => {}
+        Err(e) => println!("Error, unable to count the cash box: {}", e),
+    }
+}
+
+// Each opening is logged, so the drawer being open can be accounted for
+fn open_drawer(db: &db::DB, config: &config::Config, reason: &str) {
+    let drawer = match &config.drawer {
+        Some(d) => d,
+        None => return,
+    };
+    if let Err(e) = drawer::kick(drawer) {
+        println!("Error, unable to open the cash drawer: {}", e);
+        return;
+    }
+    if let Err(e) = db.drawer_opened(reason) {
+        println!("Error, unable to log the cash drawer opening: {}", e);
+    }
+}
+
+// Cash as it's written in its own currency
+fn format_cash(config: &config::Config, currency: &str, amount: i64) -> String {
+    match find_currency(config, currency) {
+        Ok(Some(c)) => c.format(amount),
+        Ok(None) => money::format(amount),
+        // No longer configured, so how many digits it has isn't known
+        Err(_) => format!("{} minor units of {}", amount, currency),
+    }
+}
+
+// None for pounds
+fn find_currency<'a>(
+    config: &'a config::Config,
+    code: &str,
+) -> Result<Option<&'a config::CurrencyConfig>, String> {
+    if code.eq_ignore_ascii_case(HOME_CURRENCY) {
+        return Ok(None);
+    }
+    match config.currencies.iter().find(|c| c.code.eq_ignore_ascii_case(code)) {
+        Some(c) => Ok(Some(c)),
+        None => Err(format!("unknown currency {}", code)),
+    }
+}
+
+// Shows the total in every currency we take, and asks which one they're paying with
+fn choose_currency<'a>(
+    config: &'a config::Config,
+    cart: &Cart,
+) -> Option<Option<&'a config::CurrencyConfig>> {
+    let (total, _) = config::round_cash(cart.total(), config.checkout.cash_rounding);
+    println!("Total: {}", money::format(total));
+    for c in &config.currencies {
+        println!("   or: {} ({})", c.format(c.round_cash(c.convert(cart.total())).0), c.code);
+    }
+
+    loop {
+        print!(
+            "Currency to pay in ({}{}; 'abort' to cancel): ",
+            HOME_CURRENCY,
+            config
+                .currencies
+                .iter()
+                .map(|c| format!(" / {}", c.code))
+                .collect::<String>()
+        );
+        std::io::stdout().flush().unwrap();
+
+        let buffer = read_answer();
+        let buffer = buffer.as_str();
+
+        if buffer == "abort" {
+            return None;
+        }
+        match find_currency(config, if buffer.is_empty() { HOME_CURRENCY } else { buffer }) {
+            Ok(c) => return Some(c),
+            Err(_) => println!("Invalid currency"),
+        }
+    }
+}
+
+// How much should be in each cash box since it was last emptied. Only this till's boxes unless
+// asked for all of them.
+fn cashbox(db: &db::DB, config: &config::Config, args: &[&str]) {
+    if let ["empty", currency] = args {
+        let currency = currency.to_uppercase();
+        match db.empty_cashbox(&currency) {
+            Ok(()) => println!("Marked the {} cash box as emptied", currency),
+            Err(e) => println!("Error, unable to empty cash box: {}", e),
+        }
+        return;
+    }
+    let all = args.first() == Some(&"all");
+
+    let (transactions, emptied) = match db.transactions().and_then(|t| Ok((t, db.cashbox_emptied()?))) {
+        Ok(t) => t,
+        Err(e) => {
+            println!("Error, unable to list transactions: {}", e);
+            return;
+        }
+    };
+
+    let mut boxes = std::collections::BTreeMap::<String, (String, i64)>::new();
+    boxes.insert(db::cashbox_key(db.till(), HOME_CURRENCY), (HOME_CURRENCY.to_string(), 0));
+    for t in &transactions {
+        if !all && t.till.as_deref() != db.till() {
+            continue;
+        }
+        let (currency, amount) = match reports::cash_movement(t, HOME_CURRENCY) {
+            Some(m) => m,
+            None => continue,
+        };
+        let key = db::cashbox_key(t.till.as_deref(), &currency);
+        if emptied.get(&key).is_some_and(|e| t.timestamp < *e) {
+            continue;
+        }
+        boxes.entry(key).or_insert_with(|| (currency, 0)).1 += amount;
+    }
+
+    println!("{}", Style::new().underline().paint("Cash boxes"));
+    for (key, (currency, amount)) in boxes {
+        let amount = match currency.as_str() {
+            HOME_CURRENCY => money::format_signed(amount),
+            _ if amount > 0 => format!("+{}", format_cash(config, &currency, amount)),
+            _ => format_cash(config, &currency, amount),
+        };
+        println!(
+            "{} - {}{}",
+            key,
+            amount,
+            emptied
+                .get(&key)
+                .map(|e| format!(" since {}", e.format("%Y-%m-%d %H:%M")))
+                .unwrap_or_default()
+        );
+    }
+}
+
+fn guest_checkout(
+    db: &db::DB,
+    webhooks: &webhooks::Webhooks,
+    cart: &mut Option<Cart>,
+    args: &[&str],
+) {
+    let c_cart = match cart.as_ref() {
+        Some(c) => c,
+        None => {
+            println!("Nothing in cart");
+            return;
+        }
+    };
+    if args.is_empty() {
+        println!("Usage: guest <tab name>");
+        return;
+    }
+    let tab = args.join(" ");
+
+    match db.apply_cart_to_tab(&tab, c_cart) {
+        Ok(()) => {
+            webhooks.fire(
+                webhooks::Event::Purchase,
+                serde_json::json!({
+                    "actor": db::TransactionActor::Tab(tab.clone()),
+                    "products": c_cart.products,
+                    "promo": c_cart.promo.as_ref().map(|p| &p.code),
+                    "total": c_cart.total(),
+                }),
+            );
+            println!("Added {} to tab {}", c_cart.disp_total(), Style::new().bold().paint(&tab));
+            *cart = None;
+        }
+        Err(e) => println!("Error, unable to charge: {}", e),
+    }
+}
+
+fn tabs(db: &db::DB) {
+    match db.tabs() {
+        Ok(tabs) if tabs.is_empty() => println!("No open tabs"),
+        Ok(tabs) => {
+            println!("{}", Style::new().underline().paint("Open tabs"));
+            for (name, owed) in tabs {
+                println!("{} - {}", name, money::format(owed));
+            }
+        }
+        Err(e) => println!("Error, unable to list tabs: {}", e),
+    }
+}
+
+fn fsck(db: &db::DB) {
+    match db.fsck() {
+        Ok(problems) if problems.is_empty() => println!("No problems found"),
+        Ok(problems) => {
+            println!(
+                "{}",
+                Style::new()
+                    .fg(theme().warning)
+                    .paint(format!("{} problem(s) found", problems.len()))
+            );
+            for problem in problems {
+                println!("- {}", problem);
+            }
+        }
+        Err(e) => println!("Error, unable to check database: {}", e),
+    }
+}
+
+// Checks over a database file out of a backup and shows what it would change before putting it
+// in place. The current data is kept alongside first, in case it was the wrong backup.
+async fn restore(db: &db::DB, config: &config::Config, args: &[&str], reader: &mut Receiver<CardEvent>) {
+    if args.len() != 1 {
+        println!("Usage: restore <database file from a backup>");
+        return;
+    }
+    if authenticate_role(db, config, reader, Role::Admin).await.is_none() {
+        return;
+    }
+    let backup = match std::fs::read(args[0])
+        .map_err(|e| format!("cannot read {} {}", args[0], e))
+        .and_then(|raw| db::Format::decode::<db::InnerDB>(&raw))
+    {
+        Ok(b) => b,
+        Err(e) => {
+            println!("Error, {}", e);
+            return;
+        }
+    };
+    let current = match db.snapshot() {
+        Ok(c) => c,
+        Err(e) => {
+            println!("Error, unable to read the current database: {}", e);
+            return;
+        }
+    };
+
+    println!(
+        "The backup has {} users and {} transactions, the latest from {}",
+        backup.users.len(),
+        backup.transactions.len(),
+        backup
+            .transactions
+            .iter()
+            .map(|t| t.timestamp)
+            .max()
+            .map_or("never".to_string(), |t| t.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string())
+    );
+    match db::DB::in_memory(backup.clone()).and_then(|b| b.fsck()) {
+        Ok(problems) if problems.is_empty() => println!("No problems found in the backup"),
+        Ok(problems) => {
+            println!(
+                "{}",
+                Style::new()
+                    .fg(theme().warning)
+                    .paint(format!("{} problem(s) found in the backup", problems.len()))
+            );
+            for problem in problems {
+                println!("- {}", problem);
+            }
+        }
+        Err(e) => {
+            println!("Error, unable to check the backup: {}", e);
+            return;
+        }
+    }
+    println!("{}", Style::new().underline().paint("Changes from the current data"));
+    println!("{}", reports::snapshot_diff(&current, &backup));
+
+    print!("Type 'restore' to replace the current data with the backup: ");
+    std::io::stdout().flush().unwrap();
+    if read_answer() != "restore" {
+        println!("Nothing restored");
+        return;
+    }
+    let kept = data_path(&format!("db.before-restore-{}", chrono::Utc::now().format("%Y%m%d%H%M%S")))
+        .display()
+        .to_string();
+    if let Err(e) = db::Format::Ron.encode(&current).and_then(|raw| {
+        std::fs::write(&kept, raw).map_err(|e| format!("cannot write {} {}", kept, e))
+    }) {
+        println!("Error, unable to keep the current data: {}", e);
+        return;
+    }
+    match db.restore(backup) {
+        Ok(()) => println!("Restored, the old data is in {}", kept),
+        Err(e) => println!("Error, unable to restore: {}", e),
+    }
+}
+
+// Recovers from balances that no longer match the transactions, e.g. after a partial write
+async fn rebuild_balances(
+    db: &db::DB,
+    config: &config::Config,
+    reader: &mut Receiver<CardEvent>,
+) {
+    if authenticate_role(db, config, reader, Role::Admin).await.is_none() {
+        return;
+    }
+
+    match db.rebuild_balances() {
+        Ok(changed) if changed.is_empty() => println!("All balances already match"),
+        Ok(changed) => {
+            for (id, old, new) in changed {
+                println!(
+                    "{}: {} -> {}",
+                    id,
+                    money::format(old),
+                    money::format(new)
+                );
+            }
+        }
+        Err(e) => println!("Error, unable to rebuild balances: {}", e),
+    }
+}
+
+async fn settle_tab(db: &db::DB, args: &[&str], reader: &mut Receiver<CardEvent>) {
+    let (args, note) = split_note(args);
+    if args.len() < 2 {
+        println!("Usage: settletab <cash | sponsor id> <tab name> [--note <note>]");
+        return;
+    }
+    let sponsor = match args[0] {
+        "cash" => None,
+        id => Some(id),
+    };
+    let tab = args[1..].join(" ");
+
+    // The sponsor has to agree to the charge, as with a withdrawal
+    if let Some(id) = sponsor {
+        match db.get_user(id) {
+            Some((user, _)) => {
+                if !authenticate(db, &user, reader).await {
+                    return;
+                }
+            }
+            None => {
+                println!("Error, user {} does not exist", id);
+                return;
+            }
+        }
+    }
+
+    match db.settle_tab(&tab, sponsor, note.as_deref()) {
+        Ok(amount) => match sponsor {
+            Some(id) => println!(
+                "Charged {} for tab {} to user {}",
+                money::format(amount),
+                tab,
+                id
+            ),
+            None => println!(
+                "{}",
+                Style::new().bold().paint(format!(
+                    "Please put {} in the cash box for tab {}",
+                    money::format(amount),
+                    tab
+                ))
+            ),
+        },
+        Err(e) => println!("Error, unable to settle tab: {}", e),
+    }
+}
+
+async fn complete_cart(
+    db: &db::DB,
+    config: &config::Config,
+    webhooks: &webhooks::Webhooks,
+    user: (User, Vec<Transaction>),
+    cart: &mut Option<Cart>,
+) {
+    let c_cart = cart.as_ref().unwrap();
+    let actor = if user.0.is_group() {
+        match ask_group_member(&user.0) {
+            Some(member) => db::TransactionActor::Group {
+                group: user.0.id.clone(),
+                member,
+            },
+            None => return,
+        }
+    } else {
+        db::TransactionActor::User(user.0.id.clone())
+    };
+    let total = c_cart.total() - user.0.discount_on(c_cart.total());
+    if !confirm_checkout(&config.checkout, &user.0, total) {
+        println!("Checkout cancelled, the cart is still in progress");
+        cart.as_mut().unwrap().buyer = Some(user.0);
+        return;
+    }
+    let charged = match &actor {
+        db::TransactionActor::Group { group, member } => db.apply_cart_to_group(group, member, c_cart),
+        _ => db.apply_cart_to_user(&user.0.id, c_cart),
+    };
+    match charged {
+        Ok(user) => {
+            webhooks.fire(
+                webhooks::Event::Purchase,
+                serde_json::json!({
+                    "actor": actor,
+                    "products": c_cart.products,
+                    "promo": c_cart.promo.as_ref().map(|p| &p.code),
+                    "total": total,
+                    "balance": user.balance,
+                }),
+            );
+            if total < c_cart.total() {
+                println!(
+                    "Your {}% discount took off {}",
+                    user.discount.unwrap_or_default(),
+                    money::format(c_cart.total() - total)
+                );
+            }
+            println!("Charged to {}", Style::new().bold().paint(user.display_name()));
+            bigtext::show_balance("New balance", &user);
+            display::new_balance(&user);
+            feedback::signal(feedback::Signal::Success);
+            speech::say(&format!("Thank you. New balance {}", speech::amount(user.balance as i64)));
+            if config.checkout.qr_receipt {
+                if let Some(t) = db.get_user(&user.id).and_then(|(_, t)| t.last().cloned()) {
+                    println!("Scan for your receipt:");
+                    print_qr(&receipt(&t, Some(&user)));
+                }
+            }
+            if let Some(threshold) = config.balance.warn_below {
+                warn_low_balance(webhooks, &user, total, threshold);
+            }
+            *cart = None;
+        }
+        Err(e) => {
+            feedback::signal(feedback::Signal::Failure);
+            speech::say("Payment failed");
+            println!("Error, unable to charge user: {}", e);
+            if user.0.daily_limit.is_some() || user.0.purchase_limit.is_some() {
+                println!("An admin can type 'overridelimit' to allow this purchase anyway");
+            }
+        }
+    }
+}
+
+// Shows what the purchase will leave in the account and waits for the go-ahead, if configured to
+fn confirm_checkout(config: &config::CheckoutConfig, user: &User, total: u32) -> bool {
+    let after = user.balance - total as i32;
+    match config.confirm {
+        config::ConfirmCheckout::Never => return true,
+        config::ConfirmCheckout::WhenNegative if after >= 0 => return true,
+        _ => {}
+    }
+
+    println!("Total: {}", money::format(total));
+    println!("Current balance: {}", user.disp_balance());
+    let after_str = money::format(after);
+    println!(
+        "Balance after purchase: {}",
+        if after < 0 {
+            Style::new().bold().fg(theme().negative).paint(after_str)
+        } else {
+            Style::new().bold().paint(after_str)
+        }
+    );
+    print!("Press Enter to confirm, or type 'abort' to cancel: ");
+    std::io::stdout().flush().unwrap();
+
+    matches!(read_answer().as_str(), "" | "y" | "yes")
+}
+
+// Group accounts record who in the group made each purchase
+fn ask_group_member(group: &User) -> Option<String> {
+    loop {
+        print!(
+            "{} is a group account, type your user ID ({}; 'abort' to cancel): ",
+            group.id,
+            group.members.join(" / ")
+        );
+        std::io::stdout().flush().unwrap();
+
+        let buffer = read_answer();
+        let buffer = buffer.as_str();
+
+        if buffer == "abort" {
+            return None;
+        } else if group.members.iter().any(|m| m == buffer) {
+            return Some(buffer.to_string());
+        }
+        println!("Not a member of {}", group.id);
+    }
+}
+
+fn warn_low_balance(webhooks: &webhooks::Webhooks, user: &User, spent: u32, threshold: i32) {
+    if user.balance >= threshold {
+        return;
+    }
+    println!(
+        "{}",
+        Style::new()
+            .bold()
+            .fg(theme().warning)
+            .paint("Your balance is getting low, please top up soon with 'deposit <id>'")
+    );
+
+    // Only tell them once, when they first go under, rather than on every purchase after
+    if user.balance + spent as i32 >= threshold {
+        webhooks.fire(
+            webhooks::Event::LowBalance,
+            serde_json::json!({
+                "user": user.id,
+                "email": user.email,
+                "balance": user.balance,
+                "threshold": threshold,
+            }),
+        );
+    }
+}
+
+async fn tick(interval: &mut Option<tokio::time::Interval>) {
+    match interval {
+        Some(i) => {
+            i.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+fn sync_background(db: &db::DB, sync: &config::SyncConfig) {
+    ingest_inbox(db, false);
+    sync_peers(db, &sync.peers, false);
+    if !sync.push.is_empty() {
+        push_outbox(db, false);
+    }
+}
+
+fn ingest_inbox(db: &db::DB, verbose: bool) {
+    match db.ingest_inbox() {
+        Ok(summary) => {
+            if verbose || summary.applied > 0 {
+                println!("Applied {} transactions pushed from other tills", summary.applied);
+            }
+            for e in summary.rejected {
+                println!("Error, skipped a transaction pushed from another till: {}", e);
+            }
+        }
+        Err(e) => println!("Error, unable to process inbox: {}", e),
+    }
+}
+
+fn push_outbox(db: &db::DB, verbose: bool) {
+    match db.flush_outbox() {
+        Ok(summaries) => {
+            for summary in summaries {
+                match summary.error {
+                    // Network outages are expected, so only mention them when asked
+                    Some(e) if verbose => println!(
+                        "Unable to push to {} ({} transactions queued): {}",
+                        summary.target, summary.pending, e
+                    ),
+                    Some(_) => {}
+                    None => println!(
+                        "Pushed {} transactions to {}",
+                        summary.delivered, summary.target
+                    ),
+                }
+            }
+        }
+        Err(e) => println!("Error, unable to push transactions: {}", e),
+    }
+}
+
+fn sync_peers(db: &db::DB, peers: &[String], verbose: bool) {
+    for peer in peers {
+        match db.sync_from(peer) {
+            Ok(summary) => {
+                if verbose || summary.users > 0 || summary.transactions > 0 {
+                    println!(
+                        "Synced from {}: {} new users, {} new transactions",
+                        peer, summary.users, summary.transactions
+                    );
+                }
+            }
+            Err(e) => println!("Error, unable to sync from {}: {}", peer, e),
+        }
+    }
+}
+
+fn charge_standing_orders(db: &db::DB) {
+    match db.apply_standing_orders(chrono::Utc::now()) {
+        Ok(applied) => {
+            for t in applied {
+                if let (db::TransactionActor::User(id), db::TransactionType::StandingOrder { description, amount, .. }) =
+                    (&t.actor, &t.transaction)
+                {
+                    println!(
+                        "Charged {} {} for standing order {}",
+                        id,
+                        money::format(*amount),
+                        description
+                    );
+                }
+            }
+        }
+        Err(e) => println!("Error, unable to apply standing orders: {}", e),
+    }
+}
+
+fn standing_orders(db: &db::DB, args: &[&str]) {
+    let orders = match db.standing_orders() {
+        Ok(o) => o,
+        Err(e) => {
+            println!("Error, unable to list standing orders: {}", e);
+            return;
+        }
+    };
+    let orders = orders
+        .iter()
+        .filter(|o| args.first().is_none_or(|id| o.user == *id))
+        .collect::<Vec<_>>();
+    if orders.is_empty() {
+        println!("No standing orders");
+        return;
+    }
+
+    println!("{}", Style::new().underline().paint("Standing orders"));
+    for o in orders {
+        println!(
+            "{} - {} {} {} for {}, next due {}",
+            &o.id.simple().to_string()[..8],
+            o.user,
+            money::format(o.amount),
+            o.period,
+            o.description,
+            o.next_due.format("%Y-%m-%d")
+        );
+    }
+}
+
+fn add_standing_order(db: &db::DB, args: &[&str]) {
+    if args.len() < 4 {
+        println!("Usage: addstandingorder <id> <amount> <weekly | monthly> <description>");
+        return;
+    }
+    let amount = match parse_amount(args[1]) {
+        Some(a) => a,
+        None => {
+            println!("Invalid amount");
+            return;
+        }
+    };
+    let period = match args[2].parse::<db::Period>() {
+        Ok(p) => p,
+        Err(e) => {
+            println!("Error, {}", e);
+            return;
+        }
+    };
+
+    match db.add_standing_order(args[0], amount, period, &args[3..].join(" ")) {
+        Ok(o) => {
+            println!(
+                "Added {} standing order of {} to {}",
+                o.period,
+                money::format(o.amount),
+                o.user
+            );
+            charge_standing_orders(db);
+        }
+        Err(e) => println!("Error, unable to add standing order: {}", e),
+    }
+}
+
+fn cancel_standing_order(db: &db::DB, args: &[&str]) {
+    if args.is_empty() {
+        println!("Usage: cancelstandingorder <order id>");
+        return;
+    }
+    match db.cancel_standing_order(args[0]) {
+        Ok(o) => println!("Cancelled standing order {} for {}", o.description, o.user),
+        Err(e) => println!("Error, unable to cancel standing order: {}", e),
+    }
+}
+
+async fn withdraw(db: &db::DB, args: &[&str], reader: &mut Receiver<CardEvent>) {
+    let (args, note) = split_note(args);
+    let amount = match args[..] {
+        [_, amount] => parse_amount(amount),
+        _ => None,
+    };
+    let amount = match amount {
+        Some(a) => a,
+        None => {
+            println!("Usage: withdraw <id> <amount> [--note <note>]");
+            return;
+        }
+    };
+    let user = match db.get_user(args[0]) {
+        Some((u, _)) => u,
+        None => {
+            println!("Error, user {} does not exist", args[0]);
+            return;
+        }
+    };
+    // Real cash goes out, so an account anyone could use by ID alone can't have it
+    if !has_credentials(&user) {
+        println!("Error, {} needs a PIN or card set up before withdrawing", user.id);
+        return;
+    }
+    if !authenticate(db, &user, reader).await {
+        return;
+    }
+
+    match db.withdraw_user(&user.id, amount, note.as_deref()) {
+        Ok(user) => {
+            println!("Take {} from the cash box", money::format(amount));
+            bigtext::show_balance("New balance", &user);
+        }
+        Err(e) => println!("Error, unable to withdraw: {}", e),
+    }
+}
+
+// Corrects a mistake in someone's balance, e.g. `adjust q -1.20 charged twice for a Club-Mate`
+async fn adjust(
+    db: &db::DB,
+    config: &config::Config,
+    webhooks: &webhooks::Webhooks,
+    args: &[&str],
+    reader: &mut Receiver<CardEvent>,
+) {
+    let (args, note) = split_note(args);
+    if args.len() < 3 {
+        println!("Usage: adjust <id> <+/-amount> <reason> [--note <note>]");
+        return;
+    }
+    // Has to fit a balance either way round, rather than wrapping to the other sign
+    let signed = |a: &str| parse_amount(a).and_then(|a| i32::try_from(a).ok());
+    let amount = match (
+        args[1].strip_prefix('+').and_then(signed),
+        args[1].strip_prefix('-').and_then(signed),
+    ) {
+        (Some(amount), _) => amount,
+        (_, Some(amount)) => -amount,
+        _ => {
+            println!("Error, amount must be + or - an amount, e.g. +2.50");
+            return;
+        }
+    };
+    let reason = args[2..].join(" ");
+    if db.get_user(args[0]).is_none() {
+        println!("Error, user {} does not exist", args[0]);
+        return;
+    }
+
+    let admin = match authenticate_role(db, config, reader, Role::Treasurer).await {
+        Some(a) => a,
+        None => return,
+    };
+
+    match db.adjust_user(args[0], amount, &reason, &admin, note.as_deref()) {
+        Ok(user) => {
+            webhooks.fire(
+                webhooks::Event::Adjustment,
+                serde_json::json!({
+                    "user": user.id,
+                    "amount": amount,
+                    "reason": reason,
+                    "admin": admin,
+                    "note": note,
+                }),
+            );
+            println!(
+                "Balance of {} is now {}",
+                user.id,
+                money::format(user.balance)
+            );
+        }
+        Err(e) => println!("Error, unable to adjust balance: {}", e),
+    }
+}
+
+// Hands back the money for a cash purchase or cash deposit, taking it out of the cash box
+async fn refund(
+    db: &db::DB,
+    config: &config::Config,
+    args: &[&str],
+    reader: &mut Receiver<CardEvent>,
+) {
+    let (args, note) = split_note(args);
+    if args.len() != 1 {
+        println!("Usage: refund <transaction id> [--note <note>]");
+        return;
+    }
+    if authenticate_role(db, config, reader, Role::Treasurer).await.is_none() {
+        return;
+    }
+
+    match db.refund_to_cash(args[0], note.as_deref()) {
+        Ok(t) => {
+            if let db::TransactionType::Refund { amount, tendered, .. } = &t.transaction {
+                match tendered {
+                    Some(tendered) => println!(
+                        "Hand back {} from the {} cash box",
+                        format_cash(config, &tendered.currency, tendered.amount.into()),
+                        tendered.currency
+                    ),
+                    None => println!("Hand back {} from the cash box", money::format(*amount)),
+                }
+            }
+        }
+        Err(e) => println!("Error, unable to refund: {}", e),
+    }
+}
+
+// Everything about one transaction, e.g. to sort out a dispute or reprint a receipt. The ID can
+// be shortened to as much of the start as is needed to pick it out.
+async fn transaction(db: &db::DB, config: &config::Config, args: &[&str], reader: &mut Receiver<CardEvent>) {
+    let (id, reprint) = match args {
+        [id] => (*id, false),
+        [id, "--receipt"] => (*id, true),
+        _ => {
+            println!("Usage: tx <transaction id> [--receipt]");
+            return;
+        }
+    };
+    if authenticate_role(db, config, reader, Role::Treasurer).await.is_none() {
+        return;
+    }
+    let transactions = match db.transactions() {
+        Ok(t) => t,
+        Err(e) => {
+            println!("Error, unable to read transactions: {}", e);
+            return;
+        }
+    };
+    let t = match db::find_transaction(&transactions, id) {
+        Ok(t) => t,
+        Err(e) => {
+            println!("Error, {}", e);
+            return;
+        }
+    };
+
+    println!("{}", Style::new().underline().paint(format!("Transaction {}", t.id)));
+    println!("Timestamp: {}", t.timestamp);
+    println!("By: {}", t.actor);
+    if let Some(till) = &t.till {
+        println!("Till: {}", till);
+    }
+    print_transaction(t);
+    for linked in db::linked_transactions(&transactions, t.id) {
+        let what = match linked.transaction {
+            db::TransactionType::Void { .. } => "Voided",
+            _ => "Refunded",
+        };
+        println!("{} as {} at {}", what, linked.id, linked.timestamp);
+    }
+    // Without the balance, which will have moved on since
+    if reprint {
+        print_qr(&receipt(t, None));
+    }
+}
+
+// Takes back the purchase just made, e.g. after scanning the wrong thing, without finding an
+// admin. Only the card that paid can do it, and only for checkout.void_window seconds.
+async fn void(db: &db::DB, config: &config::Config, reader: &mut Receiver<CardEvent>) {
+    if config.checkout.void_window == 0 {
+        println!("Voiding purchases yourself is turned off, ask an admin for a refund");
+        return;
+    }
+    println!("Tap the card you paid with");
+    let uid = match tokio::time::timeout(std::time::Duration::from_secs(30), reader::next_card(reader)).await {
+        Ok(Some(uid)) => card_id_string(&uid),
+        _ => {
+            println!("No card presented");
+            return;
+        }
+    };
+    let (user, transactions) = match db.get_user_by_card(&uid) {
+        Some(u) => u,
+        None => {
+            println!("Error, that card isn't registered");
+            return;
+        }
+    };
+    let purchase = transactions.iter().rev().find(|t| {
+        matches!(t.transaction, db::TransactionType::Purchase { .. })
+            && matches!(&t.actor, db::TransactionActor::User(id) if *id == user.id)
+    });
+    let (purchase, total) = match purchase {
+        Some(t @ Transaction { transaction: db::TransactionType::Purchase { total, .. }, .. }) => (t, *total),
+        _ => {
+            println!("You haven't bought anything to void");
+            return;
+        }
+    };
+
+    print!(
+        "Void {} ({})? (y/N): ",
+        reports::describe(&purchase.transaction),
+        money::format(total)
+    );
+    std::io::stdout().flush().unwrap();
+    if !read_answer().eq_ignore_ascii_case("y") {
+        println!("Nothing voided");
+        return;
+    }
+    let window = chrono::Duration::seconds(config.checkout.void_window as i64);
+    match db.void_purchase(purchase.id, &user.id, window) {
+        Ok(_) => match db.get_user(&user.id) {
+            Some((user, _)) => println!("Voided, {} now has {}", user.display_name(), user.disp_balance()),
+            None => println!("Voided"),
+        },
+        Err(e) => println!("Error, unable to void: {}, ask an admin for a refund", e),
+    }
+}
+
+// A balance as it is now, or as it was at some point with --at
+async fn balance(db: &db::DB, args: &[&str], session_user: Option<&str>, reader: &mut Receiver<CardEvent>) {
+    let (args, at) = match args.iter().position(|a| *a == "--at") {
+        Some(i) => match parse_time(&args[i + 1..]) {
+            Some(at) => (&args[..i], Some(at)),
+            None => {
+                println!("Error, invalid time, expected e.g. 2024-03-14 18:30");
+                return;
+            }
+        },
+        None => (args, None),
+    };
+    let (id, user) = match args.first().copied().or(session_user).map(|id| (id, db.get_user(id))) {
+        Some((id, Some(user))) => (id, user),
+        Some((id, None)) => {
+            println!("Error, user {} does not exist", id);
+            return;
+        }
+        None => {
+            println!("Usage: balance <id> [--at <date> [time]], or tap your card first");
+            return;
+        }
+    };
+    // Anyone else's balance needs them to say it's fine
+    if session_user != Some(id) && !authenticate(db, &user.0, reader).await {
+        return;
+    }
+    let at = match at {
+        Some(at) => at,
+        None => return user_info(user),
+    };
+    match db.balance_at(id, at) {
+        Ok(balance) => println!(
+            "Balance of {} at {}: {}",
+            user.0.display_name(),
+            at.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S"),
+            money::format(balance)
+        ),
+        Err(e) => println!("Error, unable to work out the balance: {}", e),
+    }
+}
+
+// A local date and time such as "2024-03-14 18:30". A date on its own means the end of that day.
+fn parse_time(args: &[&str]) -> Option<chrono::DateTime<chrono::Utc>> {
+    let joined = args.join(" ");
+    let naive = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M"]
+        .iter()
+        .find_map(|f| chrono::NaiveDateTime::parse_from_str(&joined, f).ok())
+        .or_else(|| {
+            chrono::NaiveDate::parse_from_str(&joined, "%Y-%m-%d")
+                .ok()
+                .and_then(|d| d.and_hms_opt(23, 59, 59))
+        })?;
+    naive.and_local_timezone(chrono::Local).earliest().map(|t| t.with_timezone(&chrono::Utc))
+}
+
+// Pulls a trailing `--note <text>` off the arguments, e.g. `deposit q --note "birthday money"`
+fn split_note<'a>(args: &[&'a str]) -> (Vec<&'a str>, Option<String>) {
+    match args.iter().position(|a| *a == "--note") {
+        Some(i) => {
+            let note = args[i + 1..].join(" ");
+            let note = note.trim_matches('"').trim();
+            (args[..i].to_vec(), (!note.is_empty()).then(|| note.to_string()))
+        }
+        None => (args.to_vec(), None),
+    }
+}
+
+// A line typed in answer to a question. Running out of input, e.g. at the end of a script,
+// counts as aborting.
+fn read_answer() -> String {
+    if let Some(pressed) = buttons::answer() {
+        println!("{}", pressed);
+        return pressed;
+    }
+    let mut buffer = String::new();
+    match std::io::stdin().read_line(&mut buffer) {
+        Ok(0) | Err(_) => {
+            println!();
+            println!("Error, no more input");
+            "abort".to_string()
+        }
+        Ok(_) => buffer.trim().to_string(),
+    }
+}
+
+// Parses a pounds and pence amount such as "2.50" into pence, with or without the symbol
+fn parse_amount(amount: &str) -> Option<u32> {
+    let amount = amount.trim();
+    let amount = match amount.strip_prefix(money::symbol()).or_else(|| amount.strip_suffix(money::symbol())) {
+        Some(a) => a.trim(),
+        None => amount,
+    };
+    let (pounds, pence) = amount.split_once(['.', money::decimal()]).unwrap_or((amount, ""));
+    let digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+    if pence.len() > 2 || !digits(pounds) || !digits(pence) || (pounds.is_empty() && pence.is_empty()) {
+        return None;
+    }
+    // Whole pence only, and never more than a balance can hold
+    let pounds = match pounds {
+        "" => 0,
+        p => p.parse::<u64>().ok()?,
+    };
+    let pence = format!("{:0<2}", pence).parse::<u64>().ok()?;
+    let total = pounds.checked_mul(100)?.checked_add(pence)?;
+    (total > 0 && total <= i32::MAX as u64).then_some(total as u32)
+}
+
+fn user_info(user: (User, Vec<Transaction>)) {
+    println!(
+        "{}",
+        Style::new()
+            .underline()
+            .paint(format!("User {}", user.0.display_name()))
+    );
+    bigtext::show_balance("Balance", &user.0);
+    if user.0.is_group() {
+        println!("Group members: {}", user.0.members.join(", "));
+    }
+    if let Some(discount) = user.0.discount {
+        println!("Discount: {}%", discount);
+    }
+    if let Some(limit) = user.0.daily_limit {
+        println!("Daily spending limit: {}", money::format(limit));
+    }
+    if let Some(limit) = user.0.purchase_limit {
+        println!("Limit per purchase: {}", money::format(limit));
+    }
+    if let Some(reason) = &user.0.frozen {
+        println!(
+            "{}",
+            Style::new()
+                .bold()
+                .fg(theme().warning)
+                .paint(format!("Account frozen: {}", reason))
+        );
+    }
+    println!("{}", Style::new().underline().paint("Recent transactions"));
+    for t in user.1.iter().rev().take(10) {
+        if let db::TransactionActor::Group { member, .. } = &t.actor {
+            println!("By {}", member);
+        }
+        print_transaction(t);
+        println!("Timestamp: {}", t.timestamp);
+        println!()
+    }
+}
+
+fn print_transaction(t: &Transaction) {
+    match &t.transaction {
+        db::TransactionType::Deposit { amount, method, fee: 0 } => println!(
+            "Deposit {} ({})",
+            money::format(*amount),
+            method
+        ),
+        db::TransactionType::Deposit { amount, method, fee } => println!(
+            "Deposit {} ({}, {} fee)",
+            money::format(*amount),
+            method,
+            money::format(*fee)
+        ),
+        db::TransactionType::Purchase { total, products, promo, discount, combos, user_discount, manual_discount, .. } => {
+            println!("Purchase (total {})", money::format(*total));
+            for p in products {
+                println!("- {}", p.disp_line());
+            }
+            for combo in combos {
+                println!("- {} (-{})", combo.name, money::format(combo.discount));
+            }
+            if let Some(code) = promo {
+                println!("- Promo code {} (-{})", code, money::format(*discount));
+            }
+            if let Some(manual) = manual_discount {
+                println!("- {} (-{})", describe_manual_discount(manual), money::format(manual.amount));
+            }
+            if *user_discount > 0 {
+                println!("- Account discount (-{})", money::format(*user_discount));
+            }
+        }
+        db::TransactionType::StandingOrder { description, amount, .. } => {
+            println!("Standing order {} ({})", money::format(*amount), description)
+        }
+        db::TransactionType::SettleTab { tab, amount } => {
+            println!("Settled tab {} ({})", tab, money::format(*amount))
+        }
+        db::TransactionType::Adjustment { amount, reason, admin } => println!(
+            "Adjustment {}{} by {} ({})",
+            if *amount < 0 { "" } else { "+" },
+            money::format(*amount),
+            admin,
+            reason
+        ),
+        db::TransactionType::Withdrawal { amount } => {
+            println!("Cash withdrawal {}", money::format(*amount))
+        }
+        db::TransactionType::Refund { transaction, amount, .. } => {
+            println!("Refunded {} in cash for {}", money::format(*amount), transaction)
+        }
+        db::TransactionType::Void { transaction, amount, .. } => {
+            println!("Voided {} ({} back)", transaction, money::format(*amount))
+        }
+        db::TransactionType::WriteOff { product, quantity, reason } => {
+            println!("Wrote off {} x {} ({})", quantity, product.name, reason)
+        }
+        db::TransactionType::Restock {
+            product,
+            quantity,
+            supplier,
+            ..
+        } => match supplier {
+            Some(s) => println!("Restocked {} x {} from {}", quantity, product.name, s),
+            None => println!("Restocked {} x {}", quantity, product.name),
+        },
+        db::TransactionType::Expense { amount, description } => {
+            println!("Paid {} from the cash box for {}", money::format(*amount), description)
+        }
+        db::TransactionType::Stocktake { product, counted } => {
+            println!("Counted {} x {}", counted, product.name)
+        }
+        db::TransactionType::DrawerOpened { reason } => println!("Opened the cash drawer ({})", reason),
+    }
+    if let Some(note) = &t.note {
+        println!("Note: {}", note);
+    }
+}
+
+#[derive(Debug)]
+pub enum StdoutMsg {
+    Text(String),
+    Signal(Signal),
+}
+
+// Sent back to the input thread once a line has been dealt with
+#[derive(Debug)]
+pub struct Prompt {
+    cart_in_progress: bool,
+    // e.g. "q: £1.20", the buyer's balance once the cart is paid for
+    projected: Option<String>,
+    // Whether to keep the line in the history
+    record: bool,
+    clear_history: bool,
+    // Command, user ID, or barcode to count towards the hints
+    used: Option<String>,
+    // Who's logged in by tapping their card
+    session: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum Signal {
+    Kill,
+    Eof,
+}
+
+fn clear(stdout: &mut Stdout) {
+    print!("{esc}[2J{esc}[1;1H", esc = 27 as char);
+    stdout.flush().unwrap();
+}
+
+fn help() {
+    println!(
+        "{}",
+        Style::new()
+            .bold()
+            .underline()
+            .paint("--- 57North Snack Bank ---")
+    );
+    println!();
+    println!("{}", Style::new().underline().paint("Buying something"));
+    println!("Scan the barcode on the item to add to cart, complete transaction by typing in your account ID.");
+    println!("Alternatively type in cash to pay with cash directly into the box.");
+    println!("Paying in another currency? Type 'cash <currency>', e.g. 'cash EUR'.");
+    println!("Visiting? Type 'guest <tab name>' to put it on a tab to be settled later.");
+    println!("Type 'promo <code>' to use a promo code on the cart.");
+    println!("Type 'buyer <id>' to see what you'll have left as you go, before paying.");
+    println!("Scanned something the bank doesn't know yet? You'll be asked for its name and price to add it.");
+    println!("Type 'abort' or 'cancel' at any time to cancel the cart.");
+    println!("Someone else waiting? Type 'park' to set the cart aside, then 'parked' and 'resume <n>' to get it back.");
+    println!("Scanned the wrong thing? Type 'void' and tap the card you paid with straight after to get it back.");
+    println!();
+    println!("{}", Style::new().underline().paint("Adding money"));
+    println!("Type 'deposit <id>' with your account ID to start the deposit process.");
+    println!("Add '--note <note>' to explain it for later, e.g. 'deposit <id> --note birthday money'.");
+    println!("Type 'withdraw <id> <amount>' to take some of your balance back out in cash.");
+    println!("Type 'topup <id> [amount]' for a QR code to pay off what you owe from home, it's added once an admin sees it arrive.");
+    println!();
+    println!("{}", Style::new().underline().paint("New users"));
+    println!("Type 'adduser <id>' with your desired account ID to create an new account.");
+    println!();
+    println!("{}", Style::new().underline().paint("Group accounts"));
+    println!("Type 'addgroup <id> <member ids...>' to create an account shared between members, for example a project's fridge fund.");
+    println!("Members can then be changed with 'addmember <id> <member id>' and 'delmember <id> <member id>'.");
+    println!();
+    println!("{}", Style::new().underline().paint("View products"));
+    println!("Type 'products' to view a product listing and prices.");
+    println!();
+    println!("{}", Style::new().underline().paint("Managing your account"));
+    println!("Type 'setpin <id>' to set a PIN, 'setname <id> <name>' to set your name, or 'setemail <id> <email>' to set your email.");
+    println!("You'll need to tap one of your cards or enter your current PIN.");
+    println!("Type 'weblink <id>' for a link to check your balance and history from your phone.");
+    println!();
+    println!("{}", Style::new().underline().paint("Linking your space account"));
+    println!("Type 'link <id>' with your account ID and scan the QR code to log in with your space account.");
+    println!();
+    println!("{}", Style::new().underline().paint("Redeeming a voucher"));
+    println!("Scan the voucher's QR code or type 'redeem <code> <id>' to add it to your account.");
+    println!();
+    println!("{}", Style::new().underline().paint("Check balance"));
+    println!("Type your user ID to view balance and recent transactions.");
+    println!();
+    println!(
+        "{}",
+        Style::new().underline().paint("Adding and removing cards")
+    );
+    println!("Type 'regcard <id> [name]' with your desired account ID and optionally the name of the card to start the card registration process");
+    println!("Type 'delcard <id> [name]' with your desired account ID and optionally the name of the card to start the card deletion process");
+    println!("New cards may need an admin to approve them before they can be used to pay.");
+    println!();
+    println!(
+        "{}",
+        Style::new()
+            .underline()
+            .paint("Other commands (generally internal use only)")
+    );
+    println!("- reload");
+    println!("- labels <output directory> [barcode...]");
+    println!("- pricelist [--pdf <path>]");
+    println!("- users");
+    println!("- deposits");
+    println!("- purchases");
+    println!("- watch <users | purchases>");
+    println!("- writeoff <barcode> <quantity> <reason>");
+    println!("- writeoffs");
+    println!("- restock <barcode> <quantity> [unit cost]");
+    println!("- stock");
+    println!("- stockvalue");
+    println!("- aging");
+    println!("- reorder [days until the next shop]");
+    println!("- forecast [days]");
+    println!("- prodstats <barcode | name>");
+    println!("- chart [days]");
+    println!("- dbdiff <snapshot A> <snapshot B>");
+    println!("- books [period]");
+    println!("- restore <database file from a backup>");
+    println!("- suppliers");
+    println!("- addsupplier <name> [contact]");
+    println!("- po [new <supplier> | receive <order id>]");
+    println!("- expense <amount> <description>");
+    println!("- margin <year | year-Qn | year-month | date>");
+    println!("- stocktake <barcode> <count>");
+    println!("- shrinkage <year | year-Qn | year-month | date>");
+    println!("- tokens");
+    println!("- issuetoken <name> <read | purchase | admin> [user]");
+    println!("- revoketoken <name>");
+    println!("- exportuser <id>");
+    println!("- exportledger <ledger | beancount> [period]");
+    println!("- sync [peer database path]");
+    println!("- importusers <csv <path> | ldap>");
+    println!("- importproducts <csv path>");
+    println!("- clearhistory");
+    println!("- standingorders [id]");
+    println!("- addstandingorder <id> <amount> <weekly | monthly> <description>");
+    println!("- cancelstandingorder <order id>");
+    println!("- makevoucher <amount>");
+    println!("- promos");
+    println!("- addpromo <code> <percent% | amount> [max uses] [expiry date]");
+    println!("- delpromo <code>");
+    println!("- cashbox [all | empty <currency>]");
+    println!("- taxreport <year | year-Qn | year-month | date>");
+    println!("- zreport");
+    println!("- freeze <id> [reason]");
+    println!("- unfreeze <id>");
+    println!("- tabs");
+    println!("- settletab <cash | sponsor id> <tab name> [--note <note>]");
+    println!("- fsck");
+    println!();
+    println!("{}", Style::new().underline().paint("Admin commands"));
+    println!("These ask an admin to tap their card or enter their PIN first.");
+    println!("- adjust <id> <+/-amount> <reason> [--note <note>]");
+    println!("- overrideprice <barcode> <price> <reason>");
+    println!("- discount <amount | percent> [reason]");
+    println!("- setdiscount <id> <percent | none>");
+    println!("- setrole <id> <member | volunteer | treasurer | admin>");
+    println!("- balance [id] [--at <date> [time]]");
+    println!("- pay");
+    println!("- logout");
+    println!("- again");
+    println!("- usual");
+    println!("- favourites [id]");
+    println!("- setfavourite <id> <code> <barcode | none>");
+    println!("- setlimit <id> <daily | purchase> <amount | none>");
+    println!("- overridelimit");
+    println!("- refund <transaction id> [--note <note>]");
+    println!("- tx <transaction id> [--receipt]");
+    println!("- pendingcards");
+    println!("- approvecard <card id>");
+    println!("- rejectcard <card id>");
+    println!("- rebuild-balances");
+}
+
+// Writes SVG sheets of shelf labels, for every product or just the ones given
+fn shelf_labels(product_store: &products::Products, args: &[&str]) {
+    if args.is_empty() {
+        println!("Usage: labels <output directory> [barcode...]");
+        return;
+    }
+
+    let mut selected = if args.len() > 1 {
+        let mut selected = Vec::new();
+        for code in &args[1..] {
+            match products::lookup(product_store, code) {
+                Some(p) => selected.push(p),
+                None => {
+                    println!("Error, unknown product {}", code);
+                    return;
+                }
+            }
+        }
+        selected
+    } else {
+        product_store.values().collect::<Vec<_>>()
+    };
+    selected.sort_by(|a, b| a.name.cmp(&b.name));
+    if selected.is_empty() {
+        println!("No products to label");
+        return;
+    }
+
+    let dir = std::path::Path::new(args[0]);
+    if let Err(e) = std::fs::create_dir_all(dir) {
+        println!("Error, unable to create {}: {}", dir.display(), e);
+        return;
+    }
+    for (i, page) in selected.chunks(labels::LABELS_PER_PAGE).enumerate() {
+        let path = dir.join(format!("labels-{}.svg", i + 1));
+        match std::fs::write(&path, labels::page(page)) {
+            Ok(()) => println!("Wrote {}", path.display()),
+            Err(e) => {
+                println!("Error, unable to write {}: {}", path.display(), e);
+                return;
+            }
+        }
+    }
+}
+
+// The products file, with names translated for the configured locale
+fn load_products(config: &config::Config) -> Result<products::Products, String> {
+    let mut products = products::read_products()?;
+    if let Some(locale) = config.locale() {
+        if let Some(names) = products::read_translations(&locale)? {
+            products::translate(&mut products, &names);
+        }
+    }
+    Ok(products)
+}
+
+fn reload(products: &mut products::Products, config: &config::Config) {
+    *products = match load_products(config) {
+        Ok(p) => p,
+        Err(e) => {
+            println!("Error, unable to load products: {}", e);
+            return;
+        }
+    };
+    update_price_list(products, config);
+}
+
+// Merges a CSV of products into the products file, e.g. after a cash-and-carry run
+fn import_products(products: &mut products::Products, config: &config::Config, args: &[&str]) {
+    if args.len() != 1 {
+        println!("Usage: importproducts <csv path>");
+        println!("Lines are <barcode>,<price in pounds>,<name>[,<category>[,<tax category>]]");
+        return;
+    }
+    let csv = match std::fs::read_to_string(args[0]) {
+        Ok(c) => c,
+        Err(e) => {
+            println!("Error, unable to read {}: {}", args[0], e);
+            return;
+        }
+    };
+    let import = products::parse_products_csv(&csv);
+    for e in &import.errors {
+        println!("Skipping {}", e);
+    }
+    if import.products.is_empty() {
+        println!("Nothing to import");
+        return;
+    }
+
+    let current = match std::fs::read_to_string(data_path("products")) {
+        Ok(p) => p,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => {
+            println!("Error, unable to load products: {}", e);
+            return;
+        }
+    };
+    let (merged, summary) = products::merge_products(&current, &import.products);
+    if let Err(e) = products::write_products(&merged) {
+        println!("Error, unable to save products: {}", e);
+        return;
+    }
+    println!(
+        "Imported {} products: {} added, {} updated, {} unchanged, {} skipped",
+        import.products.len() + import.errors.len(),
+        summary.inserted,
+        summary.updated,
+        summary.unchanged,
+        import.errors.len()
+    );
+    reload(products, config);
+}
+
+fn update_price_list(products: &products::Products, config: &config::Config) {
+    if let Some(path) = &config.pricelist {
+        if let Err(e) = pricelist::write_pdf(products, path) {
+            println!("Error, unable to update price list: {}", e);
+        }
+    }
+}
+
+fn price_list(products: &products::Products, args: &[&str]) {
+    match args {
+        [] => {
+            for (category, products) in pricelist::categorised(products) {
+                println!("{}", Style::new().underline().paint(category));
+                for p in products {
+                    println!("{} - {}", p.name, p.disp_price());
+                }
+            }
+        }
+        ["--pdf", path] => match pricelist::write_pdf(products, path) {
+            Ok(()) => println!("Price list written to {}", path),
+            Err(e) => println!("Error, unable to write price list: {}", e),
+        },
+        _ => println!("Usage: pricelist [--pdf <path>]"),
+    }
+}
+
+fn products(products: &products::Products) {
+    println!("{}", Style::new().underline().paint("Product listing"));
+    for (barcode, product) in products {
+        println!("{} - {} ({})", product.name, product.disp_price(), barcode);
+    }
+}
+
+fn adduser(db: &db::DB, webhooks: &webhooks::Webhooks, args: &[&str]) {
+    if args.len() < 1 {
+        println!("Usage: adduser <id>");
+        return;
+    }
+
+    if FORBIDDEN_USERS.contains(&args[0]) {
+        println!("Error, user ID is forbidden");
+        return;
+    }
+
+    match db.add_user(args[0]) {
+        Ok(_) => {
+            webhooks.fire(
+                webhooks::Event::UserCreated,
+                serde_json::json!({ "user": args[0] }),
+            );
+            println!("User {} added", args[0]);
+        }
+        Err(e) => {
+            println!("Error, unable to add user: {}", e);
+        }
+    }
+}
+
+fn add_group(db: &db::DB, webhooks: &webhooks::Webhooks, args: &[&str]) {
+    if args.len() < 2 {
+        println!("Usage: addgroup <id> <member ids...>");
+        return;
+    }
+
+    if FORBIDDEN_USERS.contains(&args[0]) {
+        println!("Error, user ID is forbidden");
+        return;
+    }
+
+    match db.add_group(args[0], &args[1..]) {
+        Ok(_) => {
+            webhooks.fire(
+                webhooks::Event::UserCreated,
+                serde_json::json!({ "user": args[0], "members": args[1..] }),
+            );
+            println!("Group {} added", args[0]);
+        }
+        Err(e) => {
+            println!("Error, unable to add group: {}", e);
+        }
+    }
+}
+
+fn freeze(db: &db::DB, args: &[&str]) {
+    if args.is_empty() {
+        println!("Usage: freeze <id> [reason]");
+        return;
+    }
+    let reason = if args.len() > 1 {
+        args[1..].join(" ")
+    } else {
+        "no reason given".to_string()
+    };
+
+    match db.freeze(args[0], &reason) {
+        Ok(u) => println!("Account {} frozen, purchases are blocked until it's unfrozen", u.id),
+        Err(e) => println!("Error, unable to freeze account: {}", e),
+    }
+}
+
+async fn set_discount(
+    db: &db::DB,
+    config: &config::Config,
+    args: &[&str],
+    reader: &mut Receiver<CardEvent>,
+) {
+    let percent = match args.get(1).map(|a| (*a, a.trim_end_matches('%').parse::<u8>())) {
+        Some(("none", _)) | Some((_, Ok(0))) => None,
+        Some((_, Ok(p))) if p <= 100 => Some(p),
+        _ => {
+            println!("Usage: setdiscount <id> <percent | none>");
+            return;
+        }
+    };
+    if db.get_user(args[0]).is_none() {
+        println!("Error, user {} does not exist", args[0]);
+        return;
+    }
+    if authenticate_role(db, config, reader, Role::Treasurer).await.is_none() {
+        return;
+    }
+
+    match db.set_discount(args[0], percent) {
+        Ok(u) => match u.discount {
+            Some(p) => println!("{} now gets {}% off everything", u.id, p),
+            None => println!("{} no longer gets a discount", u.id),
+        },
+        Err(e) => println!("Error, unable to set discount: {}", e),
+    }
+}
+
+async fn set_role(
+    db: &db::DB,
+    config: &config::Config,
+    args: &[&str],
+    reader: &mut Receiver<CardEvent>,
+) {
+    let role = match args {
+        [_, role] => match role.parse::<Role>() {
+            Ok(r) => r,
+            Err(e) => {
+                println!("Error, {}", e);
+                return;
+            }
+        },
+        _ => {
+            println!("Usage: setrole <id> <member | volunteer | treasurer | admin>");
+            return;
+        }
+    };
+    if db.get_user(args[0]).is_none() {
+        println!("Error, user {} does not exist", args[0]);
+        return;
+    }
+    if authenticate_role(db, config, reader, Role::Admin).await.is_none() {
+        return;
+    }
+
+    match db.set_role(args[0], role) {
+        Ok(u) if config.role(&u) != role => {
+            println!("{} is listed under admins in the config, so stays an admin", u.id)
+        }
+        Ok(u) => println!("{} is now a {}", u.id, role),
+        Err(e) => println!("Error, unable to set role: {}", e),
+    }
+}
+
+async fn set_limit(
+    db: &db::DB,
+    config: &config::Config,
+    args: &[&str],
+    reader: &mut Receiver<CardEvent>,
+) {
+    let usage = || println!("Usage: setlimit <id> <daily | purchase> <amount | none>");
+    if args.len() != 3 {
+        return usage();
+    }
+    let amount = match args[2] {
+        "none" => None,
+        a => match parse_amount(a) {
+            Some(a) => Some(a),
+            None => return usage(),
+        },
+    };
+    let user = match db.get_user(args[0]) {
+        Some((u, _)) => u,
+        None => {
+            println!("Error, user {} does not exist", args[0]);
+            return;
+        }
+    };
+    let (daily, purchase) = match args[1] {
+        "daily" => (amount, user.purchase_limit),
+        "purchase" => (user.daily_limit, amount),
+        _ => return usage(),
+    };
+    if authenticate_role(db, config, reader, Role::Treasurer).await.is_none() {
+        return;
+    }
+
+    match db.set_limits(&user.id, daily, purchase) {
+        Ok(u) => println!("Spending limits updated for {}", u.id),
+        Err(e) => println!("Error, unable to set limit: {}", e),
+    }
+}
+
+// Lets the cart in progress through any spending limits, e.g. for a parent topping up
+async fn override_limit(
+    db: &db::DB,
+    config: &config::Config,
+    cart: &mut Option<Cart>,
+    reader: &mut Receiver<CardEvent>,
+) {
+    if cart.is_none() {
+        println!("Nothing in cart");
+        return;
+    }
+    if let Some(admin) = authenticate_role(db, config, reader, Role::Volunteer).await {
+        println!("Spending limits won't apply to this cart");
+        cart.as_mut().unwrap().limit_override = Some(admin);
+    }
+}
+
+fn unfreeze(db: &db::DB, args: &[&str]) {
+    if args.is_empty() {
+        println!("Usage: unfreeze <id>");
+        return;
+    }
+
+    match db.unfreeze(args[0]) {
+        Ok(u) => println!("Account {} unfrozen", u.id),
+        Err(e) => println!("Error, unable to unfreeze account: {}", e),
+    }
+}
+
+fn group_member(db: &db::DB, args: &[&str], add: bool) {
+    if args.len() < 2 {
+        println!("Usage: {} <group id> <member id>", if add { "addmember" } else { "delmember" });
+        return;
+    }
+
+    let group = if add {
+        db.add_group_member(args[0], args[1])
+    } else {
+        db.remove_group_member(args[0], args[1])
+    };
+    match group {
+        Ok(g) if g.is_group() => println!("Members of {}: {}", g.id, g.members.join(", ")),
+        Ok(g) => println!("{} no longer has any members", g.id),
+        Err(e) => println!("Error, unable to update group: {}", e),
+    }
+}
+
+async fn deposit(
+    db: &db::DB,
+    config: &config::Config,
+    webhooks: &webhooks::Webhooks,
+    acceptor: Option<&acceptor::Acceptor>,
+    args: &[&str],
+) {
+    let (args, mut note) = split_note(args);
+    if args.len() < 1 {
+        println!("Usage: deposit <id> [--note <note>]");
+        return;
+    }
+
+    // Whether the machine counted it, in which case it's cash
+    let presets = match config.deposits.presets.is_empty() {
+        true => String::new(),
+        false => format!("{}, or ", config.deposits.menu()),
+    };
+    let (amount, counted) = loop {
+        if acceptor.is_some() {
+            print!("Amount to deposit ({}'insert' to put cash in the machine, 'abort' to cancel): ", presets);
+        } else {
+            print!("Amount to deposit ({}'abort' to cancel): ", presets);
+        }
+        std::io::stdout().flush().unwrap();
+
+        let buffer = read_answer();
+
+        if buffer == "abort" {
+            return;
+        }
+        if let (Some(acceptor), "insert") = (acceptor, buffer.as_str()) {
+            acceptor.start();
+            println!("Insert coins and notes, then press enter when you're done");
+            read_answer();
+            match acceptor.finish() {
+                0 => println!("Nothing was inserted"),
+                amount => break (amount, true),
+            }
+            continue;
+        }
+
+        let amount = match config.deposits.preset(&buffer).or_else(|| parse_amount(&buffer)) {
+            Some(amount) => amount,
+            None => {
+                println!("Invalid amount");
+                continue;
+            }
+        };
+        if let Err(e) = config.deposits.check(amount) {
+            println!("Error, {}", e);
+            continue;
+        }
+        if config.deposits.needs_confirming(amount) {
+            print!("That's {}, are you sure? (y/N): ", money::format(amount));
+            std::io::stdout().flush().unwrap();
+            if !read_answer().eq_ignore_ascii_case("y") {
+                continue;
+            }
+        }
+        break (amount, false);
+    };
+
+    let method = loop {
+        if counted {
+            break db::DepositMethod::Cash;
+        }
+        if config.sumup.is_some() {
+            print!("Deposit method (cash / bank / card; 'abort' to cancel): ");
+        } else {
+            print!("Deposit method (cash / bank; 'abort' to cancel): ");
+        }
+        std::io::stdout().flush().unwrap();
+
+        let buffer = read_answer();
+
+        if buffer == "abort" {
+            return;
+        } else if buffer == "cash" {
+            break db::DepositMethod::Cash;
+        } else if buffer == "bank" {
+            #[derive(Debug, Serialize, Deserialize, Clone)]
+            pub struct CardUID {}
+            break db::DepositMethod::BankTransfer;
+        } else if buffer == "card" && config.sumup.is_some() {
+            break db::DepositMethod::Card;
+        } else {
+            println!("Invalid method")
+        }
+    };
+
+    let fee = db.deposit_fee(amount, method);
+    if fee > 0 {
+        println!(
+            "There's a {} fee for {} deposits, so {} will be added",
+            money::format(fee),
+            method,
+            money::format(amount - fee)
+        );
+    }
+
+    if let (db::DepositMethod::Card, Some(sumup)) = (method, &config.sumup) {
+        if db.get_user(args[0]).is_none() {
+            println!("Error, unable to deposit: user {} does not exist", args[0]);
+            return;
+        }
+        match take_card_payment(sumup, amount, args[0]).await {
+            Ok(code) => {
+                // Keep the SumUp reference with the deposit for matching up later
+                note = Some(match note {
+                    Some(note) => format!("{}; SumUp {}", note, code),
+                    None => format!("SumUp {}", code),
+                });
+            }
+            Err(e) => {
+                println!("Error, card payment failed: {}", e);
+                return;
+            }
+        }
+    }
+
+    match db.deposit_user(args[0], amount, method, note.as_deref()) {
         Ok(user) => {
-            println!("Charged to user {}", Style::new().bold().paint(&user.id));
-            println!("New balance: {}", user.disp_balance());
-            *cart = None;
+            webhooks.fire(
+                webhooks::Event::Deposit,
+                serde_json::json!({
+                    "user": user.id,
+                    "amount": amount,
+                    "method": method,
+                    "fee": fee,
+                    "note": note,
+                    "balance": user.balance,
+                }),
+            );
+            println!("Deposited applied to {}", user.display_name());
+            bigtext::show_balance("New balance", &user);
+            display::new_balance(&user);
+            if method != db::DepositMethod::Card {
+                println!(
+                    "{}",
+                    Style::new()
+                        .bold()
+                        .paint("Please transfer money for this deposit / put it in the cash box")
+                );
+            }
+            // Counted cash is already in the machine
+            if method == db::DepositMethod::Cash && !counted {
+                open_drawer(db, config, "cash deposit");
+            }
+            if method == db::DepositMethod::Cash {
+                check_cash_box(db, amount as i64);
+            }
+            if method == db::DepositMethod::BankTransfer {
+                print_qr(&payment_link(amount, "57Bank"));
+            }
+        }
+        Err(e) => {
+            println!("Error, unable to deposit: {}", e);
+            return;
+        }
+    }
+}
+
+// Just the QR code to pay by bank transfer, for paying off a debt from home. Nothing's recorded
+// until an admin deposits it once the money has arrived.
+fn topup(db: &db::DB, args: &[&str]) {
+    if args.is_empty() || args.len() > 2 {
+        println!("Usage: topup <id> [amount]");
+        return;
+    }
+    let user = match db.get_user(args[0]) {
+        Some((u, _)) => u,
+        None => {
+            println!("Error, user {} does not exist", args[0]);
+            return;
+        }
+    };
+    let amount = match args.get(1) {
+        Some(a) => match parse_amount(a) {
+            Some(a) => a,
+            None => {
+                println!("Error, invalid amount {}", a);
+                return;
+            }
+        },
+        None if user.balance < 0 => user.balance.unsigned_abs(),
+        None => {
+            println!("{} doesn't owe anything, give an amount to top up by", user.display_name());
+            return;
+        }
+    };
+    println!("Transfer {} to top up {}", money::format(amount), user.display_name());
+    print_qr(&payment_link(amount, &format!("57Bank {}", user.id)));
+    println!("It'll show in your balance once an admin has seen it arrive and deposited it");
+}
+
+// Monzo.me link for a bank transfer, with a reference to tell who it's from
+fn payment_link(amount: u32, reference: &str) -> String {
+    let url = format!("https://monzo.me/{}/{:.2}", MONZO_USERNAME, amount as f64 / 100.0);
+    reqwest::Url::parse_with_params(&url, [("d", reference)]).unwrap().to_string()
+}
+
+async fn take_card_payment(
+    config: &config::SumUpConfig,
+    amount: u32,
+    user: &str,
+) -> Result<String, String> {
+    let terminal = sumup::Terminal::new(config);
+    let id = terminal
+        .checkout(amount, &format!("57Bank deposit for {}", user))
+        .await?;
+    println!("Present card on the reader for {}...", money::format(amount));
+
+    let payment = terminal.wait_for_payment(&id).await?;
+    match payment.status {
+        sumup::Status::Successful => Ok(payment.transaction_code.unwrap_or(id)),
+        sumup::Status::Cancelled => Err("cancelled on the reader".to_string()),
+        _ => Err("declined".to_string()),
+    }
+}
+
+async fn make_voucher(db: &db::DB, config: &config::Config, args: &[&str], reader: &mut Receiver<CardEvent>) {
+    let amount = match args.first().and_then(|a| parse_amount(a)) {
+        Some(a) => a,
+        None => {
+            println!("Usage: makevoucher <amount>");
+            return;
+        }
+    };
+
+    // Vouchers are as good as cash, so someone has to answer for each one
+    let treasurer = match authenticate_role(db, config, reader, Role::Treasurer).await {
+        Some(t) => t,
+        None => return,
+    };
+
+    match db.create_voucher(amount, &treasurer) {
+        Ok(voucher) => {
+            println!(
+                "{}",
+                Style::new().bold().paint(format!(
+                    "Voucher {} for {}",
+                    voucher.code,
+                    money::format(voucher.amount)
+                ))
+            );
+            println!("Scan the QR code or type the code at the bank to redeem it");
+            print_qr(&voucher.code);
+        }
+        Err(e) => println!("Error, unable to make voucher: {}", e),
+    }
+}
+
+fn redeem_voucher(db: &db::DB, webhooks: &webhooks::Webhooks, args: &[&str]) {
+    if args.is_empty() {
+        println!("Usage: redeem <code> [id]");
+        return;
+    }
+
+    let id = match args.get(1) {
+        Some(id) => id.to_string(),
+        None => {
+            print!("Account ID to credit the voucher to ('abort' to cancel): ");
+            std::io::stdout().flush().unwrap();
+
+            let buffer = read_answer();
+            if buffer.is_empty() || buffer == "abort" {
+                return;
+            }
+            buffer
+        }
+    };
+
+    match db.redeem_voucher(args[0], &id) {
+        Ok((user, voucher)) => {
+            webhooks.fire(
+                webhooks::Event::Deposit,
+                serde_json::json!({
+                    "user": user.id,
+                    "amount": voucher.amount,
+                    "method": db::DepositMethod::Voucher,
+                    "balance": user.balance,
+                }),
+            );
+            println!(
+                "Voucher for {} credited to {}",
+                money::format(voucher.amount),
+                user.display_name()
+            );
+            bigtext::show_balance("New balance", &user);
+            display::new_balance(&user);
+        }
+        Err(e) => println!("Error, unable to redeem voucher: {}", e),
+    }
+}
+
+// Plain text receipt, short enough to fit in a QR code
+fn receipt(t: &Transaction, user: Option<&User>) -> String {
+    let mut out = format!("57North Snack Bank\n{}\n", t.timestamp.format("%Y-%m-%d %H:%M"));
+    if let db::TransactionType::Purchase { products, total, combos, discount, user_discount, manual_discount, .. } =
+        &t.transaction
+    {
+        let discount = discount + manual_discount.as_ref().map_or(0, |m| m.amount);
+        for p in products {
+            out.push_str(&format!("{} {}\n", p.name, p.disp_price()));
+        }
+        for c in combos {
+            out.push_str(&format!("{} -{}\n", c.name, money::format(c.discount)));
+        }
+        if discount + *user_discount > 0 {
+            out.push_str(&format!(
+                "Discount -{}\n",
+                money::format(discount + *user_discount)
+            ));
+        }
+        out.push_str(&format!("Total {}\n", money::format(*total)));
+    }
+    if let Some(user) = user {
+        out.push_str(&format!(
+            "Balance {}\n",
+            money::format(user.balance)
+        ));
+    }
+    out.push_str(&format!("Ref {}", t.id));
+    out
+}
+
+fn print_qr(data: &str) {
+    // Block characters are meaningless to a screen reader, so give them what it would contain
+    if bank_core::style::plain() {
+        println!("{}", data);
+        return;
+    }
+    let qr_code = qrcode_generator::to_matrix(data, qrcode_generator::QrCodeEcc::Low).unwrap();
+    for _ in 0..2 {
+        for _ in 0..qr_code.len() + 4 {
+            print!("\u{2588}\u{2588}");
+        }
+        println!();
+    }
+    for row in &qr_code {
+        print!("\u{2588}\u{2588}\u{2588}\u{2588}");
+        for col in row {
+            if *col {
+                print!("  ");
+            } else {
+                print!("\u{2588}\u{2588}");
+            }
+        }
+        println!("\u{2588}\u{2588}\u{2588}\u{2588}");
+    }
+    for _ in 0..2 {
+        for _ in 0..qr_code.len() + 4 {
+            print!("\u{2588}\u{2588}");
+        }
+        println!();
+    }
+}
+
+fn users(db: &db::DB) {
+    println!("{}", Style::new().underline().paint("Users"));
+
+    for user in match db.users() {
+        Ok(u) => u,
+        Err(e) => {
+            println!("Error, unable to list users: {}", e);
+            return;
+        }
+    } {
+        println!(
+            "{} - {}{}",
+            user.display_name(),
+            user.disp_balance(),
+            if user.frozen.is_some() { " (frozen)" } else { "" }
+        );
+    }
+}
+
+// Names people have given themselves, for showing next to their IDs in listings
+fn display_names(db: &db::DB) -> std::collections::HashMap<String, String> {
+    db.users()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|u| (u.id.clone(), u.display_name()))
+        .collect()
+}
+
+fn describe_actor(
+    names: &std::collections::HashMap<String, String>,
+    actor: &db::TransactionActor,
+) -> String {
+    match actor {
+        db::TransactionActor::User(id) => format!("user {}", names.get(id).unwrap_or(id)),
+        actor => actor.to_string(),
+    }
+}
+
+// Enough of the transaction ID to refer to it, e.g. for refund
+fn short_id(t: &Transaction) -> String {
+    t.id.to_string()[..8].to_string()
+}
+
+// Whose transactions a list shows
+pub enum Shown {
+    Everyone,
+    Only(String),
+}
+
+impl Shown {
+    fn includes(&self, t: &Transaction) -> bool {
+        match self {
+            Shown::Everyone => true,
+            Shown::Only(id) => {
+                t.actor.account() == Some(id.as_str())
+                    || matches!(&t.actor, db::TransactionActor::Group { member, .. } if member == id)
+            }
+        }
+    }
+}
+
+// Whoever is logged in sees their own, unless they're a treasurer. With nobody logged in, a
+// treasurer has to step up to see everyone's.
+async fn shown_to(
+    db: &db::DB,
+    config: &config::Config,
+    user: Option<&str>,
+    reader: &mut Receiver<CardEvent>,
+) -> Option<Shown> {
+    match user.and_then(|id| db.get_user(id)) {
+        Some((user, _)) if config.role(&user) >= Role::Treasurer => Some(Shown::Everyone),
+        Some((user, _)) => Some(Shown::Only(user.id)),
+        None => authenticate_role(db, config, reader, Role::Treasurer).await.map(|_| Shown::Everyone),
+    }
+}
+
+fn deposits(db: &db::DB, shown: &Shown) {
+    println!("{}", Style::new().underline().paint("Recent deposits"));
+    let names = display_names(db);
+
+    let history = match db.history() {
+        Ok(h) => h,
+        Err(e) => {
+            println!("Error, unable to list transactions: {}", e);
+            return;
+        }
+    };
+    for t in history
+        .iter()
+        .filter(|t| matches!(t.transaction, db::TransactionType::Deposit { .. }) && shown.includes(t))
+        .rev()
+        .take(10)
+    {
+        match &t.transaction {
+            db::TransactionType::Deposit { amount, method, fee } => {
+                println!(
+                    "Deposit {} ({}{}), by {} at {} [{}]",
+                    money::format(*amount),
+                    method,
+                    match fee {
+                        0 => String::new(),
+                        fee => format!(", {} fee", money::format(*fee)),
+                    },
+                    describe_actor(&names, &t.actor),
+                    t.timestamp,
+                    short_id(t)
+                );
+                if let Some(note) = &t.note {
+                    println!("Note: {}", note);
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+// Records stock lost to damage, expiry, or theft, e.g. `writeoff 5000159461122 2 mice got in`
+fn write_off(db: &db::DB, products: &products::Products, args: &[&str]) {
+    if args.len() < 3 {
+        println!("Usage: writeoff <barcode> <quantity> <reason>");
+        return;
+    }
+    let product = match products::lookup(products, args[0]) {
+        Some(p) => p,
+        None => {
+            println!("Error, unknown product {}", args[0]);
+            return;
+        }
+    };
+    let quantity = match args[1].parse::<u32>() {
+        Ok(q) if q > 0 => q,
+        _ => {
+            println!("Error, invalid quantity {}", args[1]);
+            return;
+        }
+    };
+
+    match db.write_off(product, quantity, &args[2..].join(" ")) {
+        Ok(()) => println!("Wrote off {} x {}", quantity, product.name),
+        Err(e) => println!("Error, unable to write off stock: {}", e),
+    }
+}
+
+// Records stock being put on the shelves, e.g. `restock 4029764001401 20 0.85`
+fn restock(db: &db::DB, products: &products::Products, args: &[&str]) {
+    if args.len() < 2 || args.len() > 3 {
+        println!("Usage: restock <barcode> <quantity> [unit cost]");
+        return;
+    }
+    let product = match products::lookup(products, args[0]) {
+        Some(p) => p,
+        None => {
+            println!("Error, unknown product {}", args[0]);
+            return;
+        }
+    };
+    let quantity = match args[1].parse::<u32>() {
+        Ok(q) if q > 0 => q,
+        _ => {
+            println!("Error, invalid quantity {}", args[1]);
+            return;
+        }
+    };
+    let cost = match args.get(2).map(|c| parse_amount(c)) {
+        None => None,
+        Some(Some(c)) => Some(c),
+        Some(None) => {
+            println!("Error, invalid cost {}", args[2]);
+            return;
+        }
+    };
+
+    match db.restock(product, quantity, cost, None) {
+        Ok(()) => println!("Restocked {} x {}", quantity, product.name),
+        Err(e) => println!("Error, unable to restock: {}", e),
+    }
+}
+
+fn stocktake(db: &db::DB, products: &products::Products, args: &[&str]) {
+    let (product, counted) = match args {
+        [barcode, counted] => (
+            products::lookup(products, barcode),
+            counted.parse::<u32>().ok(),
+        ),
+        _ => (None, None),
+    };
+    let (product, counted) = match (product, counted) {
+        (Some(p), Some(c)) => (p, c),
+        _ => {
+            println!("Usage: stocktake <barcode> <count>");
+            return;
+        }
+    };
+
+    match db.stocktake(product, counted) {
+        Ok(()) => println!("Counted {} x {}", counted, product.name),
+        Err(e) => println!("Error, unable to record stocktake: {}", e),
+    }
+}
+
+fn stock(db: &db::DB, products: &products::Products) {
+    let levels = match db.transactions() {
+        Ok(t) => bank_core::stock::levels(&t),
+        Err(e) => {
+            println!("Error, unable to list transactions: {}", e);
+            return;
+        }
+    };
+    if levels.is_empty() {
+        println!("No stock recorded yet, use 'restock' when putting things on the shelves");
+        return;
+    }
+
+    println!("{}", Style::new().underline().paint("Stock levels"));
+    let mut lines = levels
+        .iter()
+        .filter_map(|(barcode, level)| Some((products.get(barcode)?, *level)))
+        .collect::<Vec<_>>();
+    lines.sort_by(|a, b| a.0.name.cmp(&b.0.name));
+    for (product, level) in lines {
+        println!("{} - {}", product.name, level);
+    }
+}
+
+// Cash taken from the box, e.g. by a volunteer doing the shopping
+fn expense(db: &db::DB, args: &[&str]) {
+    let amount = match args.first().and_then(|a| parse_amount(a)) {
+        Some(a) if args.len() > 1 => a,
+        _ => {
+            println!("Usage: expense <amount> <description>");
+            return;
         }
+    };
+    let description = args[1..].join(" ");
+
+    match db.expense(amount, &description) {
+        Ok(()) => println!(
+            "Took {} from the cash box for {}",
+            money::format(amount),
+            description
+        ),
+        Err(e) => println!("Error, unable to record expense: {}", e),
+    }
+}
+
+fn suppliers(db: &db::DB) {
+    let (suppliers, transactions) = match db.suppliers().and_then(|s| Ok((s, db.transactions()?))) {
+        Ok(s) => s,
         Err(e) => {
-            println!("Error, unable to charge user: {}", e);
+            println!("Error, unable to list suppliers: {}", e);
+            return;
         }
+    };
+    if suppliers.is_empty() {
+        println!("No suppliers, add one with 'addsupplier'");
+        return;
+    }
+
+    let spend = bank_core::stock::spend_by_supplier(&transactions);
+    println!("{}", Style::new().underline().paint("Suppliers"));
+    for s in suppliers {
+        println!(
+            "{}{} - {} spent",
+            s.name,
+            s.contact.map(|c| format!(" ({})", c)).unwrap_or_default(),
+            money::format(spend.get(&s.name).copied().unwrap_or(0))
+        );
     }
 }
 
-fn user_info(user: (User, Vec<Transaction>)) {
-    println!(
-        "{}",
-        Style::new()
-            .underline()
-            .paint(format!("User {}", user.0.id))
-    );
-    println!("Balance: {}", user.0.disp_balance());
+fn add_supplier(db: &db::DB, args: &[&str]) {
+    if args.is_empty() {
+        println!("Usage: addsupplier <name> [contact]");
+        return;
+    }
+    let contact = args[1..].join(" ");
+    match db.add_supplier(args[0], Some(contact.as_str()).filter(|c| !c.is_empty())) {
+        Ok(s) => println!("Added supplier {}", s.name),
+        Err(e) => println!("Error, unable to add supplier: {}", e),
+    }
+}
+
+fn api_tokens(db: &db::DB) {
+    let tokens = match db.api_tokens() {
+        Ok(t) => t,
+        Err(e) => {
+            println!("Error, unable to list tokens: {}", e);
+            return;
+        }
+    };
+    if tokens.is_empty() {
+        println!("No API tokens, issue one with 'issuetoken'");
+        return;
+    }
+
+    println!("{}", Style::new().underline().paint("API tokens"));
+    for t in tokens {
+        println!(
+            "{} - {}{}, issued {}",
+            t.name,
+            t.scope,
+            t.user.map(|u| format!(" as {}", u)).unwrap_or_default(),
+            t.created.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M")
+        );
+    }
+}
+
+async fn issue_token(
+    db: &db::DB,
+    config: &config::Config,
+    args: &[&str],
+    reader: &mut Receiver<CardEvent>,
+) {
+    let scope = match args {
+        [_, scope] | [_, scope, _] => match scope.parse::<db::Scope>() {
+            Ok(s) => s,
+            Err(e) => {
+                println!("Error, {}", e);
+                return;
+            }
+        },
+        _ => {
+            println!("Usage: issuetoken <name> <read | purchase | admin> [user]");
+            return;
+        }
+    };
+    if authenticate_role(db, config, reader, Role::Admin).await.is_none() {
+        return;
+    }
+
+    match db.issue_token(args[0], scope, args.get(2).copied()) {
+        Ok(token) => {
+            println!("Issued {} token {}, it won't be shown again:", scope, args[0]);
+            println!("{}", token);
+        }
+        Err(e) => println!("Error, unable to issue token: {}", e),
+    }
+}
+
+async fn revoke_token(
+    db: &db::DB,
+    config: &config::Config,
+    args: &[&str],
+    reader: &mut Receiver<CardEvent>,
+) {
+    if args.len() != 1 {
+        println!("Usage: revoketoken <name>");
+        return;
+    }
+    if authenticate_role(db, config, reader, Role::Admin).await.is_none() {
+        return;
+    }
+
+    match db.revoke_token(args[0]) {
+        Ok(t) => println!("Revoked token {}", t.name),
+        Err(e) => println!("Error, unable to revoke token: {}", e),
+    }
+}
+
+fn purchase_orders(db: &db::DB, products: &products::Products, args: &[&str]) {
+    match args {
+        ["new", supplier] => new_purchase_order(db, products, supplier),
+        ["receive", id] => match db.receive_purchase_order(id) {
+            Ok(o) => {
+                for line in &o.lines {
+                    println!("Restocked {} x {}", line.quantity, line.product.name);
+                }
+                println!("Received order from {}", o.supplier);
+            }
+            Err(e) => println!("Error, unable to receive order: {}", e),
+        },
+        [] => {
+            let orders = match db.purchase_orders() {
+                Ok(o) => o,
+                Err(e) => {
+                    println!("Error, unable to list purchase orders: {}", e);
+                    return;
+                }
+            };
+            let open = orders.iter().filter(|o| o.received.is_none()).collect::<Vec<_>>();
+            if open.is_empty() {
+                println!("No open purchase orders");
+                return;
+            }
+            println!("{}", Style::new().underline().paint("Open purchase orders"));
+            for o in open {
+                println!(
+                    "{} - {} on {}, {} line(s), {}",
+                    &o.id.simple().to_string()[..8],
+                    o.supplier,
+                    o.created.format("%Y-%m-%d"),
+                    o.lines.len(),
+                    money::format(o.total())
+                );
+            }
+        }
+        _ => println!("Usage: po [new <supplier> | receive <order id>]"),
+    }
+}
+
+fn new_purchase_order(db: &db::DB, products: &products::Products, supplier: &str) {
+    let mut lines = Vec::new();
+    loop {
+        print!("<barcode> <quantity> [unit cost], blank to finish ('abort' to cancel): ");
+        std::io::stdout().flush().unwrap();
+
+        let buffer = read_answer();
+        let parts = buffer.split_whitespace().collect::<Vec<_>>();
+        match parts[..] {
+            [] => break,
+            ["abort"] => return,
+            [barcode, quantity] | [barcode, quantity, _] => {
+                let product = match products::lookup(products, barcode) {
+                    Some(p) => p,
+                    None => {
+                        println!("Unknown product {}", barcode);
+                        continue;
+                    }
+                };
+                let (quantity, cost) = match (quantity.parse::<u32>(), parts.get(2).map(|c| parse_amount(c))) {
+                    (Ok(q), None) if q > 0 => (q, None),
+                    (Ok(q), Some(Some(c))) if q > 0 => (q, Some(c)),
+                    _ => {
+                        println!("Invalid quantity or cost");
+                        continue;
+                    }
+                };
+                lines.push(db::OrderLine {
+                    product: product.clone(),
+                    quantity,
+                    cost,
+                });
+            }
+            _ => println!("Expected <barcode> <quantity> [unit cost]"),
+        }
+    }
+
+    match db.new_purchase_order(supplier, lines) {
+        Ok(o) => println!(
+            "Created order {} with {}, {}",
+            &o.id.simple().to_string()[..8],
+            o.supplier,
+            money::format(o.total())
+        ),
+        Err(e) => println!("Error, unable to create order: {}", e),
+    }
+}
+
+fn write_offs(db: &db::DB) {
+    println!("{}", Style::new().underline().paint("Recent write-offs"));
+
+    let history = match db.history() {
+        Ok(h) => h,
+        Err(e) => {
+            println!("Error, unable to list transactions: {}", e);
+            return;
+        }
+    };
+    for t in history
+        .iter()
+        .filter(|t| matches!(t.transaction, db::TransactionType::WriteOff { .. }))
+        .rev()
+        .take(10)
+    {
+        if let db::TransactionType::WriteOff { product, quantity, reason } = &t.transaction {
+            println!(
+                "{} x {} ({}) at {}: {}",
+                quantity,
+                product.name,
+                money::format(product.price * quantity),
+                t.timestamp,
+                reason
+            );
+        }
+    }
+}
+
+fn purchases(db: &db::DB, config: &config::Config, shown: &Shown) {
+    if db.history().is_ok_and(|h| h.is_empty()) {
+        println!(
+            "{}",
+            Style::new()
+                .underline()
+                .fg(theme().warning)
+                .paint("No recent transactions")
+        );
+        return;
+    }
     println!("{}", Style::new().underline().paint("Recent transactions"));
-    for t in user.1.iter().rev().take(10) {
+    let names = display_names(db);
+
+    let history = match db.history() {
+        Ok(h) => h,
+        Err(e) => {
+            println!("Error, unable to list transactions: {}", e);
+            return;
+        }
+    };
+    let voided = history
+        .iter()
+        .filter_map(|t| match &t.transaction {
+            db::TransactionType::Void { transaction, .. } => Some(*transaction),
+            _ => None,
+        })
+        .collect::<std::collections::HashSet<_>>();
+    for t in history
+        .iter()
+        .filter(|t| matches!(t.transaction, db::TransactionType::Purchase { .. }) && shown.includes(t))
+        .rev()
+        .take(10)
+    {
         match &t.transaction {
-            db::TransactionType::Deposit { amount, method } => println!(
-                "Deposit £{:.2} ({})",
-                *amount as f64 / 100.0,
-                match method {
-                    db::DepositMethod::Cash => "cash",
-                    db::DepositMethod::BankTransfer => "bank transfer",
-                }
-            ),
-            db::TransactionType::Purchase { total, products } => {
-                println!("Purchase (total £{:.2})", *total as f64 / 100.0);
+            db::TransactionType::Purchase { products, total, promo, discount, tendered, combos, user_discount, rounding, manual_discount } => {
+                println!(
+                    "Purchase (total {}{}) by {} at {} [{}]{}",
+                    money::format(*total),
+                    tendered
+                        .as_ref()
+                        .map(|t| format!(", paid {}", format_cash(config, &t.currency, t.amount.into())))
+                        .unwrap_or_default(),
+                    describe_actor(&names, &t.actor),
+                    t.timestamp,
+                    short_id(t),
+                    if voided.contains(&t.id) { " (voided)" } else { "" }
+                );
                 for p in products {
-                    println!("- {} ({})", p.name, p.disp_price());
+                    println!("- {}", p.disp_line());
+                }
+                for combo in combos {
+                    println!("- {} (-{})", combo.name, money::format(combo.discount));
+                }
+                if let Some(code) = promo {
+                    println!("- Promo code {} (-{})", code, money::format(*discount));
+                }
+                if let Some(manual) = manual_discount {
+                    println!("- {} (-{})", describe_manual_discount(manual), money::format(manual.amount));
+                }
+                if *user_discount > 0 {
+                    println!("- Account discount (-{})", money::format(*user_discount));
+                }
+                if *rounding != 0 {
+                    println!("- Cash rounding {}", money::format_signed(*rounding));
+                }
+                if let Some(note) = &t.note {
+                    println!("Note: {}", note);
                 }
             }
+            _ => unreachable!(),
         }
-        println!("Timestamp: {}", t.timestamp);
-        println!()
     }
 }
 
-#[derive(Debug)]
-pub enum StdoutMsg {
-    Text(String),
-    Signal(Signal),
+#[derive(Serialize)]
+struct UserExport {
+    exported_at: chrono::DateTime<chrono::Utc>,
+    user: User,
+    transactions: Vec<Transaction>,
 }
 
-#[derive(Debug)]
-pub enum Signal {
-    Kill,
-    Eof,
-}
+fn export_user(db: &db::DB, args: &[&str]) {
+    if args.is_empty() {
+        println!("Usage: exportuser <id>");
+        return;
+    }
 
-fn clear(stdout: &mut Stdout) {
-    print!("{esc}[2J{esc}[1;1H", esc = 27 as char);
-    stdout.flush().unwrap();
+    let user = match db.get_user(args[0]) {
+        Some((u, _)) => u,
+        None => {
+            println!("Error, user {} does not exist", args[0]);
+            return;
+        }
+    };
+    // Everything, not just what's in the working set
+    let transactions = match db.transactions() {
+        Ok(t) => t
+            .into_iter()
+            .filter(|t| t.actor.account() == Some(&user.id))
+            .collect(),
+        Err(e) => {
+            println!("Error, unable to list transactions: {}", e);
+            return;
+        }
+    };
+
+    let export = UserExport {
+        exported_at: chrono::Utc::now(),
+        user,
+        transactions,
+    };
+    let export_json = match serde_json::to_string_pretty(&export) {
+        Ok(e) => e,
+        Err(e) => {
+            println!("Error, unable to serialize user data: {}", e);
+            return;
+        }
+    };
+
+    if let Err(e) = std::fs::create_dir_all(data_path("exports")) {
+        println!("Error, unable to create exports directory: {}", e);
+        return;
+    }
+    let path = data_path(&format!(
+        "exports/{}-{}.json",
+        export.user.id,
+        export.exported_at.format("%Y%m%d%H%M%S")
+    ))
+    .display()
+    .to_string();
+    match std::fs::write(&path, export_json) {
+        Ok(()) => println!(
+            "Exported user {} ({} transactions) to {}",
+            export.user.id,
+            export.transactions.len(),
+            path
+        ),
+        Err(e) => println!("Error, unable to write export: {}", e),
+    }
 }
 
-fn help() {
-    println!(
-        "{}",
-        Style::new()
-            .bold()
-            .underline()
-            .paint("--- 57North Snack Bank ---")
-    );
-    println!();
-    println!("{}", Style::new().underline().paint("Buying something"));
-    println!("Scan the barcode on the item to add to cart, complete transaction by typing in your account ID.");
-    println!("Alternatively type in cash to pay with cash directly into the box.");
-    println!("Type 'abort' or 'cancel' at any time to cancel the cart.");
-    println!();
-    println!("{}", Style::new().underline().paint("Adding money"));
-    println!("Type 'deposit <id>' with your account ID to start the deposit process.");
-    println!();
-    println!("{}", Style::new().underline().paint("New users"));
-    println!("Type 'adduser <id>' with your desired account ID to create an new account.");
-    println!();
-    println!("{}", Style::new().underline().paint("View products"));
-    println!("Type 'products' to view a product listing and prices.");
-    println!();
-    println!("{}", Style::new().underline().paint("Check balance"));
-    println!("Type your user ID to view balance and recent transactions.");
-    println!();
-    println!(
-        "{}",
-        Style::new().underline().paint("Adding and removing cards")
-    );
-    println!("Type 'regcard <id> [name]' with your desired account ID and optionally the name of the card to start the card registration process");
-    println!("Type 'delcard <id> [name]' with your desired account ID and optionally the name of the card to start the card deletion process");
-    println!();
-    println!(
-        "{}",
-        Style::new()
-            .underline()
-            .paint("Other commands (generally internal use only)")
-    );
-    println!("- reload");
-    println!("- users");
-    println!("- deposits");
-    println!("- purchases");
+fn export_ledger(db: &db::DB, config: &config::Config, args: &[&str]) {
+    let format = match args.first().map(|f| f.parse::<ledger::Format>()) {
+        Some(Ok(f)) => f,
+        Some(Err(e)) => {
+            println!("Error, {}", e);
+            return;
+        }
+        None => {
+            println!("Usage: exportledger <ledger | beancount> [period]");
+            return;
+        }
+    };
+    let period = match args.get(1).map(|p| reports::parse_period(p)) {
+        None => None,
+        Some(Ok(p)) => Some(p),
+        Some(Err(e)) => {
+            println!("Error, {}", e);
+            return;
+        }
+    };
+    let transactions = match db.transactions() {
+        Ok(t) => t
+            .into_iter()
+            .filter(|t| period.as_ref().is_none_or(|p| p.contains(t)))
+            .collect::<Vec<_>>(),
+        Err(e) => {
+            println!("Error, unable to list transactions: {}", e);
+            return;
+        }
+    };
+
+    if let Err(e) = std::fs::create_dir_all(data_path("exports")) {
+        println!("Error, unable to create exports directory: {}", e);
+        return;
+    }
+    let path = data_path(&format!(
+        "exports/{}-{}.{}",
+        period.as_ref().map_or("all", |p| p.label.as_str()),
+        chrono::Utc::now().format("%Y%m%d%H%M%S"),
+        format.extension()
+    ))
+    .display()
+    .to_string();
+    match std::fs::write(&path, ledger::export(&transactions, &config.ledger, format)) {
+        Ok(()) => println!("Exported {} transactions to {}", transactions.len(), path),
+        Err(e) => println!("Error, unable to write export: {}", e),
+    }
 }
 
-fn reload(products: &mut products::Products) {
-    *products = match products::read_products() {
-        Ok(p) => p,
+async fn import_users(db: &db::DB, config: &config::Config, webhooks: &webhooks::Webhooks, args: &[&str]) {
+    let members = match args {
+        ["csv", path] => members::read_csv(path),
+        ["ldap"] => match &config.ldap {
+            Some(ldap) => members::read_ldap(ldap).await,
+            None => {
+                println!("Error, LDAP is not configured");
+                return;
+            }
+        },
+        _ => {
+            println!("Usage: importusers <csv <path> | ldap>");
+            return;
+        }
+    };
+    let members = match members {
+        Ok(m) => m,
         Err(e) => {
-            println!("Error, unable to load products: {}", e);
+            println!("Error, unable to read member list: {}", e);
             return;
         }
     };
-}
 
-fn products(products: &products::Products) {
-    println!("{}", Style::new().underline().paint("Product listing"));
-    for (barcode, product) in products {
-        println!("{} - {} ({})", product.name, product.disp_price(), barcode);
+    let (members, skipped): (Vec<_>, Vec<_>) = members.into_iter().partition(|m| {
+        !m.user_id.is_empty()
+            && !m.user_id.contains(char::is_whitespace)
+            && !FORBIDDEN_USERS.contains(&m.user_id.as_str())
+    });
+    for m in &skipped {
+        println!("Skipping membership {}: user ID '{}' is not allowed", m.membership_id, m.user_id);
+    }
+
+    match db.import_members(&members) {
+        Ok(summary) => {
+            for conflict in &summary.conflicts {
+                println!("Skipping {}", conflict);
+            }
+            for id in &summary.created {
+                webhooks.fire(webhooks::Event::UserCreated, serde_json::json!({ "user": id }));
+            }
+            println!(
+                "Imported {} members: {} created, {} updated, {} unchanged, {} skipped",
+                members.len() + skipped.len(),
+                summary.created.len(),
+                summary.updated,
+                summary.unchanged,
+                skipped.len() + summary.conflicts.len()
+            );
+        }
+        Err(e) => println!("Error, unable to import users: {}", e),
     }
 }
 
-fn adduser(db: &db::DB, args: &[&str]) {
-    if args.len() < 1 {
-        println!("Usage: adduser <id>");
+async fn link_account(db: &db::DB, config: &config::Config, args: &[&str], reader: &mut Receiver<CardEvent>) {
+    if args.is_empty() {
+        println!("Usage: link <id>");
         return;
     }
-
-    if FORBIDDEN_USERS.contains(&args[0]) {
-        println!("Error, user ID is forbidden");
+    let oidc_config = match &config.oidc {
+        Some(o) => o,
+        None => {
+            println!("Error, account linking is not configured");
+            return;
+        }
+    };
+    let user = match db.get_user(args[0]) {
+        Some(u) => u.0,
+        None => {
+            println!("Error, user {} does not exist", args[0]);
+            return;
+        }
+    };
+    if !authenticate(db, &user, reader).await {
+        return;
+    }
+    // A linked login can act as the account elsewhere, so an admin has to agree as well as
+    // the owner, all the more when moving it to a different login could lock the owner out
+    let replace = user.oidc_subject.is_some();
+    if replace {
+        println!("User {} is already linked to a space account, linking will replace it", user.id);
+    }
+    if authenticate_role(db, config, reader, Role::Admin).await.is_none() {
         return;
     }
 
-    match db.add_user(args[0]) {
-        Ok(_) => {
-            println!("User {} added", args[0]);
+    let flow = match oidc::DeviceFlow::discover(oidc_config).await {
+        Ok(f) => f,
+        Err(e) => {
+            println!("Error, unable to start login: {}", e);
+            return;
         }
+    };
+    let auth = match flow.start().await {
+        Ok(a) => a,
         Err(e) => {
-            println!("Error, unable to add user: {}", e);
+            println!("Error, unable to start login: {}", e);
+            return;
         }
+    };
+
+    println!("Scan the QR code with your phone, or visit {}", auth.verification_uri);
+    println!("and enter the code {}", Style::new().bold().paint(&auth.user_code));
+    print_qr(
+        auth.verification_uri_complete
+            .as_ref()
+            .unwrap_or(&auth.verification_uri),
+    );
+    println!("Waiting for you to log in, tap any card to cancel...");
+
+    let identity = tokio::select! {
+        identity = flow.wait_for_identity(&auth) => identity,
+        _ = reader::next_card(reader) => Err("cancelled".to_string()),
+    };
+    let identity = match identity {
+        Ok(i) => i,
+        Err(e) => {
+            println!("Error, unable to log in: {}", e);
+            return;
+        }
+    };
+
+    match db.link_identity(args[0], &identity.sub, replace) {
+        Ok(_) => println!(
+            "User {} is now linked to {}",
+            args[0],
+            identity.preferred_username.as_ref().unwrap_or(&identity.sub)
+        ),
+        Err(e) => println!("Error, unable to link account: {}", e),
     }
 }
 
-fn deposit(db: &db::DB, args: &[&str]) {
-    if args.len() < 1 {
-        println!("Usage: deposit <id>");
-        return;
-    }
+fn card_id_string(uid: &[u8]) -> String {
+    uid.iter().map(|b| b.to_string()).collect::<String>()
+}
 
-    let amount = loop {
-        print!("Amount to deposit ('abort' to cancel): ");
-        std::io::stdout().flush().unwrap();
+// Checks the person at the till owns the account, by card tap or current PIN
+// Whether authenticate has anything to check, rather than letting anyone through
+fn has_credentials(user: &User) -> bool {
+    user.pin_hash.is_some() || user.cards.as_ref().is_some_and(|c| !c.is_empty())
+}
 
-        let mut buffer = String::new();
-        std::io::stdin().read_line(&mut buffer).unwrap();
-        let buffer = buffer.trim().to_string();
+async fn authenticate(db: &db::DB, user: &User, reader: &mut Receiver<CardEvent>) -> bool {
+    let has_cards = user.cards.as_ref().is_some_and(|c| !c.is_empty());
 
-        if buffer == "abort" {
-            return;
+    if user.pin_hash.is_some() {
+        let prompt = if has_cards {
+            "Enter your PIN, or leave blank and tap your card: "
+        } else {
+            "Enter your PIN: "
+        };
+        let pin = match rpassword::prompt_password(prompt) {
+            Ok(p) => p,
+            Err(_) => return false,
+        };
+        if !pin.is_empty() || !has_cards {
+            match db.verify_pin(&user.id, pin.trim()) {
+                Ok(true) => return true,
+                Ok(false) => println!("Incorrect PIN"),
+                Err(e) => println!("Error, {}", e),
+            }
+            return false;
         }
+    } else if !has_cards {
+        // Nothing to check against yet; anyone could already charge this account by ID
+        return true;
+    } else {
+        println!("Please tap one of your cards");
+    }
 
-        match buffer.parse::<f64>() {
-            Ok(amount) => {
-                if amount <= 0.0 {
-                    println!("Invalid amount");
-                    continue;
-                }
-                break (amount * 100.0) as u32;
-            }
-            Err(_) => println!("Invalid amount"),
+    match tokio::time::timeout(std::time::Duration::from_secs(30), reader::next_card(reader)).await {
+        Ok(Some(uid)) if user.has_card(&card_id_string(&uid)) => true,
+        Ok(Some(_)) => {
+            println!("That card does not belong to user {}", user.id);
+            false
         }
-    };
+        _ => {
+            println!("No card presented");
+            false
+        }
+    }
+}
 
-    let method = loop {
-        print!("Deposit method (cash / bank; 'abort' to cancel): ");
-        std::io::stdout().flush().unwrap();
+// Asks for someone with at least the given role to step up and prove who they are
+async fn authenticate_role(
+    db: &db::DB,
+    config: &config::Config,
+    reader: &mut Receiver<CardEvent>,
+    role: Role,
+) -> Option<String> {
+    let users = db.users().unwrap_or_default();
+    if !users.iter().any(|u| config.role(u) >= role) {
+        println!("Error, nobody is a {} yet, give someone the role with 'setrole'", role);
+        return None;
+    }
 
-        let mut buffer = String::new();
-        std::io::stdin().read_line(&mut buffer).unwrap();
-        let buffer = buffer.trim().to_string();
+    print!("ID of a {} or above: ", role);
+    std::io::stdout().flush().unwrap();
+    let buffer = read_answer();
+    let id = buffer.as_str();
 
-        if buffer == "abort" {
-            return;
-        } else if buffer == "cash" {
-            break db::DepositMethod::Cash;
-        } else if buffer == "bank" {
-            #[derive(Debug, Serialize, Deserialize, Clone)]
-            pub struct CardUID {}
-            break db::DepositMethod::BankTransfer;
-        } else {
-            println!("Invalid method")
+    let user = match db.get_user(id) {
+        Some((user, _)) if config.role(&user) >= role => user,
+        _ => {
+            println!("Error, {} is not a {} or above", id, role);
+            return None;
         }
     };
+    // authenticate lets anyone through for accounts without a PIN or card
+    if !has_credentials(&user) {
+        println!("Error, {} {} needs a PIN or card set up first", role, id);
+        return None;
+    }
+    if !authenticate(db, &user, reader).await {
+        return None;
+    }
+    Some(user.id)
+}
 
-    match db.deposit_user(args[0], amount, method) {
-        Ok(user) => {
-            println!("Deposited applied to user {}", user.id);
-            println!("New balance: {}", user.disp_balance());
-            println!(
-                "{}",
-                Style::new()
-                    .bold()
-                    .paint("Please transfer money for this deposit / put it in the cash box")
-            );
-            if method == db::DepositMethod::BankTransfer {
-                let qr_code = qrcode_generator::to_matrix(
-                    format!(
-                        "https://monzo.me/{}/{:.2}?d=57Bank",
-                        MONZO_USERNAME,
-                        amount as f64 / 100.0
-                    ),
-                    qrcode_generator::QrCodeEcc::Low,
-                )
-                .unwrap();
-                for _ in 0..2 {
-                    for _ in 0..qr_code.len() + 4 {
-                        print!("\u{2588}\u{2588}");
-                    }
-                    println!();
-                }
-                for row in &qr_code {
-                    print!("\u{2588}\u{2588}\u{2588}\u{2588}");
-                    for col in row {
-                        if *col {
-                            print!("  ");
-                        } else {
-                            print!("\u{2588}\u{2588}");
-                        }
-                    }
-                    println!("\u{2588}\u{2588}\u{2588}\u{2588}");
-                }
-                for _ in 0..2 {
-                    for _ in 0..qr_code.len() + 4 {
-                        print!("\u{2588}\u{2588}");
-                    }
-                    println!();
-                }
-            }
+// Link to the member's own balance page, anyone with it can see their balance so they have to
+// prove who they are first
+async fn web_link(db: &db::DB, config: &config::Config, args: &[&str], reader: &mut Receiver<CardEvent>) {
+    let (secret, url) = match config.api.as_ref().map(|a| (&a.page_secret, &a.public_url)) {
+        Some((Some(secret), Some(url))) => (secret, url),
+        _ => {
+            println!("Error, balance pages need the API's page_secret and public_url setting");
+            return;
         }
-        Err(e) => {
-            println!("Error, unable to deposit: {}", e);
+    };
+    if args.len() != 1 {
+        println!("Usage: weblink <id>");
+        return;
+    }
+    let user = match db.get_user(args[0]) {
+        Some(u) => u.0,
+        None => {
+            println!("Error, user {} does not exist", args[0]);
             return;
         }
+    };
+    if !authenticate(db, &user, reader).await {
+        return;
     }
+    println!("Scan the QR code with your phone, and keep the link to yourself");
+    print_qr(&format!(
+        "{}/me/{}/{}",
+        url.trim_end_matches('/'),
+        user.id,
+        api::page_signature(secret, &user.id)
+    ));
 }
 
-fn users(db: &db::DB) {
-    println!("{}", Style::new().underline().paint("Users"));
+async fn set_pin(args: &[&str], db: &db::DB, reader: &mut Receiver<CardEvent>) {
+    if args.is_empty() {
+        println!("Usage: setpin <id>");
+        return;
+    }
+    let user = match db.get_user(args[0]) {
+        Some(u) => u.0,
+        None => {
+            println!("Error, user {} does not exist", args[0]);
+            return;
+        }
+    };
+    if !authenticate(db, &user, reader).await {
+        return;
+    }
 
-    for user in match db.users() {
-        Ok(u) => u,
-        Err(e) => {
-            println!("Error, unable to list users: {}", e);
+    let pin = match rpassword::prompt_password("New PIN (4 to 8 digits): ") {
+        Ok(p) => p.trim().to_string(),
+        Err(_) => return,
+    };
+    if pin.len() < 4 || pin.len() > 8 || !pin.chars().all(|c| c.is_ascii_digit()) {
+        println!("Error, PIN must be 4 to 8 digits");
+        return;
+    }
+    match rpassword::prompt_password("Repeat new PIN: ") {
+        Ok(p) if p.trim() == pin => {}
+        Ok(_) => {
+            println!("Error, PINs do not match");
             return;
         }
-    } {
-        println!("{} - {}", user.id, user.disp_balance());
+        Err(_) => return,
+    }
+
+    match db.set_pin(&user.id, &pin) {
+        Ok(_) => println!("PIN updated for user {}", user.id),
+        Err(e) => println!("Error, unable to set PIN: {}", e),
     }
 }
 
-fn deposits(db: &db::DB) {
-    println!("{}", Style::new().underline().paint("Recent deposits"));
+async fn set_name(args: &[&str], db: &db::DB, reader: &mut Receiver<CardEvent>) {
+    if args.len() < 2 {
+        println!("Usage: setname <id> <name>");
+        return;
+    }
+    let user = match db.get_user(args[0]) {
+        Some(u) => u.0,
+        None => {
+            println!("Error, user {} does not exist", args[0]);
+            return;
+        }
+    };
+    if !authenticate(db, &user, reader).await {
+        return;
+    }
 
-    for t in match db.transactions() {
-        Ok(u) => u,
-        Err(e) => {
-            println!("Error, unable to list transactions: {}", e);
+    let name = args[1..].join(" ");
+    match db.set_name(&user.id, &name) {
+        Ok(_) => println!("Name for user {} set to '{}'", user.id, name),
+        Err(e) => println!("Error, unable to set name: {}", e),
+    }
+}
+
+// What the user usually buys, at today's prices, as long as it's all still sold
+fn usual_cart(transactions: &[Transaction], id: &str, products: &products::Products) -> Option<Cart> {
+    let mut cart = Cart::new();
+    for barcode in reports::usual_order(transactions, id)? {
+        cart.products.push(products.get(&barcode)?.clone());
+    }
+    Some(cart)
+}
+
+// Fills the cart with the usual order of whoever tapped, and always asks before charging it
+async fn usual(
+    db: &db::DB,
+    config: &config::Config,
+    webhooks: &webhooks::Webhooks,
+    products: &products::Products,
+    cart: &mut Option<Cart>,
+    user: Option<&str>,
+) {
+    let user = match user.and_then(|id| db.get_user(id)) {
+        Some(u) => u,
+        None => {
+            println!("Tap your card first");
             return;
         }
+    };
+    if cart.is_some() {
+        println!("Error, the cart already has things in it");
+        return;
     }
-    .iter()
-    .filter(|t| matches!(t.transaction, db::TransactionType::Deposit { .. }))
-    .rev()
-    .take(10)
-    {
-        match &t.transaction {
-            db::TransactionType::Deposit { amount, method } => {
-                println!(
-                    "Deposit £{:.2} ({}), by {} at {}",
-                    *amount as f64 / 100.0,
-                    match method {
-                        db::DepositMethod::Cash => "cash",
-                        db::DepositMethod::BankTransfer => "bank transfer",
-                    },
-                    t.actor,
-                    t.timestamp
-                );
-            }
-            _ => unreachable!(),
+    let mut usual = match usual_cart(&user.1, &user.0.id, products) {
+        Some(c) => c,
+        None => {
+            println!("{} doesn't have a usual order yet", user.0.id);
+            return;
+        }
+    };
+    usual.apply_combos(&config.combos);
+    print_cart(&usual);
+    *cart = Some(usual);
+
+    let config = config::Config {
+        checkout: config::CheckoutConfig {
+            confirm: config::ConfirmCheckout::Always,
+            ..config.checkout.clone()
+        },
+        ..config.clone()
+    };
+    complete_cart(db, &config, webhooks, user, cart).await
+}
+
+// The product a user's own code stands for
+fn favourite(db: &db::DB, user: Option<&str>, code: &str) -> Option<barcode::Barcode> {
+    let (user, _) = db.get_user(user?)?;
+    user.favourites.get(code).cloned()
+}
+
+fn print_favourites(user: &User, products: &products::Products) {
+    println!("{}", Style::new().underline().paint(format!("Codes for {}", user.id)));
+    for (code, barcode) in &user.favourites {
+        match products.get(barcode) {
+            Some(p) => println!("{} - {}", code, p.disp_line()),
+            None => println!("{} - {} (no longer sold)", code, barcode),
         }
     }
 }
 
-fn purchases(db: &db::DB) {
-    if db.transactions().is_ok_and(|tx| tx.is_empty()) {
-        println!(
-            "{}",
-            Style::new()
-                .underline()
-                .fg(Color::Red)
-                .paint("No recent transactions")
-        );
+fn favourites(db: &db::DB, products: &products::Products, args: &[&str]) {
+    let user = match args.first().and_then(|id| db.get_user(id)) {
+        Some((u, _)) => u,
+        None => {
+            println!("Usage: favourites <id>, or tap your card first");
+            return;
+        }
+    };
+    if user.favourites.is_empty() {
+        println!("{} has no codes, add one with 'setfavourite'", user.id);
         return;
     }
-    println!("{}", Style::new().underline().paint("Recent transactions"));
+    print_favourites(&user, products);
+}
 
-    for t in match db.transactions() {
-        Ok(u) => u,
-        Err(e) => {
-            println!("Error, unable to list transactions: {}", e);
+async fn set_favourite(
+    db: &db::DB,
+    products: &products::Products,
+    args: &[&str],
+    reader: &mut Receiver<CardEvent>,
+) {
+    if args.len() != 3 {
+        println!("Usage: setfavourite <id> <code> <barcode | none>");
+        return;
+    }
+    let code = args[1];
+    // Otherwise typing it would run the command, charge the account, or scan the barcode
+    if FORBIDDEN_USERS.contains(&code) || db.get_user(code).is_some() || barcode::Barcode::try_parse(code).is_some() {
+        println!("Error, {} is already a command, user, or barcode", code);
+        return;
+    }
+    let barcode = match args[2] {
+        "none" => None,
+        b => match barcode::Barcode::try_parse(b).filter(|b| products.contains_key(b)) {
+            Some(b) => Some(b),
+            None => {
+                println!("Error, unknown product {}", b);
+                return;
+            }
+        },
+    };
+    let user = match db.get_user(args[0]) {
+        Some(u) => u.0,
+        None => {
+            println!("Error, user {} does not exist", args[0]);
             return;
         }
+    };
+    if !authenticate(db, &user, reader).await {
+        return;
     }
-    .iter()
-    .filter(|t| matches!(t.transaction, db::TransactionType::Purchase { .. }))
-    .rev()
-    .take(10)
-    {
-        match &t.transaction {
-            db::TransactionType::Purchase { products, total } => {
-                println!(
-                    "Purchase (total £{:.2}) by {} at {}",
-                    *total as f64 / 100.0,
-                    t.actor,
-                    t.timestamp
-                );
-                for p in products {
-                    println!("- {} ({})", p.name, p.disp_price());
-                }
-            }
-            _ => unreachable!(),
+
+    match (db.set_favourite(&user.id, code, barcode.clone()), barcode.and_then(|b| products.get(&b))) {
+        (Ok(_), Some(p)) => println!("{} now adds {} for {}", code, p.name, user.id),
+        (Ok(_), None) => println!("Removed {} for {}", code, user.id),
+        (Err(e), _) => println!("Error, unable to set code: {}", e),
+    }
+}
+
+async fn set_email(args: &[&str], db: &db::DB, reader: &mut Receiver<CardEvent>) {
+    if args.len() != 2 || !args[1].contains('@') {
+        println!("Usage: setemail <id> <email>");
+        return;
+    }
+    let user = match db.get_user(args[0]) {
+        Some(u) => u.0,
+        None => {
+            println!("Error, user {} does not exist", args[0]);
+            return;
         }
+    };
+    if !authenticate(db, &user, reader).await {
+        return;
+    }
+
+    match db.set_email(&user.id, args[1]) {
+        Ok(_) => println!("Email for user {} set to {}", user.id, args[1]),
+        Err(e) => println!("Error, unable to set email: {}", e),
     }
 }
 
-async fn register_card(args: &[&str], db: &db::DB, reader: &mut Receiver<Vec<u8>>) {
+async fn register_card(
+    args: &[&str],
+    db: &db::DB,
+    config: &config::Config,
+    reader: &mut Receiver<CardEvent>,
+) {
     if args.is_empty() {
         println!("Usage: regcard <id> [card name]");
         return;
     }
     let id = args[0];
+    // Otherwise anyone could add their own card to someone else's account
+    let user = match db.get_user(id) {
+        Some(u) => u.0,
+        None => {
+            println!("Error, user {} does not exist", id);
+            return;
+        }
+    };
+    if !authenticate(db, &user, reader).await {
+        return;
+    }
 
     let name = if args.len() > 1 {
         Some(args[1..].join(" "))
@@ -674,10 +4957,16 @@ async fn register_card(args: &[&str], db: &db::DB, reader: &mut Receiver<Vec<u8>
         None
     };
 
-    println!("Please touch the card to the reader");
     let mut uids = Vec::new();
-    for _ in 0..2 {
-        uids.push(reader.recv().await.unwrap());
+    for prompt in ["Please touch the card to the reader", "Take the card away, then touch it again"] {
+        println!("{}", prompt);
+        match reader::next_card(reader).await {
+            Some(uid) => uids.push(uid),
+            None => {
+                println!("Error, the card reader is not available");
+                return;
+            }
+        }
     }
 
     println!("Validating card...");
@@ -687,10 +4976,21 @@ async fn register_card(args: &[&str], db: &db::DB, reader: &mut Receiver<Vec<u8>
         return;
     }
 
+    if config.approve_cards {
+        match db.request_card(id, name, card_id_string(&uids[0])) {
+            Ok(card) => println!(
+                "Card {} ('{}') will work once an admin has approved it",
+                card.uid, card.name
+            ),
+            Err(e) => println!("Error, failed to register the card: {e}"),
+        }
+        return;
+    }
+
     match db.add_card_to_user(
         id,
         name,
-        uids[0].iter().map(|b| b.to_string()).collect::<String>(),
+        card_id_string(&uids[0]),
     ) {
         Ok((name, uid)) => {
             println!("A card with ID {uid} and name '{name}' has been associated with your user")
@@ -699,12 +4999,69 @@ async fn register_card(args: &[&str], db: &db::DB, reader: &mut Receiver<Vec<u8>
     }
 }
 
-async fn delete_card(args: &[&str], db: &db::DB, reader: &mut Receiver<Vec<u8>>) {
+fn pending_cards(db: &db::DB) {
+    let cards = match db.pending_cards() {
+        Ok(c) => c,
+        Err(e) => {
+            println!("Error, unable to list cards: {}", e);
+            return;
+        }
+    };
+    if cards.is_empty() {
+        println!("No cards waiting for approval");
+        return;
+    }
+    println!("{}", Style::new().underline().paint("Cards waiting for approval"));
+    for card in cards {
+        println!(
+            "{} - '{}' for user {}, registered {}",
+            card.uid, card.name, card.user, card.requested
+        );
+    }
+}
+
+async fn review_card(
+    db: &db::DB,
+    config: &config::Config,
+    args: &[&str],
+    approve: bool,
+    reader: &mut Receiver<CardEvent>,
+) {
+    if args.len() != 1 {
+        println!("Usage: {} <card id>", if approve { "approvecard" } else { "rejectcard" });
+        return;
+    }
+    if db.pending_card(args[0]).is_none() {
+        println!("Error, no card {} is waiting for approval", args[0]);
+        return;
+    }
+    if authenticate_role(db, config, reader, Role::Volunteer).await.is_none() {
+        return;
+    }
+
+    match db.review_card(args[0], approve) {
+        Ok(card) if approve => println!("Card '{}' can now be used by {}", card.name, card.user),
+        Ok(card) => println!("Card '{}' for {} rejected", card.name, card.user),
+        Err(e) => println!("Error, unable to review card: {}", e),
+    }
+}
+
+async fn delete_card(args: &[&str], db: &db::DB, reader: &mut Receiver<CardEvent>) {
     if args.is_empty() {
         println!("Usage: delcard <id> [card name]");
         return;
     }
     let id = args[0];
+    let user = match db.get_user(id) {
+        Some(u) => u.0,
+        None => {
+            println!("Error, user {} does not exist", id);
+            return;
+        }
+    };
+    if !authenticate(db, &user, reader).await {
+        return;
+    }
     let name = if args.len() > 1 {
         Some(args[1..].join(" "))
     } else {
@@ -713,8 +5070,13 @@ async fn delete_card(args: &[&str], db: &db::DB, reader: &mut Receiver<Vec<u8>>)
 
     if name.is_none() {
         println!("Please present the card you would like to delete");
-        let uid = reader.recv().await.unwrap()
-            .iter().map(|b| b.to_string()).collect::<String>();
+        let uid = match reader::next_card(reader).await {
+            Some(uid) => card_id_string(&uid),
+            None => {
+                println!("Error, the card reader is not available");
+                return;
+            }
+        };
 
         match db.delete_card(id, db::CardNameOrID::ID(uid.clone())) {
             Ok(_) => println!("Successfully removed the card '{uid}' from the database"),
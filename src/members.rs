@@ -0,0 +1,73 @@
+use crate::config::LdapConfig;
+use bank_core::db::Member;
+use ldap3::{LdapConnAsync, Scope, SearchEntry};
+
+// Reads a CSV of "<membership id>,<bank user id>" lines, with an optional header line
+pub fn read_csv(path: &str) -> Result<Vec<Member>, String> {
+    let members_str = match std::fs::read_to_string(path) {
+        Ok(m) => m,
+        Err(e) => return Err(format!("cannot open member list {}", e)),
+    };
+
+    let mut members = Vec::new();
+    for (i, line) in members_str.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (membership_id, user_id) = match line.split_once(',') {
+            Some((m, u)) => (m.trim().trim_matches('"'), u.trim().trim_matches('"')),
+            None => return Err(format!("invalid line {}: {}", i + 1, line)),
+        };
+        if i == 0 && membership_id == "membership_id" {
+            continue;
+        }
+
+        members.push(Member {
+            membership_id: membership_id.to_string(),
+            user_id: user_id.to_string(),
+        });
+    }
+
+    Ok(members)
+}
+
+pub async fn read_ldap(config: &LdapConfig) -> Result<Vec<Member>, String> {
+    let (conn, mut ldap) = LdapConnAsync::new(&config.url)
+        .await
+        .map_err(|e| format!("cannot connect to LDAP server {}", e))?;
+    ldap3::drive!(conn);
+
+    if let Some(bind_dn) = &config.bind_dn {
+        ldap.simple_bind(bind_dn, config.bind_password.as_deref().unwrap_or_default())
+            .await
+            .and_then(|r| r.success())
+            .map_err(|e| format!("cannot bind to LDAP server {}", e))?;
+    }
+
+    let (entries, _) = ldap
+        .search(
+            &config.base_dn,
+            Scope::Subtree,
+            &config.filter,
+            vec![config.membership_attr.as_str(), config.user_attr.as_str()],
+        )
+        .await
+        .and_then(|r| r.success())
+        .map_err(|e| format!("LDAP search failed {}", e))?;
+    let _ = ldap.unbind().await;
+
+    Ok(entries
+        .into_iter()
+        .map(SearchEntry::construct)
+        .filter_map(|e| {
+            let membership_id = e.attrs.get(&config.membership_attr)?.first()?;
+            let user_id = e.attrs.get(&config.user_attr)?.first()?;
+            Some(Member {
+                membership_id: membership_id.clone(),
+                user_id: user_id.clone(),
+            })
+        })
+        .collect())
+}
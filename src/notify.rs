@@ -0,0 +1,187 @@
+use bank_core::style::{theme, Style};
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::OnceLock;
+
+// Problems that need someone to do something, and regular summaries, sent wherever the config
+// routes them
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Notice {
+    SaveFailed,
+    BackupFailed,
+    // More than the configured amount of cash since the last Z-report
+    CashBoxFull,
+    ReaderOffline,
+    EndOfDay,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub enum Channel {
+    Console,
+    // Appended to, e.g. File(path: "/var/log/57bank/notices")
+    File { path: String },
+    // e.g. Matrix(homeserver: "https://matrix.org", room: "!abc:matrix.org", token: "syt_...")
+    Matrix {
+        homeserver: String,
+        room: String,
+        token: String,
+    },
+    // A bot token and the chat it posts to
+    Telegram { token: String, chat: String },
+    // Handed to sendmail, or whatever `command` is, to send
+    Email {
+        to: String,
+        #[serde(default = "default_sendmail")]
+        command: String,
+    },
+    // Posted as JSON, e.g. to a chat bridge
+    Webhook { url: String },
+}
+
+fn default_sendmail() -> String {
+    "sendmail -t".to_string()
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct NotifyConfig {
+    // Name -> where to send things, e.g. {"treasurer": Email(to: "treasurer@57north.org.uk")}
+    pub channels: HashMap<String, Channel>,
+    // Which channels hear about what, e.g. {cash_box_full: ["treasurer", "matrix"]}
+    pub routes: HashMap<Notice, Vec<String>>,
+    // Pence in the cash box before it should be banked
+    pub cash_box_limit: Option<u32>,
+}
+
+static NOTIFIER: OnceLock<Notifier> = OnceLock::new();
+
+pub fn init(config: NotifyConfig) {
+    let _ = NOTIFIER.set(Notifier::new(config));
+}
+
+pub fn send(notice: Notice, message: &str) {
+    if let Some(notifier) = NOTIFIER.get() {
+        notifier.send(notice, message);
+    }
+}
+
+pub fn cash_box_limit() -> Option<u32> {
+    NOTIFIER.get()?.config.cash_box_limit
+}
+
+pub struct Notifier {
+    config: NotifyConfig,
+    client: reqwest::Client,
+}
+
+impl Notifier {
+    pub fn new(config: NotifyConfig) -> Self {
+        Self {
+            config,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    // Anything going over the network happens in the background, so the till never waits
+    pub fn send(&self, notice: Notice, message: &str) {
+        for name in self.config.routes.get(&notice).into_iter().flatten() {
+            let channel = match self.config.channels.get(name) {
+                Some(c) => c,
+                None => {
+                    println!("Error, notice {:?} is routed to unknown channel {}", notice, name);
+                    continue;
+                }
+            };
+            if let Err(e) = self.send_to(channel, notice, message) {
+                println!("Error, unable to send notice to {}: {}", name, e);
+            }
+        }
+    }
+
+    fn send_to(&self, channel: &Channel, notice: Notice, message: &str) -> Result<(), String> {
+        match channel {
+            Channel::Console => {
+                println!("{}", Style::new().bold().fg(theme().warning).paint(message));
+                Ok(())
+            }
+            Channel::File { path } => {
+                let mut file = std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|e| e.to_string())?;
+                writeln!(file, "{} {:?} {}", chrono::Utc::now().to_rfc3339(), notice, message)
+                    .map_err(|e| e.to_string())
+            }
+            Channel::Matrix { homeserver, room, token } => {
+                let mut url = reqwest::Url::parse(homeserver).map_err(|e| e.to_string())?;
+                url.path_segments_mut()
+                    .map_err(|_| format!("invalid homeserver {}", homeserver))?
+                    .pop_if_empty()
+                    .extend(["_matrix", "client", "v3", "rooms", room, "send", "m.room.message"])
+                    .push(&uuid::Uuid::new_v4().to_string());
+                let request = self
+                    .client
+                    .put(url)
+                    .bearer_auth(token)
+                    .json(&serde_json::json!({"msgtype": "m.text", "body": message}));
+                self.post(request);
+                Ok(())
+            }
+            Channel::Telegram { token, chat } => {
+                let request = self
+                    .client
+                    .post(format!("https://api.telegram.org/bot{}/sendMessage", token))
+                    .json(&serde_json::json!({"chat_id": chat, "text": message}));
+                self.post(request);
+                Ok(())
+            }
+            Channel::Email { to, command } => {
+                let mail = format!("To: {}\nSubject: 57Bank: {}\n\n{}\n", to, message, message);
+                let command = command.clone();
+                std::thread::spawn(move || {
+                    if let Err(e) = sendmail(&command, &mail) {
+                        println!("Error, unable to send notice email: {}", e);
+                    }
+                });
+                Ok(())
+            }
+            Channel::Webhook { url } => {
+                let request = self.client.post(url).json(&serde_json::json!({
+                    "notice": notice,
+                    "message": message,
+                    "timestamp": chrono::Utc::now(),
+                }));
+                self.post(request);
+                Ok(())
+            }
+        }
+    }
+
+    fn post(&self, request: reqwest::RequestBuilder) {
+        tokio::spawn(async move {
+            if let Err(e) = request.send().await.and_then(|r| r.error_for_status()) {
+                println!("Error, unable to send notice: {}", e);
+            }
+        });
+    }
+}
+
+fn sendmail(command: &str, mail: &str) -> Result<(), String> {
+    let mut child = std::process::Command::new("sh")
+        .args(["-c", command])
+        .stdin(std::process::Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("cannot run {}: {}", command, e))?;
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(mail.as_bytes())
+        .map_err(|e| e.to_string())?;
+    match child.wait().map_err(|e| e.to_string())? {
+        status if status.success() => Ok(()),
+        status => Err(format!("{} failed ({})", command, status)),
+    }
+}
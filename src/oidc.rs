@@ -0,0 +1,139 @@
+use crate::config::OidcConfig;
+
+const DEVICE_CODE_GRANT: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+#[derive(Debug, Deserialize)]
+struct Discovery {
+    device_authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeviceAuthorization {
+    device_code: String,
+    pub user_code: String,
+    pub verification_uri: String,
+    pub verification_uri_complete: Option<String>,
+    #[serde(default = "default_interval")]
+    interval: u64,
+    expires_in: u64,
+}
+
+fn default_interval() -> u64 {
+    5
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum TokenResponse {
+    Token { access_token: String },
+    Error { error: String },
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Identity {
+    pub sub: String,
+    pub preferred_username: Option<String>,
+}
+
+pub struct DeviceFlow {
+    client: reqwest::Client,
+    config: OidcConfig,
+    endpoints: Discovery,
+}
+
+impl DeviceFlow {
+    pub async fn discover(config: &OidcConfig) -> Result<Self, String> {
+        let client = reqwest::Client::new();
+        let endpoints = client
+            .get(format!(
+                "{}/.well-known/openid-configuration",
+                config.issuer.trim_end_matches('/')
+            ))
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| format!("cannot fetch OIDC discovery document {}", e))?
+            .json::<Discovery>()
+            .await
+            .map_err(|e| format!("invalid OIDC discovery document {}", e))?;
+
+        Ok(Self {
+            client,
+            config: config.clone(),
+            endpoints,
+        })
+    }
+
+    pub async fn start(&self) -> Result<DeviceAuthorization, String> {
+        self.client
+            .post(&self.endpoints.device_authorization_endpoint)
+            .form(&[
+                ("client_id", self.config.client_id.as_str()),
+                ("scope", self.config.scopes.as_str()),
+            ])
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| format!("cannot start device authorization {}", e))?
+            .json::<DeviceAuthorization>()
+            .await
+            .map_err(|e| format!("invalid device authorization response {}", e))
+    }
+
+    // Polls until the user has approved the login on their device, then fetches who they are
+    pub async fn wait_for_identity(&self, auth: &DeviceAuthorization) -> Result<Identity, String> {
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(auth.expires_in);
+        let mut interval = auth.interval;
+
+        let access_token = loop {
+            if std::time::Instant::now() > deadline {
+                return Err("login timed out".to_string());
+            }
+            tokio::time::sleep(std::time::Duration::from_secs(interval)).await;
+
+            let mut form = vec![
+                ("grant_type", DEVICE_CODE_GRANT),
+                ("device_code", auth.device_code.as_str()),
+                ("client_id", self.config.client_id.as_str()),
+            ];
+            if let Some(secret) = &self.config.client_secret {
+                form.push(("client_secret", secret.as_str()));
+            }
+
+            let res = self
+                .client
+                .post(&self.endpoints.token_endpoint)
+                .form(&form)
+                .send()
+                .await
+                .map_err(|e| format!("cannot reach token endpoint {}", e))?
+                .json::<TokenResponse>()
+                .await
+                .map_err(|e| format!("invalid token response {}", e))?;
+
+            match res {
+                TokenResponse::Token { access_token } => break access_token,
+                TokenResponse::Error { error } => match error.as_str() {
+                    "authorization_pending" => {}
+                    "slow_down" => interval += 5,
+                    "access_denied" => return Err("login was denied".to_string()),
+                    "expired_token" => return Err("login timed out".to_string()),
+                    e => return Err(format!("login failed: {}", e)),
+                },
+            }
+        };
+
+        self.client
+            .get(&self.endpoints.userinfo_endpoint)
+            .bearer_auth(access_token)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| format!("cannot fetch user info {}", e))?
+            .json::<Identity>()
+            .await
+            .map_err(|e| format!("invalid user info {}", e))
+    }
+}
@@ -0,0 +1,87 @@
+use bank_core::products::{Product, Products};
+use printpdf::{BuiltinFont, Mm, PdfDocument};
+
+const TITLE: &str = "57North Snack Bank";
+const PAGE_WIDTH: f32 = 210.0;
+const PAGE_HEIGHT: f32 = 297.0;
+const MARGIN: f32 = 20.0;
+const LINE_HEIGHT: f32 = 6.0;
+
+// Products grouped by category in name order, with anything uncategorised last under "Other"
+pub fn categorised(products: &Products) -> Vec<(String, Vec<&Product>)> {
+    let mut categories = std::collections::BTreeMap::<Option<&str>, Vec<&Product>>::new();
+    for product in products.values() {
+        categories
+            .entry(product.category.as_deref())
+            .or_default()
+            .push(product);
+    }
+    let mut out = categories
+        .into_iter()
+        .map(|(category, mut products)| {
+            products.sort_by(|a, b| a.name.cmp(&b.name));
+            (category, products)
+        })
+        .collect::<Vec<_>>();
+    // None sorts first, but is the least interesting
+    let uncategorised = out.iter().take_while(|(c, _)| c.is_none()).count();
+    out.rotate_left(uncategorised);
+    out.into_iter()
+        .map(|(category, products)| (capitalise(category.unwrap_or("other")), products))
+        .collect()
+}
+
+fn capitalise(s: &str) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) => c.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+pub fn write_pdf(products: &Products, path: &str) -> Result<(), String> {
+    let (doc, page, layer) = PdfDocument::new(TITLE, Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Prices");
+    let font = doc
+        .add_builtin_font(BuiltinFont::Helvetica)
+        .map_err(|e| format!("cannot load font {}", e))?;
+    let bold = doc
+        .add_builtin_font(BuiltinFont::HelveticaBold)
+        .map_err(|e| format!("cannot load font {}", e))?;
+
+    let mut layer = doc.get_page(page).get_layer(layer);
+    let mut y = PAGE_HEIGHT - MARGIN;
+    layer.use_text(TITLE, 24.0, Mm(MARGIN), Mm(y), &bold);
+    y -= LINE_HEIGHT * 2.0;
+
+    for (category, products) in categorised(products) {
+        // Keep headings with at least their first product
+        for (i, line) in std::iter::once(None).chain(products.into_iter().map(Some)).enumerate() {
+            if y < MARGIN + if i == 0 { LINE_HEIGHT * 2.0 } else { 0.0 } {
+                let (page, new_layer) = doc.add_page(Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Prices");
+                layer = doc.get_page(page).get_layer(new_layer);
+                y = PAGE_HEIGHT - MARGIN;
+            }
+            match line {
+                None => {
+                    y -= LINE_HEIGHT / 2.0;
+                    layer.use_text(&category, 14.0, Mm(MARGIN), Mm(y), &bold);
+                }
+                Some(product) => {
+                    layer.use_text(&product.name, 11.0, Mm(MARGIN + 5.0), Mm(y), &font);
+                    layer.use_text(
+                        product.disp_price(),
+                        11.0,
+                        Mm(PAGE_WIDTH - MARGIN - 20.0),
+                        Mm(y),
+                        &bold,
+                    );
+                }
+            }
+            y -= LINE_HEIGHT;
+        }
+    }
+
+    let file = std::fs::File::create(path).map_err(|e| format!("cannot create {} {}", path, e))?;
+    doc.save(&mut std::io::BufWriter::new(file))
+        .map_err(|e| format!("cannot write {} {}", path, e))
+}
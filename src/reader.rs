@@ -0,0 +1,191 @@
+use crate::feedback;
+use std::collections::HashMap;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use tokio::sync::mpsc::{Receiver, Sender};
+
+mod mock;
+#[cfg(feature = "nfc")]
+mod nfc;
+#[cfg(feature = "pcsc")]
+mod pcsc;
+
+pub use mock::MockReader;
+
+const RETRY_DELAY: std::time::Duration = std::time::Duration::from_secs(5);
+// Don't spin on a card that's left sitting on the reader
+const SEEN_DELAY: std::time::Duration = std::time::Duration::from_millis(50);
+
+#[derive(Debug)]
+pub enum CardEvent {
+    Card(Vec<u8>),
+    // The reader stopped working; it'll keep being retried in the background
+    ReaderError(String),
+    // The reader is working again after an error
+    ReaderReady,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backend {
+    // libnfc, only there when built with the nfc feature
+    #[cfg_attr(feature = "nfc", default)]
+    Nfc,
+    // pcscd, only there when built with the pcsc feature
+    Pcsc,
+    // Taps read from a script, see MockReader
+    #[cfg_attr(not(feature = "nfc"), default)]
+    Mock,
+}
+
+// A source of card taps, run on the reader thread
+pub trait CardReader: Send {
+    // Connects to the reader and reports what it sees until `stop` is set. Returns an error if
+    // the reader fails, after which it's called again to reconnect.
+    fn run(&mut self, events: &mut Events, stop: &AtomicBool) -> Result<(), String>;
+}
+
+pub fn open(config: &crate::config::ReaderConfig) -> Box<dyn CardReader> {
+    match config.backend {
+        #[cfg(feature = "nfc")]
+        Backend::Nfc => Box::new(nfc::NfcReader),
+        #[cfg(not(feature = "nfc"))]
+        Backend::Nfc => Box::new(Unsupported("this build has no libnfc support")),
+        #[cfg(feature = "pcsc")]
+        Backend::Pcsc => Box::new(pcsc::PcscReader {
+            name: config.pcsc_reader.clone(),
+        }),
+        #[cfg(not(feature = "pcsc"))]
+        Backend::Pcsc => Box::new(Unsupported("this build has no PC/SC support")),
+        Backend::Mock => match &config.mock {
+            Some(path) => Box::new(MockReader::open(path)),
+            None => Box::new(MockReader::script("")),
+        },
+    }
+}
+
+// For a backend that isn't compiled in, so it's reported like any other reader problem
+#[cfg(not(all(feature = "nfc", feature = "pcsc")))]
+struct Unsupported(&'static str);
+
+#[cfg(not(all(feature = "nfc", feature = "pcsc")))]
+impl CardReader for Unsupported {
+    fn run(&mut self, _events: &mut Events, _stop: &AtomicBool) -> Result<(), String> {
+        Err(self.0.to_string())
+    }
+}
+
+// A card only counts as a new tap once it's been off the reader for the whole window, so one
+// left lying on it fires once, while a different card, or the same one tapped again, gets through
+#[derive(Debug)]
+pub struct Debouncer {
+    window: std::time::Duration,
+    last_seen: HashMap<Vec<u8>, std::time::Instant>,
+}
+
+impl Debouncer {
+    pub fn new(window: std::time::Duration) -> Self {
+        Self {
+            window,
+            last_seen: HashMap::new(),
+        }
+    }
+
+    // Whether seeing the card now is a fresh tap
+    pub fn seen(&mut self, uid: &[u8], now: std::time::Instant) -> bool {
+        let window = self.window;
+        self.last_seen.retain(|_, at| now.duration_since(*at) < window);
+        self.last_seen.insert(uid.to_vec(), now).is_none()
+    }
+}
+
+// Where a reader reports what it sees, taking care of debouncing and only reporting
+// each outage once
+pub struct Events {
+    card_tx: Sender<CardEvent>,
+    debouncer: Debouncer,
+    failed: bool,
+}
+
+impl Events {
+    pub fn new(card_tx: Sender<CardEvent>, debounce: std::time::Duration) -> Self {
+        Self {
+            card_tx,
+            debouncer: Debouncer::new(debounce),
+            failed: false,
+        }
+    }
+
+    // The reader is up and polling. All of these return false once the main loop has gone
+    // away, so we're shutting down.
+    pub fn connected(&mut self) -> bool {
+        if !self.failed {
+            return true;
+        }
+        self.failed = false;
+        self.card_tx.blocking_send(CardEvent::ReaderReady).is_ok()
+    }
+
+    // A card is on the reader, which may well be the same one as last time round
+    pub fn seen(&mut self, uid: &[u8]) -> bool {
+        let fresh = !uid.is_empty() && self.debouncer.seen(uid, std::time::Instant::now());
+        if fresh && self.card_tx.blocking_send(CardEvent::Card(uid.to_vec())).is_err() {
+            return false;
+        }
+        std::thread::sleep(SEEN_DELAY);
+        true
+    }
+
+    fn failed(&mut self, e: String) -> bool {
+        if self.failed {
+            return true;
+        }
+        self.failed = true;
+        self.card_tx.blocking_send(CardEvent::ReaderError(e)).is_ok()
+    }
+}
+
+// Runs on its own thread for the life of the program, reconnecting to the reader whenever it fails
+pub fn card_reader(
+    mut reader: Box<dyn CardReader>,
+    card_tx: Sender<CardEvent>,
+    stop: Arc<AtomicBool>,
+    debounce: std::time::Duration,
+) {
+    let mut events = Events::new(card_tx, debounce);
+
+    while !stop.load(Ordering::Relaxed) {
+        match reader.run(&mut events, &stop) {
+            Ok(()) => return,
+            Err(e) => {
+                if !events.failed(e) {
+                    return;
+                }
+                // Keep an eye on the stop flag so shutdown isn't held up by a dead reader
+                let retry_at = std::time::Instant::now() + RETRY_DELAY;
+                while std::time::Instant::now() < retry_at && !stop.load(Ordering::Relaxed) {
+                    std::thread::sleep(std::time::Duration::from_millis(100));
+                }
+            }
+        }
+    }
+}
+
+// Waits for the next card tap, reporting any reader problems along the way.
+// Returns None if the reader thread has gone away for good.
+pub async fn next_card(reader: &mut Receiver<CardEvent>) -> Option<Vec<u8>> {
+    loop {
+        match reader.recv().await? {
+            CardEvent::Card(uid) => return Some(uid),
+            CardEvent::ReaderError(e) => {
+                feedback::signal(feedback::Signal::ReaderOffline);
+                println!("Card reader error: {}, retrying...", e)
+            }
+            CardEvent::ReaderReady => {
+                feedback::signal(feedback::Signal::ReaderOnline);
+                println!("Card reader reconnected")
+            }
+        }
+    }
+}
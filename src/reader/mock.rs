@@ -0,0 +1,97 @@
+use super::{CardReader, Events};
+use std::io::BufRead;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Stands in for a real reader, for working on the card flows without the hardware. Reads a
+// script a line at a time:
+//   04a1b2c3      a tap of the card with that UID, in hex. Tapping the same card twice needs
+//                 a wait longer than the debounce window in between.
+//   wait 1500     nothing happens for that many milliseconds
+//   error <why>   the reader fails, and is reconnected after the usual delay
+// Blank lines and lines starting with # are skipped. The file is followed as it grows, so cards
+// can be tapped by hand with e.g. `echo 04a1b2c3 >> taps`.
+pub struct MockReader {
+    source: Source,
+}
+
+enum Source {
+    File(String),
+    Lines(std::vec::IntoIter<String>),
+}
+
+impl MockReader {
+    // Runs the script at the path, opening it afresh each time the reader connects
+    pub fn open(path: &str) -> Self {
+        Self {
+            source: Source::File(path.to_string()),
+        }
+    }
+
+    pub fn script(script: &str) -> Self {
+        let lines = script.lines().map(str::to_string).collect::<Vec<_>>();
+        Self {
+            source: Source::Lines(lines.into_iter()),
+        }
+    }
+
+    // Does what the line says, returning false once nobody's listening any more
+    fn step(line: &str, events: &mut Events, stop: &AtomicBool) -> Result<bool, String> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(true);
+        }
+        if let Some(why) = line.strip_prefix("error") {
+            return Err(why.trim().to_string());
+        }
+        if let Some(ms) = line.strip_prefix("wait ") {
+            let ms = ms.trim().parse::<u64>().map_err(|e| format!("bad wait {:?}: {:?}", ms, e))?;
+            let until = std::time::Instant::now() + std::time::Duration::from_millis(ms);
+            while std::time::Instant::now() < until && !stop.load(Ordering::Relaxed) {
+                std::thread::sleep(std::time::Duration::from_millis(10));
+            }
+            return Ok(true);
+        }
+        let uid = hex::decode(line).map_err(|e| format!("bad card UID {:?}: {:?}", line, e))?;
+        Ok(events.seen(&uid))
+    }
+}
+
+impl CardReader for MockReader {
+    fn run(&mut self, events: &mut Events, stop: &AtomicBool) -> Result<(), String> {
+        let mut file = match &self.source {
+            Source::File(path) => {
+                let file = std::fs::File::open(path).map_err(|e| format!("cannot open {}: {}", path, e))?;
+                Some(std::io::BufReader::new(file))
+            }
+            Source::Lines(_) => None,
+        };
+        if !events.connected() {
+            return Ok(());
+        }
+
+        let mut line = String::new();
+        while !stop.load(Ordering::Relaxed) {
+            let next = match (&mut file, &mut self.source) {
+                (Some(file), _) => {
+                    line.clear();
+                    match file.read_line(&mut line).map_err(|e| e.to_string())? {
+                        0 => None,
+                        _ => Some(line.clone()),
+                    }
+                }
+                (None, Source::Lines(lines)) => lines.next(),
+                (None, Source::File(_)) => None,
+            };
+            match next {
+                Some(next) => {
+                    if !Self::step(&next, events, stop)? {
+                        return Ok(());
+                    }
+                }
+                // Like a reader nobody's using, until more is added to the end of the file
+                None => std::thread::sleep(std::time::Duration::from_millis(100)),
+            }
+        }
+        Ok(())
+    }
+}
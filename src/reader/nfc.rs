@@ -0,0 +1,54 @@
+use super::{CardReader, Events};
+use nfc1::target_info;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Talks to the reader directly through libnfc
+pub struct NfcReader;
+
+impl CardReader for NfcReader {
+    fn run(&mut self, events: &mut Events, stop: &AtomicBool) -> Result<(), String> {
+        poll_reader(events, stop).map_err(|e| e.to_string())
+    }
+}
+
+// Polls for cards until told to stop, or returns the error that broke the reader
+fn poll_reader(events: &mut Events, stop: &AtomicBool) -> Result<(), nfc1::Error> {
+    let mut context = nfc1::Context::new()?;
+    let mut device = context.open()?;
+    device.initiator_init()?;
+
+    if !events.connected() {
+        return Ok(());
+    }
+
+    loop {
+        if stop.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        match device.initiator_poll_target(&[nfc1::Modulation {
+            modulation_type: nfc1::ModulationType::Iso14443a,
+            baud_rate: nfc1::BaudRate::Baud106,
+        }], 255, std::time::Duration::from_millis(300)) {
+            Ok(target) => {
+                match target.target_info {
+                    target_info::TargetInfo::Iso14443a(target_info::Iso14443a { uid, uid_len, .. }) => {
+                        // The main loop has gone away, so we're shutting down
+                        if !events.seen(&uid[..uid_len]) {
+                            return Ok(());
+                        }
+                    },
+                    a => {
+                        println!("Unknown target: {:?}", a);
+                    }
+                }
+            }
+            // The reader itself has gone, e.g. unplugged
+            Err(e @ (nfc1::Error::Io
+                | nfc1::Error::NoDeviceFound
+                | nfc1::Error::NoSuchDeviceFound
+                | nfc1::Error::DeviceNotSupported)) => return Err(e),
+            // Timeouts and failed reads just mean no card this time round
+            Err(_) => continue,
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use super::{CardReader, Events};
+use pcsc::{Context, Error, Protocols, ReaderState, Scope, ShareMode, State};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Asks the reader for the UID of the card on it, understood by ACR122U-style readers
+const GET_UID: [u8; 5] = [0xff, 0xca, 0x00, 0x00, 0x00];
+
+// Goes through pcscd, which copes with a lot of cheap readers better than libnfc does
+pub struct PcscReader {
+    // Part of the reader's name, to pick one when there's more than one plugged in
+    pub name: Option<String>,
+}
+
+impl CardReader for PcscReader {
+    fn run(&mut self, events: &mut Events, stop: &AtomicBool) -> Result<(), String> {
+        poll_reader(self.name.as_deref(), events, stop).map_err(|e| e.to_string())
+    }
+}
+
+// Polls for cards until told to stop, or returns the error that broke the reader
+fn poll_reader(name: Option<&str>, events: &mut Events, stop: &AtomicBool) -> Result<(), Error> {
+    let context = Context::establish(Scope::User)?;
+    let mut names = [0; 2048];
+    let reader = context
+        .list_readers(&mut names)?
+        .find(|r| name.is_none_or(|name| r.to_string_lossy().contains(name)))
+        .ok_or(Error::UnknownReader)?
+        .to_owned();
+
+    if !events.connected() {
+        return Ok(());
+    }
+
+    let mut states = [ReaderState::new(reader.clone(), State::UNAWARE)];
+    loop {
+        if stop.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        match context.get_status_change(std::time::Duration::from_millis(300), &mut states) {
+            // Timing out with the card still there reads it again, which keeps it debounced
+            Ok(()) | Err(Error::Timeout) => states[0].sync_current_state(),
+            Err(e) => return Err(e),
+        }
+        if !states[0].current_state().contains(State::PRESENT) {
+            continue;
+        }
+
+        let card = match context.connect(&reader, ShareMode::Shared, Protocols::ANY) {
+            Ok(card) => card,
+            // Taken away again before it could be read
+            Err(Error::NoSmartcard | Error::RemovedCard) => continue,
+            Err(e) => return Err(e),
+        };
+        let mut response = [0; pcsc::MAX_BUFFER_SIZE];
+        match card.transmit(&GET_UID, &mut response) {
+            Ok([uid @ .., 0x90, 0x00]) => {
+                // The main loop has gone away, so we're shutting down
+                if !events.seen(uid) {
+                    return Ok(());
+                }
+            }
+            // Cards that won't give a UID, or that left too soon
+            Ok(_) | Err(Error::RemovedCard) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
@@ -0,0 +1,1221 @@
+use crate::config::Config;
+use crate::ledger;
+use bank_core::style::Style;
+use bank_core::db::{self, Transaction};
+use bank_core::money;
+use chrono::{DateTime, Datelike, Local, NaiveDate, Utc};
+
+// A span of time to report on, from the start up to but not including the end
+pub struct Period {
+    pub label: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+impl Period {
+    pub fn contains(&self, t: &Transaction) -> bool {
+        t.timestamp >= self.start && t.timestamp < self.end
+    }
+}
+
+// Accepts a year ("2024"), quarter ("2024-Q1"), month ("2024-03"), or day ("2024-03-14")
+pub fn parse_period(period: &str) -> Result<Period, String> {
+    let invalid = || {
+        format!(
+            "invalid period {}, expected e.g. 2024, 2024-Q1, 2024-03, or 2024-03-14",
+            period
+        )
+    };
+    let date = |y: i32, m: u32, d: u32| NaiveDate::from_ymd_opt(y, m, d).ok_or_else(invalid);
+
+    let parts = period.split('-').collect::<Vec<_>>();
+    let year = parts[0].parse::<i32>().map_err(|_| invalid())?;
+    let (start, end) = match parts[1..] {
+        [] => (date(year, 1, 1)?, date(year + 1, 1, 1)?),
+        [q] if q.starts_with(['Q', 'q']) => {
+            let q = q[1..]
+                .parse::<u32>()
+                .ok()
+                .filter(|q| (1..=4).contains(q))
+                .ok_or_else(invalid)?;
+            let start = date(year, (q - 1) * 3 + 1, 1)?;
+            let end = if q == 4 {
+                date(year + 1, 1, 1)?
+            } else {
+                date(year, q * 3 + 1, 1)?
+            };
+            (start, end)
+        }
+        [m] => {
+            let m = m.parse::<u32>().map_err(|_| invalid())?;
+            let start = date(year, m, 1)?;
+            let end = if m == 12 {
+                date(year + 1, 1, 1)?
+            } else {
+                date(year, m + 1, 1)?
+            };
+            (start, end)
+        }
+        [m, d] => {
+            let start = date(
+                year,
+                m.parse().map_err(|_| invalid())?,
+                d.parse().map_err(|_| invalid())?,
+            )?;
+            (start, start.succ_opt().ok_or_else(invalid)?)
+        }
+        _ => return Err(invalid()),
+    };
+
+    Ok(Period {
+        label: period.to_string(),
+        start: start.and_hms_opt(0, 0, 0).unwrap().and_utc(),
+        end: end.and_hms_opt(0, 0, 0).unwrap().and_utc(),
+    })
+}
+
+// Takings by tax category, with tax worked out from the tax-inclusive prices
+pub fn tax_report(db: &db::DB, config: &Config, args: &[&str]) {
+    let period = match args.first().map(|p| parse_period(p)) {
+        Some(Ok(p)) => p,
+        Some(Err(e)) => {
+            println!("Error, {}", e);
+            return;
+        }
+        None => {
+            println!("Usage: taxreport <period>");
+            return;
+        }
+    };
+    let transactions = match db.transactions() {
+        Ok(t) => t,
+        Err(e) => {
+            println!("Error, unable to list transactions: {}", e);
+            return;
+        }
+    };
+
+    // Category -> takings in pence, after any discounts
+    let mut takings = std::collections::BTreeMap::<Option<String>, f64>::new();
+    let mut tills = std::collections::BTreeMap::<Option<&str>, u64>::new();
+    for t in transactions.iter().filter(|t| period.contains(t)) {
+        if let db::TransactionType::Purchase {
+            products, total, ..
+        } = &t.transaction
+        {
+            *tills.entry(t.till.as_deref()).or_default() += *total as u64;
+            let subtotal = products.iter().map(|p| p.price).sum::<u32>();
+            if subtotal == 0 {
+                continue;
+            }
+            // Spread any discount across the products in proportion to their price
+            let scale = *total as f64 / subtotal as f64;
+            for p in products {
+                *takings.entry(p.tax.clone()).or_default() += p.price as f64 * scale;
+            }
+        }
+    }
+
+    println!(
+        "{}",
+        Style::new()
+            .underline()
+            .paint(format!("Tax report for {}", period.label))
+    );
+    if takings.is_empty() {
+        println!("No sales");
+        return;
+    }
+    let (mut total_gross, mut total_tax) = (0.0, 0.0);
+    for (category, gross) in takings {
+        let gross = gross.round();
+        total_gross += gross;
+        let rate = category.as_ref().and_then(|c| config.tax_rates.get(c));
+        let name = category.as_deref().unwrap_or("(no category)");
+        match rate {
+            Some(rate) => {
+                let tax = (gross * rate / (100.0 + rate)).round();
+                total_tax += tax;
+                println!(
+                    "{} ({}%): gross {}, net {}, tax {}",
+                    name,
+                    rate,
+                    money::format(gross as i64),
+                    money::format((gross - tax) as i64),
+                    money::format(tax as i64)
+                );
+            }
+            None if category.is_some() => {
+                println!("{} (no rate configured): gross {}", name, money::format(gross as i64))
+            }
+            None => println!("{}: gross {}", name, money::format(gross as i64)),
+        }
+    }
+    println!(
+        "Total: gross {}, net {}, tax {}",
+        money::format(total_gross as i64),
+        money::format((total_gross - total_tax) as i64),
+        money::format(total_tax as i64)
+    );
+
+    // Only worth splitting up once there's more than one till
+    if tills.keys().any(|t| t.is_some()) {
+        println!();
+        println!("{}", Style::new().underline().paint("By till"));
+        for (till, gross) in tills {
+            println!("{}: gross {}", till.unwrap_or("(untagged)"), money::format(gross));
+        }
+    }
+}
+
+// Cash going into (or out of) a cash box, as (currency, amount in pence or cents), from the
+// transaction's postings to the cash account
+pub fn cash_movement(t: &Transaction, home_currency: &str) -> Option<(String, i64)> {
+    ledger::entry(t)?.postings.into_iter().find_map(|p| match (p.account, p.tendered) {
+        (ledger::Account::Cash(_), Some((currency, minor))) => {
+            Some((currency, minor as i64 * p.amount.signum()))
+        }
+        (ledger::Account::Cash(currency), None) => {
+            Some((currency.unwrap_or_else(|| home_currency.to_string()), p.amount))
+        }
+        _ => None,
+    })
+}
+
+#[derive(Debug, Default)]
+pub struct ZSummary {
+    pub sales: usize,
+    // How sales were paid for -> total in pence
+    pub sales_by_method: std::collections::BTreeMap<String, u64>,
+    pub deposits_by_method: std::collections::BTreeMap<String, u64>,
+    // Currency -> what should have gone into (or come out of) the cash box
+    pub cash: std::collections::BTreeMap<String, i64>,
+}
+
+pub fn z_summary(transactions: &[&Transaction], home_currency: &str) -> ZSummary {
+    let mut summary = ZSummary::default();
+    for t in transactions {
+        match &t.transaction {
+            db::TransactionType::Purchase { total, .. } => {
+                summary.sales += 1;
+                let method = match &t.actor {
+                    db::TransactionActor::User(_) | db::TransactionActor::Anonymous => "balance",
+                    db::TransactionActor::Group { .. } => "group account",
+                    db::TransactionActor::Cash => "cash",
+                    db::TransactionActor::Tab(_) => "tab",
+                    db::TransactionActor::Stock => continue,
+                };
+                *summary.sales_by_method.entry(method.to_string()).or_default() += *total as u64;
+            }
+            db::TransactionType::Deposit { amount, method, .. } => {
+                *summary.deposits_by_method.entry(method.to_string()).or_default() += *amount as u64;
+            }
+            // Only ever from a balance
+            db::TransactionType::Void { amount, .. } => {
+                summary.sales = summary.sales.saturating_sub(1);
+                if let Some(total) = summary.sales_by_method.get_mut("balance") {
+                    *total = total.saturating_sub(*amount as u64);
+                }
+            }
+            _ => {}
+        }
+        if let Some((currency, amount)) = cash_movement(t, home_currency) {
+            *summary.cash.entry(currency).or_default() += amount;
+        }
+    }
+    summary
+}
+
+// Closes out a shift: everything at this till since the last Z-report, then marks the cut-off
+// Home currency cash taken since the shift was last closed, i.e. what should be in the box
+pub fn cash_in_box(db: &db::DB, home_currency: &str) -> Result<i64, String> {
+    let since = db.last_zreport()?;
+    let history = db.history()?;
+    let transactions = history
+        .iter()
+        .filter(|t| t.till.as_deref() == db.till())
+        .filter(|t| since.is_none_or(|s| t.timestamp >= s))
+        .collect::<Vec<_>>();
+    Ok(z_summary(&transactions, home_currency)
+        .cash
+        .get(home_currency)
+        .copied()
+        .unwrap_or(0))
+}
+
+pub fn z_report(db: &db::DB, config: &Config, home_currency: &str) {
+    let now = Utc::now();
+    let since = match db.last_zreport() {
+        Ok(s) => s,
+        Err(e) => {
+            println!("Error, unable to load last Z-report: {}", e);
+            return;
+        }
+    };
+    let history = match db.history() {
+        Ok(h) => h,
+        Err(e) => {
+            println!("Error, unable to list transactions: {}", e);
+            return;
+        }
+    };
+    let transactions = history
+        .iter()
+        .filter(|t| t.till.as_deref() == db.till())
+        .filter(|t| since.is_none_or(|s| t.timestamp >= s) && t.timestamp < now)
+        .collect::<Vec<_>>();
+    let summary = z_summary(&transactions, home_currency);
+    drop(history);
+
+    println!(
+        "{}",
+        Style::new().underline().paint(match since {
+            Some(s) => format!("Z-report since {}", s.format("%Y-%m-%d %H:%M")),
+            None => "Z-report".to_string(),
+        })
+    );
+    println!(
+        "Sales: {}, total {}",
+        summary.sales,
+        money::format(summary.sales_by_method.values().sum::<u64>())
+    );
+    for (method, total) in &summary.sales_by_method {
+        println!("- {}: {}", method, money::format(*total));
+    }
+    println!(
+        "Deposits: total {}",
+        money::format(summary.deposits_by_method.values().sum::<u64>())
+    );
+    for (method, total) in &summary.deposits_by_method {
+        println!("- {}: {}", method, money::format(*total));
+    }
+    println!("Expected cash movement:");
+    if summary.cash.is_empty() {
+        println!("- none");
+    }
+    for (currency, amount) in &summary.cash {
+        println!("- {}: {}", currency, crate::format_cash(config, currency, *amount));
+    }
+
+    match db.mark_zreport(now) {
+        Ok(()) => println!("Shift closed at {}", now.format("%Y-%m-%d %H:%M")),
+        Err(e) => println!("Error, unable to mark Z-report: {}", e),
+    }
+}
+
+// Everything since local midnight, put together without marking a Z-report
+pub fn day_summary(transactions: &[Transaction], now: DateTime<Local>, home_currency: &str) -> String {
+    let midnight = now.date_naive().and_hms_opt(0, 0, 0).unwrap();
+    let since = midnight.and_local_timezone(Local).earliest().unwrap_or(now).with_timezone(&Utc);
+    let today = transactions
+        .iter()
+        .filter(|t| t.timestamp >= since && t.timestamp <= now)
+        .collect::<Vec<_>>();
+    let summary = z_summary(&today, home_currency);
+    let mut sold = std::collections::BTreeMap::<&str, u32>::new();
+    for t in &today {
+        if let db::TransactionType::Purchase { products, .. } = &t.transaction {
+            for p in products {
+                *sold.entry(p.name.as_str()).or_default() += 1;
+            }
+        }
+    }
+
+    let mut lines = vec![format!("End of day {}", now.format("%Y-%m-%d"))];
+    lines.push(format!(
+        "Takings: {} from {} sales",
+        money::format(summary.sales_by_method.values().sum::<u64>()),
+        summary.sales
+    ));
+    lines.push(format!(
+        "Deposits: {}",
+        money::format(summary.deposits_by_method.values().sum::<u64>())
+    ));
+    lines.push(format!(
+        "Cash box: {}",
+        money::format_signed(summary.cash.get(home_currency).copied().unwrap_or(0))
+    ));
+    if sold.is_empty() {
+        lines.push("Nothing sold".to_string());
+    }
+    for (name, count) in sold {
+        lines.push(format!("- {} x {}", count, name));
+    }
+    lines.join("\n")
+}
+
+// One line for a transaction on someone's account
+pub fn describe(transaction: &db::TransactionType) -> String {
+    match transaction {
+        db::TransactionType::Purchase { products, .. } => {
+            products.iter().map(|p| p.name.as_str()).collect::<Vec<_>>().join(", ")
+        }
+        db::TransactionType::Deposit { method, .. } => format!("Deposit ({})", method),
+        db::TransactionType::StandingOrder { description, .. } => description.clone(),
+        db::TransactionType::SettleTab { tab, .. } => format!("Settled tab {}", tab),
+        db::TransactionType::Adjustment { reason, .. } => format!("Adjustment ({})", reason),
+        db::TransactionType::Withdrawal { .. } => "Cash withdrawal".to_string(),
+        db::TransactionType::Refund { .. } => "Cash refund".to_string(),
+        db::TransactionType::Void { products, .. } => format!(
+            "Void of {}",
+            products.iter().map(|p| p.name.as_str()).collect::<Vec<_>>().join(", ")
+        ),
+        _ => String::new(),
+    }
+}
+
+// The basket someone buys most often, as sorted barcodes, once they've bought it more than once.
+// Ties go to whichever was bought most recently.
+pub fn usual_order(transactions: &[Transaction], user: &str) -> Option<Vec<bank_core::barcode::Barcode>> {
+    let mut baskets = std::collections::HashMap::<Vec<_>, (u32, DateTime<Utc>)>::new();
+    for t in transactions {
+        match (&t.actor, &t.transaction) {
+            (db::TransactionActor::User(u), db::TransactionType::Purchase { products, .. }) if u == user => {
+                let mut basket = products.iter().map(|p| p.barcode.clone()).collect::<Vec<_>>();
+                basket.sort_by_key(|b| b.to_string());
+                let (count, last) = baskets.entry(basket).or_insert((0, t.timestamp));
+                *count += 1;
+                *last = t.timestamp.max(*last);
+            }
+            _ => {}
+        }
+    }
+    baskets
+        .into_iter()
+        .filter(|(basket, (count, _))| *count > 1 && !basket.is_empty())
+        .max_by_key(|(_, usage)| *usage)
+        .map(|(basket, _)| basket)
+}
+
+// When the end of day summary is next due, later today or otherwise tomorrow
+pub fn next_run(now: DateTime<Local>, at: chrono::NaiveTime) -> DateTime<Local> {
+    let mut day = now.date_naive();
+    loop {
+        // Times skipped by the clocks going forward just wait for the next day
+        if let Some(t) = day.and_time(at).and_local_timezone(Local).earliest() {
+            if t > now {
+                return t;
+            }
+        }
+        day = day.succ_opt().unwrap();
+    }
+}
+
+pub fn end_of_day(db: &db::DB, home_currency: &str) {
+    let transactions = match db.transactions() {
+        Ok(t) => t,
+        Err(e) => {
+            println!("Error, unable to put together the end of day summary: {}", e);
+            return;
+        }
+    };
+    let summary = day_summary(&transactions, Local::now(), home_currency);
+    println!();
+    println!("{}", summary);
+    crate::notify::send(crate::notify::Notice::EndOfDay, &summary);
+}
+
+// How far back to look when working out how fast things sell
+const SALES_WINDOW_DAYS: i64 = 28;
+// How many weeks prodstats breaks sales down into
+const STATS_WEEKS: u32 = 8;
+
+#[derive(Debug, PartialEq)]
+pub struct Reorder {
+    pub barcode: bank_core::barcode::Barcode,
+    pub quantity: u32,
+    // Per unit, from the last restock
+    pub cost: Option<u32>,
+}
+
+// Expected sales of each product over the next `days` days. Each day of the week gets what
+// sold on that day over the last four weeks on average, as Tuesday's open night goes through
+// far more than a quiet Sunday.
+pub fn demand_forecast(
+    transactions: &[Transaction],
+    now: DateTime<Utc>,
+    days: u32,
+) -> std::collections::HashMap<bank_core::barcode::Barcode, f64> {
+    let weekday = |t: DateTime<Utc>| t.with_timezone(&Local).weekday().num_days_from_monday() as usize;
+    let since = now - chrono::Duration::days(SALES_WINDOW_DAYS);
+
+    let mut by_weekday = std::collections::HashMap::<bank_core::barcode::Barcode, [u32; 7]>::new();
+    for t in transactions.iter().filter(|t| t.timestamp >= since && t.timestamp <= now) {
+        if let db::TransactionType::Purchase { products, .. } = &t.transaction {
+            for p in products {
+                by_weekday.entry(p.barcode.clone()).or_default()[weekday(t.timestamp)] += 1;
+            }
+        }
+    }
+
+    let weeks = SALES_WINDOW_DAYS as f64 / 7.0;
+    let upcoming = (1..=days as i64)
+        .map(|d| weekday(now + chrono::Duration::days(d)))
+        .collect::<Vec<_>>();
+    by_weekday
+        .into_iter()
+        .map(|(barcode, sold)| (barcode, upcoming.iter().map(|d| sold[*d] as f64 / weeks).sum()))
+        .collect()
+}
+
+pub fn forecast(db: &db::DB, products: &bank_core::products::Products, args: &[&str]) {
+    let days = match args.first().map(|d| d.parse::<u32>()) {
+        None => 7,
+        Some(Ok(d)) if d > 0 => d,
+        _ => {
+            println!("Usage: forecast [days]");
+            return;
+        }
+    };
+    let transactions = match db.transactions() {
+        Ok(t) => t,
+        Err(e) => {
+            println!("Error, unable to list transactions: {}", e);
+            return;
+        }
+    };
+
+    let mut demand = demand_forecast(&transactions, Utc::now(), days)
+        .into_iter()
+        .filter(|(_, expected)| *expected > 0.0)
+        .collect::<Vec<_>>();
+    demand.sort_by(|a, b| b.1.total_cmp(&a.1));
+    println!(
+        "{}",
+        Style::new()
+            .underline()
+            .paint(format!("Expected sales over the next {} days", days))
+    );
+    if demand.is_empty() {
+        println!("Nothing sold recently");
+    }
+    for (barcode, expected) in demand {
+        let name = products.get(&barcode).map_or("(unknown product)", |p| p.name.as_str());
+        println!("{:.1} x {}", expected, name);
+    }
+}
+
+// Enough of each stocked product to last until the next shopping run, going by the forecast
+pub fn reorder_suggestions(transactions: &[Transaction], now: DateTime<Utc>, days: u32) -> Vec<Reorder> {
+    let levels = bank_core::stock::levels(transactions);
+    let demand = demand_forecast(transactions, now, days);
+    let costs = bank_core::stock::last_costs(transactions);
+
+    let mut suggestions = levels
+        .into_iter()
+        .filter_map(|(barcode, level)| {
+            let needed = demand.get(&barcode)?.ceil() as i64 - level.max(0);
+            (needed > 0).then(|| Reorder {
+                cost: costs.get(&barcode).copied(),
+                barcode,
+                quantity: needed as u32,
+            })
+        })
+        .collect::<Vec<_>>();
+    suggestions.sort_by_key(|s| std::cmp::Reverse(s.quantity));
+    suggestions
+}
+
+pub fn reorder(db: &db::DB, products: &bank_core::products::Products, args: &[&str]) {
+    let days = match args.first().map(|d| d.parse::<u32>()) {
+        None => 7,
+        Some(Ok(d)) if d > 0 => d,
+        _ => {
+            println!("Usage: reorder [days until the next shop]");
+            return;
+        }
+    };
+    let transactions = match db.transactions() {
+        Ok(t) => t,
+        Err(e) => {
+            println!("Error, unable to list transactions: {}", e);
+            return;
+        }
+    };
+
+    let suggestions = reorder_suggestions(&transactions, Utc::now(), days);
+    println!(
+        "{}",
+        Style::new()
+            .underline()
+            .paint(format!("To last the next {} days", days))
+    );
+    if suggestions.is_empty() {
+        println!("Nothing needed");
+        return;
+    }
+    let (mut total, mut unknown) = (0, 0);
+    for s in &suggestions {
+        let name = products.get(&s.barcode).map_or("(unknown product)", |p| p.name.as_str());
+        match s.cost {
+            Some(cost) => {
+                total += cost * s.quantity;
+                println!("{} x {} (~{})", s.quantity, name, money::format(cost * s.quantity));
+            }
+            None => {
+                unknown += 1;
+                println!("{} x {}", s.quantity, name);
+            }
+        }
+    }
+    println!("Roughly {}", money::format(total));
+    if unknown > 0 {
+        println!("...plus {} product(s) with no cost recorded", unknown);
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Margin {
+    pub sales: u64,
+    // Paid out of the cash box
+    pub expenses: u64,
+    // Costed stock received on purchase orders, which is paid for some other way. Plain
+    // restocks aren't counted, as stock bought with cash-box money is already an expense.
+    pub orders: u64,
+}
+
+impl Margin {
+    pub fn net(&self) -> i64 {
+        self.sales as i64 - self.expenses as i64 - self.orders as i64
+    }
+}
+
+pub fn margin(transactions: &[&Transaction]) -> Margin {
+    let mut margin = Margin::default();
+    for t in transactions {
+        match &t.transaction {
+            db::TransactionType::Purchase { total, .. } => margin.sales += *total as u64,
+            db::TransactionType::Expense { amount, .. } => margin.expenses += *amount as u64,
+            db::TransactionType::Restock {
+                quantity,
+                cost: Some(cost),
+                order: Some(_),
+                ..
+            } => margin.orders += *cost as u64 * *quantity as u64,
+            _ => {}
+        }
+    }
+    margin
+}
+
+// What came in from sales against what went out on stock
+pub fn margin_report(db: &db::DB, args: &[&str]) {
+    let period = match args.first().map(|p| parse_period(p)) {
+        Some(Ok(p)) => p,
+        Some(Err(e)) => {
+            println!("Error, {}", e);
+            return;
+        }
+        None => {
+            println!("Usage: margin <period>");
+            return;
+        }
+    };
+    let transactions = match db.transactions() {
+        Ok(t) => t,
+        Err(e) => {
+            println!("Error, unable to list transactions: {}", e);
+            return;
+        }
+    };
+
+    let m = margin(&transactions.iter().filter(|t| period.contains(t)).collect::<Vec<_>>());
+    println!(
+        "{}",
+        Style::new()
+            .underline()
+            .paint(format!("Margin for {}", period.label))
+    );
+    println!("Sales: {}", money::format(m.sales));
+    println!("Paid from the cash box: {}", money::format(m.expenses));
+    println!("Purchase orders received: {}", money::format(m.orders));
+    let net = m.net();
+    println!(
+        "Margin: {}",
+        money::format(net)
+    );
+}
+
+// Stock that's gone missing, going by what was counted against what should have been there
+pub fn shrinkage(db: &db::DB, products: &bank_core::products::Products, args: &[&str]) {
+    let period = match args.first().map(|p| parse_period(p)) {
+        Some(Ok(p)) => p,
+        Some(Err(e)) => {
+            println!("Error, {}", e);
+            return;
+        }
+        None => {
+            println!("Usage: shrinkage <period>");
+            return;
+        }
+    };
+    let transactions = match db.transactions() {
+        Ok(t) => t,
+        Err(e) => {
+            println!("Error, unable to list transactions: {}", e);
+            return;
+        }
+    };
+
+    let movements = bank_core::stock::movements(&transactions, period.start, period.end);
+    println!(
+        "{}",
+        Style::new()
+            .underline()
+            .paint(format!("Shrinkage for {}", period.label))
+    );
+    let mut lines = movements
+        .iter()
+        .map(|(barcode, m)| {
+            (products.get(barcode).map_or("(unknown product)", |p| p.name.as_str()), barcode, m)
+        })
+        .collect::<Vec<_>>();
+    if lines.is_empty() {
+        println!("No stock movements");
+        return;
+    }
+    lines.sort_by_key(|l| l.0);
+
+    let (mut missing, mut value) = (0, 0);
+    for (name, barcode, m) in lines {
+        println!(
+            "{} - in {}, sold {}, written off {}, missing {}",
+            name, m.intake, m.sold, m.written_off, m.missing
+        );
+        missing += m.missing;
+        if let Some(p) = products.get(barcode) {
+            value += m.missing * p.price as i64;
+        }
+    }
+    println!(
+        "Total missing: {} ({} at sale price)",
+        missing,
+        money::format(value)
+    );
+}
+
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+// Characters in the longest bar
+const BAR_WIDTH: usize = 30;
+
+// One character per value, scaled to the largest
+pub fn sparkline(values: &[u64]) -> String {
+    let max = values.iter().copied().max().unwrap_or(0).max(1);
+    values
+        .iter()
+        .map(|v| SPARKS[(*v * (SPARKS.len() as u64 - 1) / max) as usize])
+        .collect()
+}
+
+pub fn bar(value: u64, max: u64) -> String {
+    "█".repeat((value * BAR_WIDTH as u64 / max.max(1)) as usize)
+}
+
+// Sales and deposits for each of the last `days` local days, oldest first, in pence
+pub fn daily_totals(transactions: &[Transaction], now: DateTime<Local>, days: u32) -> (Vec<u64>, Vec<u64>) {
+    let today = now.date_naive();
+    let (mut sales, mut deposits) = (vec![0; days as usize], vec![0; days as usize]);
+    for t in transactions {
+        let ago = (today - t.timestamp.with_timezone(&Local).date_naive()).num_days();
+        if ago < 0 || ago >= days as i64 {
+            continue;
+        }
+        let day = days as usize - 1 - ago as usize;
+        match &t.transaction {
+            db::TransactionType::Purchase { total, .. } if !matches!(t.actor, db::TransactionActor::Stock) => {
+                sales[day] += *total as u64
+            }
+            db::TransactionType::Deposit { amount, .. } => deposits[day] += *amount as u64,
+            _ => {}
+        }
+    }
+    (sales, deposits)
+}
+
+pub fn chart(db: &db::DB, args: &[&str]) {
+    let days = match args.first().map(|d| d.parse::<u32>()) {
+        None => 14,
+        Some(Ok(d)) if d > 0 => d,
+        _ => {
+            println!("Usage: chart [days]");
+            return;
+        }
+    };
+    let transactions = match db.transactions() {
+        Ok(t) => t,
+        Err(e) => {
+            println!("Error, unable to list transactions: {}", e);
+            return;
+        }
+    };
+
+    let now = Local::now();
+    let (sales, deposits) = daily_totals(&transactions, now, days);
+    println!("{}", Style::new().underline().paint(format!("The last {} days", days)));
+    for (label, totals) in [("Sales", &sales), ("Deposits", &deposits)] {
+        println!(
+            "{:<9}{} {}, best day {}",
+            label,
+            sparkline(totals),
+            money::format(totals.iter().sum::<u64>()),
+            money::format(totals.iter().max().copied().unwrap_or(0))
+        );
+    }
+
+    let since = now.with_timezone(&Utc) - chrono::Duration::days(days as i64);
+    let mut sold = std::collections::HashMap::<&str, u64>::new();
+    for t in transactions.iter().filter(|t| t.timestamp >= since) {
+        if let (db::TransactionType::Purchase { products, .. }, false) =
+            (&t.transaction, matches!(t.actor, db::TransactionActor::Stock))
+        {
+            for p in products {
+                *sold.entry(p.name.as_str()).or_default() += 1;
+            }
+        }
+    }
+    let mut top = sold.into_iter().collect::<Vec<_>>();
+    top.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    top.truncate(5);
+    println!("{}", Style::new().underline().paint("Top products"));
+    if top.is_empty() {
+        println!("Nothing sold");
+    }
+    let max = top.first().map_or(0, |t| t.1);
+    let width = top.iter().map(|t| t.0.chars().count()).max().unwrap_or(0);
+    for (name, count) in top {
+        println!("{:<width$} {} {}", name, bar(count, max), count, width = width);
+    }
+}
+
+// What changed between two copies of the database, e.g. last night's backup and now
+pub fn snapshot_diff(a: &db::InnerDB, b: &db::InnerDB) -> String {
+    let mut lines = Vec::new();
+    let mut ids = a.users.keys().chain(b.users.keys()).collect::<Vec<_>>();
+    ids.sort();
+    ids.dedup();
+    for id in ids {
+        let (before, after) = match (a.users.get(id), b.users.get(id)) {
+            (Some(before), Some(after)) => (before, after),
+            (None, Some(after)) => {
+                lines.push(format!("+ user {} (balance {})", id, money::format(after.balance)));
+                continue;
+            }
+            (Some(before), None) => {
+                lines.push(format!("- user {} (balance {})", id, money::format(before.balance)));
+                continue;
+            }
+            (None, None) => unreachable!(),
+        };
+        if before.balance != after.balance {
+            lines.push(format!(
+                "  user {} balance {} -> {} ({})",
+                id,
+                money::format(before.balance),
+                money::format(after.balance),
+                money::format_signed(after.balance - before.balance)
+            ));
+        }
+        let cards = |u: &db::User| u.cards.clone().unwrap_or_default();
+        let (old_cards, new_cards) = (cards(before), cards(after));
+        let mut changed = new_cards
+            .difference(&old_cards)
+            .map(|c| ('+', c))
+            .chain(old_cards.difference(&new_cards).map(|c| ('-', c)))
+            .collect::<Vec<_>>();
+        changed.sort();
+        for (sign, (uid, name)) in changed {
+            lines.push(format!("{} card {} ({}) of user {}", sign, uid, name, id));
+        }
+    }
+
+    let old_ids = a.transactions.iter().map(|t| t.id).collect::<std::collections::HashSet<_>>();
+    let new_ids = b.transactions.iter().map(|t| t.id).collect::<std::collections::HashSet<_>>();
+    for t in b.transactions.iter().filter(|t| !old_ids.contains(&t.id)) {
+        lines.push(format!(
+            "+ {} {} {} {}",
+            t.timestamp.with_timezone(&Local).format("%Y-%m-%d %H:%M"),
+            t.id,
+            t.actor,
+            describe(&t.transaction)
+        ));
+    }
+    // Moved out into an archive file since isn't gone
+    let (archived, missing): (Vec<_>, Vec<_>) = a
+        .transactions
+        .iter()
+        .filter(|t| !new_ids.contains(&t.id))
+        .partition(|t| b.archive.years.contains(&t.timestamp.year()));
+    for t in missing {
+        lines.push(format!(
+            "- {} {} {} {}",
+            t.timestamp.with_timezone(&Local).format("%Y-%m-%d %H:%M"),
+            t.id,
+            t.actor,
+            describe(&t.transaction)
+        ));
+    }
+    if !archived.is_empty() {
+        lines.push(format!("{} transaction(s) archived", archived.len()));
+    }
+    if lines.is_empty() {
+        lines.push("No differences".to_string());
+    }
+    lines.join("\n")
+}
+
+pub fn dbdiff(args: &[&str]) {
+    if args.len() != 2 {
+        println!("Usage: dbdiff <snapshot A> <snapshot B>");
+        return;
+    }
+    let read = |path: &str| {
+        std::fs::read(path)
+            .map_err(|e| format!("cannot read {} {}", path, e))
+            .and_then(|raw| db::Format::decode::<db::InnerDB>(&raw))
+    };
+    match (read(args[0]), read(args[1])) {
+        (Ok(a), Ok(b)) => println!("{}", snapshot_diff(&a, &b)),
+        (Err(e), _) | (_, Err(e)) => println!("Error, {}", e),
+    }
+}
+
+// Income, as opposed to money that's only held for someone
+pub fn revenue(balances: &std::collections::BTreeMap<ledger::Account, i64>) -> i64 {
+    use ledger::Account::*;
+    -[Sales, Rounding, StandingOrders, DepositFees]
+        .iter()
+        .map(|a| balances.get(a).copied().unwrap_or(0))
+        .sum::<i64>()
+}
+
+// A trial balance: what's in each account, all time or for a period
+pub fn books(db: &db::DB, config: &Config, args: &[&str]) {
+    let period = match args.first().map(|p| parse_period(p)) {
+        None => None,
+        Some(Ok(p)) => Some(p),
+        Some(Err(e)) => {
+            println!("Error, {}", e);
+            return;
+        }
+    };
+    let transactions = match db.transactions() {
+        Ok(t) => t,
+        Err(e) => {
+            println!("Error, unable to list transactions: {}", e);
+            return;
+        }
+    };
+    let balances = ledger::balances(transactions.iter().filter(|t| period.as_ref().is_none_or(|p| p.contains(t))));
+
+    let accounts = balances
+        .iter()
+        .filter(|(_, amount)| **amount != 0)
+        .map(|(account, amount)| (account.name(&config.ledger, ledger::Format::Ledger), *amount))
+        .collect::<Vec<_>>();
+    let width = accounts.iter().map(|(name, _)| name.chars().count()).max().unwrap_or(0);
+    println!(
+        "{}",
+        Style::new().underline().paint(match &period {
+            Some(p) => format!("Books for {}", p.label),
+            None => "Books".to_string(),
+        })
+    );
+    for (name, amount) in &accounts {
+        println!("{:<width$} {:>10}", name, money::format(*amount), width = width);
+    }
+    println!("{:<width$} {:>10}", "Total", money::format(balances.values().sum::<i64>()), width = width);
+    println!("Revenue: {}", money::format(revenue(&balances)));
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Valuation {
+    pub units: i64,
+    // In pence, at the last restock's cost and at today's prices
+    pub cost: i64,
+    pub retail: i64,
+    // Units with no cost recorded, left out of the cost
+    pub uncosted: i64,
+}
+
+impl Valuation {
+    fn add(&mut self, other: &Valuation) {
+        self.units += other.units;
+        self.cost += other.cost;
+        self.retail += other.retail;
+        self.uncosted += other.uncosted;
+    }
+}
+
+// What's on the shelves is worth, by category. Anything below zero is taken as none left.
+pub fn stock_valuation(
+    transactions: &[Transaction],
+    products: &bank_core::products::Products,
+) -> std::collections::BTreeMap<String, Valuation> {
+    let costs = bank_core::stock::last_costs(transactions);
+    let mut categories = std::collections::BTreeMap::<String, Valuation>::new();
+    for (barcode, level) in bank_core::stock::levels(transactions) {
+        let (product, units) = match products.get(&barcode) {
+            Some(p) if level > 0 => (p, level),
+            _ => continue,
+        };
+        let category = categories
+            .entry(product.category.clone().unwrap_or_else(|| "Uncategorised".to_string()))
+            .or_default();
+        category.units += units;
+        match costs.get(&barcode) {
+            Some(cost) => category.cost += *cost as i64 * units,
+            None => category.uncosted += units,
+        }
+        if !product.open_price {
+            category.retail += product.price as i64 * units;
+        }
+    }
+    categories
+}
+
+pub fn stock_value(db: &db::DB, products: &bank_core::products::Products) {
+    let transactions = match db.transactions() {
+        Ok(t) => t,
+        Err(e) => {
+            println!("Error, unable to list transactions: {}", e);
+            return;
+        }
+    };
+    let categories = stock_valuation(&transactions, products);
+    if categories.is_empty() {
+        println!("No stock recorded yet, use 'restock' when putting things on the shelves");
+        return;
+    }
+
+    println!("{}", Style::new().underline().paint("Stock value"));
+    let width = categories.keys().map(|c| c.chars().count()).max().unwrap_or(0).max(5);
+    println!("{:<width$} {:>6} {:>10} {:>10}", "", "Units", "At cost", "At retail", width = width);
+    let mut total = Valuation::default();
+    for (category, value) in &categories {
+        total.add(value);
+        println!(
+            "{:<width$} {:>6} {:>10} {:>10}",
+            category,
+            value.units,
+            money::format(value.cost),
+            money::format(value.retail),
+            width = width
+        );
+    }
+    println!(
+        "{:<width$} {:>6} {:>10} {:>10}",
+        "Total",
+        total.units,
+        money::format(total.cost),
+        money::format(total.retail),
+        width = width
+    );
+    if total.uncosted > 0 {
+        println!("...plus {} unit(s) with no cost recorded", total.uncosted);
+    }
+}
+
+// Someone in the red, and for how long, counted from when their balance last went below zero
+#[derive(Debug, PartialEq)]
+pub struct Debt {
+    pub user: String,
+    // In pence
+    pub owed: i64,
+    pub days: i64,
+}
+
+pub fn aging_bucket(days: i64) -> &'static str {
+    match days {
+        ..30 => "Under 30 days",
+        30..60 => "30-60 days",
+        60..90 => "60-90 days",
+        _ => "90+ days",
+    }
+}
+
+// Every negative balance, longest owed first. Works back from the balance as it is now, so if
+// history runs out before the account was in credit it's been owed at least that long.
+pub fn debts(users: &[db::User], transactions: &[Transaction], now: DateTime<Utc>) -> Vec<Debt> {
+    let mut debts = users
+        .iter()
+        .filter(|u| u.balance < 0)
+        .map(|u| {
+            let mut balance = u.balance as i64;
+            let mut since = now;
+            for t in transactions.iter().rev().filter(|t| t.actor.account() == Some(u.id.as_str())) {
+                since = t.timestamp;
+                balance -= t.balance_change() as i64;
+                if balance >= 0 {
+                    break;
+                }
+            }
+            Debt {
+                user: u.id.clone(),
+                owed: -(u.balance as i64),
+                days: (now - since).num_days(),
+            }
+        })
+        .collect::<Vec<_>>();
+    debts.sort_by(|a, b| b.days.cmp(&a.days).then(b.owed.cmp(&a.owed)));
+    debts
+}
+
+pub fn aging(db: &db::DB) {
+    let (users, transactions) = match db.users().and_then(|u| Ok((u, db.transactions()?))) {
+        Ok(d) => d,
+        Err(e) => {
+            println!("Error, unable to list transactions: {}", e);
+            return;
+        }
+    };
+    let debts = debts(&users, &transactions, Utc::now());
+    if debts.is_empty() {
+        println!("Nobody owes anything");
+        return;
+    }
+
+    let names = users
+        .iter()
+        .map(|u| (u.id.as_str(), u.display_name()))
+        .collect::<std::collections::HashMap<_, _>>();
+    println!("{}", Style::new().underline().paint("Debts by age"));
+    let mut bucket = None;
+    for debt in &debts {
+        let this = aging_bucket(debt.days);
+        if bucket != Some(this) {
+            let total = debts.iter().filter(|d| aging_bucket(d.days) == this).map(|d| d.owed).sum::<i64>();
+            println!("{} ({})", Style::new().bold().paint(this), money::format(total));
+            bucket = Some(this);
+        }
+        println!(
+            "  {} - {} for {} day(s)",
+            names.get(debt.user.as_str()).map_or(debt.user.as_str(), String::as_str),
+            money::format(debt.owed),
+            debt.days
+        );
+    }
+    println!(
+        "Total owed {}",
+        money::format(debts.iter().map(|d| d.owed).sum::<i64>())
+    );
+}
+
+// How one product has been selling
+#[derive(Debug, Default, PartialEq)]
+pub struct ProductSales {
+    // Units sold in each of the last few weeks, oldest first, the last one ending now
+    pub weekly: Vec<u64>,
+    pub units: u64,
+    // In pence, at the price it was sold at
+    pub revenue: u64,
+    pub last_sold: Option<DateTime<Utc>>,
+}
+
+pub fn product_sales(
+    transactions: &[Transaction],
+    barcode: &bank_core::barcode::Barcode,
+    now: DateTime<Utc>,
+    weeks: u32,
+) -> ProductSales {
+    let mut sales = ProductSales {
+        weekly: vec![0; weeks as usize],
+        ..Default::default()
+    };
+    for t in transactions.iter().filter(|t| t.timestamp <= now) {
+        // Voids count against the week they were made in, so a week can't go below nothing
+        let (products, voided) = match &t.transaction {
+            db::TransactionType::Purchase { products, .. } if !matches!(t.actor, db::TransactionActor::Stock) => {
+                (products, false)
+            }
+            db::TransactionType::Void { products, .. } => (products, true),
+            _ => continue,
+        };
+        let week = (weeks as i64 - 1).checked_sub((now - t.timestamp).num_weeks()).filter(|w| *w >= 0);
+        for p in products.iter().filter(|p| p.barcode == *barcode) {
+            if voided {
+                sales.units = sales.units.saturating_sub(1);
+                sales.revenue = sales.revenue.saturating_sub(p.price as u64);
+                if let Some(w) = week {
+                    sales.weekly[w as usize] = sales.weekly[w as usize].saturating_sub(1);
+                }
+            } else {
+                sales.units += 1;
+                sales.revenue += p.price as u64;
+                sales.last_sold = Some(t.timestamp);
+                if let Some(w) = week {
+                    sales.weekly[w as usize] += 1;
+                }
+            }
+        }
+    }
+    sales
+}
+
+pub fn product_stats(db: &db::DB, products: &bank_core::products::Products, args: &[&str]) {
+    if args.is_empty() {
+        println!("Usage: prodstats <barcode | name>");
+        return;
+    }
+    // By barcode or short code, otherwise any product with that in its name
+    let search = args.join(" ");
+    let found = bank_core::products::lookup(products, &search)
+        .or_else(|| bank_core::products::by_plu(products, &search));
+    let product = match found {
+        Some(p) => p,
+        None => {
+            let search = search.to_lowercase();
+            let mut matching = products
+                .values()
+                .filter(|p| p.name.to_lowercase().contains(&search))
+                .collect::<Vec<_>>();
+            match matching[..] {
+                [p] => p,
+                [] => {
+                    println!("Error, no product matches {}", args.join(" "));
+                    return;
+                }
+                _ => {
+                    matching.sort_by(|a, b| a.name.cmp(&b.name));
+                    println!("More than one product matches, which did you mean?");
+                    for p in matching {
+                        println!("{} {}", p.barcode, p.name);
+                    }
+                    return;
+                }
+            }
+        }
+    };
+    let transactions = match db.transactions() {
+        Ok(t) => t,
+        Err(e) => {
+            println!("Error, unable to list transactions: {}", e);
+            return;
+        }
+    };
+
+    let sales = product_sales(&transactions, &product.barcode, Utc::now(), STATS_WEEKS);
+    println!("{}", Style::new().underline().paint(format!("{} ({})", product.name, product.barcode)));
+    println!("Price: {}", product.disp_price());
+    match bank_core::stock::levels(&transactions).get(&product.barcode) {
+        Some(level) => println!("In stock: {}", level),
+        None => println!("In stock: never restocked"),
+    }
+    match sales.last_sold {
+        Some(at) => println!("Last sold: {}", at.with_timezone(&Local).format("%Y-%m-%d %H:%M")),
+        None => {
+            println!("Never sold");
+            return;
+        }
+    }
+    println!("Sold in total: {} ({})", sales.units, money::format(sales.revenue));
+    println!("Last {} weeks: {}", STATS_WEEKS, sparkline(&sales.weekly));
+    let max = sales.weekly.iter().copied().max().unwrap_or(0);
+    for (ago, units) in sales.weekly.iter().rev().enumerate() {
+        let label = match ago {
+            0 => "This week".to_string(),
+            1 => "Last week".to_string(),
+            n => format!("{} weeks ago", n),
+        };
+        println!("{:<12} {:>4} {}", label, units, bar(*units, max));
+    }
+}
@@ -0,0 +1,73 @@
+use crate::serial;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use tokio::sync::mpsc::Sender;
+
+const RETRY_DELAY: std::time::Duration = std::time::Duration::from_secs(5);
+
+// Runs on its own thread, reading barcodes from a scanner that shows up as a serial port rather
+// than a keyboard, and reopening the port whenever it goes away
+pub fn scanner(config: crate::config::ScannerConfig, scan_tx: Sender<String>, stop: Arc<AtomicBool>) {
+    let mut failed = false;
+
+    while !stop.load(Ordering::Relaxed) {
+        match read_scans(&config, &scan_tx, &stop, &mut failed) {
+            Ok(()) => return,
+            Err(e) => {
+                // Only report each outage once, rather than every retry
+                if !failed {
+                    println!("Barcode scanner error: {}, retrying in the background", e);
+                }
+                failed = true;
+                let retry_at = std::time::Instant::now() + RETRY_DELAY;
+                while std::time::Instant::now() < retry_at && !stop.load(Ordering::Relaxed) {
+                    std::thread::sleep(std::time::Duration::from_millis(100));
+                }
+            }
+        }
+    }
+}
+
+fn read_scans(
+    config: &crate::config::ScannerConfig,
+    scan_tx: &Sender<String>,
+    stop: &AtomicBool,
+    failed: &mut bool,
+) -> Result<(), String> {
+    let mut port = serial::open(&config.device, config.baud)?;
+    if *failed {
+        *failed = false;
+        println!("Barcode scanner reconnected");
+    }
+
+    let mut pending = Vec::new();
+    let mut buf = [0; 256];
+    while !stop.load(Ordering::Relaxed) {
+        let read = std::io::Read::read(&mut port, &mut buf).map_err(|e| e.to_string())?;
+        for scan in split_scans(&mut pending, &buf[..read]) {
+            // The main loop has gone away, so we're shutting down
+            if scan_tx.blocking_send(scan).is_err() {
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+// Scanners end each barcode with a carriage return, a newline, or both. Anything after the last
+// one is kept in `pending` until the rest of it arrives.
+pub fn split_scans(pending: &mut Vec<u8>, read: &[u8]) -> Vec<String> {
+    pending.extend_from_slice(read);
+    let end = match pending.iter().rposition(|b| *b == b'\r' || *b == b'\n') {
+        Some(end) => end,
+        None => return Vec::new(),
+    };
+    let complete = pending.drain(..=end).collect::<Vec<_>>();
+    complete
+        .split(|b| *b == b'\r' || *b == b'\n')
+        .map(|scan| String::from_utf8_lossy(scan).trim().to_string())
+        .filter(|scan| !scan.is_empty())
+        .collect()
+}
@@ -0,0 +1,103 @@
+use crate::{Prompt, Signal, StdoutMsg};
+use std::io::{BufRead, Read, Seek, Write};
+use std::os::fd::{AsRawFd, FromRawFd};
+use tokio::sync::mpsc;
+
+// With --script the file takes the place of stdin, so prompts inside commands, e.g. for a
+// price, are answered by the following lines of the script too
+pub fn open(path: &str) -> Result<(), String> {
+    let file = std::fs::File::open(path).map_err(|e| format!("cannot open script {}", e))?;
+    if unsafe { libc::dup2(file.as_raw_fd(), libc::STDIN_FILENO) } < 0 {
+        return Err(format!("cannot read script {}", std::io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+// Commands piped in, rather than typed at a terminal. Character devices such as /dev/null,
+// which systemd may give us, don't count.
+pub fn piped() -> bool {
+    let mut stat = std::mem::MaybeUninit::<libc::stat>::uninit();
+    if unsafe { libc::fstat(libc::STDIN_FILENO, stat.as_mut_ptr()) } != 0 {
+        return false;
+    }
+    let mode = unsafe { stat.assume_init() }.st_mode & libc::S_IFMT;
+    mode == libc::S_IFIFO || mode == libc::S_IFREG
+}
+
+// Stands in for the readline thread, handing over one line at a time and waiting for each to
+// finish before reading the next
+pub fn feed(tx: mpsc::Sender<StdoutMsg>, mut ready: mpsc::Receiver<Prompt>) {
+    loop {
+        let mut line = String::new();
+        match std::io::stdin().lock().read_line(&mut line) {
+            Ok(0) | Err(_) => {
+                let _ = tx.blocking_send(StdoutMsg::Signal(Signal::Kill));
+                return;
+            }
+            Ok(_) => {}
+        }
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        println!("> {}", line);
+
+        if tx.blocking_send(StdoutMsg::Text(line.to_string())).is_err() || ready.blocking_recv().is_none() {
+            return;
+        }
+    }
+}
+
+// Whether a command's output says it went wrong. Commands only print, so this goes by the
+// wording they all use.
+pub fn failed(output: &str) -> bool {
+    output.lines().any(|l| {
+        l.starts_with("Error")
+            || l.starts_with("Usage:")
+            || l.trim_start_matches('\x07').starts_with("Unknown command")
+            || l.ends_with("isn't available in read-only mode")
+    })
+}
+
+// Output of one command, written to a temporary file so it can be checked afterwards
+pub struct Capture {
+    saved: std::os::fd::RawFd,
+    file: std::fs::File,
+}
+
+impl Capture {
+    pub fn start() -> Result<Capture, String> {
+        let path = std::env::temp_dir().join(format!("57bank-script-{}", std::process::id()));
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .map_err(|e| format!("cannot capture output {}", e))?;
+        let _ = std::fs::remove_file(&path);
+
+        let _ = std::io::stdout().flush();
+        let saved = unsafe { libc::dup(libc::STDOUT_FILENO) };
+        if saved < 0 || unsafe { libc::dup2(file.as_raw_fd(), libc::STDOUT_FILENO) } < 0 {
+            return Err(format!("cannot capture output {}", std::io::Error::last_os_error()));
+        }
+        Ok(Capture { saved, file })
+    }
+
+    // Puts stdout back, passes the output on, and returns it
+    pub fn finish(mut self) -> String {
+        let _ = std::io::stdout().flush();
+        unsafe {
+            libc::dup2(self.saved, libc::STDOUT_FILENO);
+            drop(std::fs::File::from_raw_fd(self.saved));
+        }
+
+        let mut output = String::new();
+        let _ = self.file.rewind();
+        let _ = self.file.read_to_string(&mut output);
+        print!("{}", output);
+        let _ = std::io::stdout().flush();
+        output
+    }
+}
@@ -0,0 +1,74 @@
+use regex::{Captures, Regex};
+use std::collections::HashMap;
+
+// Anything in the config can be written as "secret:<name>" instead, and is looked up in order:
+// 1. $CREDENTIALS_DIRECTORY/<name>, from systemd's LoadCredential= or SetCredential=
+// 2. The BANK_SECRET_<NAME> environment variable, upper case with - and . as _
+// 3. <name>=<value> lines in secrets.env in the data directory
+// 4. The OS keyring, through `secret-tool lookup service 57bank name <name>`
+pub fn resolve_all(config: &str) -> Result<String, String> {
+    let env_file = match std::fs::read_to_string(bank_core::paths::data_path("secrets.env")) {
+        Ok(f) => parse_env_file(&f),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+        Err(e) => return Err(format!("cannot read secrets file {}", e)),
+    };
+    let reference = Regex::new(r#""secret:([A-Za-z0-9_.-]+)""#).unwrap();
+    let mut missing = None;
+    let resolved = reference.replace_all(config, |c: &Captures| match resolve(&c[1], &env_file) {
+        Some(secret) => format!("{:?}", secret),
+        None => {
+            missing.get_or_insert_with(|| c[1].to_string());
+            String::new()
+        }
+    });
+    match missing {
+        Some(name) => Err(format!("cannot find secret {}", name)),
+        None => Ok(resolved.into_owned()),
+    }
+}
+
+fn resolve(name: &str, env_file: &HashMap<String, String>) -> Option<String> {
+    if let Ok(dir) = std::env::var("CREDENTIALS_DIRECTORY") {
+        if let Ok(secret) = std::fs::read_to_string(std::path::Path::new(&dir).join(name)) {
+            return Some(secret.trim_end_matches('\n').to_string());
+        }
+    }
+    if let Ok(secret) = std::env::var(env_var(name)) {
+        return Some(secret);
+    }
+    if let Some(secret) = env_file.get(name) {
+        return Some(secret.clone());
+    }
+    keyring(name)
+}
+
+pub fn env_var(name: &str) -> String {
+    format!("BANK_SECRET_{}", name.to_uppercase().replace(['-', '.'], "_"))
+}
+
+// Blank lines and # comments are skipped, and values can be quoted
+pub fn parse_env_file(file: &str) -> HashMap<String, String> {
+    file.lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .filter_map(|l| l.split_once('='))
+        .map(|(k, v)| {
+            let v = v.trim();
+            let unquoted = v
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .or_else(|| v.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')));
+            (k.trim().to_string(), unquoted.unwrap_or(v).to_string())
+        })
+        .collect()
+}
+
+fn keyring(name: &str) -> Option<String> {
+    let output = std::process::Command::new("secret-tool")
+        .args(["lookup", "service", "57bank", "name", name])
+        .stderr(std::process::Stdio::null())
+        .output()
+        .ok()?;
+    let secret = String::from_utf8(output.stdout).ok()?;
+    (output.status.success() && !secret.is_empty()).then(|| secret.trim_end_matches('\n').to_string())
+}
@@ -0,0 +1,151 @@
+use bank_core::db::{
+    DepositMethod, InnerDB, Transaction, TransactionActor, TransactionType, User,
+};
+use bank_core::paths::{data_dir, data_path};
+use chrono::{Duration, Utc};
+use rand::{seq::SliceRandom, Rng};
+
+const SAMPLE_PRODUCTS: &str = "\
+# Space seperated lines of <barcode> <price in pence> <descriptor>
+# Attributes such as tax=<category> and category=<name> can go between the price and descriptor
+# Blank lines and lines with a # at the start are ignored
+# 6, 8, 12, 13, and 14 digit barcodes accepted
+
+4029764001401 120 category=drink Club-Mate Granat
+4029764001807 120 category=drink Club-Mate
+011152431697 200 category=drink Ramune Citrus
+011152225654 200 category=drink Ramune Lychee
+5000112637922 90 category=drink Coca-Cola
+5000159461122 85 category=snack Snickers
+5000159459228 85 category=snack Twix
+7622210449283 100 category=snack Dairy Milk
+5053990101573 150 category=snack Pringles Original
+";
+
+const SAMPLE_USERS: [&str; 12] = [
+    "q", "alex", "sam", "robin", "jo", "kit", "morgan", "charlie", "ash", "frankie", "lee", "rowan",
+];
+
+const DAYS: i64 = 90;
+
+// Fills an empty data directory with made-up users, products, and transactions
+pub fn seed() -> Result<(), String> {
+    let db = data_path("db");
+    if db.exists() {
+        return Err(format!("{} already exists, refusing to overwrite it", db.display()));
+    }
+    std::fs::create_dir_all(data_dir()).map_err(|e| format!("cannot create data directory {}", e))?;
+    if !data_path("products").exists() {
+        std::fs::write(data_path("products"), SAMPLE_PRODUCTS)
+            .map_err(|e| format!("cannot write products file {}", e))?;
+    }
+    let products = bank_core::products::read_products()?
+        .into_values()
+        .collect::<Vec<_>>();
+    if products.is_empty() {
+        return Err("no products to generate purchases from".to_string());
+    }
+
+    let mut rng = rand::thread_rng();
+    let mut data = InnerDB::default();
+    for id in SAMPLE_USERS {
+        let mut user = User::new(id);
+        if rng.gen_bool(0.6) {
+            let uid = (0..7).map(|_| rng.gen::<u8>().to_string()).collect::<String>();
+            user.cards.get_or_insert_with(Default::default).insert((uid.clone(), uid[0..5].to_owned()));
+        }
+        data.users.insert(id.to_string(), user);
+    }
+
+    let start = Utc::now() - Duration::days(DAYS);
+    for day in 0..DAYS {
+        // Evenings are when people are actually in the space
+        let mut times = (0..rng.gen_range(0..8))
+            .map(|_| start + Duration::days(day) + Duration::minutes(rng.gen_range(17 * 60..24 * 60)))
+            .collect::<Vec<_>>();
+        times.sort();
+
+        for timestamp in times {
+            let basket = (0..rng.gen_range(1..=3))
+                .map(|_| products.choose(&mut rng).unwrap().clone())
+                .collect::<Vec<_>>();
+            let total = basket.iter().map(|p| p.price).sum::<u32>();
+
+            if rng.gen_bool(0.2) {
+                data.transactions.push(Transaction {
+                    id: uuid::Uuid::new_v4(),
+                    timestamp,
+                    actor: TransactionActor::Cash,
+                    transaction: TransactionType::Purchase {
+                        products: basket,
+                        total,
+                        promo: None,
+                        discount: 0,
+                        tendered: None,
+                        combos: Vec::new(),
+                        user_discount: 0,
+                        rounding: 0,
+                        manual_discount: None,
+                    },
+                    note: None,
+                    till: None,
+                });
+                continue;
+            }
+
+            let id = *SAMPLE_USERS.choose(&mut rng).unwrap();
+
+            // Most people top up before they go too far into debt
+            if data.users[id].balance < total as i32 && rng.gen_bool(0.8) {
+                let amount = *[500, 1000, 2000].choose(&mut rng).unwrap();
+                let method = if rng.gen_bool(0.5) {
+                    DepositMethod::Cash
+                } else {
+                    DepositMethod::BankTransfer
+                };
+                data.apply(Transaction {
+                    id: uuid::Uuid::new_v4(),
+                    timestamp: timestamp - Duration::minutes(1),
+                    actor: TransactionActor::User(id.to_string()),
+                    transaction: TransactionType::Deposit {
+                        amount,
+                        method,
+                        fee: 0,
+                    },
+                    note: None,
+                    till: None,
+                });
+            }
+
+            data.apply(Transaction {
+                id: uuid::Uuid::new_v4(),
+                timestamp,
+                actor: TransactionActor::User(id.to_string()),
+                transaction: TransactionType::Purchase {
+                    products: basket,
+                    total,
+                    promo: None,
+                    discount: 0,
+                    tendered: None,
+                    combos: Vec::new(),
+                    user_discount: 0,
+                    rounding: 0,
+                    manual_discount: None,
+                },
+                note: None,
+                till: None,
+            });
+        }
+    }
+
+    let (users, transactions) = (data.users.len(), data.transactions.len());
+    bank_core::db::DB::create(data)?;
+    println!(
+        "Seeded {} with {} users, {} products, and {} transactions",
+        data_dir().display(),
+        users,
+        products.len(),
+        transactions
+    );
+    Ok(())
+}
@@ -0,0 +1,46 @@
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::OpenOptionsExt;
+
+// Opens the port raw at the given speed, with reads giving up after a moment so the stop flag
+// gets looked at
+pub fn open(device: &str, baud: u32) -> Result<std::fs::File, String> {
+    let speed = speed(baud).ok_or_else(|| format!("unsupported baud rate {}", baud))?;
+    let port = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(device)
+        .map_err(|e| format!("cannot open {}: {}", device, e))?;
+
+    let mut termios = std::mem::MaybeUninit::<libc::termios>::uninit();
+    if unsafe { libc::tcgetattr(port.as_raw_fd(), termios.as_mut_ptr()) } != 0 {
+        return Err(format!("{} isn't a serial port: {}", device, std::io::Error::last_os_error()));
+    }
+    let mut termios = unsafe { termios.assume_init() };
+    unsafe {
+        libc::cfmakeraw(&mut termios);
+        libc::cfsetspeed(&mut termios, speed);
+    }
+    termios.c_cflag |= libc::CLOCAL | libc::CREAD;
+    termios.c_cc[libc::VMIN] = 0;
+    // Tenths of a second
+    termios.c_cc[libc::VTIME] = 3;
+    if unsafe { libc::tcsetattr(port.as_raw_fd(), libc::TCSANOW, &termios) } != 0 {
+        return Err(format!("cannot set up {}: {}", device, std::io::Error::last_os_error()));
+    }
+    Ok(port)
+}
+
+fn speed(baud: u32) -> Option<libc::speed_t> {
+    Some(match baud {
+        1200 => libc::B1200,
+        2400 => libc::B2400,
+        4800 => libc::B4800,
+        9600 => libc::B9600,
+        19200 => libc::B19200,
+        38400 => libc::B38400,
+        57600 => libc::B57600,
+        115200 => libc::B115200,
+        _ => return None,
+    })
+}
@@ -0,0 +1,49 @@
+use std::time::{Duration, Instant};
+
+// Commands that act on the logged in user when they're given without an account
+const USER_COMMANDS: [&str; 7] =
+    ["setpin", "setname", "setemail", "exportuser", "favourites", "topup", "weblink"];
+
+// Someone who tapped their card, so they don't have to keep typing their ID
+#[derive(Debug)]
+pub struct Session {
+    pub user: String,
+    timeout: Duration,
+    last_active: Instant,
+}
+
+impl Session {
+    pub fn start(user: &str, timeout: Duration, now: Instant) -> Self {
+        Self {
+            user: user.to_string(),
+            timeout,
+            last_active: now,
+        }
+    }
+
+    // Keeps the session going, unless it sat idle for too long
+    pub fn touch(&mut self, now: Instant) -> bool {
+        if now.duration_since(self.last_active) > self.timeout {
+            return false;
+        }
+        self.last_active = now;
+        true
+    }
+}
+
+// The logged in user's ID for commands given without an account
+pub fn fill_in<'a>(user: Option<&'a str>, command: &str, args: Vec<&'a str>) -> Vec<&'a str> {
+    match user {
+        Some(user) if args.is_empty() && USER_COMMANDS.contains(&command) => vec![user],
+        _ => args,
+    }
+}
+
+// Ends the session if it's timed out, before whatever's just happened is dealt with
+pub fn expire(session: &mut Option<Session>, now: Instant) {
+    if session.as_mut().is_some_and(|s| !s.touch(now)) {
+        let s = session.take().unwrap();
+        println!();
+        println!("Logged out {} after {} seconds idle", s.user, s.timeout.as_secs());
+    }
+}
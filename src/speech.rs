@@ -0,0 +1,66 @@
+use std::io::Write;
+use std::sync::{mpsc, Mutex};
+
+// Spoken announcements, so the till can be used without seeing the screen. Said one at a time
+// on their own thread.
+static SPEECH: Mutex<Option<mpsc::Sender<String>>> = Mutex::new(None);
+
+pub fn start(config: &crate::config::SpeechConfig) {
+    let command = config.command.clone();
+    let (tx, rx) = mpsc::channel::<String>();
+    *SPEECH.lock().unwrap() = Some(tx);
+    std::thread::spawn(move || {
+        let mut failed = false;
+        for text in rx {
+            if let Err(e) = speak(&command, &text) {
+                // Only complain the once, rather than at every announcement
+                if !failed {
+                    println!("Speech error: {}", e);
+                }
+                failed = true;
+            }
+        }
+    });
+}
+
+pub fn say(text: &str) {
+    if let Some(tx) = SPEECH.lock().unwrap().as_ref() {
+        let _ = tx.send(text.to_string());
+    }
+}
+
+// The text goes to the command's stdin, e.g. "espeak --stdin"
+fn speak(command: &str, text: &str) -> Result<(), String> {
+    let mut child = std::process::Command::new("sh")
+        .args(["-c", command])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .map_err(|e| format!("cannot run {}: {}", command, e))?;
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(text.as_bytes())
+        .map_err(|e| e.to_string())?;
+    let status = child.wait().map_err(|e| e.to_string())?;
+    if !status.success() {
+        return Err(format!("{} failed ({})", command, status));
+    }
+    Ok(())
+}
+
+// e.g. "1 pound 20", which reads out better than £1.20
+pub fn amount(pence: i64) -> String {
+    let sign = if pence < 0 { "minus " } else { "" };
+    let (pounds, pence) = (pence.unsigned_abs() / 100, pence.unsigned_abs() % 100);
+    let spoken = match (pounds, pence) {
+        (0, p) => format!("{} pence", p),
+        (1, 0) => "1 pound".to_string(),
+        (l, 0) => format!("{} pounds", l),
+        (1, p) => format!("1 pound {}", p),
+        (l, p) => format!("{} pounds {}", l, p),
+    };
+    format!("{}{}", sign, spoken)
+}
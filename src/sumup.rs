@@ -0,0 +1,107 @@
+use crate::config::SumUpConfig;
+
+const API: &str = "https://api.sumup.com/v0.1";
+
+#[derive(Debug, Deserialize)]
+struct CheckoutResponse {
+    data: CheckoutData,
+}
+
+#[derive(Debug, Deserialize)]
+struct CheckoutData {
+    client_transaction_id: String,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Status {
+    Pending,
+    Successful,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Payment {
+    pub status: Status,
+    // Shown on the customer's receipt, handy for matching up refunds
+    pub transaction_code: Option<String>,
+}
+
+pub struct Terminal {
+    client: reqwest::Client,
+    config: SumUpConfig,
+}
+
+impl Terminal {
+    pub fn new(config: &SumUpConfig) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            config: config.clone(),
+        }
+    }
+
+    // Sends the amount to the card reader, returning an ID to wait on
+    pub async fn checkout(&self, amount: u32, description: &str) -> Result<String, String> {
+        self.client
+            .post(format!(
+                "{}/merchants/{}/readers/{}/checkout",
+                API, self.config.merchant_code, self.config.reader_id
+            ))
+            .bearer_auth(&self.config.api_key)
+            .json(&serde_json::json!({
+                "total_amount": {
+                    "value": amount,
+                    "currency": "GBP",
+                    "minor_unit": 2,
+                },
+                "description": description,
+            }))
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| format!("cannot start card payment {}", e))?
+            .json::<CheckoutResponse>()
+            .await
+            .map(|r| r.data.client_transaction_id)
+            .map_err(|e| format!("invalid card reader response {}", e))
+    }
+
+    async fn payment(&self, id: &str) -> Result<Option<Payment>, String> {
+        let res = self
+            .client
+            .get(format!("{}/me/transactions", API))
+            .query(&[("client_transaction_id", id)])
+            .bearer_auth(&self.config.api_key)
+            .send()
+            .await
+            .map_err(|e| format!("cannot reach SumUp {}", e))?;
+        // The transaction only exists once the card has been presented
+        if res.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        res.error_for_status()
+            .map_err(|e| format!("cannot fetch card payment {}", e))?
+            .json::<Payment>()
+            .await
+            .map(Some)
+            .map_err(|e| format!("invalid card payment {}", e))
+    }
+
+    // Polls until the payment succeeds, fails, or the configured timeout runs out
+    pub async fn wait_for_payment(&self, id: &str) -> Result<Payment, String> {
+        let deadline =
+            std::time::Instant::now() + std::time::Duration::from_secs(self.config.timeout);
+        loop {
+            if std::time::Instant::now() > deadline {
+                return Err("card payment timed out".to_string());
+            }
+            tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+
+            match self.payment(id).await? {
+                Some(payment) if payment.status != Status::Pending => return Ok(payment),
+                _ => {}
+            }
+        }
+    }
+}
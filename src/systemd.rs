@@ -0,0 +1,26 @@
+use sd_notify::NotifyState;
+
+// These are all no-ops when we're not running under systemd, so errors aren't worth reporting
+
+pub fn ready() {
+    let _ = sd_notify::notify(false, &[NotifyState::Ready]);
+}
+
+pub fn stopping() {
+    let _ = sd_notify::notify(false, &[NotifyState::Stopping]);
+}
+
+pub fn watchdog() {
+    let _ = sd_notify::notify(false, &[NotifyState::Watchdog]);
+}
+
+// How often to ping the watchdog, if WatchdogSec is set on the unit.
+// Pinging at half the timeout gives some leeway for a slow tick.
+pub fn watchdog_interval() -> Option<std::time::Duration> {
+    let mut usec = 0;
+    if sd_notify::watchdog_enabled(false, &mut usec) {
+        Some(std::time::Duration::from_micros(usec) / 2)
+    } else {
+        None
+    }
+}
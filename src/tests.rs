@@ -0,0 +1,2350 @@
+use crate::{
+    acceptor, api,
+    bigtext, cli,
+    completion::Hintererer,
+    add_promo, adduser, apply_promo, cash_checkout, complete_cart, config, confirm_checkout, delete_promo,
+    delete_card, display, drawer, favourite,
+    guest_checkout, import_users, receipt, redeem_voucher, settle_tab,
+    history::{self, HistoryFilter, LineKind},
+    hotkeys,
+    labels, live, notify, pricelist, reports,
+    reader::{card_reader, CardEvent, Debouncer, MockReader},
+    scanner, script, secrets, session, speech, watch,
+    park, parse_amount, parse_time, payment_link, register_card, resume, scan_product, set_buyer, split_note, sumup, withdraw, FORBIDDEN_USERS,
+    READ_ONLY_COMMANDS,
+    webhooks::Webhooks,
+};
+use bank_core::{barcode::Barcode, cart::Cart, combo::Combo, db, products};
+use tokio::sync::mpsc;
+
+const PRODUCTS: &str = "\
+# Test products
+4029764001401 120 Club-Mate Granat
+011152431697 200 Ramune Citrus
+";
+
+fn setup() -> (db::DB, products::Products, Webhooks) {
+    let db = db::DB::in_memory(db::InnerDB::default()).unwrap();
+    let products = products::parse_products(PRODUCTS).unwrap();
+    (db, products, Webhooks::new(Vec::new()))
+}
+
+fn scan(products: &products::Products, cart: &mut Option<Cart>, code: &str) {
+    scan_product(products, &[], cart, &Barcode::try_parse(code).unwrap());
+}
+
+#[tokio::test]
+async fn scan_and_checkout_to_user() {
+    let (db, products, webhooks) = setup();
+    adduser(&db, &webhooks, &["alice"]);
+    db.deposit_user("alice", 500, db::DepositMethod::Cash, None).unwrap();
+
+    let mut cart = None;
+    scan(&products, &mut cart, "4029764001401");
+    scan(&products, &mut cart, "011152431697");
+    assert_eq!(cart.as_ref().unwrap().total(), 320);
+
+    let user = db.get_user("alice").unwrap();
+    complete_cart(&db, &config::Config::default(), &webhooks, user, &mut cart).await;
+    assert!(cart.is_none());
+
+    let (user, transactions) = db.get_user("alice").unwrap();
+    assert_eq!(user.balance, 180);
+    assert_eq!(transactions.len(), 2);
+    assert!(matches!(
+        transactions[1].transaction,
+        db::TransactionType::Purchase { total: 320, .. }
+    ));
+}
+
+#[tokio::test]
+async fn bad_barcodes_do_not_start_a_cart() {
+    let (_db, products, _webhooks) = setup();
+
+    let mut cart = None;
+    // Wrong check digit
+    scan(&products, &mut cart, "4029764001402");
+    // Valid, but not in the product list
+    scan(&products, &mut cart, "5000112637922");
+    assert!(cart.is_none());
+}
+
+#[tokio::test]
+async fn cash_checkout_records_cash_purchase() {
+    let (db, products, webhooks) = setup();
+
+    let mut cart = None;
+    cash_checkout(&db, &config::Config::default(), &webhooks, &mut cart, &[]);
+    assert!(db.transactions().unwrap().is_empty());
+
+    scan(&products, &mut cart, "4029764001401");
+    cash_checkout(&db, &config::Config::default(), &webhooks, &mut cart, &[]);
+    assert!(cart.is_none());
+
+    let transactions = db.transactions().unwrap();
+    assert_eq!(transactions.len(), 1);
+    assert!(matches!(transactions[0].actor, db::TransactionActor::Cash));
+}
+
+#[tokio::test]
+async fn deposits_require_an_existing_user() {
+    let (db, _products, webhooks) = setup();
+    assert!(db.deposit_user("bob", 100, db::DepositMethod::Cash, None).is_err());
+
+    adduser(&db, &webhooks, &["bob"]);
+    let user = db.deposit_user("bob", 100, db::DepositMethod::BankTransfer, None).unwrap();
+    assert_eq!(user.balance, 100);
+}
+
+#[tokio::test]
+async fn forbidden_user_ids_are_rejected() {
+    let (db, _products, webhooks) = setup();
+    adduser(&db, &webhooks, &["cash"]);
+    assert!(db.get_user("cash").is_none());
+}
+
+#[tokio::test]
+async fn register_card_and_checkout_by_tap() {
+    let (db, products, webhooks) = setup();
+    adduser(&db, &webhooks, &["carol"]);
+
+    let (card_tx, mut card_rx) = mpsc::channel(2);
+    card_tx.send(CardEvent::Card(vec![4, 23, 101, 200])).await.unwrap();
+    card_tx.send(CardEvent::Card(vec![4, 23, 101, 200])).await.unwrap();
+    register_card(&["carol", "work", "badge"], &db, &config::Config::default(), &mut card_rx).await;
+
+    let user = db.get_user_by_card("423101200").unwrap();
+    assert_eq!(user.0.id, "carol");
+
+    let mut cart = None;
+    scan(&products, &mut cart, "4029764001401");
+    complete_cart(&db, &config::Config::default(), &webhooks, user, &mut cart).await;
+    assert_eq!(db.get_user("carol").unwrap().0.balance, -120);
+
+    // The card being removed still proves it's carol
+    card_tx.send(CardEvent::Card(vec![4, 23, 101, 200])).await.unwrap();
+    delete_card(&["carol", "work", "badge"], &db, &mut card_rx).await;
+    assert!(db.get_user_by_card("423101200").is_none());
+}
+
+#[tokio::test]
+async fn cards_can_only_be_added_by_the_account_owner() {
+    let (db, _products, webhooks) = setup();
+    adduser(&db, &webhooks, &["erin"]);
+    db.add_card_to_user("erin", None::<String>, "1234").unwrap();
+
+    // Someone else's card can't stand in for erin's own
+    let (card_tx, mut card_rx) = mpsc::channel(3);
+    for _ in 0..3 {
+        card_tx.send(CardEvent::Card(vec![9, 9, 9])).await.unwrap();
+    }
+    register_card(&["erin"], &db, &config::Config::default(), &mut card_rx).await;
+    assert!(db.get_user_by_card("999").is_none());
+
+    let (card_tx, mut card_rx) = mpsc::channel(3);
+    card_tx.send(CardEvent::Card(vec![1, 2, 3, 4])).await.unwrap();
+    card_tx.send(CardEvent::Card(vec![9, 9, 9])).await.unwrap();
+    card_tx.send(CardEvent::Card(vec![9, 9, 9])).await.unwrap();
+    register_card(&["erin"], &db, &config::Config::default(), &mut card_rx).await;
+    assert_eq!(db.get_user_by_card("999").unwrap().0.id, "erin");
+}
+
+#[tokio::test]
+async fn cards_without_a_static_uid_are_refused() {
+    let (db, _products, webhooks) = setup();
+    adduser(&db, &webhooks, &["dave"]);
+
+    let (card_tx, mut card_rx) = mpsc::channel(2);
+    card_tx.send(CardEvent::Card(vec![8, 1, 2, 3, 4])).await.unwrap();
+    card_tx.send(CardEvent::Card(vec![8, 9, 9, 9, 9])).await.unwrap();
+    register_card(&["dave"], &db, &config::Config::default(), &mut card_rx).await;
+
+    assert!(db.get_user_by_card("81234").is_none());
+    assert!(db.get_user_by_card("89999").is_none());
+}
+
+#[test]
+fn history_keeps_out_who_bought_what() {
+    let filter = HistoryFilter::new(&config::HistoryConfig {
+        barcodes: false,
+        exclude: vec!["^deposit ".to_string()],
+    })
+    .unwrap();
+
+    assert!(filter.records("products", LineKind::Command));
+    assert!(!filter.records("alice", LineKind::UserLookup));
+    assert!(!filter.records("4029764001401", LineKind::Barcode));
+    assert!(!filter.records("deposit alice", LineKind::Command));
+
+    // Whoever scrolls back later sees the command, not who it was for
+    assert_eq!(history::saved("setpin alice"), "setpin");
+    assert_eq!(history::saved("exportuser alice"), "exportuser");
+    assert_eq!(history::saved("pricelist drinks"), "pricelist drinks");
+}
+
+#[tokio::test]
+async fn standing_orders_catch_up_without_double_charging() {
+    let (db, _products, webhooks) = setup();
+    adduser(&db, &webhooks, &["erin"]);
+    let order = db
+        .add_standing_order("erin", 300, db::Period::Weekly, "fridge levy")
+        .unwrap();
+
+    // Three weeks and a bit later, the first charge and three missed ones are taken
+    let later = order.next_due + chrono::Duration::weeks(3) + chrono::Duration::days(1);
+    assert_eq!(db.apply_standing_orders(later).unwrap().len(), 4);
+    assert!(db.apply_standing_orders(later).unwrap().is_empty());
+    assert_eq!(db.get_user("erin").unwrap().0.balance, -1200);
+
+    db.cancel_standing_order(&order.id.to_string()[..8]).unwrap();
+    assert!(db.standing_orders().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn monthly_orders_keep_their_day_after_a_short_month() {
+    use chrono::TimeZone;
+    let (db, _products, webhooks) = setup();
+    adduser(&db, &webhooks, &["erin"]);
+    db.add_standing_order("erin", 500, db::Period::Monthly, "locker").unwrap();
+
+    let mut data = db.snapshot().unwrap();
+    let jan31 = chrono::Utc.with_ymd_and_hms(2025, 1, 31, 12, 0, 0).unwrap();
+    data.standing_orders[0].next_due = jan31;
+    data.standing_orders[0].anchor = Some(jan31);
+    let db = db::DB::in_memory(data).unwrap();
+
+    let taken = db
+        .apply_standing_orders(chrono::Utc.with_ymd_and_hms(2025, 3, 31, 13, 0, 0).unwrap())
+        .unwrap()
+        .iter()
+        .map(|t| t.timestamp.date_naive().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(taken, ["2025-01-31", "2025-02-28", "2025-03-31"]);
+}
+
+#[tokio::test]
+async fn vouchers_can_only_be_redeemed_once() {
+    let (db, _products, webhooks) = setup();
+    adduser(&db, &webhooks, &["fran"]);
+    adduser(&db, &webhooks, &["gus"]);
+    let voucher = db.create_voucher(250, "treasurer").unwrap();
+
+    redeem_voucher(&db, &webhooks, &[&voucher.code.to_lowercase(), "fran"]);
+    redeem_voucher(&db, &webhooks, &[&voucher.code, "gus"]);
+
+    assert_eq!(db.get_user("fran").unwrap().0.balance, 250);
+    assert_eq!(db.get_user("gus").unwrap().0.balance, 0);
+    assert!(db.get_voucher(&voucher.code).unwrap().redeemed.is_some());
+    assert_eq!(db.get_voucher(&voucher.code).unwrap().issued_by.as_deref(), Some("treasurer"));
+}
+
+#[tokio::test]
+async fn promo_codes_discount_the_cart_until_used_up() {
+    let (db, products, webhooks) = setup();
+    adduser(&db, &webhooks, &["hal"]);
+    add_promo(&db, &["welcome", "25%", "1"]);
+
+    let mut cart = None;
+    scan(&products, &mut cart, "011152431697");
+    apply_promo(&db, &mut cart, &["WELCOME"]);
+    assert_eq!(cart.as_ref().unwrap().total(), 150);
+
+    let user = db.get_user("hal").unwrap();
+    complete_cart(&db, &config::Config::default(), &webhooks, user, &mut cart).await;
+    let (user, transactions) = db.get_user("hal").unwrap();
+    assert_eq!(user.balance, -150);
+    assert!(matches!(
+        &transactions[0].transaction,
+        db::TransactionType::Purchase { promo: Some(code), discount: 50, .. } if code == "WELCOME"
+    ));
+
+    scan(&products, &mut cart, "011152431697");
+    apply_promo(&db, &mut cart, &["welcome"]);
+    assert!(cart.as_ref().unwrap().promo.is_none());
+}
+
+#[tokio::test]
+async fn a_replaced_promo_code_charges_its_new_discount() {
+    let (db, products, webhooks) = setup();
+    adduser(&db, &webhooks, &["hal"]);
+    add_promo(&db, &["welcome", "50%"]);
+
+    let mut cart = None;
+    scan(&products, &mut cart, "011152431697");
+    apply_promo(&db, &mut cart, &["welcome"]);
+
+    // Changed while the cart was still open
+    delete_promo(&db, &["welcome"]);
+    add_promo(&db, &["welcome", "10%"]);
+
+    let user = db.get_user("hal").unwrap();
+    complete_cart(&db, &config::Config::default(), &webhooks, user, &mut cart).await;
+    assert_eq!(db.get_user("hal").unwrap().0.balance, -180);
+}
+
+#[tokio::test]
+async fn cash_can_be_paid_in_other_currencies() {
+    let (db, products, webhooks) = setup();
+    let config = config::Config {
+        currencies: vec![config::CurrencyConfig {
+            code: "EUR".to_string(),
+            symbol: "€".to_string(),
+            rate: 11_700,
+            minor_digits: 2,
+            rounding: 0,
+        }],
+        ..Default::default()
+    };
+
+    let mut cart = None;
+    scan(&products, &mut cart, "4029764001401");
+    cash_checkout(&db, &config, &webhooks, &mut cart, &["eur"]);
+
+    let transactions = db.transactions().unwrap();
+    assert!(matches!(
+        &transactions[0].transaction,
+        db::TransactionType::Purchase { total: 120, tendered: Some(t), .. }
+            if t.currency == "EUR" && t.amount == 141
+    ));
+}
+
+#[test]
+fn exchange_rates_convert_exactly() {
+    let usd: config::CurrencyConfig = ron::from_str(r#"(code: "USD", symbol: "$", rate: 1.1)"#).unwrap();
+    let jpy: config::CurrencyConfig =
+        ron::from_str(r#"(code: "JPY", symbol: "¥", rate: 190.5, minor_digits: 0)"#).unwrap();
+
+    // Floats give 110.00000000000001 here, which rounded up to 111
+    assert_eq!(usd.convert(100), 110);
+    assert_eq!(usd.format(usd.convert(100)), "$1.10");
+    assert_eq!(jpy.convert(120), 229);
+    assert_eq!(jpy.format(jpy.convert(120)), "¥229");
+}
+
+#[test]
+fn products_and_periods_for_tax_reports() {
+    let products = products::parse_products("5000112637922 90 tax=standard Coca-Cola\n").unwrap();
+    let product = products.values().next().unwrap();
+    assert_eq!(product.tax.as_deref(), Some("standard"));
+    assert_eq!(product.name, "Coca-Cola");
+
+    let q4 = crate::reports::parse_period("2024-Q4").unwrap();
+    assert_eq!(q4.start.to_rfc3339(), "2024-10-01T00:00:00+00:00");
+    assert_eq!(q4.end.to_rfc3339(), "2025-01-01T00:00:00+00:00");
+    assert!(crate::reports::parse_period("2024-13").is_err());
+}
+
+#[tokio::test]
+async fn ledger_export_balances_each_entry() {
+    let (db, products, webhooks) = setup();
+    adduser(&db, &webhooks, &["iris.b"]);
+    db.deposit_user("iris.b", 500, db::DepositMethod::BankTransfer, None).unwrap();
+    let mut cart = None;
+    scan(&products, &mut cart, "4029764001401");
+    complete_cart(&db, &config::Config::default(), &webhooks, db.get_user("iris.b").unwrap(), &mut cart).await;
+
+    let out = crate::ledger::export(
+        &db.transactions().unwrap(),
+        &config::LedgerConfig::default(),
+        crate::ledger::Format::Beancount,
+    );
+    assert!(out.contains("open Liabilities:SnackBank:Users:Iris-b"));
+    assert!(out.contains("    Assets:Bank  5.00 GBP\n    Liabilities:SnackBank:Users:Iris-b  -5.00 GBP"));
+    assert!(out.contains("    Liabilities:SnackBank:Users:Iris-b  1.20 GBP\n    Income:SnackBank:Sales  -1.20 GBP"));
+}
+
+#[tokio::test]
+async fn guest_tabs_are_settled_by_a_sponsor() {
+    let (db, products, webhooks) = setup();
+    adduser(&db, &webhooks, &["jo"]);
+
+    let mut cart = None;
+    scan(&products, &mut cart, "4029764001401");
+    guest_checkout(&db, &webhooks, &mut cart, &["workshop", "attendees"]);
+    scan(&products, &mut cart, "011152431697");
+    guest_checkout(&db, &webhooks, &mut cart, &["workshop", "attendees"]);
+    assert_eq!(db.tabs().unwrap().get("workshop attendees"), Some(&320));
+
+    // A frozen sponsor can't take it on
+    db.freeze("jo", "lost card").unwrap();
+    let (_card_tx, mut card_rx) = mpsc::channel(1);
+    settle_tab(&db, &["jo", "workshop", "attendees"], &mut card_rx).await;
+    assert_eq!(db.tabs().unwrap().get("workshop attendees"), Some(&320));
+
+    db.unfreeze("jo").unwrap();
+    settle_tab(&db, &["jo", "workshop", "attendees"], &mut card_rx).await;
+    assert!(db.tabs().unwrap().is_empty());
+    assert_eq!(db.get_user("jo").unwrap().0.balance, -320);
+}
+
+#[tokio::test]
+async fn members_only_see_their_own_transactions() {
+    let (db, products, webhooks) = setup();
+    adduser(&db, &webhooks, &["kim"]);
+    adduser(&db, &webhooks, &["lou"]);
+    db.add_group("fridgefund", &["kim"]).unwrap();
+    db.deposit_user("lou", 500, db::DepositMethod::Cash, None).unwrap();
+    db.deposit_user("fridgefund", 500, db::DepositMethod::Cash, None).unwrap();
+
+    let transactions = db.transactions().unwrap();
+    let kim = crate::Shown::Only("kim".to_string());
+    assert!(!transactions.iter().any(|t| kim.includes(t)));
+    assert!(transactions.iter().all(|t| crate::Shown::Everyone.includes(t)));
+
+    let mut cart = Cart::new();
+    cart.products.push(products.values().next().unwrap().clone());
+    db.apply_cart_to_group("fridgefund", "kim", &cart).unwrap();
+    assert_eq!(db.transactions().unwrap().iter().filter(|t| kim.includes(t)).count(), 1);
+}
+
+#[tokio::test]
+async fn group_members_spend_from_a_shared_balance() {
+    let (db, products, webhooks) = setup();
+    adduser(&db, &webhooks, &["kim"]);
+    adduser(&db, &webhooks, &["lou"]);
+    db.add_group("fridgefund", &["kim"]).unwrap();
+    db.deposit_user("fridgefund", 1000, db::DepositMethod::Cash, None).unwrap();
+
+    let mut cart = None;
+    scan(&products, &mut cart, "011152431697");
+    assert!(db.apply_cart_to_group("fridgefund", "lou", cart.as_ref().unwrap()).is_err());
+    db.add_group_member("fridgefund", "lou").unwrap();
+
+    // Personal accounts can't be opened up to others
+    assert!(db.add_group_member("kim", "lou").is_err());
+
+    db.freeze("lou", "unpaid debt").unwrap();
+    assert!(db.apply_cart_to_group("fridgefund", "lou", cart.as_ref().unwrap()).is_err());
+    db.unfreeze("lou").unwrap();
+    let group = db.apply_cart_to_group("fridgefund", "lou", cart.as_ref().unwrap()).unwrap();
+    assert_eq!(group.balance, 800);
+    assert_eq!(db.get_user("lou").unwrap().0.balance, 0);
+
+    let (_, transactions) = db.get_user("fridgefund").unwrap();
+    assert!(matches!(
+        &transactions[1].actor,
+        db::TransactionActor::Group { member, .. } if member == "lou"
+    ));
+}
+
+#[tokio::test]
+async fn frozen_accounts_cannot_buy() {
+    let (db, products, webhooks) = setup();
+    adduser(&db, &webhooks, &["max"]);
+    db.freeze("max", "lost card").unwrap();
+
+    let mut cart = None;
+    scan(&products, &mut cart, "4029764001401");
+    complete_cart(&db, &config::Config::default(), &webhooks, db.get_user("max").unwrap(), &mut cart).await;
+    assert!(cart.is_some());
+    assert_eq!(db.get_user("max").unwrap().0.balance, 0);
+
+    db.unfreeze("max").unwrap();
+    complete_cart(&db, &config::Config::default(), &webhooks, db.get_user("max").unwrap(), &mut cart).await;
+    assert!(cart.is_none());
+}
+
+#[tokio::test]
+async fn deposit_notes_are_kept_with_the_transaction() {
+    let (db, _, webhooks) = setup();
+    adduser(&db, &webhooks, &["nat"]);
+    let (args, note) = split_note(&["nat", "--note", "\"birthday", "money\""]);
+    assert_eq!(args, ["nat"]);
+    db.deposit_user(args[0], 300, db::DepositMethod::Cash, note.as_deref()).unwrap();
+
+    let (_, transactions) = db.get_user("nat").unwrap();
+    assert_eq!(transactions[0].note.as_deref(), Some("birthday money"));
+    assert_eq!(split_note(&["nat"]).1, None);
+}
+
+#[tokio::test]
+async fn adjustments_correct_balances_both_ways() {
+    let (db, _, webhooks) = setup();
+    adduser(&db, &webhooks, &["oli"]);
+    db.adjust_user("oli", 250, "refund for a broken can", "q", None).unwrap();
+    let user = db.adjust_user("oli", -100, "charged twice", "q", Some("checked the logs")).unwrap();
+    assert_eq!(user.balance, 150);
+    assert!(db.adjust_user("nobody", 100, "typo", "q", None).is_err());
+
+    let (_, transactions) = db.get_user("oli").unwrap();
+    assert!(matches!(
+        &transactions[1].transaction,
+        db::TransactionType::Adjustment { amount: -100, admin, .. } if admin == "q"
+    ));
+}
+
+#[test]
+fn price_overrides_are_kept_on_the_cart_line() {
+    let products = products::parse_products(PRODUCTS).unwrap();
+    let mut cart = None;
+    scan(&products, &mut cart, "4029764001401");
+    scan(&products, &mut cart, "4029764001401");
+    let barcode = Barcode::try_parse("4029764001401").unwrap();
+
+    let cart = cart.as_mut().unwrap();
+    cart.override_price(&barcode, 60, "dented can", "q").unwrap();
+    assert_eq!(cart.total(), 180);
+    cart.override_price(&barcode, 0, "past its date", "q").unwrap();
+    assert_eq!(cart.total(), 60);
+    assert!(cart.override_price(&barcode, 10, "third time", "q").is_err());
+    assert_eq!(cart.products[1].price_override.as_ref().unwrap().original, 120);
+}
+
+#[tokio::test]
+async fn write_offs_leave_balances_alone() {
+    let (db, products, webhooks) = setup();
+    adduser(&db, &webhooks, &["pat"]);
+    let product = &products[&Barcode::try_parse("011152431697").unwrap()];
+    db.write_off(product, 3, "dropped the box").unwrap();
+
+    let transactions = db.transactions().unwrap();
+    assert!(matches!(
+        &transactions[0],
+        db::Transaction { actor: db::TransactionActor::Stock, transaction: db::TransactionType::WriteOff { quantity: 3, .. }, .. }
+    ));
+    assert!(db.get_user("pat").unwrap().1.is_empty());
+}
+
+#[test]
+fn combos_take_the_best_deal_without_sharing_products() {
+    let products = products::parse_products(
+        "4029764001401 120 category=drink Club-Mate Granat\n\
+         011152431697 200 category=drink Ramune Citrus\n\
+         5000159461122 85 category=snack Snickers\n",
+    )
+    .unwrap();
+    let combos = [Combo {
+        name: "Meal deal".to_string(),
+        categories: vec!["drink".to_string(), "snack".to_string()],
+        price: 180,
+    }];
+    let mut cart = Cart::new();
+    for code in ["4029764001401", "011152431697", "5000159461122"] {
+        cart.products.push(products[&Barcode::try_parse(code).unwrap()].clone());
+    }
+    cart.apply_combos(&combos);
+
+    // The Ramune and Snickers make the deal, the Club-Mate is full price
+    assert_eq!(cart.combos.len(), 1);
+    assert_eq!(cart.combos[0].discount, 105);
+    assert_eq!(cart.total(), 300);
+}
+
+#[test]
+fn parked_carts_can_be_resumed_in_any_order() {
+    let products = products::parse_products(PRODUCTS).unwrap();
+    let (mut cart, mut parked) = (None, Vec::new());
+    scan(&products, &mut cart, "4029764001401");
+    park(&mut cart, &mut parked);
+    assert!(cart.is_none());
+
+    scan(&products, &mut cart, "011152431697");
+    resume(&mut cart, &mut parked, &["1"]);
+    assert_eq!(cart.as_ref().unwrap().total(), 120);
+    assert_eq!(parked.len(), 1);
+    assert_eq!(parked[0].total(), 200);
+
+    resume(&mut cart, &mut parked, &["2"]);
+    assert_eq!(cart.as_ref().unwrap().total(), 120);
+}
+
+#[tokio::test]
+async fn account_discounts_apply_after_everything_else() {
+    let (db, products, webhooks) = setup();
+    adduser(&db, &webhooks, &["quinn"]);
+    db.set_discount("quinn", Some(25)).unwrap();
+    assert!(db.set_discount("quinn", Some(101)).is_err());
+
+    let mut cart = None;
+    scan(&products, &mut cart, "011152431697");
+    complete_cart(&db, &config::Config::default(), &webhooks, db.get_user("quinn").unwrap(), &mut cart).await;
+
+    let (user, transactions) = db.get_user("quinn").unwrap();
+    assert_eq!(user.balance, -150);
+    assert!(matches!(
+        &transactions[0].transaction,
+        db::TransactionType::Purchase { total: 150, user_discount: 50, .. }
+    ));
+}
+
+#[test]
+fn shelf_label_barcodes_are_drawn_as_ean() {
+    let ean13 = labels::modules(&Barcode::try_parse("4029764001401").unwrap()).unwrap();
+    assert_eq!(ean13.len(), 95);
+    // Start guard, then the second digit 0 in L code
+    let start = ean13[..10].iter().map(|m| if *m { '1' } else { '0' }).collect::<String>();
+    assert_eq!(start, "1010001101");
+
+    let ean8 = labels::modules(&Barcode::try_parse("96385074").unwrap()).unwrap();
+    assert_eq!(ean8.len(), 67);
+    assert!(labels::modules(&Barcode::try_parse("14029764001408").unwrap()).is_none());
+}
+
+#[test]
+fn price_list_groups_by_category_with_other_last() {
+    let products = products::parse_products(
+        "4029764001401 120 category=drink Club-Mate Granat\n\
+         5000159461122 85 Snickers\n\
+         011152431697 200 category=drink Ramune Citrus\n",
+    )
+    .unwrap();
+    let categories = pricelist::categorised(&products)
+        .into_iter()
+        .map(|(c, p)| (c, p.iter().map(|p| p.name.as_str()).collect::<Vec<_>>()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        categories,
+        [
+            ("Drink".to_string(), vec!["Club-Mate Granat", "Ramune Citrus"]),
+            ("Other".to_string(), vec!["Snickers"]),
+        ]
+    );
+}
+
+#[test]
+fn product_csv_imports_update_and_add_lines() {
+    let import = products::parse_products_csv(
+        "barcode,price,name,category\n\
+         4029764001401,1.50,Club-Mate Granat,drink\n\
+         5000159461122,0.85,Snickers\n\
+         5000159461123,0.85,Bad check digit\n\
+         011152431697,2.00,Ramune Citrus\n",
+    );
+    assert_eq!(import.errors.len(), 1);
+    assert!(import.errors[0].starts_with("line 4:"));
+
+    let (merged, summary) = products::merge_products(PRODUCTS, &import.products);
+    assert_eq!((summary.inserted, summary.updated, summary.unchanged), (1, 1, 1));
+    assert!(merged.starts_with("# Test products\n4029764001401 150 category=drink Club-Mate Granat\n"));
+    let merged = products::parse_products(&merged).unwrap();
+    assert_eq!(merged.len(), 3);
+    assert_eq!(merged[&Barcode::try_parse("5000159461122").unwrap()].price, 85);
+}
+
+#[test]
+fn open_price_products_are_priced_when_sold() {
+    let products = products::parse_products("2000000000008 open plu=99 Misc\n").unwrap();
+    let misc = products::by_plu(&products, "99").unwrap();
+    assert!(misc.open_price);
+    assert_eq!(misc.disp_line(), "Misc (any price)");
+
+    let line = misc.open_line(75, Some("jam"));
+    assert_eq!(line.disp_line(), "Misc: jam (£0.75)");
+    let mut cart = Cart::new();
+    cart.products.push(line);
+    assert_eq!(cart.total(), 75);
+
+    // Written back out the same way
+    let (merged, _) = products::merge_products("", &[("2000000000008".to_string(), misc.clone())]);
+    assert_eq!(merged, "2000000000008 open plu=99 Misc\n");
+}
+
+#[test]
+fn hints_put_the_most_used_completion_first() {
+    let mut hinter = Hintererer::new(&["delia".to_string()]);
+    assert_eq!(hinter.suggestions("dep"), ["deposit", "deposits"]);
+
+    hinter.record_use("delia");
+    hinter.record_use("delia");
+    hinter.record_use("deposits");
+    assert_eq!(hinter.suggestions("de")[..2], ["delia", "deposits"]);
+    // Barcodes only become candidates once they've been scanned
+    hinter.record_use("5000112637922");
+    assert_eq!(hinter.suggestions("500"), ["5000112637922"]);
+}
+
+#[test]
+fn arguments_complete_from_context() {
+    let (_, products, _) = setup();
+    let mut hinter = Hintererer::new(&["delia".to_string(), "dan".to_string()]);
+    hinter.set_products(&products);
+    hinter.record_use("deposit");
+
+    assert_eq!(hinter.suggestions("deposit d"), ["dan", "delia"]);
+    assert_eq!(hinter.suggestions("adjust dep"), Vec::<String>::new());
+    assert_eq!(hinter.suggestions("restock ramune"), ["11152431697"]);
+    assert_eq!(hinter.suggestions("restock 4029"), ["4029764001401"]);
+    assert!(hinter.suggestions("deposit delia 5").is_empty());
+}
+
+#[tokio::test]
+async fn registered_cards_wait_for_approval_when_configured() {
+    let (db, _products, webhooks) = setup();
+    adduser(&db, &webhooks, &["rene"]);
+    let config = config::Config {
+        approve_cards: true,
+        ..Default::default()
+    };
+
+    let (card_tx, mut card_rx) = mpsc::channel(2);
+    card_tx.send(CardEvent::Card(vec![4, 5, 6, 7, 8])).await.unwrap();
+    card_tx.send(CardEvent::Card(vec![4, 5, 6, 7, 8])).await.unwrap();
+    register_card(&["rene"], &db, &config, &mut card_rx).await;
+    assert!(db.get_user_by_card("45678").is_none());
+    assert_eq!(db.pending_cards().unwrap().len(), 1);
+
+    db.review_card("45678", true).unwrap();
+    assert_eq!(db.get_user_by_card("45678").unwrap().0.id, "rene");
+    assert!(db.pending_cards().unwrap().is_empty());
+    assert!(db.review_card("45678", false).is_err());
+
+    // All-zero bytes give an ID shorter than the usual name
+    assert_eq!(db.request_card("rene", None::<String>, "0000").unwrap().name, "0000");
+}
+
+#[tokio::test]
+async fn spending_limits_block_checkout_unless_overridden() {
+    let (db, products, webhooks) = setup();
+    adduser(&db, &webhooks, &["sky"]);
+    db.set_limits("sky", Some(300), Some(150)).unwrap();
+
+    let mut cart = None;
+    scan(&products, &mut cart, "011152431697");
+    complete_cart(&db, &config::Config::default(), &webhooks, db.get_user("sky").unwrap(), &mut cart).await;
+    assert!(cart.is_some());
+
+    cart = None;
+    scan(&products, &mut cart, "4029764001401");
+    complete_cart(&db, &config::Config::default(), &webhooks, db.get_user("sky").unwrap(), &mut cart).await;
+    assert!(cart.is_none());
+
+    // Another 1.20 today would go over the daily limit
+    scan(&products, &mut cart, "4029764001401");
+    scan(&products, &mut cart, "4029764001401");
+    assert!(db.apply_cart_to_user("sky", cart.as_ref().unwrap()).is_err());
+    cart.as_mut().unwrap().limit_override = Some("q".to_string());
+    complete_cart(&db, &config::Config::default(), &webhooks, db.get_user("sky").unwrap(), &mut cart).await;
+    assert!(cart.is_none());
+    assert_eq!(db.get_user("sky").unwrap().0.balance, -360);
+}
+
+#[tokio::test]
+async fn voided_purchases_dont_count_towards_the_daily_limit() {
+    let (db, products, webhooks) = setup();
+    adduser(&db, &webhooks, &["sky"]);
+    db.set_limits("sky", Some(200), None).unwrap();
+
+    let mut cart = None;
+    scan(&products, &mut cart, "4029764001401");
+    db.apply_cart_to_user("sky", cart.as_ref().unwrap()).unwrap();
+    let mis_scan = cart.take().unwrap().key;
+
+    scan(&products, &mut cart, "4029764001401");
+    assert!(db.apply_cart_to_user("sky", cart.as_ref().unwrap()).is_err());
+    db.void_purchase(mis_scan, "sky", chrono::Duration::minutes(5)).unwrap();
+    assert!(db.apply_cart_to_user("sky", cart.as_ref().unwrap()).is_ok());
+}
+
+#[test]
+fn checkout_only_asks_for_confirmation_when_configured() {
+    let mut user = db::User::new("tam");
+    user.balance = 500;
+    let mut checkout = config::CheckoutConfig::default();
+    assert!(confirm_checkout(&checkout, &user, 900));
+
+    // Still in credit afterwards, so no need to ask
+    checkout.confirm = config::ConfirmCheckout::WhenNegative;
+    assert!(confirm_checkout(&checkout, &user, 500));
+}
+
+#[tokio::test]
+async fn projected_balance_follows_the_cart() {
+    let (db, products, webhooks) = setup();
+    adduser(&db, &webhooks, &["uma"]);
+    db.deposit_user("uma", 500, db::DepositMethod::Cash, None).unwrap();
+
+    let mut cart = None;
+    scan(&products, &mut cart, "4029764001401");
+    assert_eq!(cart.as_ref().unwrap().projected_balance(), None);
+    set_buyer(&db, &mut cart, &["uma"]);
+    assert_eq!(cart.as_ref().unwrap().projected_balance(), Some(380));
+    scan(&products, &mut cart, "011152431697");
+    assert_eq!(cart.as_ref().unwrap().projected_balance(), Some(180));
+}
+
+#[tokio::test]
+async fn receipts_list_the_purchase() {
+    let (db, products, webhooks) = setup();
+    adduser(&db, &webhooks, &["val"]);
+    let mut cart = None;
+    scan(&products, &mut cart, "4029764001401");
+    let user = db.apply_cart_to_user("val", cart.as_ref().unwrap()).unwrap();
+
+    let (_, transactions) = db.get_user("val").unwrap();
+    let text = receipt(&transactions[0], Some(&user));
+    assert!(text.contains("Club-Mate Granat £1.20\nTotal £1.20\nBalance -£1.20\n"));
+    assert!(text.ends_with(&transactions[0].id.to_string()));
+}
+
+#[tokio::test]
+async fn cash_refunds_only_happen_once() {
+    let (db, products, webhooks) = setup();
+    adduser(&db, &webhooks, &["wes"]);
+    let deposit = db.deposit_user("wes", 500, db::DepositMethod::Cash, None).unwrap();
+    assert_eq!(deposit.balance, 500);
+    let mut cart = None;
+    scan(&products, &mut cart, "4029764001401");
+    db.apply_cart_to_user("wes", cart.as_ref().unwrap()).unwrap();
+
+    let transactions = db.transactions().unwrap();
+    let (deposit_id, purchase_id) = (transactions[0].id.to_string(), transactions[1].id.to_string());
+    // Card purchases never went through the cash box
+    assert!(db.refund_to_cash(&purchase_id, None).is_err());
+
+    db.refund_to_cash(&deposit_id[..8], Some("changed their mind")).unwrap();
+    assert_eq!(db.get_user("wes").unwrap().0.balance, -120);
+    assert!(db.refund_to_cash(&deposit_id, None).is_err());
+}
+
+#[tokio::test]
+async fn withdrawals_cannot_overdraw() {
+    let (db, _, webhooks) = setup();
+    adduser(&db, &webhooks, &["xan"]);
+    db.deposit_user("xan", 500, db::DepositMethod::BankTransfer, None).unwrap();
+
+    assert!(db.withdraw_user("xan", 600, None).is_err());
+    assert_eq!(db.withdraw_user("xan", 500, None).unwrap().balance, 0);
+    assert!(matches!(
+        db.get_user("xan").unwrap().1[1].transaction,
+        db::TransactionType::Withdrawal { amount: 500 }
+    ));
+}
+
+#[tokio::test]
+async fn oversized_withdrawals_cant_wrap_into_credit() {
+    let (db, _, webhooks) = setup();
+    adduser(&db, &webhooks, &["xan"]);
+    db.deposit_user("xan", 500, db::DepositMethod::BankTransfer, None).unwrap();
+
+    assert_eq!(parse_amount("2.5"), Some(250));
+    assert_eq!(parse_amount("£2.50"), Some(250));
+    assert_eq!(parse_amount("21474836.47"), Some(i32::MAX as u32));
+    assert_eq!(parse_amount("21474836.48"), None);
+    assert_eq!(parse_amount("30000000"), None);
+    assert_eq!(parse_amount("inf"), None);
+    assert_eq!(parse_amount("1.005"), None);
+
+    assert!(db.withdraw_user("xan", 3_000_000_000, None).is_err());
+    assert!(db.withdraw_user("xan", 1 << 31, None).is_err());
+    assert_eq!(db.get_user("xan").unwrap().0.balance, 500);
+
+    // No PIN or card, so nothing to stop someone else taking the cash
+    let (_card_tx, mut card_rx) = mpsc::channel(1);
+    withdraw(&db, &["xan", "1"], &mut card_rx).await;
+    assert_eq!(db.get_user("xan").unwrap().0.balance, 500);
+}
+
+#[tokio::test]
+async fn adjustments_too_big_for_a_balance_are_refused() {
+    let (db, _, webhooks) = setup();
+    adduser(&db, &webhooks, &["yul"]);
+    let (_card_tx, mut card_rx) = mpsc::channel(1);
+
+    // Both are turned away before anyone is asked to approve them
+    for amount in ["+30000000", "-2147483.648"] {
+        crate::adjust(&db, &config::Config::default(), &webhooks, &["yul", amount, "typo"], &mut card_rx).await;
+    }
+    assert_eq!(db.get_user("yul").unwrap().1.len(), 0);
+}
+
+#[test]
+fn sumup_payment_status() {
+    let payment = serde_json::from_str::<sumup::Payment>(
+        r#"{"id": "abc", "status": "SUCCESSFUL", "transaction_code": "TEENSY", "amount": 5.0}"#,
+    )
+    .unwrap();
+    assert_eq!(payment.status, sumup::Status::Successful);
+    assert_eq!(payment.transaction_code.as_deref(), Some("TEENSY"));
+}
+
+#[test]
+fn fsck_reports_inconsistencies() {
+    let (db, _, webhooks) = setup();
+    adduser(&db, &webhooks, &["yara"]);
+    adduser(&db, &webhooks, &["zed"]);
+    db.deposit_user("yara", 500, db::DepositMethod::Cash, None).unwrap();
+    db.add_card_to_user("yara", Some("card"), "04aabbccdd").unwrap();
+    assert!(db.fsck().unwrap().is_empty());
+
+    db.add_card_to_user("zed", Some("card"), "04aabbccdd").unwrap();
+    db.deposit_user("zed", 100, db::DepositMethod::Cash, None).unwrap();
+    let mut data = db::InnerDB {
+        transactions: db.transactions().unwrap(),
+        ..Default::default()
+    };
+    data.transactions[1].actor = db::TransactionActor::User("ghost".to_string());
+    for user in db.users().unwrap() {
+        data.users.insert(user.id.clone(), user);
+    }
+    let db = db::DB::in_memory(data).unwrap();
+
+    let problems = db.fsck().unwrap();
+    assert_eq!(problems.len(), 3, "{:?}", problems);
+    assert!(problems[0].contains("unknown user ghost"));
+    assert!(problems[1].starts_with("user zed has a balance of £1.00"));
+    assert!(problems[2].ends_with("04aabbccdd is registered to yara, zed"));
+}
+
+#[test]
+fn balances_rebuild_from_transactions() {
+    let (db, _, webhooks) = setup();
+    adduser(&db, &webhooks, &["yara"]);
+    db.deposit_user("yara", 500, db::DepositMethod::Cash, None).unwrap();
+    db.adjust_user("yara", -120, "lost change", "admin", None).unwrap();
+
+    let mut data = db::InnerDB {
+        transactions: db.transactions().unwrap(),
+        ..Default::default()
+    };
+    let mut yara = db.get_user("yara").unwrap().0;
+    yara.balance = 0;
+    data.users.insert("yara".to_string(), yara);
+    let db = db::DB::in_memory(data).unwrap();
+
+    assert_eq!(
+        db.rebuild_balances().unwrap(),
+        vec![("yara".to_string(), 0, 380)]
+    );
+    assert!(db.fsck().unwrap().is_empty());
+    assert!(db.rebuild_balances().unwrap().is_empty());
+}
+
+#[test]
+fn balances_are_projected_from_the_log() {
+    let mut data = db::InnerDB::default();
+    data.apply(db::Transaction {
+        id: uuid::Uuid::new_v4(),
+        timestamp: chrono::Utc::now(),
+        actor: db::TransactionActor::User("vic".to_string()),
+        transaction: db::TransactionType::Deposit {
+            amount: 700,
+            method: db::DepositMethod::Cash,
+            fee: 0,
+        },
+        note: None,
+        till: None,
+    });
+    assert_eq!(data.users["vic"].balance, 700);
+
+    data.users.get_mut("vic").unwrap().balance = 12;
+    assert_eq!(data.project(), vec![("vic".to_string(), 12, 700)]);
+    assert!(data.project().is_empty());
+}
+
+#[test]
+fn journal_replays_unsaved_transactions() {
+    let deposit = db::Transaction {
+        id: uuid::Uuid::new_v4(),
+        timestamp: chrono::Utc::now(),
+        actor: db::TransactionActor::User("wyn".to_string()),
+        transaction: db::TransactionType::Deposit {
+            amount: 250,
+            method: db::DepositMethod::Cash,
+            fee: 0,
+        },
+        note: None,
+        till: None,
+    };
+    let journal = format!("{}\n(id: \"cut short", ron::to_string(&deposit).unwrap());
+
+    let mut data = db::InnerDB::default();
+    assert_eq!(data.replay_journal(&journal, &["peer".to_string()]), 1);
+    assert_eq!(data.users["wyn"].balance, 250);
+    assert_eq!(data.outbox["peer"].len(), 1);
+
+    // Already saved, so nothing to do
+    assert_eq!(data.replay_journal(&journal, &[]), 0);
+}
+
+#[tokio::test]
+async fn journal_replay_brings_back_used_vouchers_and_promos() {
+    let (db, products, webhooks) = setup();
+    adduser(&db, &webhooks, &["wyn"]);
+    add_promo(&db, &["welcome", "25%", "1"]);
+    let voucher = db.create_voucher(250, "treasurer").unwrap();
+    let saved = db.snapshot().unwrap();
+
+    // Both used, then the till dies before saving
+    redeem_voucher(&db, &webhooks, &[&voucher.code, "wyn"]);
+    let mut cart = None;
+    scan(&products, &mut cart, "011152431697");
+    apply_promo(&db, &mut cart, &["welcome"]);
+    db.apply_cart_to_user("wyn", cart.as_ref().unwrap()).unwrap();
+    let journal = db.transactions().unwrap()[saved.transactions.len()..]
+        .iter()
+        .map(|t| ron::to_string(t).unwrap() + "\n")
+        .collect::<String>();
+
+    let mut data = saved;
+    assert_eq!(data.replay_journal(&journal, &[]), 2);
+    assert_eq!(data.vouchers[&voucher.code].redeemed.as_ref().unwrap().0, "wyn");
+    assert_eq!(data.promos["WELCOME"].uses, 1);
+}
+
+#[test]
+fn database_round_trips_through_cbor() {
+    let (db, products, webhooks) = setup();
+    adduser(&db, &webhooks, &["xia"]);
+    db.deposit_user("xia", 500, db::DepositMethod::Cash, Some("birthday")).unwrap();
+    let mut cart = Cart::new();
+    cart.products.push(products.values().next().unwrap().clone());
+    db.apply_cart_to_user("xia", &cart).unwrap();
+
+    let data = db::InnerDB {
+        transactions: db.transactions().unwrap(),
+        users: db.users().unwrap().into_iter().map(|u| (u.id.clone(), u)).collect(),
+        ..Default::default()
+    };
+    let cbor = db::Format::Cbor.encode(&data).unwrap();
+    let back: db::InnerDB = db::Format::decode(&cbor).unwrap();
+    assert_eq!(back.users["xia"].balance, data.users["xia"].balance);
+    assert_eq!(back.transactions.len(), 2);
+    assert_eq!(back.transactions[0].note.as_deref(), Some("birthday"));
+}
+
+#[test]
+fn history_iterates_from_the_tail() {
+    let (db, _, webhooks) = setup();
+    adduser(&db, &webhooks, &["yui"]);
+    adduser(&db, &webhooks, &["zoe"]);
+    for amount in [100, 200, 300] {
+        db.deposit_user("yui", amount, db::DepositMethod::Cash, None).unwrap();
+    }
+    db.deposit_user("zoe", 400, db::DepositMethod::Cash, None).unwrap();
+
+    let history = db.history().unwrap();
+    let latest = history
+        .for_account("yui")
+        .rev()
+        .take(2)
+        .filter_map(|t| match t.transaction {
+            db::TransactionType::Deposit { amount, .. } => Some(amount),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(latest, vec![300, 200]);
+    assert_eq!(history.iter().count(), 4);
+}
+
+#[test]
+fn archiving_keeps_balances() {
+    use chrono::TimeZone;
+
+    let (db, _, webhooks) = setup();
+    adduser(&db, &webhooks, &["ada"]);
+    db.deposit_user("ada", 900, db::DepositMethod::Cash, None).unwrap();
+    db.withdraw_user("ada", 200, None).unwrap();
+
+    let mut data = db::InnerDB {
+        transactions: db.transactions().unwrap(),
+        users: db.users().unwrap().into_iter().map(|u| (u.id.clone(), u)).collect(),
+        ..Default::default()
+    };
+    let mut old = data.transactions[0].clone();
+    old.timestamp = chrono::Utc.with_ymd_and_hms(2023, 6, 1, 18, 0, 0).unwrap();
+    data.transactions[0] = old.clone();
+
+    let archived = data.split_archive(chrono::Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap());
+    assert_eq!(archived.keys().copied().collect::<Vec<_>>(), vec![2023]);
+    assert_eq!(archived[&2023][0].id, old.id);
+    assert_eq!(data.transactions.len(), 1);
+    assert_eq!(data.archive.balances["ada"], 900);
+    assert!(data.project().is_empty());
+    assert_eq!(data.users["ada"].balance, 700);
+}
+
+#[test]
+fn read_only_commands_are_real_commands() {
+    for command in READ_ONLY_COMMANDS {
+        assert!(FORBIDDEN_USERS.contains(&command), "{}", command);
+    }
+    assert!(!READ_ONLY_COMMANDS.contains(&"deposit"));
+}
+
+#[test]
+fn transactions_are_tagged_with_the_till() {
+    let (mut db, _, webhooks) = setup();
+    db.set_till("kitchen");
+    adduser(&db, &webhooks, &["bea"]);
+    db.deposit_user("bea", 300, db::DepositMethod::Cash, None).unwrap();
+    assert_eq!(db.transactions().unwrap()[0].till.as_deref(), Some("kitchen"));
+
+    db.empty_cashbox("GBP").unwrap();
+    let emptied = db.cashbox_emptied().unwrap();
+    assert!(emptied.contains_key("kitchen/GBP"));
+    assert!(!emptied.contains_key("GBP"));
+}
+
+#[tokio::test]
+async fn z_report_summarises_the_shift() {
+    let (db, products, webhooks) = setup();
+    adduser(&db, &webhooks, &["cal"]);
+    db.deposit_user("cal", 1000, db::DepositMethod::Cash, None).unwrap();
+    db.deposit_user("cal", 500, db::DepositMethod::BankTransfer, None).unwrap();
+    let mut cart = None;
+    scan(&products, &mut cart, "4029764001401");
+    db.apply_cart_to_user("cal", cart.as_ref().unwrap()).unwrap();
+    // Sold again, as a separate sale
+    cart.as_mut().unwrap().key = uuid::Uuid::new_v4();
+    db.apply_cart_to_cash(cart.as_ref().unwrap(), None, 0, None).unwrap();
+    db.withdraw_user("cal", 200, None).unwrap();
+
+    let transactions = db.transactions().unwrap();
+    let summary = reports::z_summary(&transactions.iter().collect::<Vec<_>>(), "GBP");
+    assert_eq!(summary.sales, 2);
+    assert_eq!(summary.sales_by_method["balance"], 120);
+    assert_eq!(summary.sales_by_method["cash"], 120);
+    assert_eq!(summary.deposits_by_method["bank transfer"], 500);
+    assert_eq!(summary.cash["GBP"], 1000 + 120 - 200);
+
+    db.mark_zreport(chrono::Utc::now()).unwrap();
+    assert!(db.last_zreport().unwrap().is_some());
+}
+
+#[tokio::test]
+async fn reorder_covers_the_next_run() {
+    let (db, products, _) = setup();
+    let mate = products.get(&Barcode::try_parse("4029764001401").unwrap()).unwrap();
+    db.restock(mate, 10, Some(85), None).unwrap();
+    db.write_off(mate, 1, "dented").unwrap();
+    // 14 sold over the last four weeks is one every other day
+    for _ in 0..14 {
+        let mut cart = None;
+        scan(&products, &mut cart, "4029764001401");
+        db.apply_cart_to_cash(cart.as_ref().unwrap(), None, 0, None).unwrap();
+    }
+
+    let transactions = db.transactions().unwrap();
+    assert_eq!(bank_core::stock::levels(&transactions)[&mate.barcode], 10 - 1 - 14);
+    let suggestions = reports::reorder_suggestions(&transactions, chrono::Utc::now(), 14);
+    assert_eq!(suggestions.len(), 1);
+    assert_eq!(suggestions[0].quantity, 7);
+    assert_eq!(suggestions[0].cost, Some(85));
+}
+
+#[test]
+fn receiving_a_purchase_order_restocks_it() {
+    let (db, products, _) = setup();
+    let ramune = products.get(&Barcode::try_parse("011152431697").unwrap()).unwrap();
+    db.add_supplier("cashandcarry", None).unwrap();
+    assert!(db.new_purchase_order("nobody", Vec::new()).is_err());
+
+    let order = db
+        .new_purchase_order("cashandcarry", vec![db::OrderLine {
+            product: ramune.clone(),
+            quantity: 24,
+            cost: Some(110),
+        }])
+        .unwrap();
+    assert_eq!(order.total(), 24 * 110);
+
+    let id = order.id.simple().to_string();
+    db.receive_purchase_order(&id[..8]).unwrap();
+    assert!(db.receive_purchase_order(&id[..8]).is_err());
+
+    let transactions = db.transactions().unwrap();
+    assert_eq!(bank_core::stock::levels(&transactions)[&ramune.barcode], 24);
+    assert_eq!(bank_core::stock::spend_by_supplier(&transactions)["cashandcarry"], 24 * 110);
+}
+
+#[test]
+fn expenses_come_out_of_the_cash_box() {
+    let (db, products, _) = setup();
+    let mut cart = None;
+    scan(&products, &mut cart, "011152431697");
+    db.apply_cart_to_cash(cart.as_ref().unwrap(), None, 0, None).unwrap();
+    db.expense(150, "milk").unwrap();
+
+    let transactions = db.transactions().unwrap();
+    let transactions = transactions.iter().collect::<Vec<_>>();
+    assert_eq!(reports::z_summary(&transactions, "GBP").cash["GBP"], 200 - 150);
+    let margin = reports::margin(&transactions);
+    assert_eq!(margin.sales, 200);
+    assert_eq!(margin.expenses, 150);
+    assert_eq!(margin.net(), 50);
+}
+
+#[test]
+fn shrinkage_compares_counts_with_expected_stock() {
+    let (db, products, _) = setup();
+    let mate = products.get(&Barcode::try_parse("4029764001401").unwrap()).unwrap();
+    db.restock(mate, 12, None, None).unwrap();
+    let mut cart = None;
+    scan(&products, &mut cart, "4029764001401");
+    scan(&products, &mut cart, "4029764001401");
+    db.apply_cart_to_cash(cart.as_ref().unwrap(), None, 0, None).unwrap();
+    db.write_off(mate, 1, "leaking").unwrap();
+    db.stocktake(mate, 6).unwrap();
+
+    let transactions = db.transactions().unwrap();
+    let now = chrono::Utc::now();
+    let movements = bank_core::stock::movements(&transactions, now - chrono::Duration::days(1), now);
+    assert_eq!(movements[&mate.barcode], bank_core::stock::Movement {
+        intake: 12,
+        sold: 2,
+        written_off: 1,
+        missing: 3,
+    });
+    assert_eq!(bank_core::stock::levels(&transactions)[&mate.barcode], 6);
+}
+
+#[test]
+fn cash_rounding_is_recorded_separately() {
+    assert_eq!(config::round_cash(123, 5), (125, 2));
+    assert_eq!(config::round_cash(122, 5), (120, -2));
+    assert_eq!(config::round_cash(123, 0), (123, 0));
+
+    let (db, products, _) = setup();
+    let mut cart = None;
+    scan(&products, &mut cart, "4029764001401");
+    db.apply_cart_to_cash(cart.as_ref().unwrap(), None, 5, None).unwrap();
+
+    let transactions = db.transactions().unwrap();
+    assert!(matches!(
+        transactions[0].transaction,
+        db::TransactionType::Purchase { total: 120, rounding: 5, .. }
+    ));
+    let transactions = transactions.iter().collect::<Vec<_>>();
+    assert_eq!(reports::z_summary(&transactions, "GBP").cash["GBP"], 125);
+}
+
+#[test]
+fn hotkeys_only_replace_empty_lines() {
+    let pressed = hotkeys::Pressed::default();
+    *pressed.lock().unwrap() = Some("cash".to_string());
+    assert_eq!(hotkeys::take(&pressed, "alice".to_string()), "alice");
+    assert!(pressed.lock().unwrap().is_none());
+
+    *pressed.lock().unwrap() = Some("cash".to_string());
+    assert_eq!(hotkeys::take(&pressed, String::new()), "cash");
+    assert_eq!(hotkeys::take(&pressed, String::new()), "");
+    assert_eq!(hotkeys::defaults()[&'a'], "abort");
+}
+
+#[test]
+fn product_names_can_be_translated() {
+    let (_, mut products, _) = setup();
+    let names = products::parse_translations("# German\n4029764001401 Club-Mate Original\n").unwrap();
+    products::translate(&mut products, &names);
+    let mate = Barcode::try_parse("4029764001401").unwrap();
+    assert_eq!(products[&mate].name, "Club-Mate Original");
+    assert_eq!(products[&Barcode::try_parse("011152431697").unwrap()].name, "Ramune Citrus");
+    assert!(products::parse_translations("4029764001401").is_err());
+}
+
+#[test]
+fn plain_styles_paint_nothing() {
+    use bank_core::style::{Color, Style};
+    let styled = Style::new().bold().fg(Color::Red).paint("Low balance").to_string();
+    assert!(styled.contains('\u{1b}'));
+    // Other tests print, so don't leave plain mode switched on
+    bank_core::style::set_plain(true);
+    let plain = Style::new().bold().fg(Color::Red).paint("Low balance").to_string();
+    let heading = Style::new().underline().paint("Users").to_string();
+    bank_core::style::set_plain(false);
+    assert_eq!(plain, "Low balance");
+    assert_eq!(heading, "Users:");
+}
+
+#[test]
+fn theme_colours_are_parsed() {
+    use bank_core::style::{parse_colour, Color, Theme};
+    assert_eq!(parse_colour("Purple"), Ok(Color::Purple));
+    assert_eq!(parse_colour("208"), Ok(Color::Fixed(208)));
+    assert_eq!(parse_colour("#ffd700"), Ok(Color::RGB(255, 215, 0)));
+    assert!(parse_colour("mauve").is_err());
+
+    let theme: Theme = ron::from_str(r#"(warning: "purple", prompt: Some("cyan"))"#).unwrap();
+    assert_eq!(theme.warning, Color::Purple);
+    assert_eq!(theme.prompt, Some(Color::Cyan));
+    assert_eq!(theme.cart, Color::Yellow);
+}
+
+#[test]
+fn big_text_covers_amounts() {
+    let text = bigtext::render("-£12.50").unwrap();
+    assert_eq!(text.lines().count(), 5);
+    assert!(text.lines().all(|l| l.chars().count() == text.lines().next().unwrap().chars().count()));
+    assert!(bigtext::render("Total").is_none());
+}
+
+#[tokio::test]
+async fn cli_queries_answer_in_json() {
+    let (db, products, webhooks) = setup();
+    adduser(&db, &webhooks, &["dee"]);
+    db.deposit_user("dee", 500, db::DepositMethod::Cash, None).unwrap();
+    let mut cart = None;
+    scan(&products, &mut cart, "011152431697");
+    db.apply_cart_to_user("dee", cart.as_ref().unwrap()).unwrap();
+
+    assert_eq!(cli::query(&db, &["balance", "dee"]).unwrap(), "£3.00");
+    let users: serde_json::Value =
+        serde_json::from_str(&cli::query(&db, &["users", "--json"]).unwrap()).unwrap();
+    assert_eq!(users[0]["balance"], 300);
+    assert!(users[0].get("pin_hash").is_none());
+
+    let purchases: serde_json::Value =
+        serde_json::from_str(&cli::query(&db, &["purchases", "--user", "dee", "--json"]).unwrap())
+            .unwrap();
+    assert_eq!(purchases.as_array().unwrap().len(), 1);
+    assert!(cli::query(&db, &["purchases", "--since"]).is_err());
+}
+
+#[test]
+fn scripts_stop_on_error_output() {
+    assert!(!script::failed("Added user alice\n"));
+    assert!(script::failed("Adding Club-Mate to cart\nError, unable to charge: frozen\n"));
+    assert!(script::failed("Usage: restock <barcode> <quantity> [unit cost]\n"));
+    assert!(script::failed("\x07Unknown command: aliec\n"));
+}
+
+#[test]
+fn lingering_cards_only_tap_once() {
+    let start = std::time::Instant::now();
+    let ms = std::time::Duration::from_millis;
+    let mut debouncer = Debouncer::new(ms(500));
+
+    assert!(debouncer.seen(&[1, 2, 3], start));
+    // Still on the reader, however long it sits there
+    assert!(!debouncer.seen(&[1, 2, 3], start + ms(400)));
+    assert!(!debouncer.seen(&[1, 2, 3], start + ms(800)));
+    // Someone else's card straight after
+    assert!(debouncer.seen(&[4, 5, 6], start + ms(850)));
+    // Taken away and touched again
+    assert!(debouncer.seen(&[1, 2, 3], start + ms(1400)));
+}
+
+#[test]
+fn repeat_scans_are_collapsed() {
+    let start = std::time::Instant::now();
+    let ms = std::time::Duration::from_millis;
+    let window = config::Config::default().reader.scan_debounce;
+    let mut debouncer = Debouncer::new(ms(window));
+
+    assert!(debouncer.seen(b"4029764001401", start));
+    // The scanner sending it twice
+    assert!(!debouncer.seen(b"4029764001401", start + ms(20)));
+    // A second bottle scanned on purpose
+    assert!(debouncer.seen(b"4029764001401", start + ms(window + 500)));
+
+    // Turned off
+    let mut debouncer = Debouncer::new(ms(0));
+    assert!(debouncer.seen(b"4029764001401", start));
+    assert!(debouncer.seen(b"4029764001401", start));
+}
+
+#[tokio::test]
+async fn mock_reader_registers_a_card() {
+    let (db, _products, webhooks) = setup();
+    adduser(&db, &webhooks, &["erin"]);
+
+    let reader = MockReader::script("# erin's card, twice\n04a1b2c3\nwait 600\n04a1b2c3\nerror unplugged\n");
+    let (card_tx, mut card_rx) = mpsc::channel(1);
+    let stop = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let stop_reader = std::sync::Arc::clone(&stop);
+    let thread = std::thread::spawn(move || {
+        card_reader(Box::new(reader), card_tx, stop_reader, std::time::Duration::from_millis(500))
+    });
+
+    register_card(&["erin"], &db, &config::Config::default(), &mut card_rx).await;
+    assert_eq!(db.get_user_by_card("4161178195").unwrap().0.id, "erin");
+    assert!(matches!(card_rx.recv().await, Some(CardEvent::ReaderError(e)) if e == "unplugged"));
+
+    stop.store(true, std::sync::atomic::Ordering::Relaxed);
+    thread.join().unwrap();
+}
+
+#[test]
+fn serial_scans_split_on_line_endings() {
+    let mut pending = Vec::new();
+    assert!(scanner::split_scans(&mut pending, b"40297640").is_empty());
+    assert_eq!(scanner::split_scans(&mut pending, b"01401\r\n0111"), vec!["4029764001401"]);
+    assert_eq!(
+        scanner::split_scans(&mut pending, b"52431697\r5000112637922\n"),
+        vec!["011152431697", "5000112637922"]
+    );
+    assert!(pending.is_empty());
+}
+
+#[test]
+fn coin_acceptor_counts_new_credits() {
+    // Checksums make the packet add up to zero
+    assert_eq!(acceptor::packet(2, 254, &[]), vec![2, 0, 1, 254, 255]);
+
+    let mut counter = None;
+    // Whatever was in the buffer before we started watching is left alone
+    assert!(acceptor::credits(&mut counter, &[7, 3, 1, 0, 0, 0, 0, 0, 0, 0, 0]).is_empty());
+    assert_eq!(acceptor::credits(&mut counter, &[9, 4, 1, 3, 1, 0, 0, 0, 0, 0, 0]), vec![3, 4]);
+    assert!(acceptor::credits(&mut counter, &[9, 4, 1, 3, 1, 0, 0, 0, 0, 0, 0]).is_empty());
+    // Round past 255, with a rejected coin in between
+    let mut counter = Some(253);
+    assert_eq!(acceptor::credits(&mut counter, &[1, 2, 1, 0, 14, 5, 1, 0, 0, 0, 0]), vec![5, 2]);
+
+    let machine = acceptor::Acceptor::default();
+    machine.start();
+    machine.credit(100);
+    machine.credit(20);
+    assert_eq!(machine.finish(), 120);
+    machine.credit(50);
+    assert_eq!(machine.finish(), 0);
+}
+
+#[test]
+fn drawer_opens_through_the_printer() {
+    let (db, _products, _webhooks) = setup();
+    let printer = std::env::temp_dir().join(format!("57bank-drawer-{}", std::process::id()));
+    std::fs::write(&printer, b"").unwrap();
+    let config = config::DrawerConfig::Printer {
+        device: printer.to_string_lossy().to_string(),
+        connector: 0,
+    };
+
+    drawer::kick(&config).unwrap();
+    assert_eq!(std::fs::read(&printer).unwrap(), drawer::escpos_kick(0));
+    std::fs::remove_file(&printer).unwrap();
+
+    db.drawer_opened("cash purchase").unwrap();
+    let history = db.history().unwrap();
+    assert!(matches!(
+        &history.iter().last().unwrap().transaction,
+        db::TransactionType::DrawerOpened { reason } if reason == "cash purchase"
+    ));
+}
+
+#[test]
+fn customer_display_fits_the_cart() {
+    let (_db, products, _webhooks) = setup();
+    let mut cart = None;
+    scan(&products, &mut cart, "4029764001401");
+    scan(&products, &mut cart, "011152431697");
+    let cart = cart.unwrap();
+
+    // A pole display has room for the last thing scanned and the total
+    let lines = display::cart_lines(&cart, 20, Some(2));
+    assert_eq!(lines.len(), 2);
+    assert!(lines.iter().all(|l| l.chars().count() == 20));
+    assert!(lines[1].starts_with("Total") && lines[1].ends_with(&cart.disp_total()));
+
+    assert_eq!(display::cart_lines(&cart, 40, None).len(), 3);
+}
+
+#[test]
+fn buttons_stand_for_typed_lines() {
+    let buttons: std::collections::HashMap<u32, config::ButtonAction> =
+        ron::from_str("{17: Confirm, 27: Abort, 22: Help}").unwrap();
+    assert_eq!(buttons[&17].line(), "");
+    assert_eq!(buttons[&27].line(), "abort");
+    assert_eq!(buttons[&22].line(), "help");
+}
+
+#[test]
+fn amounts_are_spoken_in_words() {
+    assert_eq!(speech::amount(100), "1 pound");
+    assert_eq!(speech::amount(120), "1 pound 20");
+    assert_eq!(speech::amount(80), "80 pence");
+    assert_eq!(speech::amount(300), "3 pounds");
+    assert_eq!(speech::amount(-250), "minus 2 pounds 50");
+}
+
+#[test]
+fn notices_go_where_they_are_routed() {
+    let log = std::env::temp_dir().join(format!("57bank-notices-{}", std::process::id()));
+    let config: notify::NotifyConfig = ron::from_str(&format!(
+        r#"(channels: {{"log": File(path: "{}")}}, routes: {{save_failed: ["log"]}})"#,
+        log.display()
+    ))
+    .unwrap();
+    let notifier = notify::Notifier::new(config);
+
+    notifier.send(notify::Notice::SaveFailed, "Unable to save the database: disk full");
+    notifier.send(notify::Notice::CashBoxFull, "The cash box has £250.00 in it, please bank it");
+
+    let logged = std::fs::read_to_string(&log).unwrap();
+    std::fs::remove_file(&log).unwrap();
+    assert_eq!(logged.lines().count(), 1);
+    assert!(logged.contains("SaveFailed Unable to save the database: disk full"));
+}
+
+#[tokio::test]
+async fn forecast_follows_the_day_of_the_week() {
+    let (db, products, _) = setup();
+    for _ in 0..8 {
+        let mut cart = None;
+        scan(&products, &mut cart, "011152431697");
+        db.apply_cart_to_cash(cart.as_ref().unwrap(), None, 0, None).unwrap();
+    }
+    let transactions = db.transactions().unwrap();
+    let ramune = Barcode::try_parse("011152431697").unwrap();
+
+    // Everything sold today, so tomorrow's quiet and the same day next week is busy
+    let now = chrono::Utc::now();
+    assert_eq!(reports::demand_forecast(&transactions, now, 1)[&ramune], 0.0);
+    assert_eq!(reports::demand_forecast(&transactions, now, 7)[&ramune], 2.0);
+}
+
+#[test]
+fn api_tokens_are_scoped() {
+    let (db, products, webhooks) = setup();
+    adduser(&db, &webhooks, &["una"]);
+    db.deposit_user("una", 500, db::DepositMethod::Cash, None).unwrap();
+    let config = config::Config::default();
+    let dashboard = db.issue_token("dashboard", db::Scope::ReadOnly, None).unwrap();
+    let vending = db.issue_token("vending", db::Scope::Purchase, None).unwrap();
+    let call = |method, path, token: &str, body: &str| {
+        let auth = format!("Bearer {}", token);
+        api::handle(&db, &config, &products, method, path, Some(&auth), body.as_bytes())
+    };
+
+    assert_eq!(call("GET", "/api/users/una", &dashboard, "").1["balance"], 500);
+    let buy = r#"{"user": "una", "products": ["4029764001401"]}"#;
+    assert_eq!(call("POST", "/api/purchase", &dashboard, buy).0, 403);
+    let (status, user) = call("POST", "/api/purchase", &vending, buy);
+    assert_eq!((status, user["balance"].as_i64()), (200, Some(380)));
+    let deposit = r#"{"user": "una", "amount": 100, "method": "Cash"}"#;
+    assert_eq!(call("POST", "/api/deposit", &vending, deposit).0, 403);
+    assert_eq!(call("GET", "/api/users", "57b_guess", "").0, 401);
+
+    // Only the hash is kept, and revoked tokens stop working
+    assert!(db.api_tokens().unwrap().iter().all(|t| t.hash != vending));
+    db.revoke_token("vending").unwrap();
+    assert_eq!(call("POST", "/api/purchase", &vending, buy).0, 401);
+}
+
+#[test]
+fn roles_limit_what_tokens_can_see() {
+    let (db, products, webhooks) = setup();
+    adduser(&db, &webhooks, &["wren"]);
+    adduser(&db, &webhooks, &["xan"]);
+    let config = config::Config {
+        admins: vec!["xan".to_string()],
+        ..Default::default()
+    };
+    let token = db.issue_token("phone", db::Scope::Purchase, Some("wren")).unwrap();
+    let get = |path| {
+        let auth = format!("Bearer {}", token);
+        api::handle(&db, &config, &products, "GET", path, Some(&auth), b"").0
+    };
+
+    // Members only see themselves
+    assert_eq!(get("/api/users/wren"), 200);
+    assert_eq!(get("/api/users/xan"), 403);
+    assert_eq!(get("/api/users"), 403);
+    db.set_role("wren", db::Role::Volunteer).unwrap();
+    assert_eq!(get("/api/users/xan"), 200);
+    assert_eq!(get("/api/users"), 403);
+    db.set_role("wren", db::Role::Treasurer).unwrap();
+    assert_eq!(get("/api/users"), 200);
+
+    // Config admins stay admins whatever their role says
+    let (xan, _) = db.get_user("xan").unwrap();
+    assert_eq!(config.role(&xan), db::Role::Admin);
+}
+
+#[tokio::test]
+async fn end_of_day_sums_up_today() {
+    let (db, products, webhooks) = setup();
+    adduser(&db, &webhooks, &["yan"]);
+    db.deposit_user("yan", 500, db::DepositMethod::Cash, None).unwrap();
+    let mut cart = None;
+    scan(&products, &mut cart, "4029764001401");
+    scan(&products, &mut cart, "4029764001401");
+    db.apply_cart_to_user("yan", cart.as_ref().unwrap()).unwrap();
+
+    let summary = reports::day_summary(&db.transactions().unwrap(), chrono::Local::now(), "GBP");
+    assert!(summary.contains("Takings: £2.40 from 1 sales"), "{}", summary);
+    assert!(summary.contains("Deposits: £5.00"));
+    assert!(summary.contains("Cash box: +£5.00"));
+    assert!(summary.ends_with("- 2 x Club-Mate Granat"));
+
+    // Once today's time has gone, the next one is tomorrow
+    use chrono::{TimeZone, Timelike};
+    let at = chrono::NaiveTime::from_hms_opt(12, 0, 0).unwrap();
+    let morning = chrono::Local.with_ymd_and_hms(2024, 3, 14, 9, 0, 0).unwrap();
+    let evening = chrono::Local.with_ymd_and_hms(2024, 3, 14, 18, 0, 0).unwrap();
+    let (today, tomorrow) = (reports::next_run(morning, at), reports::next_run(evening, at));
+    assert_eq!((today.date_naive(), today.hour()), (morning.date_naive(), 12));
+    assert_eq!((tomorrow.date_naive().to_string(), tomorrow.hour()), ("2024-03-15".to_string(), 12));
+}
+
+#[test]
+fn sessions_stand_in_for_the_user_id() {
+    let start = std::time::Instant::now();
+    let timeout = std::time::Duration::from_secs(60);
+    let mut logged_in = Some(session::Session::start("zoe", timeout, start));
+
+    let user = logged_in.as_ref().map(|s| s.user.as_str());
+    assert_eq!(session::fill_in(user, "setpin", vec![]), vec!["zoe"]);
+    assert_eq!(session::fill_in(user, "setpin", vec!["amy"]), vec!["amy"]);
+    assert!(session::fill_in(user, "stock", vec![]).is_empty());
+
+    // Each action keeps it going, sitting idle ends it
+    session::expire(&mut logged_in, start + std::time::Duration::from_secs(50));
+    session::expire(&mut logged_in, start + std::time::Duration::from_secs(100));
+    assert!(logged_in.is_some());
+    session::expire(&mut logged_in, start + std::time::Duration::from_secs(200));
+    assert!(logged_in.is_none());
+}
+
+#[test]
+fn quick_buy_is_limited_to_its_products() {
+    let drinks: config::QuickBuyConfig =
+        ron::from_str(r#"(products: ["4029764001401"])"#).unwrap();
+    assert_eq!(drinks.window, 10);
+    assert!(drinks.applies(&Barcode::try_parse("04029764001401").unwrap()));
+    assert!(!drinks.applies(&Barcode::try_parse("011152431697").unwrap()));
+
+    let everything: config::QuickBuyConfig = ron::from_str("(window: 5)").unwrap();
+    assert!(everything.applies(&Barcode::try_parse("011152431697").unwrap()));
+}
+
+#[test]
+fn favourite_codes_belong_to_their_user() {
+    let (db, _, webhooks) = setup();
+    adduser(&db, &webhooks, &["ada"]);
+    adduser(&db, &webhooks, &["bo"]);
+    let mate = Barcode::try_parse("4029764001401").unwrap();
+    db.set_favourite("ada", "me1", Some(mate.clone())).unwrap();
+
+    assert_eq!(favourite(&db, Some("ada"), "me1"), Some(mate));
+    assert_eq!(favourite(&db, Some("bo"), "me1"), None);
+    assert_eq!(favourite(&db, None, "me1"), None);
+    db.set_favourite("ada", "me1", None).unwrap();
+    assert_eq!(favourite(&db, Some("ada"), "me1"), None);
+}
+
+#[tokio::test]
+async fn usual_order_is_the_most_common_basket() {
+    let (db, products, webhooks) = setup();
+    adduser(&db, &webhooks, &["cy"]);
+    db.deposit_user("cy", 2000, db::DepositMethod::Cash, None).unwrap();
+    for basket in [
+        &["011152431697", "4029764001401"][..],
+        &["4029764001401"],
+        &["4029764001401", "011152431697"],
+    ] {
+        let mut cart = None;
+        for code in basket {
+            scan(&products, &mut cart, code);
+        }
+        db.apply_cart_to_user("cy", cart.as_ref().unwrap()).unwrap();
+    }
+
+    // The same things in any order count as the same basket
+    let usual = reports::usual_order(&db.transactions().unwrap(), "cy").unwrap();
+    assert_eq!(usual.len(), 2);
+    assert!(reports::usual_order(&db.transactions().unwrap(), "nobody").is_none());
+}
+
+#[test]
+fn deposits_stay_within_bounds() {
+    let deposits: config::DepositConfig =
+        ron::from_str("(min: Some(100), max: Some(10000), confirm_above: Some(5000))").unwrap();
+    assert!(deposits.check(1).unwrap_err().contains("at least £1.00"));
+    assert!(deposits.check(500000).unwrap_err().contains("more than £100.00"));
+    assert!(deposits.check(2000).is_ok());
+    assert!(!deposits.needs_confirming(5000));
+    assert!(deposits.needs_confirming(7500));
+    assert!(config::DepositConfig::default().check(1).is_ok());
+}
+
+#[test]
+fn deposit_presets_are_picked_by_letter() {
+    let deposits: config::DepositConfig = ron::from_str("(presets: [500, 1000, 2000])").unwrap();
+    assert_eq!(deposits.menu(), "a: £5.00, b: £10.00, c: £20.00");
+    assert_eq!(deposits.preset("b"), Some(1000));
+    assert_eq!(deposits.preset("d"), None);
+    // Typed amounts aren't mistaken for presets
+    assert_eq!(deposits.preset("2"), None);
+    assert_eq!(deposits.preset("abort"), None);
+}
+
+#[test]
+fn deposit_fees_are_recorded_and_taken_off() {
+    let (mut db, _, webhooks) = setup();
+    adduser(&db, &webhooks, &["dot"]);
+    db.set_deposit_fees(
+        [(db::DepositMethod::Card, db::DepositFee { percent: 1.69, flat: 10 })].into(),
+    );
+
+    let user = db.deposit_user("dot", 1000, db::DepositMethod::Card, None).unwrap();
+    assert_eq!(user.balance, 1000 - 17 - 10);
+    let user = db.deposit_user("dot", 500, db::DepositMethod::Cash, None).unwrap();
+    assert_eq!(user.balance, 973 + 500);
+
+    let fees = db
+        .transactions()
+        .unwrap()
+        .iter()
+        .filter_map(|t| match t.transaction {
+            db::TransactionType::Deposit { fee, .. } => Some(fee),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(fees, vec![27, 0]);
+    assert_eq!(db::DepositFee { percent: 0.0, flat: 50 }.on(20), 20);
+}
+
+#[tokio::test]
+async fn charts_scale_to_the_busiest_day() {
+    assert_eq!(reports::sparkline(&[0, 350, 700]), "▁▄█");
+    assert_eq!(reports::sparkline(&[0, 0]), "▁▁");
+    assert_eq!(reports::bar(5, 10).chars().count(), 15);
+
+    let (db, products, webhooks) = setup();
+    adduser(&db, &webhooks, &["eli"]);
+    db.deposit_user("eli", 500, db::DepositMethod::Cash, None).unwrap();
+    let mut cart = None;
+    scan(&products, &mut cart, "011152431697");
+    db.apply_cart_to_user("eli", cart.as_ref().unwrap()).unwrap();
+
+    let (sales, deposits) = reports::daily_totals(&db.transactions().unwrap(), chrono::Local::now(), 3);
+    assert_eq!(sales, vec![0, 0, 200]);
+    assert_eq!(deposits, vec![0, 0, 500]);
+}
+
+#[test]
+fn secrets_are_filled_into_the_config() {
+    std::env::set_var(secrets::env_var("matrix-token"), "syt_\"quoted\"");
+    let config = r#"(notify: (channels: {"ops": Matrix(homeserver: "https://example.org", room: "!a:example.org", token: "secret:matrix-token")}))"#;
+    let config: config::Config = ron::from_str(&secrets::resolve_all(config).unwrap()).unwrap();
+    match &config.notify.channels["ops"] {
+        notify::Channel::Matrix { token, .. } => assert_eq!(token, "syt_\"quoted\""),
+        _ => panic!("not a Matrix channel"),
+    }
+    assert!(secrets::resolve_all(r#""secret:nowhere-to-be-found""#).is_err());
+
+    let file = secrets::parse_env_file("# SMTP\nsmtp='hunter2'\n\nmonzo = \"abc=def\"\n");
+    assert_eq!(file["smtp"], "hunter2");
+    assert_eq!(file["monzo"], "abc=def");
+}
+
+#[test]
+fn retention_forgets_people_but_not_balances() {
+    let (db, _, webhooks) = setup();
+    adduser(&db, &webhooks, &["fay"]);
+    db.add_card_to_user("fay", Some("fob"), "04a1b2").unwrap();
+    db.deposit_user("fay", 300, db::DepositMethod::Cash, None).unwrap();
+    let now = chrono::Utc::now();
+
+    // Unused cards start counting from the first time the policy sees them
+    let year_ago = now - chrono::Duration::days(365);
+    let (anonymised, forgotten) = db
+        .apply_retention(Some(now + chrono::Duration::minutes(1)), Some(year_ago))
+        .unwrap();
+    assert_eq!((anonymised, forgotten), (1, 0));
+    assert!(matches!(db.transactions().unwrap()[0].actor, db::TransactionActor::Anonymous));
+    assert_eq!(db.get_user("fay").unwrap().0.balance, 300);
+    assert!(db.fsck().unwrap().is_empty());
+
+    let (_, forgotten) = db.apply_retention(None, Some(now + chrono::Duration::days(1))).unwrap();
+    assert_eq!(forgotten, 1);
+    assert!(db.get_user_by_card("04a1b2").is_none());
+}
+
+#[test]
+fn topup_links_say_who_is_paying() {
+    assert_eq!(
+        payment_link(1250, "57Bank delia"),
+        "https://monzo.me/davidhibberd/12.50?d=57Bank+delia"
+    );
+    assert!(FORBIDDEN_USERS.contains(&"topup"));
+    assert!(READ_ONLY_COMMANDS.contains(&"topup"));
+}
+
+#[test]
+fn balance_pages_need_a_signed_link() {
+    let (db, _, webhooks) = setup();
+    adduser(&db, &webhooks, &["fern"]);
+    adduser(&db, &webhooks, &["gus"]);
+    db.deposit_user("fern", 500, db::DepositMethod::Cash, None).unwrap();
+
+    let signature = api::page_signature("hunter2", "fern");
+    let (status, html) = api::balance_page(&db, "hunter2", &format!("/me/fern/{}", signature));
+    assert_eq!(status, 200);
+    assert!(html.contains("<strong>£5.00</strong>"));
+    assert!(html.contains("Deposit (cash)"));
+
+    // Someone else's link, or one signed with an old secret
+    assert_eq!(api::balance_page(&db, "hunter2", &format!("/me/gus/{}", signature)).0, 404);
+    assert_eq!(api::balance_page(&db, "hunter3", &format!("/me/fern/{}", signature)).0, 404);
+    assert_eq!(api::balance_page(&db, "hunter2", "/me/fern").0, 404);
+}
+
+#[test]
+fn live_feed_streams_taps_and_webhook_events() {
+    let (db, _, webhooks) = setup();
+    let mut feed = live::subscribe();
+    adduser(&db, &webhooks, &["yara"]);
+    live::card_tap("yara");
+    // Other tests share the feed, so look for ours among theirs
+    let mut ours = Vec::new();
+    while let Ok(event) = feed.try_recv() {
+        let event: serde_json::Value = serde_json::from_str(&event).unwrap();
+        if event["data"]["user"] == "yara" {
+            ours.push(event["event"].as_str().unwrap().to_string());
+        }
+    }
+    assert_eq!(ours, ["user_created", "card_tap"]);
+
+    let config = config::Config::default();
+    let wall = format!("Bearer {}", db.issue_token("wall", db::Scope::ReadOnly, None).unwrap());
+    assert!(api::live_allowed(&db, &config, Some(&wall)).is_ok());
+    let own = format!("Bearer {}", db.issue_token("own", db::Scope::ReadOnly, Some("yara")).unwrap());
+    assert_eq!(api::live_allowed(&db, &config, Some(&own)).unwrap_err().0, 403);
+    assert_eq!(api::live_allowed(&db, &config, None).unwrap_err().0, 401);
+}
+
+#[test]
+fn snapshot_diffs_show_what_happened_overnight() {
+    let (db, _, webhooks) = setup();
+    adduser(&db, &webhooks, &["abe"]);
+    let snapshot = |db: &db::DB| {
+        let mut data = db::InnerDB {
+            transactions: db.transactions().unwrap(),
+            ..Default::default()
+        };
+        for user in db.users().unwrap() {
+            data.users.insert(user.id.clone(), user);
+        }
+        data
+    };
+    let before = snapshot(&db);
+    assert_eq!(reports::snapshot_diff(&before, &before), "No differences");
+
+    adduser(&db, &webhooks, &["bea"]);
+    db.add_card_to_user("abe", Some("keyfob"), "04aabbccdd").unwrap();
+    db.deposit_user("abe", 250, db::DepositMethod::Cash, None).unwrap();
+    let diff = reports::snapshot_diff(&before, &snapshot(&db));
+    let lines = diff.lines().collect::<Vec<_>>();
+    assert_eq!(lines[0], "  user abe balance £0.00 -> £2.50 (+£2.50)");
+    assert_eq!(lines[1], "+ card 04aabbccdd (keyfob) of user abe");
+    assert_eq!(lines[2], "+ user bea (balance £0.00)");
+    assert!(lines[3].ends_with("user abe Deposit (cash)"));
+    assert_eq!(lines.len(), 4);
+}
+
+#[test]
+fn restoring_swaps_in_the_whole_database() {
+    let (db, _, webhooks) = setup();
+    adduser(&db, &webhooks, &["cal"]);
+    let backup = db.snapshot().unwrap();
+    db.deposit_user("cal", 300, db::DepositMethod::Cash, None).unwrap();
+    adduser(&db, &webhooks, &["dee"]);
+
+    db.restore(backup).unwrap();
+    assert_eq!(db.get_user("cal").unwrap().0.balance, 0);
+    assert!(db.get_user("dee").is_none());
+    assert!(db.transactions().unwrap().is_empty());
+    assert!(db.fsck().unwrap().is_empty());
+}
+
+#[test]
+fn balances_can_be_looked_up_in_the_past() {
+    let (db, _, webhooks) = setup();
+    adduser(&db, &webhooks, &["eli"]);
+    let before = chrono::Utc::now();
+    db.deposit_user("eli", 500, db::DepositMethod::Cash, None).unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(5));
+    let between = chrono::Utc::now();
+    std::thread::sleep(std::time::Duration::from_millis(5));
+    db.adjust_user("eli", -800, "tab", "admin", None).unwrap();
+
+    assert_eq!(db.balance_at("eli", before).unwrap(), 0);
+    assert_eq!(db.balance_at("eli", between).unwrap(), 500);
+    assert_eq!(db.balance_at("eli", chrono::Utc::now()).unwrap(), -300);
+    assert!(db.balance_at("nobody", before).is_err());
+
+    let evening = parse_time(&["2024-03-14", "18:30"]).unwrap();
+    assert_eq!(evening.with_timezone(&chrono::Local).format("%H:%M").to_string(), "18:30");
+    assert!(parse_time(&["2024-03-14"]).unwrap() > evening);
+    assert!(parse_time(&["yesterday"]).is_none());
+}
+
+#[tokio::test]
+async fn the_books_always_balance() {
+    let (mut db, products, webhooks) = setup();
+    db.set_deposit_fees([(db::DepositMethod::Card, db::DepositFee { percent: 0.0, flat: 20 })].into());
+    adduser(&db, &webhooks, &["fay"]);
+    db.deposit_user("fay", 1000, db::DepositMethod::Card, None).unwrap();
+    db.deposit_user("fay", 500, db::DepositMethod::Cash, None).unwrap();
+    let mut cart = None;
+    scan(&products, &mut cart, "011152431697");
+    complete_cart(&db, &config::Config::default(), &webhooks, db.get_user("fay").unwrap(), &mut cart).await;
+    db.adjust_user("fay", -30, "spilt", "admin", None).unwrap();
+
+    let transactions = db.transactions().unwrap();
+    for t in &transactions {
+        let entry = crate::ledger::entry(t).unwrap();
+        assert_eq!(entry.postings.iter().map(|p| p.amount).sum::<i64>(), 0, "{}", entry.description);
+    }
+    let balances = crate::ledger::balances(&transactions);
+    assert_eq!(balances.values().sum::<i64>(), 0);
+    let fay = db.get_user("fay").unwrap().0.balance as i64;
+    assert_eq!(balances[&crate::ledger::Account::User("fay".to_string())], -fay);
+    assert_eq!(balances[&crate::ledger::Account::Cash(None)], 500);
+    assert_eq!(reports::revenue(&balances), 200 + 20);
+}
+
+#[test]
+fn stock_is_valued_at_cost_and_retail() {
+    let (db, _, _) = setup();
+    let products = products::parse_products(
+        "4029764001401 120 category=drink Club-Mate Granat\n011152431697 200 Ramune Citrus\n",
+    )
+    .unwrap();
+    let mate = &products[&Barcode::try_parse("4029764001401").unwrap()];
+    let ramune = &products[&Barcode::try_parse("011152431697").unwrap()];
+    db.restock(mate, 10, Some(85), None).unwrap();
+    db.restock(ramune, 3, None, None).unwrap();
+    db.write_off(mate, 2, "dropped").unwrap();
+
+    let value = reports::stock_valuation(&db.transactions().unwrap(), &products);
+    assert_eq!(
+        value["drink"],
+        reports::Valuation { units: 8, cost: 8 * 85, retail: 8 * 120, uncosted: 0 }
+    );
+    assert_eq!(
+        value["Uncategorised"],
+        reports::Valuation { units: 3, cost: 0, retail: 600, uncosted: 3 }
+    );
+}
+
+#[test]
+fn data_dir_follows_systemd_then_xdg() {
+    use bank_core::paths::default_data_dir;
+    use std::path::PathBuf;
+
+    const EVERYTHING: &[(&str, &str)] = &[
+        ("STATE_DIRECTORY", "/var/lib/57bank:/var/lib/other"),
+        ("XDG_DATA_HOME", "/home/bank/.data"),
+        ("HOME", "/home/bank"),
+    ];
+    let env = |vars: &'static [(&str, &str)]| {
+        move |name: &str| vars.iter().find(|(k, _)| *k == name).map(|(_, v)| v.to_string())
+    };
+    assert_eq!(default_data_dir(env(EVERYTHING), true), PathBuf::from("/var/lib/57bank"));
+    assert_eq!(default_data_dir(env(&EVERYTHING[1..]), true), PathBuf::from("./data"));
+    assert_eq!(
+        default_data_dir(env(&EVERYTHING[1..]), false),
+        PathBuf::from("/home/bank/.data/57bank")
+    );
+    assert_eq!(
+        default_data_dir(env(&[("XDG_DATA_HOME", ""), ("HOME", "/home/bank")]), false),
+        PathBuf::from("/home/bank/.local/share/57bank")
+    );
+}
+
+#[test]
+fn watch_views_redraw_on_new_transactions() {
+    let (db, _, _) = setup();
+    assert_eq!("purchases".parse(), Ok(watch::View::Purchases));
+    assert!("deposits".parse::<watch::View>().is_err());
+
+    assert_eq!(watch::latest(&db), None);
+    db.add_user("alice").unwrap();
+    db.deposit_user("alice", 500, db::DepositMethod::Cash, None).unwrap();
+    let before = watch::latest(&db);
+    assert!(before.is_some());
+    assert_eq!(watch::latest(&db), before);
+    db.deposit_user("alice", 100, db::DepositMethod::Cash, None).unwrap();
+    assert_ne!(watch::latest(&db), before);
+}
+
+#[test]
+fn retries_never_charge_twice() {
+    let (db, products, webhooks) = setup();
+    adduser(&db, &webhooks, &["ola"]);
+    db.deposit_user("ola", 500, db::DepositMethod::Cash, None).unwrap();
+    let mut cart = None;
+    scan(&products, &mut cart, "4029764001401");
+    db.apply_cart_to_user("ola", cart.as_ref().unwrap()).unwrap();
+    assert!(db.apply_cart_to_user("ola", cart.as_ref().unwrap()).is_err());
+    assert!(db.apply_cart_to_cash(cart.as_ref().unwrap(), None, 0, None).is_err());
+    assert_eq!(db.get_user("ola").unwrap().0.balance, 380);
+
+    let config = config::Config::default();
+    let admin = db.issue_token("kiosk", db::Scope::Admin, None).unwrap();
+    let call = |path, body: &str| {
+        let auth = format!("Bearer {}", admin);
+        api::handle(&db, &config, &products, "POST", path, Some(&auth), body.as_bytes())
+    };
+    let buy = r#"{"user": "ola", "products": ["4029764001401"], "key": "vend-0042"}"#;
+    assert_eq!(call("/api/purchase", buy).0, 200);
+    assert_eq!(call("/api/purchase", buy).0, 409);
+    let deposit = r#"{"user": "ola", "amount": 100, "method": "Cash", "key": "a7f1c2d0-6b1e-4c57-9a3e-5710bade0001"}"#;
+    assert_eq!(call("/api/deposit", deposit).0, 200);
+    assert_eq!(call("/api/deposit", deposit).0, 409);
+    assert_eq!(db.get_user("ola").unwrap().0.balance, 380 - 120 + 100);
+}
+
+#[test]
+fn barcodes_are_found_whatever_their_leading_zeros() {
+    use bank_core::barcode::Symbology;
+
+    let parsed = |code| Barcode::parse(code).map(|(b, s)| (b.gtin(), s));
+    assert_eq!(parsed("4029764001401"), Some(("04029764001401".to_string(), Symbology::Ean13)));
+    assert_eq!(parsed("011152431697"), Some(("00011152431697".to_string(), Symbology::UpcA)));
+    assert_eq!(parsed("0011152431697"), Some(("00011152431697".to_string(), Symbology::UpcA)));
+    assert_eq!(parsed("11152431697"), Some(("00011152431697".to_string(), Symbology::UpcA)));
+    assert_eq!(parsed("96385074"), Some(("00000096385074".to_string(), Symbology::Ean8)));
+    assert_eq!(parsed("14029764001408").map(|p| p.1), Some(Symbology::Gtin14));
+    assert_eq!(parsed("04029764001401").map(|p| p.1), Some(Symbology::Ean13));
+    assert_eq!(parsed("12345"), None);
+
+    let upc = Barcode::try_parse("011152431697").unwrap();
+    assert_eq!(upc.symbology(), Symbology::UpcA);
+    assert_eq!(upc.stripped(), "11152431697");
+    assert_eq!(upc.padded(13).as_deref(), Some("0011152431697"));
+    assert_eq!(upc.padded(8), None);
+    assert_eq!(upc.written(Symbology::UpcA), "011152431697");
+
+    let products = products::parse_products("0011152431697 200 Ramune Citrus\n").unwrap();
+    assert_eq!(products::lookup(&products, "11152431697").unwrap().name, "Ramune Citrus");
+}
+
+#[tokio::test]
+async fn manual_discounts_come_off_the_whole_cart() {
+    let (db, products, webhooks) = setup();
+    adduser(&db, &webhooks, &["pia"]);
+    db.deposit_user("pia", 1000, db::DepositMethod::Cash, None).unwrap();
+    let mut cart = None;
+    scan(&products, &mut cart, "4029764001401");
+    scan(&products, &mut cart, "011152431697");
+    let c_cart = cart.as_mut().unwrap();
+    c_cart.manual_discount = Some(db::ManualDiscount {
+        discount: "10%".parse().unwrap(),
+        amount: 0,
+        reason: Some("end of the night".to_string()),
+        admin: "q".to_string(),
+    });
+    assert_eq!(c_cart.manual_discount_amount(), 32);
+    assert_eq!(c_cart.total(), 320 - 32);
+    complete_cart(&db, &config::Config::default(), &webhooks, db.get_user("pia").unwrap(), &mut cart).await;
+
+    let (user, transactions) = db.get_user("pia").unwrap();
+    assert_eq!(user.balance, 1000 - 288);
+    match &transactions.last().unwrap().transaction {
+        db::TransactionType::Purchase { total: 288, manual_discount: Some(m), .. } => {
+            assert_eq!((m.amount, m.reason.as_deref(), m.admin.as_str()), (32, Some("end of the night"), "q"));
+        }
+        t => panic!("unexpected {:?}", t),
+    }
+}
+
+#[test]
+fn debts_are_aged_from_when_the_balance_went_negative() {
+    let now = chrono::Utc::now();
+    let adjust = |user: &str, amount: i32, days_ago: i64| db::Transaction {
+        id: uuid::Uuid::new_v4(),
+        timestamp: now - chrono::Duration::days(days_ago),
+        actor: db::TransactionActor::User(user.to_string()),
+        transaction: db::TransactionType::Adjustment { amount, reason: String::new(), admin: "q".to_string() },
+        note: None,
+        till: None,
+    };
+    let mut data = db::InnerDB::default();
+    // In credit, then under 100 days ago, topped up but still owing
+    data.apply(adjust("rae", 500, 200));
+    data.apply(adjust("rae", -800, 100));
+    data.apply(adjust("rae", 100, 10));
+    // Went negative recently
+    data.apply(adjust("sol", 200, 50));
+    data.apply(adjust("sol", -250, 40));
+    // Never owed anything
+    data.apply(adjust("tam", 100, 5));
+    let users = data.users.values().cloned().collect::<Vec<_>>();
+
+    let debts = reports::debts(&users, &data.transactions, now);
+    assert_eq!(
+        debts,
+        vec![
+            reports::Debt { user: "rae".to_string(), owed: 200, days: 100 },
+            reports::Debt { user: "sol".to_string(), owed: 50, days: 40 },
+        ]
+    );
+    assert_eq!(reports::aging_bucket(100), "90+ days");
+    assert_eq!(reports::aging_bucket(40), "30-60 days");
+    assert_eq!(reports::aging_bucket(29), "Under 30 days");
+}
+
+#[tokio::test]
+async fn buyers_can_void_their_own_purchase_straight_after() {
+    let (db, products, webhooks) = setup();
+    adduser(&db, &webhooks, &["uma"]);
+    adduser(&db, &webhooks, &["vic"]);
+    db.deposit_user("uma", 500, db::DepositMethod::Cash, None).unwrap();
+    let mate = &products[&Barcode::try_parse("4029764001401").unwrap()];
+    db.restock(mate, 5, None, None).unwrap();
+    let mut cart = None;
+    scan(&products, &mut cart, "4029764001401");
+    complete_cart(&db, &config::Config::default(), &webhooks, db.get_user("uma").unwrap(), &mut cart).await;
+    let purchase = db.get_user("uma").unwrap().1.last().unwrap().id;
+
+    let window = chrono::Duration::seconds(120);
+    assert!(db.void_purchase(purchase, "vic", window).is_err());
+    assert!(db.void_purchase(purchase, "uma", chrono::Duration::zero()).is_err());
+    let void = db.void_purchase(purchase, "uma", window).unwrap();
+    assert!(matches!(void.transaction, db::TransactionType::Void { transaction, amount: 120, .. } if transaction == purchase));
+    assert!(db.void_purchase(purchase, "uma", window).is_err());
+
+    assert_eq!(db.get_user("uma").unwrap().0.balance, 500);
+    let transactions = db.transactions().unwrap();
+    assert_eq!(bank_core::stock::levels(&transactions)[&mate.barcode], 5);
+    let balances = crate::ledger::balances(transactions.iter());
+    assert_eq!(balances.values().sum::<i64>(), 0);
+    assert_eq!(reports::revenue(&balances), 0);
+}
+
+#[test]
+fn transactions_are_found_by_the_start_of_their_id() {
+    let (db, _, webhooks) = setup();
+    adduser(&db, &webhooks, &["wes"]);
+    let deposit = uuid::Uuid::new_v4();
+    db.deposit_user_once(deposit, "wes", 300, db::DepositMethod::Cash, Some("float")).unwrap();
+    let refund = db.refund_to_cash(&deposit.to_string()[..8], None).unwrap();
+    let transactions = db.transactions().unwrap();
+
+    let found = db::find_transaction(&transactions, &deposit.to_string()[..8]).unwrap();
+    assert_eq!(found.id, deposit);
+    assert_eq!(found.note.as_deref(), Some("float"));
+    assert!(db::find_transaction(&transactions, "").is_err());
+    assert!(db::find_transaction(&transactions, "not-an-id").is_err());
+    let linked = db::linked_transactions(&transactions, deposit);
+    assert_eq!(linked.iter().map(|t| t.id).collect::<Vec<_>>(), vec![refund.id]);
+}
+
+#[tokio::test]
+async fn product_sales_are_broken_down_by_week() {
+    let (db, products, webhooks) = setup();
+    adduser(&db, &webhooks, &["xan"]);
+    db.deposit_user("xan", 1000, db::DepositMethod::Cash, None).unwrap();
+    let mate = Barcode::try_parse("4029764001401").unwrap();
+    for _ in 0..3 {
+        let mut cart = None;
+        scan(&products, &mut cart, "4029764001401");
+        complete_cart(&db, &config::Config::default(), &webhooks, db.get_user("xan").unwrap(), &mut cart).await;
+    }
+    let last = db.get_user("xan").unwrap().1.last().unwrap().id;
+    db.void_purchase(last, "xan", chrono::Duration::seconds(120)).unwrap();
+    let transactions = db.transactions().unwrap();
+
+    let now = chrono::Utc::now();
+    let sales = reports::product_sales(&transactions, &mate, now, 4);
+    assert_eq!(sales.weekly, vec![0, 0, 0, 2]);
+    assert_eq!((sales.units, sales.revenue), (2, 240));
+    assert!(sales.last_sold.is_some());
+    let later = reports::product_sales(&transactions, &mate, now + chrono::Duration::days(10), 4);
+    assert_eq!(later.weekly, vec![0, 0, 2, 0]);
+    let other = reports::product_sales(&transactions, &Barcode::try_parse("5000112637922").unwrap(), now, 4);
+    assert_eq!(other, reports::ProductSales { weekly: vec![0; 4], ..Default::default() });
+}
+
+#[test]
+fn money_is_written_the_way_the_locale_expects() {
+    use bank_core::money::MoneyFormat;
+    let default = MoneyFormat::default();
+    assert_eq!(default.format(123456), "£1234.56");
+    assert_eq!(default.format(-5), "-£0.05");
+
+    let german: MoneyFormat = ron::from_str(r#"(locale: Some("de_DE.UTF-8"))"#).unwrap();
+    assert_eq!(german.format(-123456), "-1.234,56 £");
+    assert_eq!(german.format_signed(250), "+2,50 £");
+    assert_eq!(default.format_signed(-250), "-£2.50");
+    assert_eq!(default.format_signed(0), "£0.00");
+    let british: MoneyFormat = ron::from_str(r#"(locale: Some("en-GB"))"#).unwrap();
+    assert_eq!(british.format(100000000), "£1,000,000.00");
+    let custom: MoneyFormat = ron::from_str(r#"(symbol: Some("€"), decimal: Some(','), locale: Some("nl"))"#).unwrap();
+    assert_eq!(custom.format(9950), "€99,50");
+
+    assert!(ron::from_str::<MoneyFormat>(r#"(locale: Some("tlh"))"#).is_err());
+}
+
+#[test]
+fn two_tills_converge_after_syncing_both_ways() {
+    let (kitchen, _, webhooks) = setup();
+    let (hackspace, _, _) = setup();
+    adduser(&kitchen, &webhooks, &["yan"]);
+    kitchen.deposit_user("yan", 500, db::DepositMethod::Cash, None).unwrap();
+    adduser(&hackspace, &webhooks, &["zed"]);
+    hackspace.deposit_user("zed", 300, db::DepositMethod::Cash, None).unwrap();
+
+    let summary = kitchen.merge_from(hackspace.snapshot().unwrap()).unwrap();
+    assert_eq!((summary.users, summary.transactions), (1, 1));
+    hackspace.merge_from(kitchen.snapshot().unwrap()).unwrap();
+    hackspace.deposit_user("yan", 200, db::DepositMethod::Cash, None).unwrap();
+    kitchen.merge_from(hackspace.snapshot().unwrap()).unwrap();
+
+    for till in [&kitchen, &hackspace] {
+        assert_eq!(till.get_user("yan").unwrap().0.balance, 700);
+        assert_eq!(till.get_user("zed").unwrap().0.balance, 300);
+        assert_eq!(till.transactions().unwrap().len(), 3);
+    }
+    let again = kitchen.merge_from(hackspace.snapshot().unwrap()).unwrap();
+    assert_eq!((again.users, again.transactions), (0, 0));
+
+    // Transactions from before IDs were stored get the same one on every till
+    let mut peer = hackspace.snapshot().unwrap();
+    let mut old = peer.transactions[0].clone();
+    old.id = uuid::Uuid::nil();
+    old.timestamp -= chrono::Duration::days(1);
+    peer.transactions.push(old);
+    assert_eq!(kitchen.merge_from(peer.clone()).unwrap().transactions, 1);
+    assert_eq!(kitchen.merge_from(peer).unwrap().transactions, 0);
+
+    // Someone who's only used the other till, in a year it has since archived
+    let mut peer = hackspace.snapshot().unwrap();
+    let mut quinn = db::User::new("quinn");
+    quinn.balance = 400;
+    peer.users.insert("quinn".to_string(), quinn);
+    peer.archive.years.insert(2024);
+    peer.archive.balances.insert("quinn".to_string(), 400);
+    assert_eq!(kitchen.merge_from(peer).unwrap().users, 1);
+    assert_eq!(kitchen.get_user("quinn").unwrap().0.balance, 400);
+    assert!(kitchen.rebuild_balances().unwrap().is_empty());
+}
+
+#[test]
+fn promos_and_standing_orders_follow_syncing() {
+    let (kitchen, _, webhooks) = setup();
+    let (hackspace, _, _) = setup();
+    adduser(&hackspace, &webhooks, &["ada"]);
+    add_promo(&hackspace, &["welcome", "25%", "3"]);
+    let order = hackspace.add_standing_order("ada", 500, db::Period::Monthly, "locker").unwrap();
+    hackspace.apply_standing_orders(chrono::Utc::now()).unwrap();
+
+    kitchen.merge_from(hackspace.snapshot().unwrap()).unwrap();
+    assert_eq!(kitchen.get_promo("welcome").unwrap().max_uses, Some(3));
+    assert_eq!(kitchen.standing_orders().unwrap().len(), 1);
+    // The first charge came over with the order rather than being taken again
+    assert!(kitchen.apply_standing_orders(chrono::Utc::now()).unwrap().is_empty());
+    assert_eq!(kitchen.get_user("ada").unwrap().0.balance, -500);
+
+    // Cancelling or removing on one till sticks on both
+    kitchen.cancel_standing_order(&order.id.to_string()).unwrap();
+    kitchen.remove_promo("welcome").unwrap();
+    kitchen.merge_from(hackspace.snapshot().unwrap()).unwrap();
+    hackspace.merge_from(kitchen.snapshot().unwrap()).unwrap();
+    for till in [&kitchen, &hackspace] {
+        assert!(till.standing_orders().unwrap().is_empty());
+        assert!(till.get_promo("welcome").is_none());
+    }
+
+    // Adding the code again afterwards wins over the older removal
+    add_promo(&hackspace, &["welcome", "10%"]);
+    kitchen.merge_from(hackspace.snapshot().unwrap()).unwrap();
+    assert_eq!(kitchen.get_promo("welcome").unwrap().max_uses, None);
+}
+
+#[test]
+fn cards_deleted_on_one_till_stay_deleted_after_syncing() {
+    let (kitchen, _, webhooks) = setup();
+    let (hackspace, _, _) = setup();
+    adduser(&kitchen, &webhooks, &["amy"]);
+    kitchen.add_card_to_user("amy", Some("keyring"), "04a1b2c3d4").unwrap();
+    hackspace.merge_from(kitchen.snapshot().unwrap()).unwrap();
+    assert!(hackspace.get_user_by_card("04a1b2c3d4").is_some());
+
+    kitchen.delete_card("amy", db::CardNameOrID::Name("keyring".to_string())).unwrap();
+    kitchen.merge_from(hackspace.snapshot().unwrap()).unwrap();
+    assert!(kitchen.get_user_by_card("04a1b2c3d4").is_none());
+    hackspace.merge_from(kitchen.snapshot().unwrap()).unwrap();
+    assert!(hackspace.get_user_by_card("04a1b2c3d4").is_none());
+
+    // Adding it back afterwards wins over the older deletion
+    hackspace.add_card_to_user("amy", Some("keyring"), "04a1b2c3d4").unwrap();
+    kitchen.merge_from(hackspace.snapshot().unwrap()).unwrap();
+    assert!(kitchen.get_user_by_card("04a1b2c3d4").is_some());
+}
+
+#[test]
+fn a_broken_inbox_file_doesnt_hold_up_the_rest() {
+    let (kitchen, _, webhooks) = setup();
+    let (hackspace, _, _) = setup();
+    adduser(&kitchen, &webhooks, &["bea"]);
+    hackspace.merge_from(kitchen.snapshot().unwrap()).unwrap();
+    hackspace.deposit_user("bea", 250, db::DepositMethod::Cash, None).unwrap();
+    let deposit = hackspace.transactions().unwrap().pop().unwrap();
+
+    let inbox = std::env::temp_dir().join(format!("57bank-inbox-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&inbox);
+    std::fs::create_dir_all(&inbox).unwrap();
+    std::fs::write(inbox.join("00000000000000000001-broken.ron"), "(half a transac").unwrap();
+    std::fs::write(inbox.join("00000000000000000002-deposit.ron"), ron::to_string(&deposit).unwrap()).unwrap();
+
+    let summary = kitchen.ingest_from(&inbox).unwrap();
+    assert_eq!(summary.applied, 1);
+    assert_eq!(summary.rejected.len(), 1);
+    assert_eq!(kitchen.get_user("bea").unwrap().0.balance, 250);
+    assert!(inbox.join("bad/00000000000000000001-broken.ron").exists());
+
+    // Nothing's left to trip over next time
+    let again = kitchen.ingest_from(&inbox).unwrap();
+    assert_eq!((again.applied, again.rejected.len()), (0, 0));
+    let _ = std::fs::remove_dir_all(&inbox);
+}
+
+#[tokio::test]
+async fn imported_members_announce_their_new_accounts() {
+    let (db, _, webhooks) = setup();
+    adduser(&db, &webhooks, &["cat"]);
+    let list = std::env::temp_dir().join(format!("57bank-members-{}.csv", std::process::id()));
+    std::fs::write(&list, "membership_id,user_id\nM1,cat\nM2,dov\n").unwrap();
+    let mut feed = live::subscribe();
+    import_users(&db, &config::Config::default(), &webhooks, &["csv", list.to_str().unwrap()]).await;
+    let _ = std::fs::remove_file(&list);
+
+    let mut created = Vec::new();
+    while let Ok(event) = feed.try_recv() {
+        let event: serde_json::Value = serde_json::from_str(&event).unwrap();
+        if event["event"] == "user_created" && ["cat", "dov"].contains(&event["data"]["user"].as_str().unwrap_or("")) {
+            created.push(event["data"]["user"].as_str().unwrap().to_string());
+        }
+    }
+    assert_eq!(created, ["dov"]);
+    assert_eq!(db.get_user("dov").unwrap().0.membership_id.as_deref(), Some("M2"));
+}
+
+#[test]
+fn linking_never_quietly_moves_an_account_to_another_login() {
+    let (db, _, webhooks) = setup();
+    adduser(&db, &webhooks, &["eve"]);
+    db.link_identity("eve", "sub-eve", false).unwrap();
+    assert!(db.link_identity("eve", "sub-eve", false).is_ok());
+    assert!(db.link_identity("eve", "sub-mallory", false).is_err());
+    assert_eq!(db.get_user("eve").unwrap().0.oidc_subject.as_deref(), Some("sub-eve"));
+    db.link_identity("eve", "sub-eve-new", true).unwrap();
+    assert_eq!(db.get_user("eve").unwrap().0.oidc_subject.as_deref(), Some("sub-eve-new"));
+}
+
+#[test]
+fn pins_are_slow_to_guess_and_lock_after_too_many_tries() {
+    let (db, _, webhooks) = setup();
+    adduser(&db, &webhooks, &["fay"]);
+    db.set_pin("fay", "4821").unwrap();
+    let user = db.get_user("fay").unwrap().0;
+    assert!(user.pin_hash.as_deref().unwrap().starts_with("$argon2id$"));
+    assert!(user.check_pin("4821"));
+    assert!(!user.check_pin("4822"));
+
+    assert!(db.verify_pin("fay", "4821").unwrap());
+    for _ in 0..4 {
+        assert!(!db.verify_pin("fay", "0000").unwrap());
+    }
+    // A right PIN resets the count
+    assert!(db.verify_pin("fay", "4821").unwrap());
+    for _ in 0..5 {
+        assert!(!db.verify_pin("fay", "0000").unwrap());
+    }
+    assert!(db.verify_pin("fay", "4821").is_err());
+    db.set_pin("fay", "1357").unwrap();
+    assert!(db.verify_pin("fay", "1357").unwrap());
+
+    // Old style hashes still work, and are upgraded once used
+    use sha2::Digest;
+    let legacy = format!("salt${}", hex::encode(sha2::Sha256::digest("salt2468")));
+    let mut inner = db.snapshot().unwrap();
+    inner.users.get_mut("fay").unwrap().pin_hash = Some(legacy);
+    let db = db::DB::in_memory(inner).unwrap();
+    assert!(db.verify_pin("fay", "2468").unwrap());
+    assert!(db.get_user("fay").unwrap().0.pin_hash.unwrap().starts_with("$argon2id$"));
+}
@@ -0,0 +1,96 @@
+use bank_core::db;
+use bank_core::style::{theme, Style};
+use std::io::Write;
+use std::str::FromStr;
+
+// Other tills write to the database file rather than telling us, so check it this often too
+const POLL: std::time::Duration = std::time::Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum View {
+    Users,
+    Purchases,
+}
+
+impl FromStr for View {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "users" => Ok(View::Users),
+            "purchases" => Ok(View::Purchases),
+            _ => Err(format!("cannot watch {}, only users or purchases", s)),
+        }
+    }
+}
+
+// The newest transaction and how many there are, which changes whenever anything is recorded
+pub fn latest(db: &db::DB) -> Option<(usize, uuid::Uuid)> {
+    let history = db.history().ok()?;
+    let count = history.iter().count();
+    history.iter().next_back().map(|t| (count, t.id))
+}
+
+// Keeps the table on screen, redrawn whenever a transaction is recorded, until a key is pressed.
+// Purchases are only the ones `visible` lets the viewer see.
+pub async fn watch(db: &db::DB, config: &crate::config::Config, view: View, visible: &crate::Shown) {
+    if unsafe { libc::isatty(libc::STDIN_FILENO) } == 0 {
+        println!("Error, watching needs a terminal to press a key on");
+        return;
+    }
+    let Some(cooked) = crate::save_terminal() else {
+        println!("Error, unable to set up the terminal");
+        return;
+    };
+    // Keys come through one at a time without being echoed, rather than waiting for enter
+    let mut cbreak = cooked;
+    cbreak.c_lflag &= !(libc::ICANON | libc::ECHO);
+    cbreak.c_cc[libc::VMIN] = 1;
+    unsafe {
+        libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &cbreak);
+    }
+
+    let mut key = tokio::task::spawn_blocking(|| {
+        let mut byte = [0; 1];
+        let _ = std::io::Read::read(&mut std::io::stdin(), &mut byte);
+    });
+    let mut events = crate::live::subscribe();
+    let mut poll = tokio::time::interval(POLL);
+    let mut shown = None;
+    loop {
+        tokio::select! {
+            _ = &mut key => break,
+            _ = events.recv() => {}
+            _ = poll.tick() => {}
+        }
+        let now = latest(db);
+        if shown.is_some() && shown == Some(now) {
+            continue;
+        }
+        shown = Some(now);
+        draw(db, config, view, visible);
+    }
+    crate::restore_terminal(&Some(cooked));
+    println!();
+}
+
+fn draw(db: &db::DB, config: &crate::config::Config, view: View, visible: &crate::Shown) {
+    let mut stdout = std::io::stdout();
+    crate::clear(&mut stdout);
+    println!(
+        "{}",
+        Style::new().bold().on(theme().mode).paint(format!(
+            "Watching {}, updated {}, press any key to stop",
+            match view {
+                View::Users => "users",
+                View::Purchases => "purchases",
+            },
+            chrono::Local::now().format("%H:%M:%S")
+        ))
+    );
+    match view {
+        View::Users => crate::users(db),
+        View::Purchases => crate::purchases(db, config, visible),
+    }
+    let _ = stdout.flush();
+}
@@ -0,0 +1,93 @@
+use crate::config::WebhookConfig;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+const MAX_ATTEMPTS: u32 = 5;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Event {
+    Purchase,
+    Deposit,
+    UserCreated,
+    // A user's balance dropped below the configured warning level
+    LowBalance,
+    // An admin corrected a user's balance
+    Adjustment,
+}
+
+#[derive(Serialize)]
+struct Payload<'a> {
+    event: Event,
+    timestamp: chrono::DateTime<chrono::Utc>,
+    data: &'a serde_json::Value,
+}
+
+pub struct Webhooks {
+    hooks: Vec<WebhookConfig>,
+    client: reqwest::Client,
+}
+
+impl Webhooks {
+    pub fn new(hooks: Vec<WebhookConfig>) -> Self {
+        Self {
+            hooks,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    // Sends the event to every webhook subscribed to it, in the background so the till
+    // never waits on a slow receiver
+    pub fn fire(&self, event: Event, data: serde_json::Value) {
+        let body = match serde_json::to_vec(&Payload {
+            event,
+            timestamp: chrono::Utc::now(),
+            data: &data,
+        }) {
+            Ok(b) => b,
+            Err(e) => {
+                println!("Error, unable to serialize webhook payload: {}", e);
+                return;
+            }
+        };
+        crate::live::publish(&body);
+
+        for hook in &self.hooks {
+            if !hook.events.is_empty() && !hook.events.contains(&event) {
+                continue;
+            }
+
+            let mut mac = Hmac::<Sha256>::new_from_slice(hook.secret.as_bytes())
+                .expect("HMAC accepts keys of any length");
+            mac.update(&body);
+            let signature = format!("sha256={}", hex::encode(mac.finalize().into_bytes()));
+
+            let client = self.client.clone();
+            let url = hook.url.clone();
+            let body = body.clone();
+            tokio::spawn(async move {
+                let mut delay = std::time::Duration::from_secs(1);
+                for attempt in 1..=MAX_ATTEMPTS {
+                    let res = client
+                        .post(&url)
+                        .header("Content-Type", "application/json")
+                        .header("X-57Bank-Signature", &signature)
+                        .body(body.clone())
+                        .send()
+                        .await
+                        .and_then(|r| r.error_for_status());
+                    match res {
+                        Ok(_) => return,
+                        Err(e) if attempt == MAX_ATTEMPTS => {
+                            println!("Error, webhook {} failed after {} attempts: {}", url, attempt, e);
+                        }
+                        Err(_) => {
+                            tokio::time::sleep(delay).await;
+                            delay *= 2;
+                        }
+                    }
+                }
+            });
+        }
+    }
+}